@@ -0,0 +1,54 @@
+// src/clock.rs
+
+//! Time source for the engine entry points.
+//!
+//! Services keep taking a plain `now: Timestamp`; the executor's mutating
+//! entry points (`submit_order`, `execute_order`, `settle_market`, LP
+//! withdrawals, `apply_price_update`, ...) read it from a `Clock`, so
+//! multi-step scenarios only need to advance one clock. Read-only queries
+//! keep an explicit `now` to evaluate "as of" any time.
+use std::cell::Cell;
+
+use crate::types::Timestamp;
+
+pub trait Clock {
+    fn now(&self) -> Timestamp;
+}
+
+/// Deterministic clock for tests and simulations: only moves when told to.
+#[derive(Debug, Default, Clone)]
+pub struct MockClock {
+    now: Cell<Timestamp>,
+}
+
+impl MockClock {
+    pub fn new(start: Timestamp) -> Self {
+        Self {
+            now: Cell::new(start),
+        }
+    }
+
+    /// Move the clock forward by `secs` and return the new time.
+    pub fn advance(&self, secs: u64) -> Timestamp {
+        let next = self.now.get().saturating_add(secs);
+        self.now.set(next);
+        next
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.set(now);
+    }
+}
+
+/// A fixed instant: callers that already hold a timestamp pass `&now`.
+impl Clock for Timestamp {
+    fn now(&self) -> Timestamp {
+        *self
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        self.now.get()
+    }
+}
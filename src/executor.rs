@@ -1,30 +1,79 @@
+use std::collections::HashMap;
+
 use primitive_types::U256;
 
+use crate::clock::Clock;
 use crate::math;
-use crate::oracle::Oracle;
+use crate::math::rounding::{Rounding, rounding_leakage};
+use crate::oracle::{self, Oracle};
 use crate::risk;
 use crate::risk::{
     RiskCfg, liquidation,
     liquidation::{LiquidationFeeCfg, LiquidationPreview},
 };
 use crate::services::borrowing::apply_borrowing_fees_to_pool;
-use crate::services::price_impact::ImpactRebalanceConfig;
-use crate::services::pricing::ExecutionPriceParams;
-use crate::services::step_costs::{apply_step_costs_to_position, compute_step_costs};
+use crate::services::price_impact;
+use crate::services::pricing::{ExecutionPriceParams, PriceSelection};
+use crate::services::settlement::{PositionSettlement, SettlementParams, settle_market_all};
+use crate::services::step_costs::{
+    StepCostParams, StepCosts, apply_step_costs_to_position, compute_step_costs,
+};
 use crate::services::*;
 use crate::state::{
-    Claimables, MarketState, PoolBalances, Position, PositionKey, PositionStore, State,
+    Claimables, MarketState, MarketSummary, PoolBalances, Position, PositionKey, PositionStore,
+    State,
 };
 use crate::types::{
     AssetId, ExecutionType, OraclePrices, Order, OrderId, OrderType, Side, SignedU256, Timestamp,
-    TokenAmount, Usd, AccountId, MarketId,
+    TokenAmount, Usd, AccountId, MarketId, WithdrawalId,
 };
 
+/// When market-level funding/borrowing indices are advanced relative to a trade.
+///
+/// Funding is driven by the long/short OI split, so the choice decides which OI
+/// prices the interval since the last update:
+///  - `SettleThenTrade` (default): indices are advanced with the pre-trade OI,
+///    i.e. the interval is charged to the OI that actually existed during it;
+///  - `TradeThenSettle`: the trade is applied first and indices are advanced with
+///    the post-trade OI, so a trade changes the rate paid for time already
+///    elapsed (for itself and for every other position in the market). The
+///    traded position is only charged for that interval on the size it had
+///    before the trade (nothing for a new position), and a failed trade leaves
+///    the indices where they were.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SettlementOrder {
+    #[default]
+    SettleThenTrade,
+    TradeThenSettle,
+}
+
+/// Protocol-wide trading status (emergency kill switch).
+///
+/// Checked before any per-market status, so it takes precedence: a global
+/// `Halted` stops increases on an active market, while `Active` never re-opens
+/// a market that is paused on its own. Decreases and liquidations are never
+/// blocked, so users can always exit.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum GlobalStatus {
+    #[default]
+    Active,
+    /// Increases are rejected at execution everywhere; they can still be queued.
+    ReduceOnly,
+    /// Increases are rejected at submission and execution everywhere.
+    Halted,
+}
+
 #[derive(Clone)]
 pub struct Executor<S: ServicesBundle, O: Oracle> {
     pub state: State,
     pub services: S,
     pub oracle: O,
+    /// Protocol-level risk constraints used by order execution.
+    pub risk: RiskCfg,
+    /// Whether indices are advanced before or after the trade is applied.
+    pub settlement_order: SettlementOrder,
+    /// Protocol-wide kill switch, applied before per-market pauses.
+    pub global_status: GlobalStatus,
 }
 
 impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
@@ -33,58 +82,73 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             state,
             services,
             oracle,
+            risk: RiskCfg::default(),
+            settlement_order: SettlementOrder::default(),
+            global_status: GlobalStatus::default(),
         }
     }
-    fn validate_order_on_submit(order: &Order) -> Result<(), String> {
-        use ExecutionType as Ex;
-        if order.valid_until <= order.valid_from {
-            return Err("invalid_order_time_window".into());
-        }
-        if order.execution_type == Ex::Market && order.trigger_price.is_some() {
-            return Err("market_order_must_not_have_trigger_price".into());
-        }
-        if matches!(
-            order.execution_type,
-            Ex::Limit | Ex::StopLoss | Ex::TakeProfit
-        ) && order.trigger_price.is_none()
-        {
-            return Err("trigger_price_required".into());
+    /// Validate and store `order`. `now` is the trusted submission time (never
+    /// the user-supplied `order.created_at`); it drives expired-order pruning.
+    ///
+    /// The order's `execution_fee_tokens` are taken up front and held on the
+    /// stored order until it is executed (paid to the keeper), cancelled or
+    /// pruned (refunded to the owner):
+    ///  - increases pay it out of `collateral_delta_tokens`;
+    ///  - decreases / liquidations pay it out of the position's collateral.
+    pub fn submit_order(&mut self, clock: &dyn Clock, mut order: Order) -> Result<OrderId, String> {
+        let now = clock.now();
+        risk::validation::validate_order_shape(&order)?;
+        if self.global_status == GlobalStatus::Halted && order.order_type == OrderType::Increase {
+            return Err("protocol_halted".into());
         }
-
-        if order.order_type == OrderType::Increase
-            && matches!(order.execution_type, Ex::StopLoss | Ex::TakeProfit)
-        {
-            return Err("stop_loss_take_profit_only_for_decrease".into());
-        }
-
-        if order.order_type == OrderType::Liquidation {
-            if order.execution_type != Ex::Market {
-                return Err("liquidation_must_be_market".into());
-            }
-            if order.trigger_price.is_some() {
-                return Err("liquidation_must_not_have_trigger_price".into());
-            }
+        if self.state.orders.is_full() {
+            self.prune_expired_orders(clock);
         }
-
-        match order.order_type {
-            OrderType::Increase => {
-                if order.target_leverage_x == 0 {
-                    return Err("target_leverage_must_be_positive".into());
+        self.state.orders.check_create(now, &order)?;
+
+        let fee = order.execution_fee_tokens;
+        if !fee.is_zero() {
+            match order.order_type {
+                OrderType::Increase => {
+                    order.collateral_delta_tokens = order
+                        .collateral_delta_tokens
+                        .checked_sub(fee)
+                        .ok_or("insufficient_collateral_for_execution_fee")?;
                 }
-            }
-            OrderType::Decrease => {
-                if order.size_delta_usd.is_zero() {
-                    return Err("size_delta_usd_must_be_positive_for_decrease".into());
+                OrderType::Decrease | OrderType::Liquidation => {
+                    let key = PositionKey {
+                        account: order.account,
+                        market_id: order.market_id,
+                        collateral_token: order.collateral_token,
+                        side: order.side,
+                    };
+                    let pos = self
+                        .state
+                        .positions
+                        .get_mut(&key)
+                        .ok_or("position_not_found")?;
+                    pos.collateral_amount = pos
+                        .collateral_amount
+                        .checked_sub(fee)
+                        .ok_or("insufficient_collateral_for_execution_fee")?;
                 }
             }
-            OrderType::Liquidation => {}
         }
-        Ok(())
+        self.state.orders.try_create(now, order)
     }
 
-    pub fn submit_order(&mut self, order: Order) -> Result<OrderId, String> {
-        Self::validate_order_on_submit(&order)?;
-        Ok(self.state.orders.create(order))
+    /// Drop orders expired as of `clock.now()`, refunding their held execution
+    /// fees to the owners' claimables. Returns how many were removed.
+    pub fn prune_expired_orders(&mut self, clock: &dyn Clock) -> usize {
+        let removed = self.state.orders.prune_expired(clock.now());
+        for order in removed.iter() {
+            self.state.claimables.add_fee(
+                order.account,
+                order.collateral_token,
+                order.execution_fee_tokens,
+            );
+        }
+        removed.len()
     }
 
      pub fn cancel_order(&mut self, caller: AccountId, order_id: OrderId) -> Result<(), String> {
@@ -92,67 +156,81 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         if order.account != caller {
             return Err("not_order_owner".into());
         }
-        self.state.orders.remove(order_id);
+        let order = self
+            .state
+            .orders
+            .remove(order_id)
+            .ok_or("order_not_found")?;
+        // Refund the held execution fee.
+        self.state.claimables.add_fee(
+            order.account,
+            order.collateral_token,
+            order.execution_fee_tokens,
+        );
         Ok(())
     }
 
-    fn check_order_trigger(order: &Order, prices: &OraclePrices) -> Result<(), String> {
-        use ExecutionType as Ex;
-
-        if order.execution_type == Ex::Market {
-            return Ok(());
-        }
-
-        let trigger = order
-            .trigger_price
-            .ok_or_else(|| "trigger_price_required".to_string())?;
-
-        // Liquidation is always executed by liquidation flow (no triggers here)
-        if order.order_type == OrderType::Liquidation {
-            return Ok(());
-        }
-
-        let satisfied = match (order.execution_type, order.order_type, order.side) {
-            // -------------------- LIMIT --------------------
-            (Ex::Limit, OrderType::Increase, Side::Long) => prices.index_price_max <= trigger,
-            (Ex::Limit, OrderType::Increase, Side::Short) => prices.index_price_min >= trigger,
-
-            (Ex::Limit, OrderType::Decrease, Side::Long) => prices.index_price_min >= trigger,
-            (Ex::Limit, OrderType::Decrease, Side::Short) => prices.index_price_max <= trigger,
-
-            // -------------------- STOP LOSS (Decrease only) --------------------
-            (Ex::StopLoss, OrderType::Decrease, Side::Long) => prices.index_price_min <= trigger,
-            (Ex::StopLoss, OrderType::Decrease, Side::Short) => prices.index_price_max >= trigger,
-
-            // -------------------- TAKE PROFIT (Decrease only) --------------------
-            (Ex::TakeProfit, OrderType::Decrease, Side::Long) => prices.index_price_min >= trigger,
-            (Ex::TakeProfit, OrderType::Decrease, Side::Short) => prices.index_price_max <= trigger,
-
-            // Others
-            _ => return Err("unsupported_order_execution_type".into()),
-        };
-
-        if satisfied {
-            Ok(())
-        } else {
-            Err("order_not_triggered".into())
-        }
+    /// Execute a queued order on behalf of `keeper`.
+    ///
+    /// The order's held execution fee is credited to the keeper's claimables
+    /// when the order executes or is removed as expired; a failed execution
+    /// leaves the order (and its fee) queued.
+    pub fn execute_order(
+        &mut self,
+        keeper: AccountId,
+        clock: &dyn Clock,
+        order_id: OrderId,
+    ) -> Result<(), String> {
+        self.execute_order_inner(keeper, clock.now(), order_id, true)
     }
 
-    pub fn execute_order(&mut self, now: Timestamp, order_id: OrderId) -> Result<(), String> {
+    /// `execute_order` body. `require_execution_fee` is false only for orders
+    /// the protocol builds and executes itself in the same call (no keeper to
+    /// pay, see `reduce_to_target_leverage`).
+    fn execute_order_inner(
+        &mut self,
+        keeper: AccountId,
+        now: Timestamp,
+        order_id: OrderId,
+        require_execution_fee: bool,
+    ) -> Result<(), String> {
         let mut order = match self.state.orders.get(order_id) {
             Some(o) => o.clone(),
             None => return Err("order_not_found".into()),
         };
+        let risk = self.risk.with_collateral_haircut_bps(
+            self.state.tokens.collateral_haircut_bps(order.collateral_token),
+        );
+
+        // Reduce-only orders must never grow a position.
+        if order.reduce_only && order.order_type == OrderType::Increase {
+            return Err("reduce_only_order_would_increase_position".into());
+        }
+
+        if order.order_type == OrderType::Increase {
+            match self.global_status {
+                GlobalStatus::Active => {}
+                GlobalStatus::ReduceOnly => return Err("protocol_reduce_only".into()),
+                GlobalStatus::Halted => return Err("protocol_halted".into()),
+            }
+        }
 
         let prices = self.oracle.validate_and_get_prices(order.market_id)?;
-        Self::check_order_trigger(&order, &prices)?;
+        risk::validation::check_order_trigger(&order, &prices)?;
+        if require_execution_fee {
+            risk::validation::check_execution_fee(&order, &prices, risk)?;
+        }
 
         if now < order.valid_from {
             return Err("order_not_active_yet".into());
         }
         if now > order.valid_until {
             self.state.orders.remove(order_id);
+            self.state.claimables.add_fee(
+                keeper,
+                order.collateral_token,
+                order.execution_fee_tokens,
+            );
             return Err("order_expired".into());
         }
 
@@ -162,6 +240,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             pool_balances,
             claimables,
             orders,
+            ..
         } = &mut self.state;
 
         let market: &mut MarketState = markets.entry(order.market_id).or_insert_with(|| {
@@ -170,9 +249,38 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             m
         });
 
+        if market.paused && order.order_type == OrderType::Increase {
+            return Err("market_paused".into());
+        }
+
+        // Circuit breaker against fat-finger / manipulated index updates.
+        // Liquidations and reduce-only orders are exempt so positions can
+        // always be closed out.
+        let band_checked = order.order_type != OrderType::Liquidation && !order.reduce_only;
+        if band_checked {
+            oracle::check_price_deviation(
+                market.last_index_price,
+                &prices,
+                market.max_price_deviation_bps,
+            )?;
+        }
+        // Wide spreads only price new exposure unfavorably; exits stay open.
+        if order.order_type == OrderType::Increase {
+            oracle::check_price_spread(&prices, market.max_price_spread_bps)?;
+        }
+
         // Sync market-level time-based indices
-        self.services.funding().update_indices(market, now);
-        self.services.borrowing().update_index(market, now);
+        if self.settlement_order == SettlementOrder::SettleThenTrade {
+            self.services.funding().update_indices(market, now);
+            self.services.borrowing().update_index(market, now);
+        }
+        let key = PositionKey {
+            account: order.account,
+            market_id: order.market_id,
+            collateral_token: order.collateral_token,
+            side: order.side,
+        };
+        let pre_trade_size = positions.get(&key).map(|p| (p.size_usd, p.size_tokens));
 
         let result = match order.order_type {
             OrderType::Increase => Self::increase_position_core(
@@ -181,6 +289,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 claimables,
                 market,
                 &self.services,
+                risk,
                 now,
                 &order,
                 &prices,
@@ -191,19 +300,87 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 claimables,
                 market,
                 &self.services,
+                risk,
                 now,
                 &mut order,
                 &prices,
             ),
         };
 
+        let result = if self.settlement_order == SettlementOrder::TradeThenSettle {
+            result.and_then(|()| {
+                let (funding, borrowing) = (market.funding.clone(), market.borrowing.clone());
+                self.services.funding().update_indices(market, now);
+                self.services.borrowing().update_index(market, now);
+                let Some(pos) = positions.get_mut(&key) else {
+                    return Ok(());
+                };
+                let res = settle_elapsed_on_pre_trade_size(
+                    &self.services,
+                    market,
+                    pos,
+                    pre_trade_size,
+                    now,
+                );
+                if res.is_err() {
+                    market.funding = funding;
+                    market.borrowing = borrowing;
+                }
+                res
+            })
+        } else {
+            result
+        };
+
         if result.is_ok() {
+            // Exempt orders must not move the band reference to an unchecked price.
+            if band_checked {
+                market.last_index_price = oracle::mid_index_price(&prices);
+            }
             orders.remove(order_id);
+            claimables.add_fee(keeper, order.collateral_token, order.execution_fee_tokens);
         }
 
         result
     }
 
+    /// Sync the market's funding / borrowing indices to `clock.now()` and settle
+    /// every position of the market (see `settle_market_all`, all-or-nothing).
+    pub fn settle_market(
+        &mut self,
+        clock: &dyn Clock,
+        market_id: MarketId,
+    ) -> Result<Vec<PositionSettlement>, String> {
+        let now = clock.now();
+        let prices = self.oracle.validate_and_get_prices(market_id)?;
+        let State {
+            positions,
+            markets,
+            pool_balances,
+            claimables,
+            ..
+        } = &mut self.state;
+        let market = markets.get_mut(&market_id).ok_or("market_not_found")?;
+
+        self.services.funding().update_indices(market, now);
+        self.services.borrowing().update_index(market, now);
+
+        settle_market_all(
+            self.services.funding(),
+            self.services.borrowing(),
+            self.services.telemetry(),
+            SettlementParams {
+                market,
+                prices: &prices,
+                risk: &self.risk,
+                now,
+            },
+            positions,
+            pool_balances,
+            claimables,
+        )
+    }
+
     pub fn is_liquidatable_by_margin(
         &self,
         now: Timestamp,
@@ -220,7 +397,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         let price_impact_usd_on_close =
             self.preview_close_price_impact_usd(market, pos, &prices)?;
 
-        let risk = RiskCfg::default();
+        let risk = self.risk;
 
         // mvp
         let fee_cfg = LiquidationFeeCfg {
@@ -228,11 +405,16 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             liquidation_fee_bps: 0,
         };
 
-        liquidation::is_liquidatable_by_margin(
+        let accrued = liquidation::AccruedCosts::preview(
+            self.services.funding().rate_model(),
             market,
             pos,
-            &prices,
             now,
+        )?;
+        liquidation::is_liquidatable_by_margin(
+            pos,
+            &prices,
+            accrued,
             risk,
             fee_cfg,
             price_impact_usd_on_close,
@@ -255,7 +437,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         let price_impact_usd_on_close =
             self.preview_close_price_impact_usd(market, pos, &prices)?;
 
-        let risk = RiskCfg::default();
+        let risk = self.risk;
 
         // zero liquidation fee for mvp
         let fee_cfg = LiquidationFeeCfg {
@@ -263,11 +445,16 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             liquidation_fee_bps: 0,
         };
 
-        liquidation::calculate_liquidation_price(
+        let accrued = liquidation::AccruedCosts::preview(
+            self.services.funding().rate_model(),
             market,
             pos,
-            &prices,
             now,
+        )?;
+        liquidation::calculate_liquidation_price(
+            pos,
+            &prices,
+            accrued,
             risk,
             fee_cfg,
             price_impact_usd_on_close,
@@ -280,32 +467,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         pos: &Position,
         prices: &OraclePrices,
     ) -> Result<SignedU256, String> {
-        let oi_params = self.services.open_interest().for_decrease(
-            market.oi_long_usd,
-            market.oi_short_usd,
-            pos.size_usd,
-            pos.key.side,
-        );
-
-        let impact_cfg = ImpactRebalanceConfig::default_quadratic();
-
-        let exec = self
-            .services
-            .pricing()
-            .get_execution_price(
-                self.services.price_impact(),
-                crate::services::pricing::ExecutionPriceParams {
-                    oi: &oi_params,
-                    impact_cfg: &impact_cfg,
-                    side: pos.key.side,
-                    direction: crate::services::pricing::TradeDirection::Decrease,
-                    size_delta_usd: pos.size_usd,
-                    prices: *prices,
-                },
-            )
-            .map_err(|e| format!("pricing_error:{:?}", e))?;
-
-        Ok(exec.price_impact_usd)
+        close_price_impact_usd(&self.services, market, pos, prices)
     }
 
     fn increase_position_core(
@@ -314,15 +476,23 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         claimables: &mut Claimables,
         market: &mut MarketState,
         services: &S,
+        risk: RiskCfg,
         now: Timestamp,
         order: &Order,
         prices: &OraclePrices,
     ) -> Result<(), String> {
+        if !market.accepts_collateral(order.collateral_token) {
+            return Err("collateral_not_supported".into());
+        }
+
         // Derive notional in USD from collateral and leverage (oracle-based).
         let size_delta_usd: Usd = derive_size_delta_usd(order, prices)?;
         if size_delta_usd.is_zero() {
             return Err("size_delta_usd_must_be_positive".into());
         }
+        market.check_status_allows_increase(order.side, size_delta_usd)?;
+        market.check_oi_rate_limit(size_delta_usd, now)?;
+        market.check_oi_skew(order.side, size_delta_usd)?;
 
         let key = PositionKey {
             account: order.account,
@@ -331,25 +501,10 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             side: order.side,
         };
 
-        let pos: &mut Position = positions.get_or_insert_with(key, |k| {
-            // Initial funding index depends on side (long/short).
-            let initial_funding_index = match k.side {
-                Side::Long => market.funding.cumulative_index_long,
-                Side::Short => market.funding.cumulative_index_short,
-            };
-
-            Position {
-                key: k,
-                size_usd: U256::zero(),
-                size_tokens: U256::zero(),
-                collateral_amount: U256::zero(),
-                pending_impact_tokens: SignedU256::zero(),
-                funding_index: initial_funding_index,
-                borrowing_index: market.borrowing.cumulative_factor,
-                opened_at: now,
-                last_updated_at: now,
-            }
-        });
+        risk::validation::precheck_increase_position_count(positions, &key, risk)?;
+
+        let pos: &mut Position =
+            positions.get_or_insert_with(key, |k| new_position(k, market, now));
 
         if order.collateral_delta_tokens > U256::zero() {
             pos.collateral_amount += order.collateral_delta_tokens;
@@ -363,7 +518,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             order.side,
         );
 
-        let impact_cfg = ImpactRebalanceConfig::default_quadratic();
+        let impact_cfg = market.effective_impact_config()?;
 
         let pricing = services.pricing();
         let price_impact_svc = services.price_impact();
@@ -378,9 +533,14 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                     size_delta_usd,
                     direction: pricing::TradeDirection::Increase,
                     prices: *prices,
+                    price_selection: PriceSelection::Conservative,
                 },
             )
             .map_err(|e| format!("pricing_error: {:?}", e))?;
+        // Base and impact tokens are priced in the index token.
+        services
+            .telemetry()
+            .on_rounding(market.index_token, exec.rounding_leakage_usd);
 
         // 6) Step costs: funding + borrowing + (position + liquidation) fees.
         //
@@ -390,13 +550,18 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             services.funding(),
             services.borrowing(),
             services.fees(),
-            market,
+            services.telemetry(),
+            StepCostParams {
+                market,
+                prices,
+                order,
+                balance_was_improved: exec.balance_was_improved,
+                price_impact_usd: exec.price_impact_usd,
+                size_delta_usd,
+                now,
+            },
             pos,
             claimables,
-            prices,
-            order,
-            exec.balance_was_improved,
-            size_delta_usd,
         )?;
 
         // 7) Apply total step costs to position collateral.
@@ -404,7 +569,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         // This converts total_usd to collateral tokens via collateral_price_min
         // and subtracts from pos.collateral_amount, reverting on insufficient
         // collateral.
-        apply_step_costs_to_position(pos, prices, &step_costs)?;
+        apply_step_costs_to_position(pos, prices, &step_costs, services.telemetry())?;
 
         // 8) Route trading fees (position + liquidation) into the pool.
         //
@@ -427,8 +592,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         //
         // exec.base_size_delta_tokens  - tokens from pure sizeDeltaUsd / price
         // exec.price_impact_amount_tokens - bonus/penalty tokens due to price impact
-        pos.size_usd += size_delta_usd;
-        pos.size_tokens += exec.base_size_delta_tokens;
+        pos.apply_increase(size_delta_usd, exec.base_size_delta_tokens)?;
         pos.pending_impact_tokens =
             math::signed_add(pos.pending_impact_tokens, exec.price_impact_amount_tokens);
         pos.last_updated_at = now;
@@ -441,6 +605,8 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 market.oi_short_usd += size_delta_usd;
             }
         }
+        market.record_oi_increase(size_delta_usd, now);
+        market.sync_reserved_usd()?;
         // TODO (future work):
         //  - update market-level "total_pending_impact_tokens" if you keep it;
         //  - run min-collateral / max-leverage checks similar to GMX
@@ -455,6 +621,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         claimables: &mut Claimables,
         market: &mut MarketState,
         services: &S,
+        risk: RiskCfg,
         now: Timestamp,
         order: &mut Order,
         prices: &OraclePrices,
@@ -494,7 +661,6 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
 
             // Risk precheck (may clamp withdraw or force full close).
             // Note: this is a conservative check (no PnL / no fees included).
-            let risk = risk::RiskCfg::default();
             let (mut size_delta_usd, mut withdraw_tokens, mut is_full_close) =
                 risk::validation::precheck_decrease_and_withdraw(&pos, &order, prices, risk)?;
 
@@ -515,8 +681,13 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
 
             // Realize a proportional part of pending impact.
             // pending_impact_realized_tokens = pos.pending_impact_tokens * size_delta_usd / pos.size_usd
+            // (rounded per the market's `pending_impact_rounding`; exact on full close).
             let pending_impact_realized_tokens =
-                math::position::proportional_pending_impact_tokens(&pos, size_delta_usd)?;
+                math::position::proportional_pending_impact_tokens(
+                    &pos,
+                    size_delta_usd,
+                    market.pending_impact_rounding,
+                )?;
 
             //  Pricing call (mainly to obtain balance_was_improved + impact)
             //    OI params for decrease: current -> next (subtract size_delta_usd)
@@ -526,7 +697,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 size_delta_usd,
                 order.side,
             );
-            let impact_cfg = ImpactRebalanceConfig::default_quadratic();
+            let impact_cfg = market.effective_impact_config()?;
             let exec = services
                 .pricing()
                 .get_execution_price(
@@ -538,33 +709,48 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                         direction: crate::services::pricing::TradeDirection::Decrease,
                         size_delta_usd,
                         prices: *prices,
+                        price_selection: PriceSelection::Conservative,
                     },
                 )
                 .map_err(|e| format!("pricing_error:{:?}", e))?;
 
-            // Funding + borrowing + trading fees: compute and apply to position collateral.
+            // Realize funding and borrowing accrued since the position's last update
+            // (plus this step's trading fees) before any PnL. The cost is taken from
+            // collateral, so it is netted into the payout below; funding received by
+            // the position is credited to claimables instead.
             let step_costs = compute_step_costs(
                 services.funding(),
                 services.borrowing(),
                 services.fees(),
-                market,
+                services.telemetry(),
+                StepCostParams {
+                    market,
+                    prices,
+                    order: &order,
+                    balance_was_improved: exec.balance_was_improved,
+                    price_impact_usd: exec.price_impact_usd,
+                    size_delta_usd,
+                    now,
+                },
                 pos,
                 claimables,
-                prices,
-                &order,
-                exec.balance_was_improved,
-                size_delta_usd,
             )?;
 
-            if let Err(e) = apply_step_costs_to_position(pos, prices, &step_costs) {
+            if let Err(e) =
+                apply_step_costs_to_position(pos, prices, &step_costs, services.telemetry())
+            {
                 // Insolvent liquidation path: allow full close, seize remaining collateral.
                 if is_liq && is_full_close {
                     let seized = pos.collateral_amount;
                     pos.collateral_amount = U256::zero();
 
-                    // credit collateral to the pool as fees.
+                    // credit collateral to the pool fee bucket (not fee revenue).
                     if seized > U256::zero() {
-                        pool_balances.add_fee_to_pool(market.id, pos.key.collateral_token, seized);
+                        pool_balances.add_seized_collateral_to_pool(
+                            market.id,
+                            pos.key.collateral_token,
+                            seized,
+                        );
                     }
 
                     // Update OI (full close).
@@ -582,11 +768,13 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                                 .ok_or("oi_short_underflow")?;
                         }
                     }
+                    market.sync_reserved_usd()?;
 
                     // Close fields (we will remove from store after scope ends).
                     pos.size_usd = U256::zero();
                     pos.size_tokens = U256::zero();
-                    pos.pending_impact_tokens = SignedU256::zero();
+                    let rest_impact = pos.pending_impact_tokens;
+                    pos.realize_impact(rest_impact);
                     pos.last_updated_at = now;
 
                     return Ok(DecreaseResult {
@@ -622,14 +810,21 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             //   if impactTokens < 0 => use index_price_max
             // Realize pending impact to signed USD (conservative)
             let realized_pending_impact_usd: SignedU256 =
-                impact_tokens_to_usd_conservative(pending_impact_realized_tokens, prices)?;
+                math::pnl::impact_tokens_to_usd_conservative(
+                    pending_impact_realized_tokens,
+                    prices,
+                )?;
 
             println!("REALISED BASE PNL {:?}", realized_base_pnl_usd);
             println!("REALISED BASE PNL {:?}", realized_pending_impact_usd);
-            // Include close price impact
+            // Include close price impact (negative part scaled by the market's close-impact setting).
+            let close_impact_usd = price_impact::scale_close_impact(
+                exec.price_impact_usd,
+                market.impact_on_close_bps_scale,
+            );
             let realized_total_usd: SignedU256 = math::signed_add(
                 math::signed_add(realized_base_pnl_usd, realized_pending_impact_usd),
-                exec.price_impact_usd,
+                close_impact_usd,
             );
 
             // Convert realized_total_usd into collateral token delta (signed):
@@ -637,6 +832,24 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
             //   -Usd => -ceil(abs / collateral_price_min)
             let pnl_tokens_signed: SignedU256 =
                 math::pnl::pnl_usd_to_collateral_tokens(realized_total_usd, prices)?;
+            services.telemetry().on_rounding(
+                pos.key.collateral_token,
+                if realized_total_usd.is_negative {
+                    rounding_leakage(
+                        realized_total_usd.mag,
+                        prices.collateral_price_min,
+                        Rounding::Up,
+                        true,
+                    )
+                } else {
+                    rounding_leakage(
+                        realized_total_usd.mag,
+                        prices.collateral_price_max,
+                        Rounding::Down,
+                        false,
+                    )
+                },
+            );
 
             println!("PNL {:?}", pnl_tokens_signed);
             let collateral_asset = pos.key.collateral_token;
@@ -698,6 +911,7 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                         .ok_or("oi_short_underflow")?;
                 }
             }
+            market.sync_reserved_usd()?;
 
             //  Close or update position state.
             if is_full_close || size_delta_usd == pos.size_usd {
@@ -712,7 +926,8 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 // Zero out fields and mark as closed.
                 pos.size_usd = U256::zero();
                 pos.size_tokens = U256::zero();
-                pos.pending_impact_tokens = SignedU256::zero();
+                let rest_impact = pos.pending_impact_tokens;
+                pos.realize_impact(rest_impact);
                 pos.last_updated_at = now;
 
                 // Credit output into claimables (withdrawable balance).
@@ -738,10 +953,8 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
                 .checked_sub(size_delta_tokens)
                 .ok_or("pos_size_tokens_underflow")?;
 
-            // Remove proportional pending impact (already realized above).
-            // pending_impact_tokens -= realized_pending
-            pos.pending_impact_tokens =
-                math::signed_sub(pos.pending_impact_tokens, pending_impact_realized_tokens);
+            // Move the proportional pending impact (realized above) into realized_impact_tokens.
+            pos.realize_impact(pending_impact_realized_tokens);
 
             pos.last_updated_at = now;
 
@@ -761,12 +974,132 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
         })()?;
 
         if res.should_remove {
-            positions.remove(&key);
+            positions.remove_if_closed(&key)?;
         }
 
         Ok(())
     }
 
+    /// Deleverage `key` to `target_leverage_x` with a market decrease at current prices
+    /// (size from `math::position::reduce_to_target_leverage`).
+    ///
+    /// Fees and PnL of the decrease are paid from collateral, so the resulting
+    /// leverage is approximately (not exactly) the target. The order is executed
+    /// immediately rather than by a keeper, so it carries no execution fee.
+    pub fn reduce_to_target_leverage(
+        &mut self,
+        clock: &dyn Clock,
+        key: PositionKey,
+        target_leverage_x: u32,
+    ) -> Result<(), String> {
+        let now = clock.now();
+        let pos = self.state.positions.get(&key).ok_or("position_not_found")?;
+        let prices = self.oracle.validate_and_get_prices(key.market_id)?;
+        let (size_delta_usd, withdraw_tokens) =
+            math::position::reduce_to_target_leverage(pos, target_leverage_x, &prices)?;
+        if size_delta_usd.is_zero() {
+            return Ok(());
+        }
+
+        let order_id = self.submit_order(
+            clock,
+            Order {
+                account: key.account,
+                market_id: key.market_id,
+                collateral_token: key.collateral_token,
+                side: key.side,
+                order_type: OrderType::Decrease,
+                execution_type: ExecutionType::Market,
+                collateral_delta_tokens: U256::zero(),
+                size_delta_usd,
+                trigger_price: None,
+                acceptable_price: None,
+                withdraw_collateral_amount: withdraw_tokens,
+                execution_fee_tokens: U256::zero(),
+                reduce_only: true,
+                target_leverage_x,
+                created_at: now,
+                valid_from: now,
+                valid_until: now + 1,
+            },
+        )?;
+        // Executed in place by the owner: no keeper fee is required.
+        let res = self.execute_order_inner(key.account, now, order_id, false);
+        if res.is_err() {
+            self.state.orders.remove(order_id);
+        }
+        res
+    }
+
+    /// Deposit LP liquidity into a market pool. The market's `liquidity_usd`
+    /// (pool depth used for utilization and impact scaling) grows by the
+    /// deposit's value at the oracle's min price (see `pool_asset_value_usd`).
+    pub fn add_liquidity(
+        &mut self,
+        market_id: MarketId,
+        asset: AssetId,
+        amount: TokenAmount,
+    ) -> Result<Usd, String> {
+        let prices = self.oracle.validate_and_get_prices(market_id)?;
+        let market = self
+            .state
+            .markets
+            .get_mut(&market_id)
+            .ok_or("market_not_found")?;
+        let value_usd = pool_asset_value_usd(market, asset, amount, &prices)?;
+        market.liquidity_usd = market
+            .liquidity_usd
+            .checked_add(value_usd)
+            .ok_or("liquidity_usd_overflow")?;
+        self.state
+            .pool_balances
+            .add_liquidity(market_id, asset, amount);
+        Ok(value_usd)
+    }
+
+    /// Queue a delayed LP withdrawal (see `PoolBalances::request_withdrawal`).
+    pub fn request_withdrawal(
+        &mut self,
+        clock: &dyn Clock,
+        market_id: MarketId,
+        asset: AssetId,
+        amount: TokenAmount,
+    ) -> Result<WithdrawalId, String> {
+        if !self.state.markets.contains_key(&market_id) {
+            return Err("market_not_found".into());
+        }
+        self.state
+            .pool_balances
+            .request_withdrawal(market_id, asset, amount, clock.now())
+    }
+
+    /// Execute a queued LP withdrawal once its delay has elapsed, shrinking
+    /// the market's `liquidity_usd` by the withdrawn value (floored at zero).
+    pub fn execute_withdrawal(
+        &mut self,
+        clock: &dyn Clock,
+        id: WithdrawalId,
+    ) -> Result<TokenAmount, String> {
+        let w = *self
+            .state
+            .pool_balances
+            .get_withdrawal(id)
+            .ok_or("withdrawal_not_found")?;
+        let prices = self.oracle.validate_and_get_prices(w.market_id)?;
+        let market = self
+            .state
+            .markets
+            .get_mut(&w.market_id)
+            .ok_or("market_not_found")?;
+        let value_usd = pool_asset_value_usd(market, w.asset, w.amount, &prices)?;
+        let taken = self
+            .state
+            .pool_balances
+            .execute_withdrawal(id, clock.now())?;
+        market.liquidity_usd = market.liquidity_usd.saturating_sub(value_usd);
+        Ok(taken)
+    }
+
     pub fn claim_all(
         &mut self,
         caller: AccountId,
@@ -803,15 +1136,20 @@ impl<S: ServicesBundle, O: Oracle> Executor<S, O> {
 
     /// List all positions for account.
     pub fn get_positions_by_account(&self, account: AccountId) -> Vec<Position> {
-        self.state.positions
+        self.state
+            .positions
+            .positions_for_account(account)
+            .cloned()
+            .collect()
+    }
+
+    /// Positions older than `risk.max_position_age_secs`, to be force-closed by keepers.
+    pub fn expired_positions(&self, now: Timestamp) -> Vec<PositionKey> {
+        self.state
+            .positions
             .iter()
-            .filter_map(|(_k, p)| {
-                if p.key.account == account {
-                    Some(p.clone())
-                } else {
-                    None
-                }
-            })
+            .filter(|(_, p)| risk::validation::is_position_expired(p, now, self.risk))
+            .map(|(k, _)| *k)
             .collect()
     }
 
@@ -844,6 +1182,347 @@ struct DecreaseResult {
     output_tokens: TokenAmount,
 }
 
+/// Read-only preview of an increase order.
+///
+/// Produced by `quote_increase`, which runs the same pricing and step-cost
+/// pipeline as `increase_position_core` on copies of the inputs.
+#[derive(Debug, Clone)]
+pub struct IncreaseQuote {
+    /// Notional added by the order (collateral * leverage).
+    pub size_delta_usd: Usd,
+    /// Execution price including price impact.
+    pub execution_price: Usd,
+    /// Base size delta in index tokens (size_delta_usd / index price).
+    pub size_delta_tokens: TokenAmount,
+    /// Signed price impact in USD (positive = bonus, negative = penalty).
+    pub price_impact_usd: SignedU256,
+    /// Whether the trade reduces the long/short imbalance.
+    pub balance_was_improved: bool,
+    /// Funding + borrowing + trading costs charged on this step.
+    pub costs: StepCosts,
+    /// Position size after the increase.
+    pub next_size_usd: Usd,
+    /// Collateral after the deposit and step costs, in tokens.
+    pub next_collateral_amount: TokenAmount,
+    /// size / collateral value after the increase, fp(1e18).
+    pub leverage_fp: U256,
+    /// Liquidation price of the resulting position.
+    pub liquidation_price: Usd,
+}
+
+/// Simulate an increase without touching state.
+///
+/// `pos` is the existing position (if any). Funding is settled against a
+/// scratch `Claimables` and telemetry is not notified, so the quote has no
+/// observable side effects. Market indices are used as given, so callers
+/// quoting at a later `now` should advance them on a copy first.
+pub fn quote_increase<S: ServicesBundle>(
+    order: &Order,
+    pos: Option<&Position>,
+    market: &MarketState,
+    prices: &OraclePrices,
+    services: &S,
+    risk: RiskCfg,
+    now: Timestamp,
+) -> Result<IncreaseQuote, String> {
+    if !market.accepts_collateral(order.collateral_token) {
+        return Err("collateral_not_supported".into());
+    }
+
+    let size_delta_usd = derive_size_delta_usd(order, prices)?;
+    if size_delta_usd.is_zero() {
+        return Err("size_delta_usd_must_be_positive".into());
+    }
+    market.check_status_allows_increase(order.side, size_delta_usd)?;
+    market.check_oi_rate_limit(size_delta_usd, now)?;
+    market.check_oi_skew(order.side, size_delta_usd)?;
+
+    let key = PositionKey {
+        account: order.account,
+        market_id: order.market_id,
+        collateral_token: order.collateral_token,
+        side: order.side,
+    };
+    let mut next = match pos {
+        Some(p) => p.clone(),
+        None => new_position(key, market, now),
+    };
+    next.collateral_amount = next
+        .collateral_amount
+        .checked_add(order.collateral_delta_tokens)
+        .ok_or("collateral_overflow")?;
+
+    let oi_params = services.open_interest().for_increase(
+        market.oi_long_usd,
+        market.oi_short_usd,
+        size_delta_usd,
+        order.side,
+    );
+    let impact_cfg = market.effective_impact_config()?;
+    let exec = services
+        .pricing()
+        .get_execution_price(
+            services.price_impact(),
+            ExecutionPriceParams {
+                oi: &oi_params,
+                impact_cfg: &impact_cfg,
+                side: order.side,
+                size_delta_usd,
+                direction: pricing::TradeDirection::Increase,
+                prices: *prices,
+                price_selection: PriceSelection::Conservative,
+            },
+        )
+        .map_err(|e| format!("pricing_error: {:?}", e))?;
+
+    let costs = compute_step_costs(
+        services.funding(),
+        services.borrowing(),
+        services.fees(),
+        &NoopTelemetry,
+        StepCostParams {
+            market,
+            prices,
+            order,
+            balance_was_improved: exec.balance_was_improved,
+            price_impact_usd: exec.price_impact_usd,
+            size_delta_usd,
+            now,
+        },
+        &mut next,
+        &mut Claimables::default(),
+    )?;
+    apply_step_costs_to_position(&mut next, prices, &costs, &NoopTelemetry)?;
+
+    next.apply_increase(size_delta_usd, exec.base_size_delta_tokens)?;
+    next.pending_impact_tokens =
+        math::signed_add(next.pending_impact_tokens, exec.price_impact_amount_tokens);
+    next.last_updated_at = now;
+
+    let collateral_usd = next
+        .collateral_amount
+        .checked_mul(prices.collateral_price_min)
+        .ok_or("collateral_usd_overflow")?;
+    if collateral_usd.is_zero() {
+        return Err("zero_collateral".into());
+    }
+    let leverage_fp = next
+        .size_usd
+        .checked_mul(U256::exp10(18))
+        .ok_or("leverage_overflow")?
+        / collateral_usd;
+
+    let mut next_market = market.clone();
+    match order.side {
+        Side::Long => next_market.oi_long_usd += size_delta_usd,
+        Side::Short => next_market.oi_short_usd += size_delta_usd,
+    }
+    let impact_on_close = close_price_impact_usd(services, &next_market, &next, prices)?;
+    let accrued = liquidation::AccruedCosts::preview(
+        services.funding().rate_model(),
+        &next_market,
+        &next,
+        now,
+    )?;
+    let liquidation_price = liquidation::calculate_liquidation_price(
+        &next,
+        prices,
+        accrued,
+        risk,
+        LiquidationFeeCfg {
+            close_position_fee_bps: 0,
+            liquidation_fee_bps: 0,
+        },
+        impact_on_close,
+    )?;
+
+    Ok(IncreaseQuote {
+        size_delta_usd,
+        execution_price: exec.execution_price,
+        size_delta_tokens: exec.base_size_delta_tokens,
+        price_impact_usd: exec.price_impact_usd,
+        balance_was_improved: exec.balance_was_improved,
+        costs,
+        next_size_usd: next.size_usd,
+        next_collateral_amount: next.collateral_amount,
+        leverage_fp,
+        liquidation_price,
+    })
+}
+
+/// Keeper entry point: apply a bundle of oracle prices to several markets.
+///
+/// For each `(market, prices)`: checks the prices (`oracle::validate_prices` and
+/// the market's price band; the spread limit only gates increases, see
+/// `execute_order`), advances funding / borrowing indices to
+/// `clock.now()` and records the mid price as the new band reference. Every update is
+/// validated before any market is touched, so the call is all-or-nothing.
+/// Positions are not settled; use `Executor::settle_market` for that.
+///
+/// Returns the post-update summaries in the order of `updates`.
+pub fn apply_price_update<S: ServicesBundle>(
+    updates: &[(MarketId, OraclePrices)],
+    clock: &dyn Clock,
+    state: &mut State,
+    services: &S,
+) -> Result<Vec<MarketSummary>, String> {
+    let now = clock.now();
+    for (i, (market_id, prices)) in updates.iter().enumerate() {
+        if updates[..i].iter().any(|(m, _)| m == market_id) {
+            return Err("duplicate_market_in_price_update".into());
+        }
+        let market = state.markets.get(market_id).ok_or("market_not_found")?;
+        oracle::validate_prices(prices)?;
+        oracle::check_price_deviation(
+            market.last_index_price,
+            prices,
+            market.max_price_deviation_bps,
+        )?;
+    }
+
+    let mut summaries = Vec::with_capacity(updates.len());
+    for (market_id, prices) in updates {
+        let market = state
+            .markets
+            .get_mut(market_id)
+            .expect("market checked above");
+        services.funding().update_indices(market, now);
+        services.borrowing().update_index(market, now);
+        market.last_index_price = oracle::mid_index_price(prices);
+        summaries.push(market.summary(services.funding().rate_model()));
+    }
+    Ok(summaries)
+}
+
+/// USD value of `amount` atoms of a pool asset at the oracle's min price:
+/// the market's long asset is priced as the index token, anything else as
+/// collateral.
+fn pool_asset_value_usd(
+    market: &MarketState,
+    asset: AssetId,
+    amount: TokenAmount,
+    prices: &OraclePrices,
+) -> Result<Usd, String> {
+    let price = if asset == market.long_asset && asset != market.short_asset {
+        prices.index_price_min
+    } else {
+        prices.collateral_price_min
+    };
+    amount
+        .checked_mul(price)
+        .ok_or_else(|| "liquidity_value_overflow".to_string())
+}
+
+/// `TradeThenSettle` advances the indices after the trade, while the traded
+/// position is still checkpointed at the pre-trade index. Settle that elapsed
+/// interval on the size the position had before the trade (zero for a new
+/// one), carry the result as pending funding / unpaid cost, and checkpoint the
+/// position at the market's current indices.
+fn settle_elapsed_on_pre_trade_size<S: ServicesBundle>(
+    services: &S,
+    market: &MarketState,
+    pos: &mut Position,
+    pre_trade_size: Option<(Usd, TokenAmount)>,
+    now: Timestamp,
+) -> Result<(), String> {
+    let (size_usd, size_tokens) = pre_trade_size.unwrap_or_default();
+    let mut before = Position {
+        size_usd,
+        size_tokens,
+        pending_funding_usd: SignedU256::zero(),
+        ..pos.clone()
+    };
+    let funding = services
+        .funding()
+        .settle_position_funding(market, &mut before)?;
+    let borrowing = services
+        .borrowing()
+        .settle_position_borrowing(market, &mut before, now)?;
+
+    // Deferred funding lands in `pending_funding_usd`; carry it either way.
+    let funding_usd = math::checked_signed_add(funding.funding_fee_usd, before.pending_funding_usd)
+        .and_then(|f| math::checked_signed_add(pos.pending_funding_usd, f))
+        .ok_or("funding_overflow")?;
+    pos.unpaid_cost_usd = pos
+        .unpaid_cost_usd
+        .checked_add(borrowing.borrowing_fee_usd)
+        .ok_or("settlement_cost_overflow")?;
+    pos.pending_funding_usd = funding_usd;
+    pos.funding_index = match pos.key.side {
+        Side::Long => market.funding.cumulative_index_long,
+        Side::Short => market.funding.cumulative_index_short,
+    };
+    pos.funding_updated_at = market.funding.last_updated_at;
+    pos.borrowing_index = market.borrowing.cumulative_factor;
+    Ok(())
+}
+
+/// Fresh, empty position checkpointed at the market's current indices.
+fn new_position(key: PositionKey, market: &MarketState, now: Timestamp) -> Position {
+    // Initial funding index depends on side (long/short).
+    let initial_funding_index = match key.side {
+        Side::Long => market.funding.cumulative_index_long,
+        Side::Short => market.funding.cumulative_index_short,
+    };
+
+    Position {
+        key,
+        size_usd: U256::zero(),
+        size_tokens: U256::zero(),
+        collateral_amount: U256::zero(),
+        collateral_balances: HashMap::new(),
+        pending_impact_tokens: SignedU256::zero(),
+        realized_impact_tokens: SignedU256::zero(),
+        funding_index: initial_funding_index,
+        funding_updated_at: market.funding.last_updated_at,
+        pending_funding_usd: SignedU256::zero(),
+        borrowing_index: market.borrowing.cumulative_factor,
+        unpaid_cost_usd: U256::zero(),
+        needs_liquidation: false,
+        opened_at: now,
+        last_updated_at: now,
+    }
+}
+
+/// Price impact of closing `pos` in full at current OI, scaled by the
+/// market's close-impact factor.
+fn close_price_impact_usd<S: ServicesBundle>(
+    services: &S,
+    market: &MarketState,
+    pos: &Position,
+    prices: &OraclePrices,
+) -> Result<SignedU256, String> {
+    let oi_params = services.open_interest().for_decrease(
+        market.oi_long_usd,
+        market.oi_short_usd,
+        pos.size_usd,
+        pos.key.side,
+    );
+
+    let impact_cfg = market.effective_impact_config()?;
+
+    let exec = services
+        .pricing()
+        .get_execution_price(
+            services.price_impact(),
+            ExecutionPriceParams {
+                oi: &oi_params,
+                impact_cfg: &impact_cfg,
+                side: pos.key.side,
+                direction: pricing::TradeDirection::Decrease,
+                size_delta_usd: pos.size_usd,
+                prices: *prices,
+                price_selection: PriceSelection::Conservative,
+            },
+        )
+        .map_err(|e| format!("pricing_error:{:?}", e))?;
+
+    Ok(price_impact::scale_close_impact(
+        exec.price_impact_usd,
+        market.impact_on_close_bps_scale,
+    ))
+}
+
 /// Derive size_delta_usd from collateral deposit and target leverage.
 fn derive_size_delta_usd(order: &Order, prices: &OraclePrices) -> Result<Usd, String> {
     // 1) collateral_usd_1e30 = atoms * price_per_unit_1e30
@@ -859,34 +1538,6 @@ fn derive_size_delta_usd(order: &Order, prices: &OraclePrices) -> Result<Usd, St
     Ok(size_delta_usd)
 }
 
-/// Convert signed impact tokens -> signed USD, conservative:
-/// +tokens => * index_price_min
-/// -tokens => * index_price_max
-fn impact_tokens_to_usd_conservative(
-    tokens: SignedU256,
-    prices: &OraclePrices,
-) -> Result<SignedU256, String> {
-    if tokens.is_zero() {
-        return Ok(SignedU256::zero());
-    }
-    let px = if tokens.is_negative {
-        prices.index_price_max
-    } else {
-        prices.index_price_min
-    };
-    if px.is_zero() {
-        return Err("invalid_index_price_for_pending_impact".into());
-    }
-    let mag = tokens
-        .mag
-        .checked_mul(px)
-        .ok_or("pending_impact_usd_overflow")?;
-    Ok(SignedU256 {
-        is_negative: tokens.is_negative,
-        mag,
-    })
-}
-
 #[cfg(test)]
 #[path = "executor_tests/mod.rs"]
 mod tests;
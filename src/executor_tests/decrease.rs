@@ -4,13 +4,14 @@ use primitive_types::U256;
 
 use crate::math;
 use crate::oracle::Oracle;
-use crate::services::ServicesBundle;
-use crate::services::borrowing::apply_borrowing_fees_to_pool;
+use crate::services::borrowing::{apply_borrowing_fees_to_pool, preview_borrowing_fee_usd};
+use crate::services::funding::preview_funding_fee_usd;
 use crate::services::open_interest::OpenInterestService;
-use crate::services::price_impact::ImpactRebalanceConfig;
+use crate::services::price_impact::{ImpactRebalanceConfig, quote_impact, scale_close_impact};
 use crate::services::pricing::PricingService;
-use crate::services::pricing::{self, ExecutionPriceParams};
+use crate::services::pricing::{self, ExecutionPriceParams, PriceSelection};
 use crate::services::step_costs::{apply_step_costs_to_position, compute_step_costs};
+use crate::services::{BasicFeesService, FundingService, ServicesBundle};
 use crate::types::{ExecutionType, OraclePrices, Order, OrderType, Side, SignedU256, Timestamp};
 
 const SECONDS_PER_DAY: u64 = 86_400;
@@ -203,6 +204,7 @@ fn decrease_full_close_long_profit_fees_and_indices() {
                 size_delta_usd: pos_before.size_usd,
                 direction: pricing::TradeDirection::Increase,
                 prices: prices_open,
+                price_selection: PriceSelection::Conservative,
             },
         )
         .expect("pricing increase");
@@ -238,6 +240,8 @@ fn decrease_full_close_long_profit_fees_and_indices() {
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: t2,
         valid_from: t2.saturating_sub(1),
         valid_until: t2 + 300,
@@ -263,11 +267,18 @@ fn decrease_full_close_long_profit_fees_and_indices() {
             mag: delta_funding_fp,
         },
     );
+    // Receivers are credited OI-weighted so that total flow is conserved.
+    let receive_funding_fp = mul_div_u256(
+        delta_funding_fp,
+        m_before.oi_long_usd,
+        m_before.oi_short_usd,
+    )
+    .expect("funding receive mul/div");
     let expected_funding_short_after = crate::math::signed_sub(
         m_before.funding.cumulative_index_short,
         SignedU256 {
             is_negative: false,
-            mag: delta_funding_fp,
+            mag: receive_funding_fp,
         },
     );
 
@@ -338,6 +349,7 @@ fn decrease_full_close_long_profit_fees_and_indices() {
                 size_delta_usd: close_order.size_delta_usd,
                 direction: pricing::TradeDirection::Decrease,
                 prices: prices_close,
+                price_selection: PriceSelection::Conservative,
             },
         )
         .expect("pricing decrease");
@@ -383,7 +395,14 @@ fn decrease_full_close_long_profit_fees_and_indices() {
             .expect("total pnl usd->collateral tokens");
     // Costs are taken from collateral first
 
-    let close_costs_tokens = trading_fee_tokens + expected_borrowing_tokens + funding_cost_tokens;
+    // The engine converts the summed costs once, rounding up.
+    let close_costs_tokens = div_ceil_u256(
+        trading_fee_usd + expected_borrowing_usd + funding_cost_usd,
+        prices_close.collateral_price_min,
+    );
+    assert!(
+        close_costs_tokens >= trading_fee_tokens + expected_borrowing_tokens + funding_cost_tokens
+    );
 
     assert!(
         pos_before.collateral_amount >= close_costs_tokens,
@@ -535,3 +554,275 @@ fn decrease_full_close_long_profit_fees_and_indices() {
         "pool receive mismatch"
     );
 }
+
+#[test]
+fn partial_closes_move_pending_impact_into_realized() {
+    let mut env = setup_env(3_000);
+    let t0: Timestamp = 1_000;
+
+    let key = open_position(
+        &mut env.executor,
+        t0,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let opened = get_position(&env.executor, &key);
+    let (realized, pending0) = opened.impact_summary();
+    assert!(realized.is_zero());
+    assert!(!pending0.is_zero());
+
+    // Two partial closes of a quarter each.
+    let quarter = opened.size_usd / 4;
+    let mut last_realized = SignedU256::zero();
+    for i in 1..=2u64 {
+        close_position_partial_with_withdraw(
+            &mut env.executor,
+            t0 + 10 * i,
+            key,
+            quarter,
+            U256::zero(),
+        );
+        let pos = get_position(&env.executor, &key);
+        let (realized, pending) = pos.impact_summary();
+
+        // Realized grows in magnitude, and realized + pending is conserved.
+        assert!(realized.mag > last_realized.mag);
+        assert_eq!(math::signed_add(realized, pending), pending0);
+        last_realized = realized;
+    }
+}
+
+#[test]
+fn close_impact_follows_market_close_scale() {
+    let t0: Timestamp = 1_000;
+
+    // Open then fully close a $5k long with the given close-impact scale,
+    // optionally against a heavier $10k short. Returns the close impact quoted
+    // on the pre-close market and the user's output.
+    let run = |scale_bps: u32, heavier_short: bool| {
+        let mut env = setup_env(3_000);
+        if heavier_short {
+            open_position(
+                &mut env.executor,
+                t0,
+                env.account_b,
+                env.market_id,
+                Side::Short,
+                env.collateral_token,
+                2_000,
+                env.collateral_decimals,
+                5,
+            );
+        }
+        let key = open_position(
+            &mut env.executor,
+            t0,
+            env.account_a,
+            env.market_id,
+            Side::Long,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        );
+        let size_usd = get_position(&env.executor, &key).size_usd;
+        let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+        market.impact_on_close_bps_scale = scale_bps;
+        let (quoted, _) = quote_impact(
+            market,
+            Side::Long,
+            size_usd,
+            false,
+            &ImpactRebalanceConfig::default_quadratic(),
+        )
+        .unwrap();
+
+        close_position_full(&mut env.executor, t0, key);
+        let output = fee_claimable(
+            &env.executor.state.claimables,
+            env.account_a,
+            env.collateral_token,
+        );
+        (quoted, output, env.executor.oracle.prices)
+    };
+
+    // Closing the long into a short-heavy market worsens the skew: negative impact.
+    let (impact, full_output, prices) = run(10_000, true);
+    assert!(impact.is_negative && !impact.is_zero());
+
+    // Waived on close: the user keeps exactly the impact (in collateral tokens).
+    let (_, waived_output, _) = run(0, true);
+    let impact_tokens = impact.mag / prices.collateral_price_max;
+    assert!(u256_abs_diff(waived_output - full_output, impact_tokens) <= U256::one());
+    assert!(scale_close_impact(impact, 0).is_zero());
+    assert_eq!(scale_close_impact(impact, 10_000), impact);
+    assert_eq!(scale_close_impact(impact, 5_000).mag, impact.mag / 2);
+
+    // Closing the only long rebalances the market: the bonus is never scaled.
+    let (bonus, full_output, _) = run(10_000, false);
+    assert!(!bonus.is_negative && !bonus.is_zero());
+    let (_, scaled_output, _) = run(0, false);
+    assert_eq!(scaled_output, full_output);
+    assert_eq!(scale_close_impact(bonus, 0), bonus);
+}
+
+#[test]
+fn reduce_to_target_leverage_halves_a_10x_position() {
+    let mut env = setup_env(3_000);
+    let t0: Timestamp = 1_000;
+
+    let key = open_position(
+        &mut env.executor,
+        t0,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        10,
+    );
+    let prices = env.executor.oracle.prices;
+    let leverage_x100 = |pos: &crate::state::Position| {
+        pos.size_usd * 100 / (pos.collateral_amount * prices.collateral_price_min)
+    };
+    let before = get_position(&env.executor, &key);
+    assert!(leverage_x100(&before) >= U256::from(1_000u64));
+
+    let (size_delta, withdraw) =
+        math::position::reduce_to_target_leverage(&before, 5, &prices).unwrap();
+    assert!(withdraw.is_zero());
+    assert_eq!(
+        before.size_usd - size_delta,
+        before.collateral_amount * prices.collateral_price_min * 5
+    );
+
+    // Keeper gas is configured; the in-place deleverage needs no keeper fee.
+    env.executor.risk.execution_gas_price_usd = usd(1);
+    env.executor.risk.execution_gas_units = 1;
+    env.executor
+        .reduce_to_target_leverage(&(t0 + 10), key, 5)
+        .unwrap();
+
+    // Close fees come out of collateral, so the result lands just above 5x.
+    let after = get_position(&env.executor, &key);
+    let lev = leverage_x100(&after);
+    assert!(lev >= U256::from(500u64) && lev <= U256::from(502u64));
+
+    // Already at the target: nothing to do.
+    env.executor
+        .reduce_to_target_leverage(&(t0 + 20), key, 10)
+        .unwrap();
+    assert_eq!(get_position(&env.executor, &key).size_usd, after.size_usd);
+    assert!(env.executor.state.orders.iter().next().is_none());
+}
+
+#[test]
+fn close_payout_nets_accrued_funding_and_borrowing() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    let t2 = t + SECONDS_PER_DAY;
+
+    // Lone long: pays funding, nobody receives it.
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let pos = get_position(&env.executor, &key);
+    let market = env.executor.get_market(env.market_id).unwrap();
+    let funding_usd = preview_funding_fee_usd(
+        env.executor.services.funding().rate_model(),
+        &market,
+        &pos,
+        t2,
+    )
+    .unwrap();
+    let borrowing_usd = preview_borrowing_fee_usd(&market, &pos, t2).unwrap();
+    assert!(!funding_usd.is_negative && !funding_usd.is_zero());
+
+    // Same close with nothing accrued, for reference.
+    let mut immediate = env.executor.clone();
+    close_position_full(&mut immediate, t, key);
+    close_position_full(&mut env.executor, t2, key);
+
+    let payout_now = immediate.get_claimable(env.account_a, env.collateral_token);
+    let payout_later = env
+        .executor
+        .get_claimable(env.account_a, env.collateral_token);
+    let price = env
+        .executor
+        .oracle
+        .validate_and_get_prices(env.market_id)
+        .unwrap()
+        .collateral_price_min;
+
+    // The day of funding + borrowing comes out of the payout (costs round up
+    // together with the trading fee, so allow one atom).
+    let carry_tokens = (funding_usd.mag + borrowing_usd) / price;
+    let diff = payout_now - payout_later;
+    assert!(
+        diff >= carry_tokens && diff <= carry_tokens + 1,
+        "payout diff {diff} vs carry {carry_tokens}"
+    );
+}
+
+#[test]
+fn open_then_close_at_same_prices_loses_only_the_round_trip_delta() {
+    for side in [Side::Long, Side::Short] {
+        let mut env = setup_env(3_000);
+        let t = 1_000;
+        // No fees or impact and no elapsed time: only token rounding is left.
+        env.executor.services.fees = BasicFeesService::new(0, 0, 0, 0);
+        env.executor
+            .state
+            .markets
+            .get_mut(&env.market_id)
+            .unwrap()
+            .impact_config =
+            ImpactRebalanceConfig::new(2, U256::zero(), U256::zero(), U256::zero(), U256::zero())
+                .unwrap();
+        // Odd atom price so the token sizing leaves a remainder.
+        let px = env.executor.oracle.prices.index_price_min * 3_137 / 3_000 + 1;
+        set_index_price_atom(&mut env.executor, px);
+
+        let key = open_position(
+            &mut env.executor,
+            t,
+            env.account_a,
+            env.market_id,
+            side,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        );
+        let size_usd = get_position(&env.executor, &key).size_usd;
+        close_position_full(&mut env.executor, t, key);
+
+        let prices = env.executor.oracle.prices;
+        let deposit = to_atoms(1_000, env.collateral_decimals);
+        let payout = env
+            .executor
+            .get_claimable(env.account_a, env.collateral_token);
+        let delta = math::position::round_trip_delta(size_usd, side, &prices).unwrap();
+        assert!(!delta.is_zero(), "{side:?}");
+        assert_eq!(
+            deposit - payout,
+            div_ceil_u256(delta, prices.collateral_price_min),
+            "{side:?}"
+        );
+        assert!(deposit - payout <= U256::one(), "{side:?}");
+    }
+}
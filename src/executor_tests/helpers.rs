@@ -43,6 +43,9 @@ pub fn normalize_price_per_atom(
     (min_atom, max_atom)
 }
 
+/// Account executing orders in tests (receives execution fees).
+pub const KEEPER: AccountId = AccountId([9; 32]);
+
 /// Common test environment bundle: executor + IDs + decimals.
 #[derive(Clone)]
 pub struct TestEnv {
@@ -211,9 +214,9 @@ pub fn submit_and_execute(
     now: Timestamp,
     order: Order,
 ) -> OrderId {
-    let id: OrderId = executor.submit_order(order).expect("Error during order submittion");
+    let id: OrderId = executor.submit_order(&now, order).expect("Error during order submittion");
     executor
-        .execute_order(now, id)
+        .execute_order(KEEPER, &now, id)
         .expect("execute_order must succeed");
     assert!(
         executor.state.orders.get(id).is_none(),
@@ -251,6 +254,8 @@ pub fn open_position(
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: now,
         valid_from: now.saturating_sub(1),
         valid_until: now + 300,
@@ -293,6 +298,8 @@ pub fn close_position_full(
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: now,
         valid_from: now.saturating_sub(1),
         valid_until: now + 300,
@@ -325,6 +332,8 @@ pub fn close_position_partial_with_withdraw(
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: withdraw_tokens,
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: now,
         valid_from: now.saturating_sub(1),
         valid_until: now + 300,
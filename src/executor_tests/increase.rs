@@ -2,7 +2,7 @@ use super::helpers::*;
 
 use primitive_types::{U256, U512};
 
-use crate::executor::Executor;
+use crate::executor::{Executor, quote_increase};
 use crate::math::{signed_add, signed_sub};
 use crate::services::open_interest::OpenInterestService;
 use crate::services::price_impact::ImpactRebalanceConfig;
@@ -154,14 +154,16 @@ fn full_increase_flow_with_real_services() {
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: t1,
         valid_from: t1 - 30,
         valid_until: t1 + 300,
     };
 
-    let order1_id: OrderId = executor.submit_order(order1.clone()).expect("Error during order type submission");
+    let order1_id: OrderId = executor.submit_order(&t1, order1.clone()).expect("Error during order type submission");
     executor
-        .execute_order(t1, order1_id)
+        .execute_order(KEEPER, &t1, order1_id)
         .expect("step1 execute must succeed");
     assert!(
         executor.state.orders.get(order1_id).is_none(),
@@ -245,19 +247,21 @@ fn full_increase_flow_with_real_services() {
         trigger_price: None,
         acceptable_price: None,
         withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
         created_at: t2,
         valid_from: t2 - 30,
         valid_until: t2 + 300,
     };
 
-    let order2_id: OrderId = executor.submit_order(order2.clone()).expect("Error during order type submission");
+    let order2_id: OrderId = executor.submit_order(&t2, order2.clone()).expect("Error during order type submission");
 
     let pos_before2 = pos_after1.clone();
     let m_before2 = executor.state.markets.get(&market_id).unwrap().clone();
     let fee_pool_before2 = fee_pool_after1;
 
     executor
-        .execute_order(t2, order2_id)
+        .execute_order(KEEPER, &t2, order2_id)
         .expect("step2 execute must succeed");
 
     let pos_after2 = executor
@@ -298,11 +302,18 @@ fn full_increase_flow_with_real_services() {
             mag: delta_index_funding_fp,
         },
     );
+    // Receivers are credited OI-weighted so that total flow is conserved.
+    let receive_index_funding_fp = mul_div_u256(
+        delta_index_funding_fp,
+        m_before2.oi_long_usd,
+        m_before2.oi_short_usd,
+    )
+    .expect("funding receive mul/div");
     let expected_funding_index_short_after2 = signed_sub(
         m_before2.funding.cumulative_index_short,
         SignedU256 {
             is_negative: false,
-            mag: delta_index_funding_fp,
+            mag: receive_index_funding_fp,
         },
     );
 
@@ -394,6 +405,7 @@ fn full_increase_flow_with_real_services() {
                 size_delta_usd: expected_size_delta_usd2,
                 direction: pricing::TradeDirection::Increase,
                 prices: oracle_prices,
+                price_selection: pricing::PriceSelection::Conservative,
             },
         )
         .expect("pricing must succeed");
@@ -421,10 +433,112 @@ fn full_increase_flow_with_real_services() {
     // Collateral after step2 should reflect: +deposit2 - (trading+borrowing)
     let expected_total_usd2: Usd = expected_borrowing_usd2 + expected_trading_fee_usd2;
     let expected_total_tokens2: TokenAmount =
-        expected_total_usd2 / oracle_prices.collateral_price_min;
+        div_ceil_u256(expected_total_usd2, oracle_prices.collateral_price_min);
 
     let expected_collateral_after2 =
         pos_before2.collateral_amount + collateral_delta_tokens2 - expected_total_tokens2;
 
     assert_eq!(pos_after2.collateral_amount, expected_collateral_after2);
 }
+
+#[test]
+fn quote_increase_matches_pricing_and_execution_without_mutating_state() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    // Skew the market so the quoted trade carries non-zero impact.
+    open_position(
+        &mut env.executor,
+        t,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let order = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(500, env.collateral_decimals),
+        target_leverage_x: 4,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t,
+        valid_until: t + 300,
+    };
+
+    let prices = env.executor.oracle.prices;
+    let market = env.executor.get_market(env.market_id).unwrap();
+    let before = env.executor.state.snapshot();
+
+    let quote = quote_increase(
+        &order,
+        None,
+        &market,
+        &prices,
+        &env.executor.services,
+        env.executor.risk,
+        t,
+    )
+    .expect("quote must succeed");
+
+    assert!(crate::state::diff_state(&before, &env.executor.state.snapshot()).is_empty());
+
+    // Same execution price as a direct pricing call.
+    let oi_params = env.executor.services.open_interest().for_increase(
+        market.oi_long_usd,
+        market.oi_short_usd,
+        quote.size_delta_usd,
+        Side::Long,
+    );
+    let exec = env
+        .executor
+        .services
+        .pricing()
+        .get_execution_price(
+            env.executor.services.price_impact(),
+            pricing::ExecutionPriceParams {
+                oi: &oi_params,
+                impact_cfg: &ImpactRebalanceConfig::default_quadratic(),
+                side: Side::Long,
+                size_delta_usd: quote.size_delta_usd,
+                direction: pricing::TradeDirection::Increase,
+                prices,
+                price_selection: pricing::PriceSelection::Conservative,
+            },
+        )
+        .expect("pricing must succeed");
+
+    assert_eq!(quote.execution_price, exec.execution_price);
+    assert_eq!(quote.price_impact_usd, exec.price_impact_usd);
+    assert!(quote.balance_was_improved);
+    assert!(!quote.price_impact_usd.is_zero());
+
+    // Executing the same order lands exactly on the quoted position.
+    submit_and_execute(&mut env.executor, t, order);
+    let pos = get_position(&env.executor, &env.key_a(Side::Long));
+    assert_eq!(pos.size_usd, quote.next_size_usd);
+    assert_eq!(pos.collateral_amount, quote.next_collateral_amount);
+    assert_eq!(
+        env.executor
+            .calculate_liquidation_price(t, env.key_a(Side::Long))
+            .unwrap(),
+        quote.liquidation_price
+    );
+
+    // Leverage is just above 4x: step costs came out of the collateral.
+    assert!(quote.leverage_fp > U256::from(4u64) * U256::exp10(18));
+    assert!(quote.leverage_fp < U256::from(41u64) * U256::exp10(17));
+}
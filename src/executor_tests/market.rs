@@ -0,0 +1,549 @@
+use super::helpers::*;
+
+use primitive_types::U256;
+
+use crate::executor::{Executor, apply_price_update};
+use crate::math::pnl::mark_to_market;
+use crate::oracle::{Oracle, mid_index_price};
+use crate::services::borrowing::current_borrowing_rate_fp_per_sec;
+use crate::services::{BasicServicesBundle, BorrowingService, FundingService, ServicesBundle};
+use crate::state::{MarketState, UtilizationMode};
+use crate::types::{MarketId, OraclePrices, Side, SignedU256};
+
+#[test]
+fn market_summary_matches_state_and_index_growth() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 3_600;
+
+    open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let exec = &mut env.executor;
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    let summary = market.summary(exec.services.funding().rate_model());
+
+    assert_eq!(summary.oi_long_usd, market.oi_long_usd);
+    assert_eq!(summary.oi_short_usd, market.oi_short_usd);
+    assert_eq!(summary.liquidity_usd, market.liquidity_usd);
+    assert_eq!(summary.funding_payer, Some(Side::Long));
+
+    // Advancing the indices by dt moves them by rate * dt.
+    let funding_before = market.funding.cumulative_index_long;
+    let borrowing_before = market.borrowing.cumulative_factor;
+    exec.services.funding().update_indices(market, t2);
+    exec.services.borrowing().update_index(market, t2);
+
+    let dt = U256::from(t2 - t1);
+    assert_eq!(
+        market.funding.cumulative_index_long.mag - funding_before.mag,
+        summary.funding_rate_fp_per_sec * dt
+    );
+    assert_eq!(
+        market.borrowing.cumulative_factor - borrowing_before,
+        summary.borrowing_rate_fp_per_sec * dt
+    );
+
+    // No OI change => same summary.
+    assert_eq!(
+        market.summary(exec.services.funding().rate_model()),
+        summary
+    );
+}
+
+#[test]
+fn mark_to_market_reports_signed_pnl_per_position() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let long = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let short = open_position(
+        &mut env.executor,
+        t,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    // +10%: longs gain, shorts lose.
+    set_index_price_usd_per_token(&mut env.executor, 3_300, env.index_decimals);
+    let prices = env.executor.oracle.prices;
+    let marks = mark_to_market(&env.executor.state.positions, env.market_id, &prices);
+    assert_eq!(marks.len(), 2);
+
+    let pnl_of = |key| marks.iter().find(|(k, _)| *k == key).unwrap().1;
+    let long_pos = get_position(&env.executor, &long);
+    let short_pos = get_position(&env.executor, &short);
+    assert_eq!(
+        pnl_of(long),
+        SignedU256::pos(long_pos.size_tokens * prices.index_price_min - long_pos.size_usd)
+    );
+    assert_eq!(
+        pnl_of(short),
+        SignedU256::neg(short_pos.size_tokens * prices.index_price_max - short_pos.size_usd)
+    );
+
+    // Other markets are not included.
+    assert!(mark_to_market(&env.executor.state.positions, MarketId(999), &prices).is_empty());
+}
+
+#[test]
+fn positions_past_max_age_are_flagged_for_closure() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    env.executor.risk.max_position_age_secs = 86_400;
+
+    let old = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let young = open_position(
+        &mut env.executor,
+        t + 3_600,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let pos = get_position(&env.executor, &old);
+    assert_eq!(pos.age_secs(t + 86_400), 86_400);
+    assert_eq!(pos.age_secs(t - 1), 0);
+
+    // Exactly at the limit is still allowed.
+    assert!(env.executor.expired_positions(t + 86_400).is_empty());
+    assert_eq!(env.executor.expired_positions(t + 86_401), vec![old]);
+
+    let mut expired = env.executor.expired_positions(t + 3_600 + 86_401);
+    expired.sort_by_key(|k| k.side == Side::Short);
+    assert_eq!(expired, vec![old, young]);
+
+    // Disabled by default.
+    env.executor.risk.max_position_age_secs = 0;
+    assert!(env.executor.expired_positions(u64::MAX).is_empty());
+}
+
+#[test]
+fn oi_growth_is_rate_limited_per_rolling_window() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    {
+        let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+        market.max_oi_change_per_window_usd = usd(8_000);
+        market.oi_window_secs = 60;
+    }
+
+    // Size = deposit x 5.
+    let order = |account, side, deposit: u128, now| crate::types::Order {
+        account,
+        market_id: env.market_id,
+        side,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(deposit, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: crate::types::OrderType::Increase,
+        execution_type: crate::types::ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: now,
+        valid_from: now,
+        valid_until: now + 300,
+    };
+    let mut run = |account, side, deposit, now| {
+        let id = env
+            .executor
+            .submit_order(&now, order(account, side, deposit, now))
+            .unwrap();
+        env.executor.execute_order(KEEPER, &now, id)
+    };
+    let (a, b) = (env.account_a, env.account_b);
+
+    // $5_000 fits; another $5_000 in the same window does not, $3_000 does.
+    run(a, Side::Long, 1_000, t).unwrap();
+    assert_eq!(
+        run(b, Side::Short, 1_000, t + 30).unwrap_err(),
+        "oi_rate_limit_exceeded"
+    );
+    run(b, Side::Short, 600, t + 59).unwrap();
+
+    // The first increase leaves the window at t + 60, the second one only at
+    // t + 119: no fresh $8_000 right after the boundary.
+    run(b, Side::Short, 1_000, t + 60).unwrap();
+    assert_eq!(
+        run(a, Side::Long, 200, t + 61).unwrap_err(),
+        "oi_rate_limit_exceeded"
+    );
+    run(a, Side::Long, 200, t + 119).unwrap();
+
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(market.oi_long_usd, usd(6_000));
+    assert_eq!(market.oi_short_usd, usd(8_000));
+    assert_eq!(market.oi_added_in_window(t + 119), usd(6_000));
+    assert_eq!(market.oi_window.increases.len(), 2);
+}
+
+#[test]
+fn oi_skew_cap_blocks_worsening_but_not_rebalancing_increases() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    // $10_000 long vs $5_000 short, opened before the cap is set.
+    for (account, side, collateral) in [
+        (env.account_a, Side::Long, 2_000),
+        (env.account_b, Side::Short, 1_000),
+    ] {
+        open_position(
+            &mut env.executor,
+            t,
+            account,
+            env.market_id,
+            side,
+            env.collateral_token,
+            collateral,
+            env.collateral_decimals,
+            5,
+        );
+    }
+    env.executor
+        .state
+        .markets
+        .get_mut(&env.market_id)
+        .unwrap()
+        .max_oi_skew_bps = 2_000;
+
+    let increase = |side: Side, account| crate::types::Order {
+        account,
+        market_id: env.market_id,
+        side,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(200, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: crate::types::OrderType::Increase,
+        execution_type: crate::types::ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+
+    // +$1_000 long: 11k / 5k => 37.5% skew > 20%.
+    let id = env
+        .executor
+        .submit_order(&t, increase(Side::Long, env.account_a))
+        .unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "oi_skew_exceeded"
+    );
+    assert_eq!(
+        env.executor.get_market(env.market_id).unwrap().oi_long_usd,
+        usd(10_000)
+    );
+
+    // +$1_000 short: 10k / 6k is still 25% skewed, but it shrinks the skew.
+    submit_and_execute(&mut env.executor, t, increase(Side::Short, env.account_b));
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(market.oi_short_usd, usd(6_000));
+
+    // After three more shorts (10k / 9k) the same long only reaches 11k / 9k = 10%.
+    for _ in 0..3 {
+        submit_and_execute(&mut env.executor, t, increase(Side::Short, env.account_b));
+    }
+    env.executor.execute_order(KEEPER, &t, id).unwrap();
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(
+        (market.oi_long_usd, market.oi_short_usd),
+        (usd(11_000), usd(9_000))
+    );
+}
+
+#[test]
+fn oi_skew_cap_exempts_only_thin_markets() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    market.max_oi_skew_bps = 2_000;
+    market.min_oi_for_skew_cap_usd = usd(20_000);
+    let open = |exec: &mut Executor<BasicServicesBundle, TestOracle>, account, side, collateral| {
+        let order = crate::types::Order {
+            account,
+            market_id: env.market_id,
+            side,
+            collateral_token: env.collateral_token,
+            size_delta_usd: U256::zero(),
+            collateral_delta_tokens: to_atoms(collateral, env.collateral_decimals),
+            target_leverage_x: 5,
+            order_type: crate::types::OrderType::Increase,
+            execution_type: crate::types::ExecutionType::Market,
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: U256::zero(),
+            execution_fee_tokens: U256::zero(),
+            reduce_only: false,
+            created_at: t,
+            valid_from: t - 1,
+            valid_until: t + 300,
+        };
+        let id = exec.submit_order(&t, order).unwrap();
+        exec.execute_order(KEEPER, &t, id)
+    };
+
+    // The first trade of an empty market is 100% skewed but below the minimum OI.
+    open(&mut env.executor, env.account_a, Side::Long, 3_000).unwrap();
+    // Growing the lone side past the minimum is capped even with no shorts.
+    assert_eq!(
+        open(&mut env.executor, env.account_a, Side::Long, 1_000).unwrap_err(),
+        "oi_skew_exceeded"
+    );
+    // $15k long / $5k short: the short rebalances, the next long is capped.
+    open(&mut env.executor, env.account_b, Side::Short, 1_000).unwrap();
+    assert_eq!(
+        open(&mut env.executor, env.account_a, Side::Long, 200).unwrap_err(),
+        "oi_skew_exceeded"
+    );
+
+    // Below the minimum total OI the cap is not enforced.
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    market.min_oi_for_skew_cap_usd = usd(50_000);
+    open(&mut env.executor, env.account_a, Side::Long, 200).unwrap();
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(
+        (market.oi_long_usd, market.oi_short_usd),
+        (usd(16_000), usd(5_000))
+    );
+}
+
+#[test]
+fn price_update_advances_indices_in_every_market() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let other = MarketId(2);
+    let mut second = MarketState::new(other, usd(1_000_000), t);
+    second.oi_short_usd = usd(50_000);
+    env.executor.state.markets.insert(other, second);
+
+    let prices = env
+        .executor
+        .oracle
+        .validate_and_get_prices(env.market_id)
+        .unwrap();
+    let before: Vec<MarketState> = [env.market_id, other]
+        .iter()
+        .map(|id| env.executor.get_market(*id).unwrap())
+        .collect();
+
+    let later = t + 3_600;
+    let summaries = apply_price_update(
+        &[(env.market_id, prices), (other, prices)],
+        &later,
+        &mut env.executor.state,
+        &env.executor.services,
+    )
+    .unwrap();
+    assert_eq!(summaries.len(), 2);
+
+    for (prev, summary) in before.iter().zip(&summaries) {
+        let market = env.executor.get_market(prev.id).unwrap();
+        let model = env.executor.services.funding().rate_model();
+        assert_eq!(market.summary(model), *summary);
+        assert_eq!(market.funding.last_updated_at, later);
+        assert_eq!(market.borrowing.last_updated_at, later);
+        assert!(market.borrowing.cumulative_factor > prev.borrowing.cumulative_factor);
+        // The paying side's index grows (longs in market 1, shorts in market 2).
+        let index = |m: &MarketState| {
+            (
+                m.funding.cumulative_index_long,
+                m.funding.cumulative_index_short,
+            )
+        };
+        assert_ne!(index(&market), index(prev));
+        assert_eq!(market.last_index_price, mid_index_price(&prices));
+    }
+
+    // One bad entry rejects the whole bundle.
+    let bad = OraclePrices {
+        index_price_min: prices.index_price_max + 1,
+        ..prices
+    };
+    let snapshot = env.executor.get_market(other).unwrap();
+    assert_eq!(
+        apply_price_update(
+            &[(other, prices), (env.market_id, bad)],
+            &(later + 60),
+            &mut env.executor.state,
+            &env.executor.services,
+        )
+        .unwrap_err(),
+        "invalid_oracle_prices"
+    );
+    assert_eq!(
+        env.executor
+            .get_market(other)
+            .unwrap()
+            .funding
+            .last_updated_at,
+        snapshot.funding.last_updated_at
+    );
+}
+
+#[test]
+fn lp_deposits_deepen_the_pool_and_soften_impact() {
+    let t = 1_000;
+    let mut env = setup_env(3_000);
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    market.impact_config.reference_liquidity_usd = usd(5_000_000);
+
+    // Impact charged to the same long opened into an empty book.
+    let open_impact = |exec: &Executor<BasicServicesBundle, TestOracle>| {
+        let mut exec = exec.clone();
+        let key = open_position(
+            &mut exec,
+            t,
+            env.account_a,
+            env.market_id,
+            Side::Long,
+            env.collateral_token,
+            10_000,
+            env.collateral_decimals,
+            5,
+        );
+        let impact = get_position(&exec, &key).pending_impact_tokens;
+        assert!(impact.is_negative && !impact.is_zero());
+        impact.mag
+    };
+    let shallow = open_impact(&env.executor);
+
+    // Doubling the pool through an LP deposit doubles `liquidity_usd`...
+    let amount = to_atoms(5_000_000, env.collateral_decimals);
+    let value = env
+        .executor
+        .add_liquidity(env.market_id, env.collateral_token, amount)
+        .unwrap();
+    assert_eq!(value, usd(5_000_000));
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(market.liquidity_usd, usd(10_000_000));
+
+    // ...and roughly halves the impact of the same trade.
+    let deep = open_impact(&env.executor);
+    assert!(deep < shallow);
+    assert!(deep * 2 <= shallow + 1 && shallow <= deep * 2 + 1);
+
+    // Withdrawing it again restores the original depth and impact.
+    let id = env
+        .executor
+        .request_withdrawal(&t, env.market_id, env.collateral_token, amount)
+        .unwrap();
+    assert_eq!(env.executor.execute_withdrawal(&t, id).unwrap(), amount);
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(market.liquidity_usd, usd(5_000_000));
+    assert_eq!(open_impact(&env.executor), shallow);
+}
+
+#[test]
+fn reservations_follow_oi_and_drive_reserved_utilization() {
+    let t = 1_000;
+    let mut env = setup_env(3_000);
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    market.utilization_mode = UtilizationMode::Reserved;
+    market.reserve_factor_short_bps = 5_000;
+    let rate = |exec: &Executor<BasicServicesBundle, TestOracle>| {
+        current_borrowing_rate_fp_per_sec(&exec.get_market(env.market_id).unwrap())
+    };
+    let idle_rate = rate(&env.executor);
+
+    // A long reserves its whole size...
+    let long = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        10_000,
+        env.collateral_decimals,
+        5,
+    );
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(market.reserved_usd, market.oi_long_usd);
+    let long_rate = rate(&env.executor);
+    assert!(long_rate > idle_rate);
+
+    // ...a short of the same size only half of it.
+    let short = open_position(
+        &mut env.executor,
+        t,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        10_000,
+        env.collateral_decimals,
+        5,
+    );
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(
+        market.reserved_usd,
+        market.oi_long_usd + market.oi_short_usd / 2
+    );
+    assert!(rate(&env.executor) > long_rate);
+
+    // Closing releases the reservations.
+    close_position_full(&mut env.executor, t, long);
+    close_position_full(&mut env.executor, t, short);
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert!(market.reserved_usd.is_zero());
+    assert_eq!(rate(&env.executor), idle_rate);
+}
@@ -2,3 +2,8 @@ mod decrease;
 mod helpers;
 mod increase;
 mod liquidation;
+mod market;
+mod orders;
+mod price_band;
+mod settlement;
+mod snapshot;
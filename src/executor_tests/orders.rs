@@ -0,0 +1,505 @@
+use super::helpers::*;
+
+use primitive_types::U256;
+
+use crate::executor::GlobalStatus;
+use crate::state::MarketStatus;
+use crate::types::{ExecutionType, Order, OrderType, Side};
+
+#[test]
+fn reduce_only_increase_is_rejected_and_order_kept() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let order = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(1_000, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: true,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+
+    let id = env
+        .executor
+        .submit_order(&t, order)
+        .expect("submit must succeed");
+    let err = env.executor.execute_order(KEEPER, &t, id).unwrap_err();
+
+    assert_eq!(err, "reduce_only_order_would_increase_position");
+    assert!(env.executor.state.orders.contains(id));
+    assert!(
+        env.executor
+            .state
+            .positions
+            .get(&env.key_a(Side::Long))
+            .is_none()
+    );
+}
+
+#[test]
+fn reduce_only_close_proceeds() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let pos = get_position(&env.executor, &key);
+
+    let order = Order {
+        account: key.account,
+        market_id: key.market_id,
+        side: key.side,
+        collateral_token: key.collateral_token,
+        size_delta_usd: pos.size_usd,
+        collateral_delta_tokens: U256::zero(),
+        target_leverage_x: 1,
+        order_type: OrderType::Decrease,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: true,
+        created_at: t + 10,
+        valid_from: t,
+        valid_until: t + 300,
+    };
+
+    submit_and_execute(&mut env.executor, t + 10, order);
+    assert_position_removed(&env.executor, &key);
+}
+
+#[test]
+fn new_positions_beyond_account_cap_are_rejected() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    env.executor.risk.max_positions_per_account = 2;
+
+    for side in [Side::Long, Side::Short] {
+        open_position(
+            &mut env.executor,
+            t,
+            env.account_a,
+            env.market_id,
+            side,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        );
+    }
+
+    // A third position (different collateral) is over the cap.
+    let order = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.long_asset,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: U256::from(1_000u64),
+        target_leverage_x: 2,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&t, order).unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "too_many_positions"
+    );
+    assert_eq!(
+        env.executor.get_positions_by_account(env.account_a).len(),
+        2
+    );
+
+    // Growing an existing position is still allowed.
+    let key = open_position(
+        &mut env.executor,
+        t + 10,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        500,
+        env.collateral_decimals,
+        5,
+    );
+    assert!(get_position(&env.executor, &key).size_usd > U256::zero());
+
+    // Other accounts are counted separately.
+    open_position(
+        &mut env.executor,
+        t + 10,
+        env.account_b,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+}
+
+#[test]
+fn increase_with_non_whitelisted_collateral_is_rejected() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    env.executor
+        .state
+        .markets
+        .get_mut(&env.market_id)
+        .unwrap()
+        .allowed_collateral
+        .insert(env.collateral_token);
+
+    let order = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.long_asset,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: U256::from(1_000u64),
+        target_leverage_x: 2,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&t, order).unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "collateral_not_supported"
+    );
+    assert!(env.executor.state.orders.contains(id));
+    assert!(
+        env.executor
+            .get_positions_by_account(env.account_a)
+            .is_empty()
+    );
+
+    // The whitelisted token opens normally.
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    assert!(get_position(&env.executor, &key).size_usd > U256::zero());
+}
+
+#[test]
+fn global_halt_overrides_active_market() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    assert!(!env.executor.get_market(env.market_id).unwrap().paused);
+
+    let increase = Order {
+        account: env.account_b,
+        market_id: env.market_id,
+        side: Side::Short,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(1_000, env.collateral_decimals),
+        target_leverage_x: 2,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&t, increase.clone()).unwrap();
+
+    // A halt blocks queued and new increases but lets the existing long close.
+    env.executor.global_status = GlobalStatus::Halted;
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "protocol_halted"
+    );
+    assert!(env.executor.state.orders.contains(id));
+    assert_eq!(
+        env.executor.submit_order(&t, increase.clone()).unwrap_err(),
+        "protocol_halted"
+    );
+    close_position_full(&mut env.executor, t + 10, key);
+    assert_position_removed(&env.executor, &key);
+
+    // Reduce-only still blocks the increase but accepts it into the queue.
+    env.executor.global_status = GlobalStatus::ReduceOnly;
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "protocol_reduce_only"
+    );
+    let queued = env.executor.submit_order(&t, increase).unwrap();
+    env.executor.cancel_order(env.account_b, queued).unwrap();
+
+    env.executor.global_status = GlobalStatus::Active;
+    env.executor.execute_order(KEEPER, &(t + 10), id).unwrap();
+}
+
+#[test]
+fn reduce_skew_only_accepts_light_side_increases_only() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    // $10_000 long vs $5_000 short.
+    let long_key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        2_000,
+        env.collateral_decimals,
+        5,
+    );
+    open_position(
+        &mut env.executor,
+        t,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    env.executor
+        .state
+        .markets
+        .get_mut(&env.market_id)
+        .unwrap()
+        .status = MarketStatus::ReduceSkewOnly;
+
+    let increase = |account, side| Order {
+        account,
+        market_id: env.market_id,
+        side,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(200, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+
+    // Heavy (long) side is rejected and the order stays queued.
+    let id = env
+        .executor
+        .submit_order(&t, increase(env.account_a, Side::Long))
+        .unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "market_reduce_skew_only"
+    );
+    assert!(env.executor.state.orders.contains(id));
+
+    // Light (short) side goes through.
+    submit_and_execute(&mut env.executor, t, increase(env.account_b, Side::Short));
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(
+        (market.oi_long_usd, market.oi_short_usd),
+        (usd(10_000), usd(6_000))
+    );
+
+    // Decreases are always allowed; closing the long flips the heavy side.
+    close_position_full(&mut env.executor, t + 10, long_key);
+    assert_position_removed(&env.executor, &long_key);
+    let id = env
+        .executor
+        .submit_order(&(t + 10), increase(env.account_b, Side::Short))
+        .unwrap();
+    assert_eq!(
+        env.executor
+            .execute_order(KEEPER, &(t + 10), id)
+            .unwrap_err(),
+        "market_reduce_skew_only"
+    );
+    submit_and_execute(
+        &mut env.executor,
+        t + 10,
+        increase(env.account_a, Side::Long),
+    );
+}
+
+#[test]
+fn execution_fee_is_held_on_submit_and_paid_to_keeper() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    // $1 of keeper gas per order.
+    env.executor.risk.execution_gas_price_usd = usd(1);
+    env.executor.risk.execution_gas_units = 1;
+
+    let deposit = to_atoms(1_000, env.collateral_decimals);
+    let fee = to_atoms(2, env.collateral_decimals);
+    let increase = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: deposit,
+        target_leverage_x: 5,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: fee,
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+
+    // Cancelling refunds the held fee to the owner.
+    let id = env.executor.submit_order(&t, increase.clone()).unwrap();
+    assert_eq!(
+        env.executor.get_order(id).unwrap().collateral_delta_tokens,
+        deposit - fee
+    );
+    env.executor.cancel_order(env.account_a, id).unwrap();
+    assert_eq!(
+        env.executor
+            .get_claimable(env.account_a, env.collateral_token),
+        fee
+    );
+
+    // Executing pays it to the keeper; the position only gets the rest.
+    let id = env.executor.submit_order(&t, increase.clone()).unwrap();
+    env.executor.execute_order(KEEPER, &t, id).unwrap();
+    assert_eq!(
+        env.executor.get_claimable(KEEPER, env.collateral_token),
+        fee
+    );
+    let pos = get_position(&env.executor, &env.key_a(Side::Long));
+    assert!(pos.collateral_amount <= deposit - fee);
+
+    // Decrease orders pay the fee out of the position's collateral.
+    let before = pos.collateral_amount;
+    let decrease = Order {
+        order_type: OrderType::Decrease,
+        collateral_delta_tokens: U256::zero(),
+        size_delta_usd: pos.size_usd / 2,
+        ..increase
+    };
+    env.executor.submit_order(&t, decrease).unwrap();
+    assert_eq!(
+        get_position(&env.executor, &env.key_a(Side::Long)).collateral_amount,
+        before - fee
+    );
+}
+
+#[test]
+fn paused_market_blocks_increases_but_lets_positions_close() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    env.executor
+        .state
+        .markets
+        .get_mut(&env.market_id)
+        .unwrap()
+        .paused = true;
+
+    let increase = Order {
+        account: env.account_b,
+        market_id: env.market_id,
+        side: Side::Short,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(1_000, env.collateral_decimals),
+        target_leverage_x: 2,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&t, increase).unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "market_paused"
+    );
+
+    close_position_full(&mut env.executor, t + 10, key);
+    assert_position_removed(&env.executor, &key);
+}
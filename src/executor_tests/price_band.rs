@@ -0,0 +1,194 @@
+use super::helpers::*;
+
+use primitive_types::U256;
+
+use crate::executor::apply_price_update;
+use crate::types::{ExecutionType, Order, OrderType, Side};
+
+#[test]
+fn index_price_jump_beyond_band_is_rejected() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    let reference = market.last_index_price;
+    assert!(!reference.is_zero());
+    market.max_price_deviation_bps = 1_000; // 10%
+
+    // +50% in one update: circuit breaker trips, position untouched.
+    set_index_price_usd_per_token(&mut env.executor, 4_500, env.index_decimals);
+    let pos_before = get_position(&env.executor, &key);
+    let order = Order {
+        account: key.account,
+        market_id: key.market_id,
+        side: key.side,
+        collateral_token: key.collateral_token,
+        size_delta_usd: pos_before.size_usd,
+        collateral_delta_tokens: U256::zero(),
+        target_leverage_x: 1,
+        order_type: OrderType::Decrease,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t + 10,
+        valid_from: t,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&(t + 10), order).unwrap();
+    assert_eq!(
+        env.executor
+            .execute_order(KEEPER, &(t + 10), id)
+            .unwrap_err(),
+        "price_deviation_too_large"
+    );
+    assert_eq!(get_position(&env.executor, &key), pos_before);
+    assert!(env.executor.state.orders.contains(id));
+
+    // +1%: within the band, the same order executes.
+    set_index_price_usd_per_token(&mut env.executor, 3_030, env.index_decimals);
+    env.executor.execute_order(KEEPER, &(t + 20), id).unwrap();
+    assert_position_removed(&env.executor, &key);
+    assert_ne!(
+        env.executor.state.markets[&env.market_id].last_index_price,
+        reference
+    );
+}
+
+#[test]
+fn liquidations_and_reduce_only_orders_bypass_the_band() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let keys = [
+        (env.account_a, OrderType::Decrease),
+        (env.account_b, OrderType::Liquidation),
+    ]
+    .map(|(account, order_type)| {
+        let key = open_position(
+            &mut env.executor,
+            t,
+            account,
+            env.market_id,
+            Side::Long,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        );
+        (key, order_type)
+    });
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    let reference = market.last_index_price;
+    market.max_price_deviation_bps = 1_000; // 10%
+
+    // -15% in one update.
+    set_index_price_usd_per_token(&mut env.executor, 2_550, env.index_decimals);
+    for (key, order_type) in keys {
+        let order = Order {
+            account: key.account,
+            market_id: key.market_id,
+            side: key.side,
+            collateral_token: key.collateral_token,
+            size_delta_usd: get_position(&env.executor, &key).size_usd,
+            collateral_delta_tokens: U256::zero(),
+            target_leverage_x: 1,
+            order_type,
+            execution_type: ExecutionType::Market,
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: U256::zero(),
+            execution_fee_tokens: U256::zero(),
+            reduce_only: true,
+            created_at: t + 10,
+            valid_from: t,
+            valid_until: t + 300,
+        };
+        let id = env.executor.submit_order(&(t + 10), order).unwrap();
+        env.executor.execute_order(KEEPER, &(t + 10), id).unwrap();
+        assert_position_removed(&env.executor, &key);
+    }
+
+    // The unchecked price did not become the new reference.
+    assert_eq!(
+        env.executor.state.markets[&env.market_id].last_index_price,
+        reference
+    );
+}
+
+#[test]
+fn wide_oracle_spread_is_rejected() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    env.executor
+        .state
+        .markets
+        .get_mut(&env.market_id)
+        .unwrap()
+        .max_price_spread_bps = 50; // 0.5%
+
+    // $3_000 .. $3_060 is a 2% spread.
+    let (min, max) = normalize_price_per_atom(usd(3_000), usd(3_060), env.index_decimals);
+    env.executor.oracle.prices.index_price_min = min;
+    env.executor.oracle.prices.index_price_max = max;
+
+    let order = Order {
+        account: env.account_a,
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(1_000, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t,
+        valid_from: t - 1,
+        valid_until: t + 300,
+    };
+    let id = env.executor.submit_order(&t, order).unwrap();
+    assert_eq!(
+        env.executor.execute_order(KEEPER, &t, id).unwrap_err(),
+        "price_spread_too_wide"
+    );
+    assert!(env.executor.state.orders.contains(id));
+
+    // $3_000 .. $3_015 is exactly 0.5%: accepted.
+    let (_, max) = normalize_price_per_atom(usd(3_000), usd(3_015), env.index_decimals);
+    env.executor.oracle.prices.index_price_max = max;
+    env.executor.execute_order(KEEPER, &t, id).unwrap();
+    let pos = get_position(&env.executor, &env.key_a(Side::Long));
+    assert!(!pos.size_usd.is_zero());
+
+    // A wide spread neither blocks index updates nor exits.
+    let (_, max) = normalize_price_per_atom(usd(3_000), usd(3_060), env.index_decimals);
+    env.executor.oracle.prices.index_price_max = max;
+    let prices = env.executor.oracle.prices;
+    apply_price_update(
+        &[(env.market_id, prices)],
+        &(t + 10),
+        &mut env.executor.state,
+        &env.executor.services,
+    )
+    .unwrap();
+    close_position_full(&mut env.executor, t + 10, pos.key);
+    assert_position_removed(&env.executor, &pos.key);
+}
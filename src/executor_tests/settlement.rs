@@ -0,0 +1,890 @@
+use super::helpers::*;
+
+use primitive_types::U256;
+
+use crate::clock::{Clock, MockClock};
+use crate::executor::{Executor, SettlementOrder};
+use crate::math::rounding::RoundingAudit;
+use crate::services::settlement::{SettlementParams, settle_market_all, settle_market_borrowing};
+use crate::services::{
+    BasicServicesBundle, BorrowingService, FundingService, NoopTelemetry, ServicesBundle,
+};
+use crate::state::Position;
+use crate::types::{
+    AccountId, AssetId, ExecutionType, Order, OrderType, Side, SignedU256, Timestamp,
+};
+
+#[test]
+fn settle_market_all_rolls_back_every_position_on_error() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 86_400;
+
+    let key_a = open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let key_b = open_position(
+        &mut env.executor,
+        t1,
+        env.account_b,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    // Position B cannot pay a day of funding + borrowing.
+    env.executor
+        .state
+        .positions
+        .get_mut(&key_b)
+        .unwrap()
+        .collateral_amount = U256::one();
+
+    let exec = &mut env.executor;
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    exec.services.funding().update_indices(market, t2);
+    exec.services.borrowing().update_index(market, t2);
+    let market = market.clone();
+
+    let before = exec.state.snapshot();
+    let prices = exec.oracle.prices;
+    let audit = RoundingAudit::new();
+    let res = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &audit,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: t2,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    );
+
+    assert!(res.is_err());
+    for key in [key_a, key_b] {
+        let pos = get_position(exec, &key);
+        assert_eq!(pos, before.positions[&key]);
+        assert_ne!(pos.borrowing_index, market.borrowing.cumulative_factor);
+    }
+    assert_eq!(exec.state.pool_balances.fees, before.pool_fees);
+    assert!(audit.leakage(env.collateral_token).mag.is_zero());
+
+    // Once B is healthy again, both positions settle to the market indices.
+    exec.state
+        .positions
+        .get_mut(&key_b)
+        .unwrap()
+        .collateral_amount = to_atoms(1_000, env.collateral_decimals);
+    let pool_fee_before = exec
+        .state
+        .pool_balances
+        .get_fee_for_pool(env.market_id, env.collateral_token);
+    let settled = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &audit,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: t2,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    )
+    .expect("settlement must succeed");
+
+    assert_eq!(settled.len(), 2);
+    // Costs round up; the rounding surplus goes to the pool with borrowing.
+    let mut fee_tokens = U256::zero();
+    let mut leakage = U256::zero();
+    for s in &settled {
+        let owed = s.funding_usd + s.borrowing_usd;
+        assert_eq!(
+            s.cost_tokens,
+            div_ceil_u256(owed, prices.collateral_price_min)
+        );
+        fee_tokens += s.cost_tokens - s.funding_usd / prices.collateral_price_min;
+        leakage += s.cost_tokens * prices.collateral_price_min - owed;
+    }
+    // Only the committed settlement reports its rounding remainder.
+    assert_eq!(
+        audit.leakage(env.collateral_token),
+        SignedU256::pos(leakage)
+    );
+    let pool_fee_after = exec
+        .state
+        .pool_balances
+        .get_fee_for_pool(env.market_id, env.collateral_token);
+    assert_eq!(pool_fee_after, pool_fee_before + fee_tokens);
+    for key in [key_a, key_b] {
+        let pos = get_position(exec, &key);
+        assert_eq!(pos.funding_index, market.funding.cumulative_index_long);
+        assert_eq!(pos.borrowing_index, market.borrowing.cumulative_factor);
+    }
+}
+
+#[test]
+fn settle_market_borrowing_accrues_proportionally_to_size() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 86_400;
+
+    // Same leverage, collateral 1k / 2k / 3k => sizes 5k / 10k / 15k.
+    let keys: Vec<_> = [
+        (env.account_a, Side::Long, 1_000),
+        (env.account_b, Side::Long, 2_000),
+        (env.account_a, Side::Short, 3_000),
+    ]
+    .into_iter()
+    .map(|(account, side, deposit)| {
+        open_position(
+            &mut env.executor,
+            t1,
+            account,
+            env.market_id,
+            side,
+            env.collateral_token,
+            deposit,
+            env.collateral_decimals,
+            5,
+        )
+    })
+    .collect();
+
+    let exec = &mut env.executor;
+    let before = exec.state.snapshot();
+    let prices = exec.oracle.prices;
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    let steps = settle_market_borrowing(
+        exec.services.borrowing(),
+        &NoopTelemetry,
+        market,
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &prices,
+        t2,
+    )
+    .expect("settlement must succeed");
+    let factor = market.borrowing.cumulative_factor;
+
+    assert_eq!(steps.len(), 3);
+    let cost_of = |i: usize| {
+        steps
+            .iter()
+            .find(|(k, _)| *k == keys[i])
+            .unwrap()
+            .1
+            .cost_usd
+    };
+    let size_of = |i: usize| before.positions[&keys[i]].size_usd;
+
+    assert!(!cost_of(0).is_zero());
+    for i in 1..3 {
+        // cost_i / size_i == cost_0 / size_0 (up to rounding)
+        let lhs = cost_of(i) * size_of(0);
+        let rhs = cost_of(0) * size_of(i);
+        assert!(u256_abs_diff(lhs, rhs) <= size_of(0) + size_of(i));
+    }
+
+    let mut taken = U256::zero();
+    for key in keys.iter() {
+        let pos = get_position(exec, key);
+        assert_eq!(pos.borrowing_index, factor);
+        taken += before.positions[key].collateral_amount - pos.collateral_amount;
+    }
+    let fee_key = (env.market_id, env.collateral_token);
+    assert_eq!(
+        exec.state.pool_balances.fees[&fee_key] - before.pool_fees[&fee_key],
+        taken
+    );
+}
+
+#[test]
+fn settle_market_borrowing_records_shortfall_and_rounds_up() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 86_400;
+
+    let healthy = open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let broke = open_position(
+        &mut env.executor,
+        t1,
+        env.account_b,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    // B cannot cover a day of borrowing.
+    env.executor
+        .state
+        .positions
+        .get_mut(&broke)
+        .unwrap()
+        .collateral_amount = U256::one();
+
+    let exec = &mut env.executor;
+    let before = exec.state.snapshot();
+    let mut prices = exec.oracle.prices;
+
+    // A zero collateral price is rejected before anything moves.
+    prices.collateral_price_min = U256::zero();
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    let index_updated_at = market.borrowing.last_updated_at;
+    let audit = RoundingAudit::new();
+    let res = settle_market_borrowing(
+        exec.services.borrowing(),
+        &audit,
+        market,
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &prices,
+        t2,
+    );
+    assert_eq!(res.unwrap_err(), "invalid_collateral_price_min");
+    assert_eq!(market.borrowing.last_updated_at, index_updated_at);
+    assert_eq!(exec.state.pool_balances.fees, before.pool_fees);
+
+    let prices = exec.oracle.prices;
+    let steps = settle_market_borrowing(
+        exec.services.borrowing(),
+        &audit,
+        market,
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &prices,
+        t2,
+    )
+    .expect("settlement must succeed");
+    let cost_of = |key| steps.iter().find(|(k, _)| *k == key).unwrap().1.cost_usd;
+
+    // The healthy position pays the cost rounded up to whole atoms.
+    let pos = get_position(exec, &healthy);
+    let taken = before.positions[&healthy].collateral_amount - pos.collateral_amount;
+    let cost = cost_of(healthy);
+    assert!(taken * prices.collateral_price_min >= cost);
+    assert!((taken - 1) * prices.collateral_price_min < cost);
+    assert!(pos.unpaid_cost_usd.is_zero() && !pos.needs_liquidation);
+    // Only the healthy position leaks: the broke one pays its collateral exactly.
+    // Nothing was reported for the rejected attempt.
+    assert_eq!(
+        audit.leakage(env.collateral_token),
+        SignedU256::pos(taken * prices.collateral_price_min - cost)
+    );
+
+    // The broke one pays what it has and carries the rest as debt.
+    let pos = get_position(exec, &broke);
+    assert!(pos.collateral_amount.is_zero());
+    assert_eq!(
+        pos.unpaid_cost_usd,
+        cost_of(broke) - prices.collateral_price_min
+    );
+    assert!(pos.needs_liquidation);
+    assert!(
+        exec.is_liquidatable_by_margin(t2, broke)
+            .unwrap()
+            .is_liquidatable
+    );
+
+    let fee_key = (env.market_id, env.collateral_token);
+    assert_eq!(
+        exec.state.pool_balances.fees[&fee_key] - before.pool_fees[&fee_key],
+        taken + U256::one()
+    );
+}
+
+#[test]
+fn settlement_cost_is_capped_and_position_flagged() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    // ~10 years of downtime: accrued costs exceed the whole collateral.
+    let t2 = t1 + 3_650 * 86_400;
+
+    let key = open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let exec = &mut env.executor;
+    exec.risk.max_settlement_cost_bps = 5_000; // 50% of collateral per settlement
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    exec.services.funding().update_indices(market, t2);
+    exec.services.borrowing().update_index(market, t2);
+    let market = market.clone();
+
+    let collateral_before = get_position(exec, &key).collateral_amount;
+    let prices = exec.oracle.prices;
+    let settled = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &NoopTelemetry,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: t2,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    )
+    .expect("capped settlement must succeed");
+
+    let s = &settled[0];
+    let uncapped = (s.funding_usd + s.borrowing_usd) / prices.collateral_price_min;
+    assert!(uncapped > collateral_before);
+    assert!(s.needs_liquidation);
+    assert_eq!(s.cost_tokens, collateral_before / 2);
+
+    let pos = get_position(exec, &key);
+    assert_eq!(
+        pos.collateral_amount,
+        collateral_before - collateral_before / 2
+    );
+    assert_eq!(pos.borrowing_index, market.borrowing.cumulative_factor);
+
+    // The uncharged remainder is carried on the position, not dropped.
+    let owed = s.funding_usd + s.borrowing_usd;
+    let paid = s.cost_tokens * prices.collateral_price_min;
+    assert_eq!(s.unpaid_usd, owed - paid);
+    assert_eq!(pos.unpaid_cost_usd, s.unpaid_usd);
+    assert!(pos.needs_liquidation);
+    let preview = exec.is_liquidatable_by_margin(t2, key).unwrap();
+    assert!(preview.is_liquidatable);
+    assert_eq!(preview.unpaid_cost_usd, s.unpaid_usd);
+
+    // The next settlement charges the carried debt first, again capped.
+    let remaining = pos.collateral_amount;
+    let settled = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &NoopTelemetry,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: t2,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    )
+    .unwrap();
+    assert_eq!(settled[0].cost_tokens, remaining / 2);
+    assert_eq!(
+        settled[0].unpaid_usd,
+        s.unpaid_usd - remaining / 2 * prices.collateral_price_min
+    );
+}
+
+#[test]
+fn failed_decrease_keeps_carried_debt_and_liquidation_flag() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 3_650 * 86_400;
+    let t3 = t2 + 3_600;
+
+    let key = open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    // A capped settlement leaves debt on the position and flags it.
+    let exec = &mut env.executor;
+    exec.risk.max_settlement_cost_bps = 5_000;
+    exec.settle_market(&t2, env.market_id).unwrap();
+    let before = get_position(exec, &key);
+    assert!(!before.unpaid_cost_usd.is_zero() && before.needs_liquidation);
+
+    // A partial close cannot pay the debt and fails...
+    let order = Order {
+        account: key.account,
+        market_id: key.market_id,
+        side: key.side,
+        collateral_token: key.collateral_token,
+        size_delta_usd: before.size_usd / 2,
+        collateral_delta_tokens: U256::zero(),
+        target_leverage_x: 1,
+        order_type: OrderType::Decrease,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t3,
+        valid_from: t3,
+        valid_until: t3 + 300,
+    };
+    let id = exec.submit_order(&t3, order).unwrap();
+    let err = exec.execute_order(KEEPER, &t3, id).unwrap_err();
+    assert!(
+        err.starts_with("insufficient_collateral_for_costs"),
+        "{err}"
+    );
+
+    // ...without erasing the debt or the flag. The hour accrued by the failed
+    // step joins the debt, since its snapshots already advanced.
+    let pos = get_position(exec, &key);
+    assert_eq!(pos.collateral_amount, before.collateral_amount);
+    assert!(pos.needs_liquidation);
+    assert!(pos.unpaid_cost_usd > before.unpaid_cost_usd);
+    assert!(
+        exec.is_liquidatable_by_margin(t3, key)
+            .unwrap()
+            .is_liquidatable
+    );
+}
+
+#[test]
+fn capped_payers_scale_down_receiver_funding() {
+    let mut env = setup_env(3_000);
+    let t1 = 1_000;
+    let t2 = t1 + 3_650 * 86_400;
+
+    // Long-heavy market: the long pays funding, the short receives it.
+    let payer = open_position(
+        &mut env.executor,
+        t1,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        2_000,
+        env.collateral_decimals,
+        5,
+    );
+    let receiver = open_position(
+        &mut env.executor,
+        t1,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let exec = &mut env.executor;
+    exec.risk.max_settlement_cost_bps = 1;
+    let market = exec.state.markets.get_mut(&env.market_id).unwrap();
+    exec.services.funding().update_indices(market, t2);
+    exec.services.borrowing().update_index(market, t2);
+    let market = market.clone();
+
+    let prices = exec.oracle.prices;
+    let reward_usd = exec
+        .services
+        .funding()
+        .settle_position_funding(&market, &mut get_position(exec, &receiver))
+        .unwrap()
+        .funding_fee_usd
+        .mag;
+    let settled = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &NoopTelemetry,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: t2,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    )
+    .unwrap();
+
+    let p = settled.iter().find(|s| s.key == payer).unwrap();
+    assert!(p.needs_liquidation);
+    // Funding is paid first out of the capped charge.
+    let funding_paid = p
+        .funding_usd
+        .min(p.cost_tokens * prices.collateral_price_min);
+    assert!(funding_paid < p.funding_usd);
+
+    let expected = reward_usd * funding_paid / p.funding_usd / prices.collateral_price_max;
+    assert_eq!(
+        exec.get_claimable(env.account_b, env.collateral_token),
+        expected
+    );
+    assert!(!expected.is_zero());
+    assert!(expected < reward_usd / prices.collateral_price_max);
+}
+
+#[test]
+fn settle_market_all_rejects_cap_above_100_percent() {
+    let mut env = setup_env(3_000);
+    let exec = &mut env.executor;
+    exec.risk.max_settlement_cost_bps = 10_001;
+    let market = exec.get_market(env.market_id).unwrap();
+    let prices = exec.oracle.prices;
+
+    let err = settle_market_all(
+        exec.services.funding(),
+        exec.services.borrowing(),
+        &NoopTelemetry,
+        SettlementParams {
+            market: &market,
+            prices: &prices,
+            risk: &exec.risk,
+            now: 1_000,
+        },
+        &mut exec.state.positions,
+        &mut exec.state.pool_balances,
+        &mut exec.state.claimables,
+    )
+    .unwrap_err();
+    assert_eq!(err, "invalid_max_settlement_cost_bps");
+}
+
+#[test]
+fn settlement_order_changes_which_oi_prices_elapsed_funding() {
+    let t1 = 1_000;
+    let t2 = t1 + 3_600;
+
+    let run = |order: SettlementOrder| {
+        let mut env = setup_env(3_000);
+        env.executor.settlement_order = order;
+        open_position(
+            &mut env.executor,
+            t1,
+            env.account_a,
+            env.market_id,
+            Side::Long,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        );
+        // A larger short flips the market from long-heavy to short-heavy.
+        open_position(
+            &mut env.executor,
+            t2,
+            env.account_b,
+            env.market_id,
+            Side::Short,
+            env.collateral_token,
+            2_000,
+            env.collateral_decimals,
+            5,
+        );
+        env.executor.get_market(env.market_id).unwrap().funding
+    };
+
+    // Pre-trade OI was long-heavy: longs paid for the elapsed hour.
+    let settle_first = run(SettlementOrder::SettleThenTrade);
+    assert!(!settle_first.cumulative_index_long.is_negative);
+    assert!(!settle_first.cumulative_index_long.is_zero());
+
+    // Post-trade OI is short-heavy: the same hour is priced as longs receiving.
+    let trade_first = run(SettlementOrder::TradeThenSettle);
+    assert!(trade_first.cumulative_index_long.is_negative);
+    assert_eq!(settle_first.last_updated_at, trade_first.last_updated_at);
+}
+
+#[test]
+fn trade_then_settle_charges_elapsed_time_only_on_pre_trade_size() {
+    let t1 = 1_000;
+    let t2 = t1 + 3_600;
+    let t3 = t2 + 3_600;
+    let mut env = setup_env(3_000);
+    env.executor.settlement_order = SettlementOrder::TradeThenSettle;
+    let open = |exec: &mut Executor<_, _>, t, account, side| {
+        open_position(
+            exec,
+            t,
+            account,
+            env.market_id,
+            side,
+            env.collateral_token,
+            1_000,
+            env.collateral_decimals,
+            5,
+        )
+    };
+    let long = open(&mut env.executor, t1, env.account_a, Side::Long);
+
+    // A position opened an hour later starts at the advanced indices.
+    let short = open(&mut env.executor, t2, env.account_b, Side::Short);
+    let market = env.executor.get_market(env.market_id).unwrap();
+    let pos = get_position(&env.executor, &short);
+    assert_eq!(pos.funding_index, market.funding.cumulative_index_short);
+    assert_eq!(pos.borrowing_index, market.borrowing.cumulative_factor);
+    assert!(pos.pending_funding_usd.is_zero() && pos.unpaid_cost_usd.is_zero());
+
+    // Increasing an existing position charges the elapsed hour (from the
+    // pre-trade index on) on its old size.
+    let pre = get_position(&env.executor, &long);
+    let stale = env.executor.get_market(env.market_id).unwrap();
+    open(&mut env.executor, t3, env.account_a, Side::Long);
+    let market = env.executor.get_market(env.market_id).unwrap();
+    let services = &env.executor.services;
+    let mut expected = Position {
+        funding_index: stale.funding.cumulative_index_long,
+        funding_updated_at: stale.funding.last_updated_at,
+        borrowing_index: stale.borrowing.cumulative_factor,
+        ..pre.clone()
+    };
+    let funding = services
+        .funding()
+        .settle_position_funding(&market, &mut expected)
+        .unwrap();
+    let borrowing = services
+        .borrowing()
+        .settle_position_borrowing(&market, &mut expected, t3)
+        .unwrap();
+    assert!(!borrowing.borrowing_fee_usd.is_zero());
+    let pos = get_position(&env.executor, &long);
+    assert!(pos.size_usd > pre.size_usd);
+    assert_eq!(pos.unpaid_cost_usd, borrowing.borrowing_fee_usd);
+    assert_eq!(pos.pending_funding_usd, funding.funding_fee_usd);
+    assert_eq!(pos.funding_index, market.funding.cumulative_index_long);
+    assert_eq!(pos.borrowing_index, market.borrowing.cumulative_factor);
+
+    // A trade that fails leaves the indices where they were.
+    let t4 = t3 + 3_600;
+    let order = Order {
+        account: AccountId([3; 32]),
+        market_id: env.market_id,
+        side: Side::Long,
+        collateral_token: env.collateral_token,
+        size_delta_usd: U256::zero(),
+        collateral_delta_tokens: to_atoms(1_000, env.collateral_decimals),
+        target_leverage_x: 5,
+        order_type: OrderType::Increase,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: t4,
+        valid_from: t4,
+        valid_until: t4 + 300,
+    };
+    let id = env.executor.submit_order(&t4, order).unwrap();
+    let market = env.executor.state.markets.get_mut(&env.market_id).unwrap();
+    market.allowed_collateral.insert(AssetId(99));
+    let before = market.clone();
+    assert!(env.executor.execute_order(KEEPER, &t4, id).is_err());
+    let market = env.executor.get_market(env.market_id).unwrap();
+    assert_eq!(
+        market.funding.last_updated_at,
+        before.funding.last_updated_at
+    );
+    assert_eq!(
+        market.borrowing.cumulative_factor,
+        before.borrowing.cumulative_factor
+    );
+}
+
+#[test]
+fn mock_clock_drives_repeated_market_settlement() {
+    let mut env = setup_env(3_000);
+    let clock = MockClock::new(1_000);
+
+    let key = open_position(
+        &mut env.executor,
+        clock.now(),
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+
+    let mut last = get_position(&env.executor, &key);
+    for step in 1..=3u64 {
+        assert_eq!(clock.advance(3_600), 1_000 + 3_600 * step);
+        let settled = env
+            .executor
+            .settle_market(&clock, env.market_id)
+            .expect("settlement must succeed");
+        assert_eq!(settled.len(), 1);
+
+        // Each hour of funding + borrowing is taken from collateral.
+        let pos = get_position(&env.executor, &key);
+        let market = env.executor.get_market(env.market_id).unwrap();
+        assert_eq!(market.funding.last_updated_at, clock.now());
+        assert_eq!(market.borrowing.last_updated_at, clock.now());
+        assert_eq!(pos.funding_index, market.funding.cumulative_index_long);
+        assert_eq!(
+            last.collateral_amount - pos.collateral_amount,
+            settled[0].cost_tokens
+        );
+        assert!(!settled[0].cost_tokens.is_zero());
+        last = pos;
+    }
+
+    // Settling again without advancing the clock costs nothing.
+    let settled = env.executor.settle_market(&clock, env.market_id).unwrap();
+    assert!(settled[0].cost_tokens.is_zero());
+}
+
+#[test]
+fn rounding_audit_never_favors_users_across_trades() {
+    let env = setup_env(3_000);
+    let services = BasicServicesBundle::default().with_telemetry(RoundingAudit::new());
+    let mut ex = Executor::new(env.executor.state.clone(), services, env.executor.oracle);
+    ex.risk = env.executor.risk;
+
+    let order = |account: AccountId,
+                 side: Side,
+                 order_type: OrderType,
+                 collateral: U256,
+                 size_delta_usd: U256,
+                 now: Timestamp| Order {
+        account,
+        market_id: env.market_id,
+        side,
+        collateral_token: env.collateral_token,
+        size_delta_usd,
+        collateral_delta_tokens: collateral,
+        target_leverage_x: 5,
+        order_type,
+        execution_type: ExecutionType::Market,
+        trigger_price: None,
+        acceptable_price: None,
+        withdraw_collateral_amount: U256::zero(),
+        execution_fee_tokens: U256::zero(),
+        reduce_only: false,
+        created_at: now,
+        valid_from: now,
+        valid_until: now + 300,
+    };
+    let run = |ex: &mut Executor<_, _>, o: Order, now| {
+        let id = ex.submit_order(&now, o).unwrap();
+        ex.execute_order(KEEPER, &now, id).unwrap();
+    };
+
+    let t = 1_000;
+    let (a, b) = (env.account_a, env.account_b);
+    let deposit = to_atoms(1_000, env.collateral_decimals) + U256::from(333_333u64);
+    run(
+        &mut ex,
+        order(a, Side::Long, OrderType::Increase, deposit, U256::zero(), t),
+        t,
+    );
+    run(
+        &mut ex,
+        order(
+            b,
+            Side::Short,
+            OrderType::Increase,
+            deposit / 3,
+            U256::zero(),
+            t + 77,
+        ),
+        t + 77,
+    );
+
+    // Odd price and elapsed times so every conversion leaves a remainder.
+    ex.oracle.prices.index_price_min = ex.oracle.prices.index_price_min * 3_137 / 3_000;
+    ex.oracle.prices.index_price_max = ex.oracle.prices.index_price_min;
+
+    let long_size = ex.get_position(&env.key_a(Side::Long)).unwrap().size_usd;
+    let short_size = ex.get_position(&env.key_b(Side::Short)).unwrap().size_usd;
+    let t2 = t + 3_701;
+    run(
+        &mut ex,
+        order(
+            a,
+            Side::Long,
+            OrderType::Decrease,
+            U256::zero(),
+            long_size / 3,
+            t2,
+        ),
+        t2,
+    );
+    let t3 = t2 + 9_013;
+    run(
+        &mut ex,
+        order(
+            b,
+            Side::Short,
+            OrderType::Decrease,
+            U256::zero(),
+            short_size,
+            t3,
+        ),
+        t3,
+    );
+    let rest = ex.get_position(&env.key_a(Side::Long)).unwrap().size_usd;
+    run(
+        &mut ex,
+        order(
+            a,
+            Side::Long,
+            OrderType::Decrease,
+            U256::zero(),
+            rest,
+            t3 + 11,
+        ),
+        t3 + 11,
+    );
+
+    let leakage = ex.services.telemetry.leakage(env.collateral_token);
+    assert!(!leakage.is_negative, "rounding favored users: {leakage:?}");
+    assert!(!leakage.is_zero());
+    // Each conversion moves less than one collateral atom; 5 trades x 3 conversions.
+    assert!(leakage.mag < ex.oracle.prices.collateral_price_max * U256::from(15u64));
+
+    // Base and impact token sizing of the two increases.
+    let index_token = ex.state.markets[&env.market_id].index_token;
+    let sizing = ex.services.telemetry.leakage(index_token);
+    assert!(
+        !sizing.is_negative,
+        "index sizing favored users: {sizing:?}"
+    );
+    assert!(!sizing.is_zero());
+    assert!(sizing.mag < ex.oracle.prices.index_price_max * U256::from(4u64));
+}
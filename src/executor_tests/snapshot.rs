@@ -0,0 +1,129 @@
+use super::helpers::*;
+
+use std::collections::HashMap;
+
+use primitive_types::U256;
+
+use crate::oracle::Oracle;
+use crate::risk::solvency::check_invariants;
+use crate::services::{FundingService, ServicesBundle};
+use crate::state::diff_state;
+use crate::types::{AssetId, Side, SignedU256};
+
+#[test]
+fn diff_after_increase_shows_position_creation_and_oi_bump() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+
+    let before = env.executor.state.snapshot();
+    let key = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let after = env.executor.state.snapshot();
+
+    let diff = diff_state(&before, &after);
+    let pos = get_position(&env.executor, &key);
+
+    assert_eq!(diff.positions_created, vec![key]);
+    assert!(diff.positions_removed.is_empty());
+    assert!(diff.positions_changed.is_empty());
+
+    assert_eq!(diff.oi_deltas.len(), 1);
+    let oi = diff.oi_deltas[&env.market_id];
+    assert_eq!(oi.long_usd, SignedU256::pos(pos.size_usd));
+    assert!(oi.short_usd.is_zero());
+
+    // Opening fee lands in the pool fee bucket; liquidity and claimables are untouched.
+    let fee_delta = diff.pool_fee_deltas[&(env.market_id, env.collateral_token)];
+    assert!(!fee_delta.is_negative && !fee_delta.is_zero());
+    assert!(diff.pool_liquidity_deltas.is_empty());
+    assert!(diff.claimable_funding_deltas.is_empty());
+    assert!(diff.claimable_fee_deltas.is_empty());
+
+    assert!(diff_state(&after, &after).is_empty());
+}
+
+#[test]
+fn executed_sequence_keeps_invariants_and_corruption_is_reported() {
+    let mut env = setup_env(3_000);
+    let t = 1_000;
+    let long = open_position(
+        &mut env.executor,
+        t,
+        env.account_a,
+        env.market_id,
+        Side::Long,
+        env.collateral_token,
+        1_000,
+        env.collateral_decimals,
+        5,
+    );
+    let short = open_position(
+        &mut env.executor,
+        t + 10,
+        env.account_b,
+        env.market_id,
+        Side::Short,
+        env.collateral_token,
+        500,
+        env.collateral_decimals,
+        3,
+    );
+    close_position_full(&mut env.executor, t + 3_600, short);
+
+    let prices = env
+        .executor
+        .oracle
+        .validate_and_get_prices(env.market_id)
+        .unwrap();
+    let prices_by_market = HashMap::from([(env.market_id, prices)]);
+    let risk = env.executor.risk;
+    let model = env.executor.services.funding().rate_model();
+    let now = t + 3_600;
+    assert_eq!(
+        check_invariants(&env.executor.state, &prices_by_market, risk, model, None),
+        Ok(())
+    );
+    assert_eq!(
+        check_invariants(
+            &env.executor.state,
+            &prices_by_market,
+            risk,
+            model,
+            Some(now)
+        ),
+        Ok(())
+    );
+
+    // OI drift, a half-zeroed position and an unbacked claimable.
+    let state = &mut env.executor.state;
+    state.markets.get_mut(&env.market_id).unwrap().oi_short_usd = usd(1);
+    state.positions.get_mut(&long).unwrap().size_tokens = U256::zero();
+    state
+        .claimables
+        .add_fee(env.account_b, AssetId(99), U256::one());
+
+    assert_eq!(
+        check_invariants(
+            &env.executor.state,
+            &prices_by_market,
+            risk,
+            model,
+            Some(now)
+        )
+        .unwrap_err(),
+        vec![
+            "claimables_unbacked: asset 99".to_string(),
+            format!("oi_mismatch: market {} Short", env.market_id.0),
+            format!("position_size_inconsistent: {:?}", long),
+        ]
+    );
+}
@@ -0,0 +1,76 @@
+// src/math/fp.rs
+
+//! Generic fixed-point scale shared by impact factors and risk factors.
+use primitive_types::U256;
+
+/// Number of decimals in the generic fixed-point scale.
+pub const DECIMALS: u32 = 18;
+
+/// Fixed-point scale = 10^18 (U256 form).
+pub const SCALE: U256 = U256([1_000_000_000_000_000_000, 0, 0, 0]);
+
+/// Fixed-point scale = 10^18 (i128 form, for signed index math).
+pub const SCALE_I128: i128 = 1_000_000_000_000_000_000;
+
+/// Fixed-point scale with a custom number of decimals.
+///
+/// Useful in tests to run the same math with a coarser / finer precision.
+pub fn scale_with_decimals(decimals: u32) -> U256 {
+    U256::exp10(decimals as usize)
+}
+
+/// Re-express a fixed-point value from one precision to another.
+///
+/// Down-scaling rounds toward zero.
+pub fn rescale(v_fp: U256, from_decimals: u32, to_decimals: u32) -> Result<U256, String> {
+    if from_decimals == to_decimals {
+        return Ok(v_fp);
+    }
+    if to_decimals > from_decimals {
+        v_fp.checked_mul(scale_with_decimals(to_decimals - from_decimals))
+            .ok_or("fp_rescale_overflow".into())
+    } else {
+        Ok(v_fp / scale_with_decimals(from_decimals - to_decimals))
+    }
+}
+
+/// Convert an i128 fixed-point value into U256. Negative values are rejected.
+pub fn i128_to_u256(v_fp: i128) -> Result<U256, String> {
+    if v_fp < 0 {
+        return Err("fp_negative_value".into());
+    }
+    Ok(U256::from(v_fp as u128))
+}
+
+/// Convert a U256 fixed-point value into i128, failing if it does not fit.
+pub fn u256_to_i128(v_fp: U256) -> Result<i128, String> {
+    if v_fp > U256::from(i128::MAX as u128) {
+        return Err("fp_i128_overflow".into());
+    }
+    Ok(v_fp.as_u128() as i128)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn u256_and_i128_scales_are_equal() {
+        assert_eq!(SCALE, U256::exp10(DECIMALS as usize));
+        assert_eq!(SCALE, scale_with_decimals(DECIMALS));
+        assert_eq!(i128_to_u256(SCALE_I128).unwrap(), SCALE);
+        assert_eq!(u256_to_i128(SCALE).unwrap(), SCALE_I128);
+    }
+
+    #[test]
+    fn factor_round_trip_is_lossless() {
+        // 4.2e-8, same shape as the default harmful impact factor.
+        let factor_fp = SCALE * 42 / 1_000_000_000;
+        let as_i128 = u256_to_i128(factor_fp).unwrap();
+        assert_eq!(i128_to_u256(as_i128).unwrap(), factor_fp);
+
+        // Up-scaling then down-scaling returns the same value.
+        let hi = rescale(factor_fp, DECIMALS, 27).unwrap();
+        assert_eq!(rescale(hi, 27, DECIMALS).unwrap(), factor_fp);
+    }
+}
@@ -1,5 +1,6 @@
 use crate::types::SignedU256;
 use primitive_types::U256;
+pub mod fp;
 pub mod pnl;
 pub mod position;
 pub mod rounding;
@@ -57,6 +58,25 @@ pub fn signed_sub(a: SignedU256, b: SignedU256) -> SignedU256 {
     signed_add(a, b.negated())
 }
 
+/// a + b, `None` if the magnitude overflows U256.
+pub fn checked_signed_add(a: SignedU256, b: SignedU256) -> Option<SignedU256> {
+    if a.is_negative == b.is_negative {
+        let mag = a.mag.checked_add(b.mag)?;
+        return Some(if a.is_negative {
+            SignedU256::neg(mag)
+        } else {
+            SignedU256::pos(mag)
+        });
+    }
+    // Mixed signs never overflow.
+    Some(signed_add(a, b))
+}
+
+/// a - b, `None` if the magnitude overflows U256.
+pub fn checked_signed_sub(a: SignedU256, b: SignedU256) -> Option<SignedU256> {
+    checked_signed_add(a, b.negated())
+}
+
 /// abs(a)
 pub fn signed_abs(a: SignedU256) -> U256 {
     a.mag
@@ -1,6 +1,9 @@
+use primitive_types::U256;
+
+use crate::math;
 use crate::math::rounding::{Rounding, div_round};
-use crate::state::Position;
-use crate::types::{OraclePrices, Side, SignedU256, TokenAmount, Usd};
+use crate::state::{Position, PositionKey, PositionStore};
+use crate::types::{MarketId, OraclePrices, Side, SignedU256, TokenAmount, Usd};
 fn pick_price_for_pnl(side: Side, prices: &OraclePrices) -> Usd {
     let p = match side {
         Side::Long => prices.index_price_min,
@@ -16,12 +19,18 @@ fn pick_price_for_pnl(side: Side, prices: &OraclePrices) -> Usd {
 /// - pos.size_tokens is in atoms
 /// - prices.index_price_* is USD(1e30) per 1 atom (per-unit)
 /// - pos.size_usd is USD(1e30)
+///
+/// A position with size but no tokens is corrupted (a long would read as a
+/// total loss) and errors with `"inconsistent_position_tokens"`.
 pub fn total_position_pnl_usd(pos: &Position, prices: &OraclePrices) -> Result<SignedU256, String> {
     let px = pick_price_for_pnl(pos.key.side, prices);
 
     if px.is_zero() {
         return Err("invalid_index_price_for_pnl".into());
     }
+    if !pos.size_usd.is_zero() && pos.size_tokens.is_zero() {
+        return Err("inconsistent_position_tokens".into());
+    }
 
     // value_usd = size_tokens * price_per_unit
     let value = pos
@@ -51,6 +60,22 @@ pub fn total_position_pnl_usd(pos: &Position, prices: &OraclePrices) -> Result<S
     Ok(pnl)
 }
 
+/// Unrealized PnL (`total_position_pnl_usd`) of every position on `market_id`.
+///
+/// Positions whose PnL cannot be computed (e.g. overflow) are skipped.
+/// Order follows the store's iteration order.
+pub fn mark_to_market(
+    positions: &PositionStore,
+    market_id: MarketId,
+    prices: &OraclePrices,
+) -> Vec<(PositionKey, SignedU256)> {
+    positions
+        .iter()
+        .filter(|(k, _)| k.market_id == market_id)
+        .filter_map(|(k, p)| total_position_pnl_usd(p, prices).ok().map(|pnl| (*k, pnl)))
+        .collect()
+}
+
 /// Realized PnL for partial close
 pub fn realized_pnl_usd(
     total_pnl_usd: SignedU256,
@@ -84,6 +109,38 @@ pub fn realized_pnl_usd(
     })
 }
 
+/// Net USD delta to the user's collateral when closing `size_delta_tokens`.
+///
+/// net = realized_pnl(total_pnl_usd, size_delta_tokens, pos_size_tokens)
+///     + realized_impact_usd
+///     - funding_usd
+///     - borrowing_usd
+///
+/// Input signs:
+/// - `total_pnl_usd`: PnL of the whole position (+ profit, - loss); realized proportionally.
+/// - `realized_impact_usd`: impact realized on this close, already proportional
+///   (+ bonus to the user, - penalty).
+/// - `funding_usd`: accrued funding (+ user pays, - user receives), as in `FundingDelta`.
+/// - `borrowing_usd`: accrued borrowing, always a cost.
+///
+/// Result: + credits collateral, - debits it.
+pub fn net_realized_pnl_usd(
+    total_pnl_usd: SignedU256,
+    size_delta_tokens: TokenAmount,
+    pos_size_tokens: TokenAmount,
+    realized_impact_usd: SignedU256,
+    funding_usd: SignedU256,
+    borrowing_usd: Usd,
+) -> Result<SignedU256, String> {
+    let pnl = realized_pnl_usd(total_pnl_usd, size_delta_tokens, pos_size_tokens)?;
+
+    let net = math::checked_signed_add(pnl, realized_impact_usd)
+        .and_then(|v| math::checked_signed_sub(v, funding_usd))
+        .and_then(|v| math::checked_signed_sub(v, SignedU256::pos(borrowing_usd)))
+        .ok_or("net_realized_pnl_overflow")?;
+    Ok(net)
+}
+
 /// Convert +/- pnlUsd to collateral tokens:
 /// +PnL: floor(pnlUsd / collateral_price_max) (min payout tokens)
 /// -PnL: ceil(abs(pnlUsd) / collateral_price_min) (max cost tokens)
@@ -110,3 +167,362 @@ pub fn pnl_usd_to_collateral_tokens(
         Ok(SignedU256::neg(mag))
     }
 }
+
+/// Full-close payout split into returned principal and realized profit/loss,
+/// in collateral tokens (atoms).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayoutBreakdown {
+    /// Collateral returned to the user (collateral minus realized loss, >= 0).
+    pub principal_tokens: TokenAmount,
+    /// Realized profit (+) or loss (-) after fees and funding.
+    /// A loss is capped at the position collateral.
+    pub profit_tokens: SignedU256,
+    /// Total paid out: principal + max(profit, 0).
+    pub total_tokens: TokenAmount,
+    /// Loss in excess of collateral that the pool absorbs.
+    pub bad_debt_tokens: TokenAmount,
+}
+
+/// Break down a full-close payout: collateral + PnL - fees - funding.
+///
+/// - `pnl_usd`: realized PnL of the whole position (+ profit, - loss), incl. impact.
+/// - `fees_usd`: close + borrowing fees, always a cost.
+/// - `funding_usd`: + => user pays, - => user receives.
+///
+/// Net USD is converted with `pnl_usd_to_collateral_tokens` (profit at price max
+/// rounded down, loss at price min rounded up).
+pub fn close_payout_breakdown(
+    pos: &Position,
+    pnl_usd: SignedU256,
+    fees_usd: Usd,
+    funding_usd: SignedU256,
+    prices: &OraclePrices,
+) -> Result<PayoutBreakdown, String> {
+    let net_usd = math::checked_signed_sub(pnl_usd, SignedU256::pos(fees_usd))
+        .and_then(|v| math::checked_signed_sub(v, funding_usd))
+        .ok_or("close_payout_overflow")?;
+    let net_tokens = pnl_usd_to_collateral_tokens(net_usd, prices)?;
+    let collateral = pos.collateral_amount;
+
+    if !net_tokens.is_negative {
+        let total_tokens = collateral
+            .checked_add(net_tokens.mag)
+            .ok_or("close_payout_overflow")?;
+        return Ok(PayoutBreakdown {
+            principal_tokens: collateral,
+            profit_tokens: net_tokens,
+            total_tokens,
+            bad_debt_tokens: U256::zero(),
+        });
+    }
+
+    let loss = net_tokens.mag;
+    let covered = loss.min(collateral);
+    let principal_tokens = collateral - covered;
+    Ok(PayoutBreakdown {
+        principal_tokens,
+        profit_tokens: SignedU256::neg(covered),
+        total_tokens: principal_tokens,
+        bad_debt_tokens: loss - covered,
+    })
+}
+
+/// Outcome of a full close once losses are netted against collateral.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClosePayout {
+    /// Collateral tokens paid back to the user (zero when the loss eats it all).
+    pub user_tokens: TokenAmount,
+    /// Loss beyond the collateral value, in USD, to be covered by the
+    /// insurance fund / pool.
+    pub bad_debt_usd: Usd,
+}
+
+/// Full close where the loss may exceed the collateral.
+///
+/// net = pnl_usd - fees_usd. The user payout is `close_payout_breakdown`'s
+/// total and never goes below zero; whatever part of a net loss the collateral
+/// (valued at `collateral_price_min`) cannot cover is reported as bad debt.
+pub fn close_with_loss(
+    pos: &Position,
+    pnl_usd: SignedU256,
+    fees_usd: Usd,
+    prices: &OraclePrices,
+) -> Result<ClosePayout, String> {
+    let breakdown = close_payout_breakdown(pos, pnl_usd, fees_usd, SignedU256::zero(), prices)?;
+
+    let net_usd = math::checked_signed_sub(pnl_usd, SignedU256::pos(fees_usd))
+        .ok_or("close_payout_overflow")?;
+    let bad_debt_usd = if net_usd.is_negative {
+        let collateral_usd = pos
+            .collateral_amount
+            .checked_mul(prices.collateral_price_min)
+            .ok_or("close_payout_overflow")?;
+        net_usd.mag.saturating_sub(collateral_usd)
+    } else {
+        U256::zero()
+    };
+
+    Ok(ClosePayout {
+        user_tokens: breakdown.total_tokens,
+        bad_debt_usd,
+    })
+}
+
+/// Convert signed impact tokens -> signed USD, conservative:
+/// +tokens => * index_price_min
+/// -tokens => * index_price_max
+pub fn impact_tokens_to_usd_conservative(
+    tokens: SignedU256,
+    prices: &OraclePrices,
+) -> Result<SignedU256, String> {
+    if tokens.is_zero() {
+        return Ok(SignedU256::zero());
+    }
+    let px = if tokens.is_negative {
+        prices.index_price_max
+    } else {
+        prices.index_price_min
+    };
+    if px.is_zero() {
+        return Err("invalid_index_price_for_pending_impact".into());
+    }
+    let mag = tokens
+        .mag
+        .checked_mul(px)
+        .ok_or("pending_impact_usd_overflow")?;
+    Ok(SignedU256 {
+        is_negative: tokens.is_negative,
+        mag,
+    })
+}
+
+/// Index price (USD(1e30) per atom) at which closing the position nets zero PnL
+/// after accrued costs.
+///
+/// - `accrued_fees_usd`: fees owed (borrowing + close fees), always a cost.
+/// - `accrued_funding_usd`: + => user pays, - => user receives.
+/// - pending impact tokens are valued at `prices` (conservative) and included.
+///
+/// Long:  T*P - entry + impact - costs = 0  =>  P = (entry + costs - impact) / T  (round UP)
+/// Short: entry - T*P + impact - costs = 0  =>  P = (entry - costs + impact) / T  (round DOWN)
+///
+/// Returns 0 for a short whose costs exceed entry + impact (no break-even price).
+pub fn break_even_price(
+    pos: &Position,
+    accrued_fees_usd: Usd,
+    accrued_funding_usd: SignedU256,
+    prices: &OraclePrices,
+) -> Result<Usd, String> {
+    if pos.size_usd.is_zero() || pos.size_tokens.is_zero() {
+        return Err("position_empty".into());
+    }
+
+    let impact_usd = impact_tokens_to_usd_conservative(pos.pending_impact_tokens, prices)?;
+
+    // net_costs = fees + funding - impact (signed)
+    let mut net_costs = math::signed_add(SignedU256::pos(accrued_fees_usd), accrued_funding_usd);
+    net_costs = math::signed_sub(net_costs, impact_usd);
+
+    let (numer, rounding) = match pos.key.side {
+        Side::Long => (
+            math::apply_signed_add(pos.size_usd, net_costs).unwrap_or(U256::zero()),
+            Rounding::Up,
+        ),
+        Side::Short => (
+            math::apply_signed_sub(pos.size_usd, net_costs).unwrap_or(U256::zero()),
+            Rounding::Down,
+        ),
+    };
+
+    div_round(numer, pos.size_tokens, rounding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PositionKey;
+    use crate::types::{AccountId, AssetId, MarketId};
+    use std::collections::HashMap;
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    fn pos(side: Side) -> Position {
+        Position {
+            key: PositionKey {
+                account: AccountId([1u8; 32]),
+                market_id: MarketId(1),
+                collateral_token: AssetId(10),
+                side,
+            },
+            size_usd: usd(200),         // entry notional $200
+            size_tokens: U256::from(2), // entry price $100 per atom
+            collateral_amount: U256::from(50),
+            collateral_balances: HashMap::new(),
+            pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
+            funding_index: SignedU256::zero(),
+            funding_updated_at: 0,
+            pending_funding_usd: SignedU256::zero(),
+            borrowing_index: U256::zero(),
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
+            opened_at: 1,
+            last_updated_at: 1,
+        }
+    }
+
+    fn prices() -> OraclePrices {
+        OraclePrices {
+            index_price_min: usd(100),
+            index_price_max: usd(100),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        }
+    }
+
+    #[test]
+    fn size_without_tokens_is_rejected_not_read_as_total_loss() {
+        for side in [Side::Long, Side::Short] {
+            let mut p = pos(side);
+            p.size_tokens = U256::zero();
+            assert_eq!(
+                total_position_pnl_usd(&p, &prices()),
+                Err("inconsistent_position_tokens".into())
+            );
+        }
+
+        // An empty position is still fine.
+        let mut p = pos(Side::Long);
+        p.size_usd = U256::zero();
+        p.size_tokens = U256::zero();
+        assert!(total_position_pnl_usd(&p, &prices()).unwrap().is_zero());
+    }
+
+    #[test]
+    fn loss_beyond_collateral_pays_nothing_and_reports_bad_debt() {
+        // $50 collateral; price halves => -$100 PnL, plus $4 fees.
+        let p = pos(Side::Long);
+        let out = close_with_loss(&p, SignedU256::neg(usd(100)), usd(4), &prices()).unwrap();
+        assert_eq!(
+            out,
+            ClosePayout {
+                user_tokens: U256::zero(),
+                bad_debt_usd: usd(54),
+            }
+        );
+
+        // A loss the collateral still covers leaves no bad debt.
+        let out = close_with_loss(&p, SignedU256::neg(usd(30)), usd(4), &prices()).unwrap();
+        assert_eq!(out.user_tokens, U256::from(16));
+        assert!(out.bad_debt_usd.is_zero());
+
+        // Exactly wiped out: zero payout, zero bad debt.
+        let out = close_with_loss(&p, SignedU256::neg(usd(46)), usd(4), &prices()).unwrap();
+        assert!(out.user_tokens.is_zero() && out.bad_debt_usd.is_zero());
+    }
+
+    #[test]
+    fn break_even_long_is_above_entry() {
+        // costs = $4 fees + $2 funding => P = (200 + 6) / 2 = $103
+        let p =
+            break_even_price(&pos(Side::Long), usd(4), SignedU256::pos(usd(2)), &prices()).unwrap();
+        assert_eq!(p, usd(103));
+        assert!(p > usd(100));
+
+        // Funding received lowers the break-even: (200 + 4 - 2) / 2 = $101
+        let p =
+            break_even_price(&pos(Side::Long), usd(4), SignedU256::neg(usd(2)), &prices()).unwrap();
+        assert_eq!(p, usd(101));
+    }
+
+    #[test]
+    fn break_even_short_is_below_entry() {
+        // costs = $4 fees + $2 funding => P = (200 - 6) / 2 = $97
+        let p = break_even_price(
+            &pos(Side::Short),
+            usd(4),
+            SignedU256::pos(usd(2)),
+            &prices(),
+        )
+        .unwrap();
+        assert_eq!(p, usd(97));
+        assert!(p < usd(100));
+    }
+
+    #[test]
+    fn net_realized_pnl_nets_impact_funding_and_borrowing() {
+        // Close half: +$100 total PnL => +$50 realized.
+        let net = net_realized_pnl_usd(
+            SignedU256::pos(usd(100)),
+            U256::from(1),
+            U256::from(2),
+            SignedU256::neg(usd(5)), // impact penalty
+            SignedU256::pos(usd(3)), // funding paid
+            usd(2),                  // borrowing
+        )
+        .unwrap();
+        assert_eq!(net, SignedU256::pos(usd(40)));
+
+        // Received funding adds; costs larger than PnL turn the delta negative.
+        let net = net_realized_pnl_usd(
+            SignedU256::pos(usd(10)),
+            U256::from(2),
+            U256::from(2),
+            SignedU256::neg(usd(30)),
+            SignedU256::neg(usd(4)),
+            usd(1),
+        )
+        .unwrap();
+        assert_eq!(net, SignedU256::neg(usd(17)));
+    }
+
+    #[test]
+    fn close_payout_splits_principal_and_profit() {
+        // 50 collateral atoms at $1.
+        let p = pos(Side::Long);
+
+        // +$30 PnL - $4 fees - $1 funding => +25 profit.
+        let b = close_payout_breakdown(
+            &p,
+            SignedU256::pos(usd(30)),
+            usd(4),
+            SignedU256::pos(usd(1)),
+            &prices(),
+        )
+        .unwrap();
+        assert_eq!(b.principal_tokens, U256::from(50));
+        assert_eq!(b.profit_tokens, SignedU256::pos(U256::from(25)));
+        assert_eq!(b.total_tokens, U256::from(75));
+        assert!(b.bad_debt_tokens.is_zero());
+
+        // -$20 PnL - $4 fees + $2 funding received => -22, within collateral.
+        let b = close_payout_breakdown(
+            &p,
+            SignedU256::neg(usd(20)),
+            usd(4),
+            SignedU256::neg(usd(2)),
+            &prices(),
+        )
+        .unwrap();
+        assert_eq!(b.principal_tokens, U256::from(28));
+        assert_eq!(b.profit_tokens, SignedU256::neg(U256::from(22)));
+        assert_eq!(b.total_tokens, U256::from(28));
+        assert!(b.bad_debt_tokens.is_zero());
+
+        // -$60 PnL - $5 fees => -65: payout capped at zero, 15 atoms of bad debt.
+        let b = close_payout_breakdown(
+            &p,
+            SignedU256::neg(usd(60)),
+            usd(5),
+            SignedU256::zero(),
+            &prices(),
+        )
+        .unwrap();
+        assert!(b.principal_tokens.is_zero());
+        assert_eq!(b.profit_tokens, SignedU256::neg(U256::from(50)));
+        assert!(b.total_tokens.is_zero());
+        assert_eq!(b.bad_debt_tokens, U256::from(15));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::math::rounding::{Rounding, div_round};
 use crate::state::Position;
-use crate::types::{Side, SignedU256, TokenAmount, Usd};
+use crate::types::{OraclePrices, Side, SignedU256, TokenAmount, Usd};
+
 /// - full close => all tokens
 /// - partial:
 ///   - long => ceil(pos.size_tokens * size_delta_usd / pos.size_usd)
@@ -32,10 +33,84 @@ pub fn size_delta_in_tokens(
     Ok(t.min(pos.size_tokens))
 }
 
-/// Proportional pending impact tokens (MVP, toward-zero):
+/// `size_delta_in_tokens` for a sequence of partial closes.
+///
+/// On the last close of the sequence (`is_last`) returns exactly the remaining
+/// `pos.size_tokens` instead of a rounded proportion, so the closes always add
+/// up to the original token count and no dust is left behind.
+pub fn size_delta_in_tokens_remaining(
+    pos: &Position,
+    size_delta_usd: Usd,
+    is_last: bool,
+) -> Result<TokenAmount, String> {
+    if is_last {
+        return Ok(pos.size_tokens);
+    }
+    size_delta_in_tokens(pos, size_delta_usd, false)
+}
+
+/// Invariant: tokens taken by a sequence of closes add up to the original size.
+pub fn check_closed_tokens_sum(
+    original_size_tokens: TokenAmount,
+    closed_tokens: &[TokenAmount],
+) -> Result<(), String> {
+    let mut sum = TokenAmount::zero();
+    for t in closed_tokens {
+        sum = sum.checked_add(*t).ok_or("closed_tokens_overflow")?;
+    }
+    if sum != original_size_tokens {
+        return Err("closed_tokens_mismatch".into());
+    }
+    Ok(())
+}
+
+/// Decrease needed to bring `pos` down to `target_leverage_x` at current prices.
+///
+/// leverage = size_usd / (collateral * collateral_price_min). Returns
+/// `(size_delta_usd, withdraw_tokens)`: the size is cut to
+/// `collateral_usd * target_leverage_x` and collateral is kept (withdraw = 0).
+/// A position already at or below the target needs no decrease.
+/// PnL, fees and impact of the decrease itself are not accounted for.
+pub fn reduce_to_target_leverage(
+    pos: &Position,
+    target_leverage_x: u32,
+    prices: &OraclePrices,
+) -> Result<(Usd, TokenAmount), String> {
+    if target_leverage_x == 0 {
+        return Err("invalid_target_leverage".into());
+    }
+    let collateral_usd = pos
+        .collateral_amount
+        .checked_mul(prices.collateral_price_min)
+        .ok_or("collateral_usd_overflow")?;
+    let target_size_usd = collateral_usd.saturating_mul(Usd::from(target_leverage_x));
+    Ok((
+        pos.size_usd.saturating_sub(target_size_usd),
+        TokenAmount::zero(),
+    ))
+}
+
+/// Rounding of the pending impact realized on a partial close
+/// (see `MarketState::pending_impact_rounding`).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum PendingImpactRounding {
+    /// Magnitude rounded down regardless of sign.
+    TowardZero,
+    /// Against the user: negative impact (owed by the user) rounds up,
+    /// positive impact (owed to the user) rounds down.
+    #[default]
+    Conservative,
+}
+
+/// Pending impact tokens realized by closing `size_delta_usd` of `pos`:
+/// pending * size_delta_usd / size_usd, rounded per `rounding`.
+///
+/// A full close realizes the whole remaining pending impact, so whatever
+/// earlier partial closes rounded away is settled there and no tokens are lost.
 pub fn proportional_pending_impact_tokens(
     pos: &Position,
     size_delta_usd: Usd,
+    rounding: PendingImpactRounding,
 ) -> Result<SignedU256, String> {
     if pos.size_usd.is_zero() || size_delta_usd.is_zero() {
         return Ok(SignedU256::zero());
@@ -49,13 +124,20 @@ pub fn proportional_pending_impact_tokens(
     if pending.mag.is_zero() {
         return Ok(SignedU256::zero());
     }
-    // mag = floor(pending.mag * size_delta_usd / pos.size_usd)
+    if size_delta_usd == pos.size_usd {
+        return Ok(pending);
+    }
+
     let prod = pending
         .mag
         .checked_mul(size_delta_usd)
         .ok_or("pending_impact_mul_overflow")?;
-
-    let mag = prod / pos.size_usd; // floor
+    let mag = match rounding {
+        PendingImpactRounding::Conservative if pending.is_negative => {
+            div_round(prod, pos.size_usd, Rounding::Up)?
+        }
+        _ => div_round(prod, pos.size_usd, Rounding::Down)?,
+    };
 
     if mag.is_zero() {
         return Ok(SignedU256::zero());
@@ -66,3 +148,222 @@ pub fn proportional_pending_impact_tokens(
         mag,
     })
 }
+
+/// Base tokens of an increase (no impact), rounded against the trader:
+///   - long  => floor(size_usd / index_price_max) (fewest tokens bought)
+///   - short => ceil(size_usd / index_price_min)  (most tokens sold)
+///
+/// Same rounding as `BasicPricingService` with `PriceSelection::Conservative`.
+/// A full close then values these tokens at the opposite price (long: min,
+/// short: max), so both legs round the same way and never in the trader's favour.
+pub fn increase_size_in_tokens(
+    size_usd: Usd,
+    side: Side,
+    prices: &OraclePrices,
+) -> Result<TokenAmount, String> {
+    match side {
+        Side::Long => div_round(size_usd, prices.index_price_max, Rounding::Down),
+        Side::Short => div_round(size_usd, prices.index_price_min, Rounding::Up),
+    }
+}
+
+/// USD lost by opening `size_usd` and fully closing it at the same `prices`,
+/// ignoring fees, funding, borrowing and impact.
+///
+/// Equals spread cost plus token rounding. With `index_price_min == index_price_max == p`
+/// the rounding part is always `< p`, i.e. less than one index atom.
+pub fn round_trip_delta(size_usd: Usd, side: Side, prices: &OraclePrices) -> Result<Usd, String> {
+    let size_tokens = increase_size_in_tokens(size_usd, side, prices)?;
+    let close_value = match side {
+        Side::Long => prices.index_price_min,
+        Side::Short => prices.index_price_max,
+    }
+    .checked_mul(size_tokens)
+    .ok_or("round_trip_value_overflow")?;
+
+    Ok(match side {
+        Side::Long => size_usd.saturating_sub(close_value),
+        Side::Short => close_value.saturating_sub(size_usd),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::pnl::{pnl_usd_to_collateral_tokens, total_position_pnl_usd};
+    use crate::state::{MarketState, PositionKey};
+    use crate::types::{AccountId, AssetId, MarketId};
+    use primitive_types::U256;
+
+    fn prices(index: U256) -> OraclePrices {
+        OraclePrices {
+            index_price_min: index,
+            index_price_max: index,
+            // $1 per 6-decimals atom.
+            collateral_price_min: U256::exp10(24),
+            collateral_price_max: U256::exp10(24),
+        }
+    }
+
+    fn close(pos: &mut Position, size_delta_usd: Usd, is_last: bool) -> TokenAmount {
+        let tokens = size_delta_in_tokens_remaining(pos, size_delta_usd, is_last).unwrap();
+        pos.size_usd -= size_delta_usd;
+        pos.size_tokens -= tokens;
+        tokens
+    }
+
+    #[test]
+    fn last_partial_close_takes_exactly_the_remaining_tokens() {
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Short,
+        };
+        let third = U256::exp10(30);
+        let original = U256::from(100u64);
+
+        // Thirds of 100 tokens, with the last close one USD atom short of the rest.
+        let mut pos = Position::open(
+            key,
+            &MarketState::default(),
+            third * 3,
+            original,
+            U256::one(),
+            1,
+        )
+        .unwrap();
+        let closed = [
+            close(&mut pos, third, false),
+            close(&mut pos, third, false),
+            close(&mut pos, third - 1, true),
+        ];
+        assert_eq!(
+            closed,
+            [U256::from(33u64), U256::from(33u64), U256::from(34u64)]
+        );
+        assert!(pos.size_tokens.is_zero());
+        assert_eq!(check_closed_tokens_sum(original, &closed), Ok(()));
+
+        // A rounded proportion on the last close would leave a dust token.
+        let mut pos = Position::open(
+            key,
+            &MarketState::default(),
+            third * 3,
+            original,
+            U256::one(),
+            1,
+        )
+        .unwrap();
+        let closed = [
+            close(&mut pos, third, false),
+            close(&mut pos, third, false),
+            close(&mut pos, third - 1, false),
+        ];
+        assert_eq!(pos.size_tokens, U256::one());
+        assert_eq!(
+            check_closed_tokens_sum(original, &closed).unwrap_err(),
+            "closed_tokens_mismatch"
+        );
+    }
+
+    #[test]
+    fn open_then_close_at_same_price_loses_at_most_one_atom() {
+        // ~$3_000 per 18-decimals token with an odd per-atom price.
+        let px = U256::from(3_000_000_000_007u64);
+        let prices = prices(px);
+        let collateral = U256::from(1_000u64) * U256::exp10(6);
+
+        for side in [Side::Long, Side::Short] {
+            for size in [10_000u64, 12_345, 99_999] {
+                let size_usd = U256::from(size) * U256::exp10(30) + U256::from(123_456u64);
+                let delta = round_trip_delta(size_usd, side, &prices).unwrap();
+                assert!(delta < px);
+
+                let key = PositionKey {
+                    account: AccountId([1u8; 32]),
+                    market_id: MarketId(1),
+                    collateral_token: AssetId(10),
+                    side,
+                };
+                let tokens = increase_size_in_tokens(size_usd, side, &prices).unwrap();
+                let pos = Position::open(
+                    key,
+                    &MarketState::default(),
+                    size_usd,
+                    tokens,
+                    collateral,
+                    1,
+                )
+                .unwrap();
+
+                // Closing realizes exactly the round-trip cost as a loss...
+                let pnl = total_position_pnl_usd(&pos, &prices).unwrap();
+                assert!(pnl.is_zero() || pnl.is_negative);
+                assert_eq!(pnl.mag, delta);
+
+                // ...which costs at most one collateral atom.
+                let pnl_tokens = pnl_usd_to_collateral_tokens(pnl, &prices).unwrap();
+                assert!(pnl_tokens.mag <= U256::one());
+            }
+        }
+    }
+
+    #[test]
+    fn repeated_partial_closes_realize_all_pending_impact() {
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let third = U256::exp10(30);
+
+        for (pending, rounding, expected) in [
+            // Owed by the user: conservative rounds up, toward-zero down.
+            (
+                SignedU256::neg(U256::from(100u64)),
+                PendingImpactRounding::Conservative,
+                [34u64, 33, 33],
+            ),
+            (
+                SignedU256::neg(U256::from(100u64)),
+                PendingImpactRounding::TowardZero,
+                [33, 33, 34],
+            ),
+            // Owed to the user: both round down.
+            (
+                SignedU256::pos(U256::from(100u64)),
+                PendingImpactRounding::Conservative,
+                [33, 33, 34],
+            ),
+        ] {
+            let mut pos = Position::open(
+                key,
+                &MarketState::default(),
+                third * 3,
+                U256::from(300u64),
+                U256::one(),
+                1,
+            )
+            .unwrap();
+            pos.pending_impact_tokens = pending;
+
+            let mut realized = Vec::new();
+            for _ in 0..3 {
+                let tokens = proportional_pending_impact_tokens(&pos, third, rounding).unwrap();
+                // Each partial is within one token of the exact third.
+                assert!(tokens.mag >= U256::from(32u64) && tokens.mag <= U256::from(34u64));
+                assert_eq!(tokens.is_negative, pending.is_negative);
+                pos.realize_impact(tokens);
+                pos.size_usd -= third;
+                realized.push(tokens.mag.as_u64());
+            }
+
+            // The last (full) close picks up the rounding remainder.
+            assert_eq!(realized, expected);
+            assert!(pos.pending_impact_tokens.is_zero());
+            assert_eq!(pos.realized_impact_tokens, pending);
+        }
+    }
+}
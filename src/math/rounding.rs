@@ -1,4 +1,10 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
 use primitive_types::U256;
+
+use crate::types::{AssetId, SignedU256};
+
 pub fn div_ceil_u(a: i128, b: i128) -> Result<i128, String> {
     if a < 0 || b <= 0 {
         return Err("div_ceil_invalid".into());
@@ -39,3 +45,91 @@ pub fn div_round(n: U256, d: U256, rounding: Rounding) -> Result<U256, String> {
         }
     })
 }
+
+/// Value moved by rounding `n / d`, signed from the protocol's side, in the
+/// units of `n` (USD(1e30) for USD -> token conversions).
+///
+/// `user_pays`: the quotient is taken from the user (costs, losses); otherwise
+/// it is paid to the user (rewards, profit). Rounding a charge up or a payout
+/// down keeps the remainder in the protocol (+); the opposite hands it to the
+/// user (-). Zero for exact divisions and for `d == 0`.
+pub fn rounding_leakage(n: U256, d: U256, rounding: Rounding, user_pays: bool) -> SignedU256 {
+    if d.is_zero() {
+        return SignedU256::zero();
+    }
+    let r = n % d;
+    if r.is_zero() {
+        return SignedU256::zero();
+    }
+    match (rounding, user_pays) {
+        (Rounding::Up, true) => SignedU256::pos(d - r),
+        (Rounding::Down, false) => SignedU256::pos(r),
+        (Rounding::Up, false) => SignedU256::neg(d - r),
+        (Rounding::Down, true) => SignedU256::neg(r),
+    }
+}
+
+/// Rounding audit collector: accumulates `rounding_leakage` per asset.
+///
+/// Fed through `Telemetry::on_rounding` from the user-facing conversions:
+/// step costs taken from collateral, funding rewards and realized PnL on
+/// decrease (keyed by collateral token), and the base / impact token sizing
+/// of increases (keyed by index token). A non-negative total means rounding
+/// never favored users.
+#[derive(Debug, Default, Clone)]
+pub struct RoundingAudit {
+    leakage: RefCell<HashMap<AssetId, SignedU256>>,
+}
+
+impl RoundingAudit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, asset: AssetId, leakage: SignedU256) {
+        if leakage.is_zero() {
+            return;
+        }
+        let mut map = self.leakage.borrow_mut();
+        let entry = map.entry(asset).or_insert_with(SignedU256::zero);
+        *entry = crate::math::signed_add(*entry, leakage);
+    }
+
+    /// Net leakage recorded for `asset` (+ kept by the protocol).
+    pub fn leakage(&self, asset: AssetId) -> SignedU256 {
+        self.leakage
+            .borrow()
+            .get(&asset)
+            .copied()
+            .unwrap_or_else(SignedU256::zero)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leakage_sign_follows_who_keeps_the_remainder() {
+        let (n, d) = (U256::from(10), U256::from(3));
+        assert_eq!(
+            rounding_leakage(n, d, Rounding::Up, true),
+            SignedU256::pos(U256::from(2))
+        );
+        assert_eq!(
+            rounding_leakage(n, d, Rounding::Down, false),
+            SignedU256::pos(U256::one())
+        );
+        assert_eq!(
+            rounding_leakage(n, d, Rounding::Down, true),
+            SignedU256::neg(U256::one())
+        );
+        assert!(rounding_leakage(U256::from(9), d, Rounding::Up, false).is_zero());
+
+        let audit = RoundingAudit::new();
+        audit.record(AssetId(1), SignedU256::pos(U256::from(5)));
+        audit.record(AssetId(1), SignedU256::neg(U256::from(2)));
+        assert_eq!(audit.leakage(AssetId(1)), SignedU256::pos(U256::from(3)));
+        assert!(audit.leakage(AssetId(2)).is_zero());
+    }
+}
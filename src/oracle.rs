@@ -1,5 +1,79 @@
-use crate::types::{MarketId, OraclePrices};
+use primitive_types::U256;
+
+use crate::types::{MarketId, OraclePrices, Usd};
 
 pub trait Oracle {
     fn validate_and_get_prices(&self, market_id: MarketId) -> Result<OraclePrices, String>;
 }
+
+/// Mid index price (min + max) / 2.
+pub fn mid_index_price(prices: &OraclePrices) -> Usd {
+    prices
+        .index_price_min
+        .saturating_add(prices.index_price_max)
+        / 2
+}
+
+/// Sanity check of a price bundle: every price non-zero and min <= max.
+pub fn validate_prices(prices: &OraclePrices) -> Result<(), String> {
+    if prices.index_price_min.is_zero()
+        || prices.collateral_price_min.is_zero()
+        || prices.index_price_min > prices.index_price_max
+        || prices.collateral_price_min > prices.collateral_price_max
+    {
+        return Err("invalid_oracle_prices".into());
+    }
+    Ok(())
+}
+
+/// Reject a degraded quote whose index spread is too wide:
+/// `(index_price_max - index_price_min) / index_price_min > max_spread_bps / 10_000`.
+///
+/// A zero `max_spread_bps` disables the check.
+pub fn check_price_spread(prices: &OraclePrices, max_spread_bps: u32) -> Result<(), String> {
+    if max_spread_bps == 0 {
+        return Ok(());
+    }
+    let spread = prices
+        .index_price_max
+        .saturating_sub(prices.index_price_min);
+
+    // Cross-multiplied, so a zero min with a non-zero max is rejected too.
+    let lhs = spread.saturating_mul(U256::from(10_000u64));
+    let rhs = prices
+        .index_price_min
+        .saturating_mul(U256::from(max_spread_bps));
+    if lhs > rhs {
+        return Err("price_spread_too_wide".into());
+    }
+    Ok(())
+}
+
+/// Price-band circuit breaker: reject when the mid index price moved more than
+/// `max_deviation_bps` away from `reference_price`.
+///
+/// A zero reference (first update) or zero band always passes.
+pub fn check_price_deviation(
+    reference_price: Usd,
+    prices: &OraclePrices,
+    max_deviation_bps: u32,
+) -> Result<(), String> {
+    if reference_price.is_zero() || max_deviation_bps == 0 {
+        return Ok(());
+    }
+
+    let mid = mid_index_price(prices);
+    let diff = if mid >= reference_price {
+        mid - reference_price
+    } else {
+        reference_price - mid
+    };
+
+    // diff / reference > bps / 10_000  <=>  diff * 10_000 > reference * bps
+    let lhs = diff.saturating_mul(U256::from(10_000u64));
+    let rhs = reference_price.saturating_mul(U256::from(max_deviation_bps));
+    if lhs > rhs {
+        return Err("price_deviation_too_large".into());
+    }
+    Ok(())
+}
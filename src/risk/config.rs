@@ -1,15 +1,25 @@
-use crate::types::Usd;
+use crate::math::fp;
+use crate::types::{OrderType, TokenAmount, Usd};
 use primitive_types::U256;
 
-/// Generic fixed-point scale = 10^18.
-fn fp_scale() -> U256 {
-    U256::exp10(18)
-}
+/// Basis points denominator (100% = 10_000 bps).
+pub const BPS_DENOM: u32 = 10_000;
 
 pub fn usd_scale() -> U256 {
     U256::exp10(30)
 }
 
+/// What to do when a partial decrease would leave a position below
+/// `min_position_size_usd`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DustPolicy {
+    /// Escalate to a full close (withdraw request is dropped).
+    #[default]
+    ForceClose,
+    /// Reject the decrease with `"remaining_below_min"`.
+    Reject,
+}
+
 /// Protocol-level risk constraints.
 #[derive(Clone, Copy, Debug)]
 pub struct RiskCfg {
@@ -21,6 +31,11 @@ pub struct RiskCfg {
     /// USD(1e30)
     pub min_collateral_usd: Usd,
 
+    /// Absolute minimum collateral in raw atoms of the position's collateral token,
+    /// enforced alongside `min_collateral_usd` to keep out dust positions when the
+    /// collateral price is low. `None` = USD floor only.
+    pub min_collateral_tokens: Option<TokenAmount>,
+
     /// Maintenance margin factor vs position notional (fraction in FP(1e18)).
     ///
     /// Interpretation:
@@ -31,6 +46,33 @@ pub struct RiskCfg {
 
     /// Fixed-point scale used by `min_collateral_factor_fp`.
     pub factor_scale: U256,
+
+    /// Behavior when a partial decrease leaves dust.
+    pub dust_policy: DustPolicy,
+
+    /// Max simultaneous positions per account. Zero = unlimited.
+    pub max_positions_per_account: u32,
+
+    /// Cap on funding + borrowing taken in one settlement, in bps of the position's
+    /// collateral. Costs above it are not charged and the position is flagged for
+    /// liquidation instead. Zero = no cap.
+    pub max_settlement_cost_bps: u32,
+
+    /// Haircut on collateral value in the pre/post collateral sufficiency checks, in bps.
+    ///
+    /// The per-token value lives in `TokenMeta::collateral_haircut_bps`; the executor
+    /// resolves it for the order's collateral token via `with_collateral_haircut_bps`.
+    pub collateral_haircut_bps: u32,
+
+    /// USD(1e30) cost of one unit of keeper gas, used to size execution fees.
+    pub execution_gas_price_usd: Usd,
+
+    /// Gas units an order execution is expected to consume. Zero (or a zero gas
+    /// price) disables the execution fee check.
+    pub execution_gas_units: u64,
+
+    /// Positions older than this must be closed (expiring futures). Zero = no expiry.
+    pub max_position_age_secs: u64,
 }
 
 impl RiskCfg {
@@ -46,6 +88,66 @@ impl RiskCfg {
         )
     }
 
+    /// MVP thresholds with max leverage set directly (e.g. 20 => factor 1/20 = 5%).
+    pub fn with_max_leverage(max_x: u32) -> Self {
+        assert!(max_x > 0, "max_leverage_x must be > 0");
+
+        let mvp = Self::mvp();
+        Self {
+            min_collateral_factor_fp: mvp.factor_scale / U256::from(max_x),
+            ..mvp
+        }
+    }
+
+    /// Max leverage implied by `min_collateral_factor_fp`: floor(factor_scale / factor).
+    ///
+    /// Inverts `with_max_leverage` exactly. A zero factor means no leverage limit
+    /// (`u32::MAX`).
+    pub fn max_leverage_x(&self) -> u32 {
+        if self.min_collateral_factor_fp.is_zero() {
+            return u32::MAX;
+        }
+        let x = self.factor_scale / self.min_collateral_factor_fp;
+        if x > U256::from(u32::MAX) {
+            u32::MAX
+        } else {
+            x.as_u32()
+        }
+    }
+
+    /// Keeper gas reimbursement an order must prepay, in USD(1e30):
+    /// `execution_gas_price_usd * execution_gas_units`.
+    ///
+    /// Liquidations are keeper-initiated and carry no user-funded execution fee.
+    pub fn execution_fee_required(&self, order_type: OrderType) -> Result<Usd, String> {
+        match order_type {
+            OrderType::Liquidation => Ok(U256::zero()),
+            OrderType::Increase | OrderType::Decrease => self
+                .execution_gas_price_usd
+                .checked_mul(U256::from(self.execution_gas_units))
+                .ok_or_else(|| "execution_fee_overflow".into()),
+        }
+    }
+
+    /// Reject configs the engine cannot apply.
+    ///
+    /// `max_settlement_cost_bps` above 100% would let a settlement take more than
+    /// the position's collateral.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_settlement_cost_bps > BPS_DENOM {
+            return Err("invalid_max_settlement_cost_bps".into());
+        }
+        Ok(())
+    }
+
+    /// Same config with the collateral haircut set (capped at 100%).
+    pub fn with_collateral_haircut_bps(self, bps: u32) -> Self {
+        Self {
+            collateral_haircut_bps: bps.min(BPS_DENOM),
+            ..self
+        }
+    }
+
     /// Helper constructor: provide human-readable USD thresholds (no scale),
     /// and a max leverage which is converted to a maintenance factor.
     pub fn with_max_leverage_and_thresholds(
@@ -55,7 +157,7 @@ impl RiskCfg {
     ) -> Self {
         assert!(max_leverage_x > 0, "max_leverage_x must be > 0");
 
-        let scale_fp = fp_scale();
+        let scale_fp = fp::SCALE;
 
         // min_collateral_factor_fp = 1 / maxLeverage in FP(1e18).
         // For 50x: 1e18 / 50 = 2e16 (2%).
@@ -64,8 +166,16 @@ impl RiskCfg {
         Self {
             min_position_size_usd: U256::from(dust_usd) * usd_scale(),
             min_collateral_usd: U256::from(min_collateral_usd) * usd_scale(),
+            min_collateral_tokens: None,
             min_collateral_factor_fp,
             factor_scale: scale_fp,
+            dust_policy: DustPolicy::ForceClose,
+            max_positions_per_account: 0,
+            max_settlement_cost_bps: 0,
+            collateral_haircut_bps: 0,
+            execution_gas_price_usd: U256::zero(),
+            execution_gas_units: 0,
+            max_position_age_secs: 0,
         }
     }
 }
@@ -75,3 +185,23 @@ impl Default for RiskCfg {
         Self::mvp()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn max_leverage_round_trips_through_factor() {
+        let cfg = RiskCfg::with_max_leverage(20);
+        assert_eq!(cfg.min_collateral_factor_fp, fp::SCALE / 20);
+        assert_eq!(cfg.max_leverage_x(), 20);
+        // Thresholds stay at the MVP defaults.
+        assert_eq!(cfg.min_collateral_usd, RiskCfg::mvp().min_collateral_usd);
+
+        // Factors that do not divide the scale evenly still invert exactly.
+        for x in [1, 3, 7, 50, 125, 1_000] {
+            assert_eq!(RiskCfg::with_max_leverage(x).max_leverage_x(), x);
+        }
+        assert_eq!(RiskCfg::mvp().max_leverage_x(), 50);
+    }
+}
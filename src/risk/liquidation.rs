@@ -4,9 +4,11 @@ use crate::math;
 use crate::math::pnl;
 use crate::math::rounding::{Rounding, div_round};
 use crate::risk::RiskCfg;
+use crate::risk::validation::maintenance_margin_usd;
+use crate::services::funding::FundingRateModel;
 use crate::services::{borrowing, funding};
 use crate::state::{MarketState, Position};
-use crate::types::{OraclePrices, Side, SignedU256, Timestamp};
+use crate::types::{OraclePrices, Side, SignedU256, Timestamp, TokenAmount};
 
 /// Fee config for liquidation preview.
 #[derive(Clone, Copy, Debug)]
@@ -17,6 +19,31 @@ pub struct LiquidationFeeCfg {
     pub liquidation_fee_bps: u32,
 }
 
+/// Borrowing/funding costs accrued since the position's last settlement.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AccruedCosts {
+    pub borrowing_fee_usd: U256,
+    /// + => user pays, - => user receives.
+    pub funding_fee_usd: SignedU256,
+}
+
+impl AccruedCosts {
+    /// Costs the position would settle if indices were advanced to `now`,
+    /// with funding priced by `funding_model` (the market's
+    /// `FundingService::rate_model`).
+    pub fn preview(
+        funding_model: &dyn FundingRateModel,
+        market: &MarketState,
+        pos: &Position,
+        now: Timestamp,
+    ) -> Result<Self, String> {
+        Ok(Self {
+            borrowing_fee_usd: borrowing::preview_borrowing_fee_usd(market, pos, now)?,
+            funding_fee_usd: funding::preview_funding_fee_usd(funding_model, market, pos, now)?,
+        })
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LiquidationPreview {
     pub collateral_value_usd: U256,
@@ -25,6 +52,7 @@ pub struct LiquidationPreview {
     pub borrowing_fee_usd: U256,
     pub funding_fee_usd: SignedU256, // preview delta; included as positive-only cost
     pub close_fees_usd: U256,        // position + liquidation fees (USD)
+    pub unpaid_cost_usd: U256,       // settlement debt carried on the position
     pub equity_usd: SignedU256,      // final equity (signed)
     pub required_usd: U256,
     pub is_liquidatable: bool,
@@ -42,15 +70,7 @@ fn collateral_value_usd(pos: &Position, prices: &OraclePrices) -> Result<U256, S
 
 /// required_usd = max(min_collateral_usd, size_usd * min_collateral_factor_fp / factor_scale)
 pub fn required_collateral_usd(pos: &Position, risk: RiskCfg) -> Result<U256, String> {
-    if risk.factor_scale.is_zero() {
-        return Err("invalid_factor_scale".into());
-    }
-    let required_by_leverage = pos
-        .size_usd
-        .checked_mul(risk.min_collateral_factor_fp)
-        .ok_or("required_by_leverage_mul_overflow")?
-        / risk.factor_scale;
-
+    let required_by_leverage = maintenance_margin_usd(pos, risk)?;
     Ok(required_by_leverage.max(risk.min_collateral_usd))
 }
 
@@ -75,14 +95,14 @@ fn funding_cost_only(f: SignedU256) -> U256 {
 
 /// Main predicate:
 /// - computes equity at conservative oracle mark (your pnl::total_position_pnl_usd already uses min/max)
-/// - subtracts preview borrowing/funding costs
-/// - subtracts close fees
+/// - subtracts accrued borrowing/funding costs (see `AccruedCosts::preview`)
+/// - subtracts close fees and settlement debt carried on the position
 /// - includes negative-only price impact (if provided)
+/// - a position flagged by a capped settlement (`needs_liquidation`) is always liquidatable
 pub fn is_liquidatable_by_margin(
-    market: &MarketState,
     pos: &Position,
     prices: &OraclePrices,
-    now: Timestamp,
+    accrued: AccruedCosts,
     risk: RiskCfg,
     fee_cfg: LiquidationFeeCfg,
     price_impact_usd_on_close: SignedU256,
@@ -93,8 +113,8 @@ pub fn is_liquidatable_by_margin(
     let collateral_usd = collateral_value_usd(pos, prices)?;
     let required = required_collateral_usd(pos, risk)?;
 
-    let borrowing_fee = borrowing::preview_borrowing_fee_usd(market, pos, now)?;
-    let funding_fee = funding::preview_funding_fee_usd(market, pos, now)?;
+    let borrowing_fee = accrued.borrowing_fee_usd;
+    let funding_fee = accrued.funding_fee_usd;
 
     let close_fees = close_fees_usd(pos.size_usd, fee_cfg);
 
@@ -105,7 +125,7 @@ pub fn is_liquidatable_by_margin(
     let impact_usd = negative_only(price_impact_usd_on_close);
 
     // Equity:
-    // equity = collateral + pnl + impact - borrowing - close_fees - funding_cost
+    // equity = collateral + pnl + impact - borrowing - close_fees - funding_cost - unpaid
     let mut equity = SignedU256::pos(collateral_usd);
     equity = math::signed_add(equity, pnl_usd);
     equity = math::signed_add(equity, impact_usd);
@@ -117,8 +137,9 @@ pub fn is_liquidatable_by_margin(
     if !funding_cost.is_zero() {
         equity = math::signed_sub(equity, SignedU256::pos(funding_cost));
     }
+    equity = math::signed_sub(equity, SignedU256::pos(pos.unpaid_cost_usd));
 
-    let is_liq = if equity.is_negative {
+    let is_liq = if pos.needs_liquidation || equity.is_negative {
         true
     } else {
         equity.mag < required
@@ -130,6 +151,7 @@ pub fn is_liquidatable_by_margin(
         borrowing_fee_usd: borrowing_fee,
         funding_fee_usd: funding_fee,
         close_fees_usd: close_fees,
+        unpaid_cost_usd: pos.unpaid_cost_usd,
         equity_usd: equity,
         required_usd: required,
         is_liquidatable: is_liq,
@@ -139,7 +161,7 @@ pub fn is_liquidatable_by_margin(
 /// Calculate liquidation price (USD(1e30) per 1 atom of index token).
 ///
 /// IMPORTANT (MVP/conservative):
-/// - uses costs preview at `now`
+/// - uses the `accrued` costs (typically `AccruedCosts::preview` at `now`)
 /// - ignores positive funding rewards
 /// - ignores positive price impact
 /// - uses only close fees (position + liquidation)
@@ -148,7 +170,7 @@ pub fn is_liquidatable_by_margin(
 /// Let:
 ///   C = collateral_value_usd
 ///   R = required_usd
-///   K = borrowing_fee + close_fees + funding_cost + negative_price_impact_cost
+///   K = borrowing_fee + close_fees + funding_cost + negative_price_impact_cost + unpaid_cost
 /// For Long:
 ///   equity = C + (T*P - entry) - K
 ///   liquidate when equity < R
@@ -162,10 +184,9 @@ pub fn is_liquidatable_by_margin(
 ///   => T*P = entry + C - K - R
 ///   => P = (entry + C - K - R) / T  (round DOWN for short)
 pub fn calculate_liquidation_price(
-    market: &MarketState,
     pos: &Position,
     prices: &OraclePrices,
-    now: Timestamp,
+    accrued: AccruedCosts,
     risk: RiskCfg,
     fee_cfg: LiquidationFeeCfg,
     price_impact_usd_on_close: SignedU256,
@@ -176,9 +197,8 @@ pub fn calculate_liquidation_price(
     let c = collateral_value_usd(pos, prices)?;
     let r = required_collateral_usd(pos, risk)?;
 
-    let borrowing_fee = borrowing::preview_borrowing_fee_usd(market, pos, now)?;
-    let funding_fee = funding::preview_funding_fee_usd(market, pos, now)?;
-    let funding_cost = funding_cost_only(funding_fee);
+    let borrowing_fee = accrued.borrowing_fee_usd;
+    let funding_cost = funding_cost_only(accrued.funding_fee_usd);
 
     let close_fees = close_fees_usd(pos.size_usd, fee_cfg);
     // negative-only impact cost in USD
@@ -191,7 +211,67 @@ pub fn calculate_liquidation_price(
     let k = borrowing_fee
         .saturating_add(funding_cost)
         .saturating_add(close_fees)
-        .saturating_add(impact_cost);
+        .saturating_add(impact_cost)
+        .saturating_add(pos.unpaid_cost_usd);
+
+    solve_liquidation_price(pos, c, r, k)
+}
+
+/// Liquidation price for a position given already-accrued costs
+/// (USD(1e30) per 1 atom of index token).
+///
+/// Same boundary as `calculate_liquidation_price`, with
+/// K = accrued_fees_usd + funding cost (funding rewards are ignored, like in
+/// `is_liquidatable_by_margin`). Collateral is valued at `collateral_price_min`.
+///
+/// Long: below entry. Short: above entry.
+pub fn liquidation_price(
+    pos: &Position,
+    accrued_fees_usd: U256,
+    accrued_funding_usd: SignedU256,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Result<U256, String> {
+    if pos.size_usd.is_zero() || pos.size_tokens.is_zero() {
+        return Err("position_empty".into());
+    }
+    let c = collateral_value_usd(pos, prices)?;
+    let r = required_collateral_usd(pos, risk)?;
+    let k = accrued_fees_usd.saturating_add(funding_cost_only(accrued_funding_usd));
+
+    solve_liquidation_price(pos, c, r, k)
+}
+
+/// Collateral tokens to add so that `pos` clears both the min-collateral and
+/// the leverage requirement (`required_collateral_usd`) at current prices.
+///
+/// equity = C + pnl_usd - pending_fees_usd; shortfall = R - equity, converted
+/// at `collateral_price_min` and rounded up so the deposit is always enough.
+/// Zero if the position is already healthy (equity >= R).
+pub fn collateral_needed_to_rescue(
+    pos: &Position,
+    prices: &OraclePrices,
+    pnl_usd: SignedU256,
+    pending_fees_usd: U256,
+    risk: RiskCfg,
+) -> Result<TokenAmount, String> {
+    let c = collateral_value_usd(pos, prices)?;
+    let r = required_collateral_usd(pos, risk)?;
+
+    let equity = math::signed_sub(
+        math::signed_add(SignedU256::pos(c), pnl_usd),
+        SignedU256::pos(pending_fees_usd),
+    );
+    let shortfall = math::signed_sub(SignedU256::pos(r), equity);
+    if shortfall.is_negative || shortfall.is_zero() {
+        return Ok(U256::zero());
+    }
+
+    div_round(shortfall.mag, prices.collateral_price_min, Rounding::Up)
+}
+
+/// Solve `C + pnl(P) - K = R` for P (see `calculate_liquidation_price`).
+fn solve_liquidation_price(pos: &Position, c: U256, r: U256, k: U256) -> Result<U256, String> {
     let entry = pos.size_usd;
     let t = pos.size_tokens;
 
@@ -232,10 +312,12 @@ pub fn calculate_liquidation_price(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::services::funding::FixedRateFundingModel;
     use crate::state::{MarketState, Position, PositionKey};
     use crate::types::{AccountId, AssetId, MarketId, Side};
     use crate::types::{OraclePrices, SignedU256};
     use primitive_types::U256;
+    use std::collections::HashMap;
 
     fn usd(x: u64) -> U256 {
         U256::from(x) * U256::exp10(30)
@@ -252,9 +334,15 @@ mod tests {
             size_usd: usd(200),                // entry notional $200
             size_tokens: U256::from(2),        // 2 atoms/tokens of index
             collateral_amount: U256::from(50), // 50 collateral tokens/atoms
+            collateral_balances: HashMap::new(),
             pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
             funding_index: SignedU256::zero(),
+            funding_updated_at: 0,
+            pending_funding_usd: SignedU256::zero(),
             borrowing_index: U256::zero(),
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
             opened_at: 1,
             last_updated_at: 1,
         }
@@ -299,10 +387,9 @@ mod tests {
         };
 
         let p = calculate_liquidation_price(
-            &market,
             &pos,
             &prices,
-            100,
+            AccruedCosts::preview(&FixedRateFundingModel, &market, &pos, 100).unwrap(),
             risk,
             fee_cfg,
             SignedU256::zero(),
@@ -337,10 +424,9 @@ mod tests {
         };
 
         let prev = is_liquidatable_by_margin(
-            &market,
             &pos,
             &prices,
-            100,
+            AccruedCosts::preview(&FixedRateFundingModel, &market, &pos, 100).unwrap(),
             risk,
             fee_cfg,
             SignedU256::zero(),
@@ -375,10 +461,9 @@ mod tests {
         };
 
         let prev = is_liquidatable_by_margin(
-            &market,
             &pos,
             &prices,
-            100,
+            AccruedCosts::preview(&FixedRateFundingModel, &market, &pos, 100).unwrap(),
             risk,
             fee_cfg,
             SignedU256::zero(),
@@ -389,4 +474,126 @@ mod tests {
         assert!(!prev.equity_usd.is_negative);
         assert!(prev.equity_usd.mag >= prev.required_usd);
     }
+
+    fn risk_10x() -> RiskCfg {
+        let mut risk = RiskCfg::default();
+        risk.factor_scale = U256::exp10(18);
+        risk.min_collateral_factor_fp = risk.factor_scale / U256::from(10u64); // 10%
+        risk.min_collateral_usd = usd(5);
+        risk
+    }
+
+    fn usd_prices(index: u64) -> OraclePrices {
+        OraclePrices {
+            index_price_min: usd(index),
+            index_price_max: usd(index),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        }
+    }
+
+    #[test]
+    fn liquidation_price_long_below_entry_and_moves_down_with_collateral() {
+        // entry $100/atom, C=$50, R=$20, K=$4 fees + $2 funding
+        // => P = (200 + 20 + 6 - 50) / 2 = $88
+        let mut pos = base_pos(Side::Long);
+        let p = liquidation_price(
+            &pos,
+            usd(4),
+            SignedU256::pos(usd(2)),
+            &usd_prices(100),
+            risk_10x(),
+        )
+        .unwrap();
+        assert_eq!(p, usd(88));
+        assert!(p < usd(100));
+
+        pos.collateral_amount += U256::from(20);
+        let p_more = liquidation_price(
+            &pos,
+            usd(4),
+            SignedU256::pos(usd(2)),
+            &usd_prices(100),
+            risk_10x(),
+        )
+        .unwrap();
+        assert!(p_more < p);
+    }
+
+    #[test]
+    fn liquidation_price_short_above_entry_and_moves_up_with_collateral() {
+        // P = (200 + 50 - 6 - 20) / 2 = $112
+        let mut pos = base_pos(Side::Short);
+        let p = liquidation_price(
+            &pos,
+            usd(4),
+            SignedU256::pos(usd(2)),
+            &usd_prices(100),
+            risk_10x(),
+        )
+        .unwrap();
+        assert_eq!(p, usd(112));
+        assert!(p > usd(100));
+
+        pos.collateral_amount += U256::from(20);
+        let p_more = liquidation_price(
+            &pos,
+            usd(4),
+            SignedU256::pos(usd(2)),
+            &usd_prices(100),
+            risk_10x(),
+        )
+        .unwrap();
+        assert!(p_more > p);
+    }
+
+    #[test]
+    fn rescue_collateral_restores_health_just_and_well_below_threshold() {
+        // C=$50, R=$20 (10% of $200), $1 pending fees.
+        let pos = base_pos(Side::Long);
+        let risk = risk_10x();
+        let fees = usd(1);
+        let pnl_at = |index| pnl::total_position_pnl_usd(&pos, &usd_prices(index)).unwrap();
+
+        // $86: pnl -$28 => equity $21, healthy.
+        assert_eq!(
+            collateral_needed_to_rescue(&pos, &usd_prices(86), pnl_at(86), fees, risk).unwrap(),
+            U256::zero()
+        );
+
+        // $85: pnl -$30 => equity $19, one $1 token short.
+        let just_below =
+            collateral_needed_to_rescue(&pos, &usd_prices(85), pnl_at(85), fees, risk).unwrap();
+        assert_eq!(just_below, U256::from(1));
+
+        // $70: pnl -$60 => equity -$11, needs $31.
+        let well_below =
+            collateral_needed_to_rescue(&pos, &usd_prices(70), pnl_at(70), fees, risk).unwrap();
+        assert_eq!(well_below, U256::from(31));
+
+        // Depositing the suggested amount clears the threshold exactly.
+        let mut rescued = pos.clone();
+        rescued.collateral_amount += well_below;
+        assert_eq!(
+            collateral_needed_to_rescue(&rescued, &usd_prices(70), pnl_at(70), fees, risk).unwrap(),
+            U256::zero()
+        );
+
+        // A tiny position is bound by min_collateral_usd ($5), not leverage.
+        let mut small = base_pos(Side::Long);
+        small.size_usd = usd(20);
+        small.size_tokens = U256::from(1);
+        small.collateral_amount = U256::from(3);
+        assert_eq!(
+            collateral_needed_to_rescue(
+                &small,
+                &usd_prices(20),
+                SignedU256::zero(),
+                U256::zero(),
+                risk
+            )
+            .unwrap(),
+            U256::from(2)
+        );
+    }
 }
@@ -1,4 +1,5 @@
 pub mod config;
 pub mod liquidation;
+pub mod solvency;
 pub mod validation;
-pub use config::RiskCfg;
+pub use config::{BPS_DENOM, DustPolicy, RiskCfg};
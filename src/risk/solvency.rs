@@ -0,0 +1,394 @@
+// src/risk/solvency.rs
+
+use std::collections::HashMap;
+
+use primitive_types::U256;
+
+use crate::math;
+use crate::math::pnl::total_position_pnl_usd;
+use crate::risk::RiskCfg;
+use crate::risk::liquidation::{
+    AccruedCosts, LiquidationFeeCfg, is_liquidatable_by_margin, required_collateral_usd,
+};
+use crate::services::funding::FundingRateModel;
+use crate::state::{Claimables, Position, PositionStore, State};
+use crate::types::{
+    AccountId, AssetId, MarketId, OraclePrices, Side, SignedU256, Timestamp, TokenAmount, Usd,
+};
+
+/// Total amount the protocol owes in `asset`, in USD(1e30).
+///
+/// liabilities = sum(positive PnL of positions collateralized in `asset`)
+///             + (funding + fee claimables in `asset`) * `claimable_price`
+///
+/// - Losing positions are NOT netted against winners: their losses are
+///   not owed to anyone until realized.
+/// - `prices_by_market` must contain prices for every market that has a
+///   position in `asset`.
+/// - `claimable_price` is USD(1e30) per atom of `asset`
+///   (pass `collateral_price_max` for a conservative figure).
+pub fn total_liabilities(
+    positions: &PositionStore,
+    claimables: &Claimables,
+    prices_by_market: &HashMap<MarketId, OraclePrices>,
+    asset: AssetId,
+    claimable_price: Usd,
+) -> Result<Usd, String> {
+    let mut total = U256::zero();
+
+    for (key, pos) in positions.iter() {
+        if key.collateral_token != asset {
+            continue;
+        }
+        let prices = prices_by_market
+            .get(&key.market_id)
+            .ok_or("market_prices_not_found")?;
+        let pnl = total_position_pnl_usd(pos, prices)?;
+        if !pnl.is_negative {
+            total = total.checked_add(pnl.mag).ok_or("liabilities_overflow")?;
+        }
+    }
+
+    let claimable_tokens = claimables
+        .funding_entries()
+        .chain(claimables.fee_entries())
+        .filter(|((_, a), _)| *a == asset)
+        .fold(U256::zero(), |acc, (_, amount)| acc.saturating_add(*amount));
+    let claimable_usd = claimable_tokens
+        .checked_mul(claimable_price)
+        .ok_or("liabilities_overflow")?;
+
+    total
+        .checked_add(claimable_usd)
+        .ok_or_else(|| "liabilities_overflow".into())
+}
+
+/// Traders' net directional exposure on `market_id`: long OI - short OI (`size_usd`).
+///
+/// The pool is the counterparty of every position, so it is effectively
+/// short this skew (positive => pool short the index, negative => pool long).
+pub fn pool_skew(positions: &PositionStore, market_id: MarketId) -> SignedU256 {
+    let (long, short) = positions.open_interest_for_market(market_id);
+    math::signed_sub(SignedU256::pos(long), SignedU256::pos(short))
+}
+
+/// Net directional exposure of `account` on `market_id`: its long `size_usd`
+/// minus its short `size_usd`, over every collateral token.
+///
+/// Signed, since an account can be net short (positive => net long).
+pub fn net_exposure(
+    positions: &PositionStore,
+    account: AccountId,
+    market_id: MarketId,
+) -> SignedU256 {
+    let (long, short) = positions
+        .positions_for_account(account)
+        .filter(|p| p.key.market_id == market_id)
+        .fold((U256::zero(), U256::zero()), |(long, short), p| {
+            match p.key.side {
+                Side::Long => (long.saturating_add(p.size_usd), short),
+                Side::Short => (long, short.saturating_add(p.size_usd)),
+            }
+        });
+    math::signed_sub(SignedU256::pos(long), SignedU256::pos(short))
+}
+
+/// `pool_skew` in index atoms at the mid index price, rounded toward zero.
+///
+/// The size of the index position that would hedge the pool's exposure.
+pub fn pool_skew_tokens(
+    positions: &PositionStore,
+    market_id: MarketId,
+    prices: &OraclePrices,
+) -> Result<SignedU256, String> {
+    let mid = prices
+        .index_price_min
+        .saturating_add(prices.index_price_max)
+        / 2;
+    if mid.is_zero() {
+        return Err("invalid_index_price".into());
+    }
+    let skew = pool_skew(positions, market_id);
+    Ok(SignedU256 {
+        is_negative: skew.is_negative,
+        mag: skew.mag / mid,
+    })
+}
+
+/// Check the engine-wide invariants of `state`, collecting every violation.
+///
+/// Each violation is `"<code>: <context>"`; the list is sorted. Checks:
+///  - `oi_mismatch`: market OI differs from the sum of its positions' sizes;
+///  - `pool_over_reserved`: queued withdrawals exceed liquidity (`PoolBalances::audit`);
+///  - `claimables_unbacked`: claimables in an asset exceed the pool's liquidity
+///    plus fees in that asset (coarse: the pool is the only tracked backing);
+///  - `position_size_inconsistent`: exactly one of `size_usd` / `size_tokens` is zero;
+///  - `position_unpriced`: no prices for the position's market;
+///  - `position_below_maintenance_not_liquidatable`: collateral + PnL - accrued
+///    costs is below `required_collateral_usd` but the liquidation predicate
+///    does not flag it (evaluated without fees or impact);
+///  - `liquidation_check_failed`: the predicate itself errors.
+///
+/// `accrue_to`: with `Some(now)`, funding (priced by `funding_model`, the
+/// service's `rate_model`) and borrowing accrued up to `now` are previewed
+/// for every position; with `None`, positions are taken as settled to the
+/// market indices and nothing is previewed.
+///
+/// Unsigned amounts cannot go negative, so non-negativity is structural.
+pub fn check_invariants(
+    state: &State,
+    prices_by_market: &HashMap<MarketId, OraclePrices>,
+    risk: RiskCfg,
+    funding_model: &dyn FundingRateModel,
+    accrue_to: Option<Timestamp>,
+) -> Result<(), Vec<String>> {
+    let mut violations = Vec::new();
+
+    for (market_id, market) in state.markets.iter() {
+        let (long, short) = state.positions.open_interest_for_market(*market_id);
+        for (side, expected, actual) in [
+            (Side::Long, long, market.oi_long_usd),
+            (Side::Short, short, market.oi_short_usd),
+        ] {
+            if expected != actual {
+                violations.push(format!("oi_mismatch: market {} {:?}", market_id.0, side));
+            }
+        }
+    }
+
+    for (market_id, asset, _) in state.pool_balances.audit() {
+        violations.push(format!(
+            "pool_over_reserved: market {} asset {}",
+            market_id.0, asset.0
+        ));
+    }
+
+    let mut backing: HashMap<AssetId, TokenAmount> = HashMap::new();
+    for ((_, asset), amount) in state
+        .pool_balances
+        .liquidity
+        .iter()
+        .chain(state.pool_balances.fees.iter())
+    {
+        let entry = backing.entry(*asset).or_insert(U256::zero());
+        *entry = entry.saturating_add(*amount);
+    }
+    let mut owed: HashMap<AssetId, TokenAmount> = HashMap::new();
+    for ((_, asset), amount) in state
+        .claimables
+        .funding_entries()
+        .chain(state.claimables.fee_entries())
+    {
+        let entry = owed.entry(*asset).or_insert(U256::zero());
+        *entry = entry.saturating_add(*amount);
+    }
+    for (asset, amount) in owed {
+        if amount > backing.get(&asset).copied().unwrap_or_default() {
+            violations.push(format!("claimables_unbacked: asset {}", asset.0));
+        }
+    }
+
+    for (key, pos) in state.positions.iter() {
+        if pos.size_usd.is_zero() != pos.size_tokens.is_zero() {
+            violations.push(format!("position_size_inconsistent: {:?}", key));
+            continue;
+        }
+        if pos.size_usd.is_zero() {
+            continue;
+        }
+        let (Some(prices), Some(market)) = (
+            prices_by_market.get(&key.market_id),
+            state.markets.get(&key.market_id),
+        ) else {
+            violations.push(format!("position_unpriced: {:?}", key));
+            continue;
+        };
+        let accrued = match accrue_to {
+            Some(now) => match AccruedCosts::preview(funding_model, market, pos, now) {
+                Ok(accrued) => accrued,
+                Err(e) => {
+                    violations.push(format!("liquidation_check_failed: {:?} {}", key, e));
+                    continue;
+                }
+            },
+            None => AccruedCosts::default(),
+        };
+        let below = match below_maintenance(pos, prices, accrued, risk) {
+            Ok(below) => below,
+            Err(e) => {
+                violations.push(format!("liquidation_check_failed: {:?} {}", key, e));
+                continue;
+            }
+        };
+        let no_fees = LiquidationFeeCfg {
+            close_position_fee_bps: 0,
+            liquidation_fee_bps: 0,
+        };
+        match is_liquidatable_by_margin(pos, prices, accrued, risk, no_fees, SignedU256::zero()) {
+            Ok(preview) if below && !preview.is_liquidatable => violations.push(format!(
+                "position_below_maintenance_not_liquidatable: {:?}",
+                key
+            )),
+            Ok(_) => {}
+            Err(e) => violations.push(format!("liquidation_check_failed: {:?} {}", key, e)),
+        }
+    }
+
+    if violations.is_empty() {
+        return Ok(());
+    }
+    violations.sort();
+    Err(violations)
+}
+
+/// collateral (at `collateral_price_min`) + PnL - accrued costs <
+/// `required_collateral_usd`. Funding received is not counted, as in the
+/// liquidation predicate.
+fn below_maintenance(
+    pos: &Position,
+    prices: &OraclePrices,
+    accrued: AccruedCosts,
+    risk: RiskCfg,
+) -> Result<bool, String> {
+    let collateral_usd = pos
+        .collateral_amount
+        .checked_mul(prices.collateral_price_min)
+        .ok_or("collateral_value_overflow")?;
+    let mut equity = math::signed_add(
+        SignedU256::pos(collateral_usd),
+        total_position_pnl_usd(pos, prices)?,
+    );
+    equity = math::signed_sub(equity, SignedU256::pos(accrued.borrowing_fee_usd));
+    if !accrued.funding_fee_usd.is_negative {
+        equity = math::signed_sub(equity, accrued.funding_fee_usd);
+    }
+    Ok(equity.is_negative || equity.mag < required_collateral_usd(pos, risk)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MarketState, Position, PositionKey};
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    fn prices(index: u64) -> OraclePrices {
+        OraclePrices {
+            index_price_min: usd(index),
+            index_price_max: usd(index),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        }
+    }
+
+    fn pos(account: u8, side: Side, collateral_token: AssetId) -> Position {
+        let key = PositionKey {
+            account: AccountId([account; 32]),
+            market_id: MarketId(1),
+            collateral_token,
+            side,
+        };
+        // 10 atoms entered at $100 each.
+        Position::open(
+            key,
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(10),
+            U256::from(500),
+            1,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn only_positive_pnl_and_claimables_are_counted() {
+        let usdc = AssetId(10);
+        let other = AssetId(20);
+
+        let mut positions = PositionStore::new();
+        positions.upsert(pos(1, Side::Long, usdc)); // +$200 at $120
+        positions.upsert(pos(2, Side::Short, usdc)); // -$200, ignored
+        positions.upsert(pos(3, Side::Long, other)); // different asset, ignored
+
+        let mut claimables = Claimables::default();
+        claimables.add_funding(AccountId([1; 32]), usdc, U256::from(30));
+        claimables.add_fee(AccountId([2; 32]), usdc, U256::from(20));
+        claimables.add_funding(AccountId([1; 32]), other, U256::from(1_000));
+
+        let mut prices_by_market = HashMap::new();
+        prices_by_market.insert(MarketId(1), prices(120));
+
+        let total =
+            total_liabilities(&positions, &claimables, &prices_by_market, usdc, usd(1)).unwrap();
+        assert_eq!(total, usd(200) + usd(50));
+
+        assert_eq!(
+            total_liabilities(&positions, &claimables, &HashMap::new(), usdc, usd(1)).unwrap_err(),
+            "market_prices_not_found"
+        );
+    }
+
+    #[test]
+    fn skew_is_long_minus_short_oi_of_the_market() {
+        let usdc = AssetId(10);
+        let mut positions = PositionStore::new();
+        positions.upsert(pos(1, Side::Long, usdc)); // $1_000 long
+        positions.upsert(pos(2, Side::Long, usdc)); // $1_000 long
+        positions.upsert(pos(3, Side::Short, usdc)); // $1_000 short
+        let mut other_market = pos(4, Side::Short, usdc);
+        other_market.key.market_id = MarketId(2);
+        positions.upsert(other_market);
+
+        // Net long $1_000: the pool is short $1_000 of the index.
+        assert_eq!(
+            pool_skew(&positions, MarketId(1)),
+            SignedU256::pos(usd(1_000))
+        );
+        // 1_000 / 125 = 8 atoms.
+        assert_eq!(
+            pool_skew_tokens(&positions, MarketId(1), &prices(125)).unwrap(),
+            SignedU256::pos(U256::from(8))
+        );
+
+        // Net short on market 2.
+        assert_eq!(
+            pool_skew(&positions, MarketId(2)),
+            SignedU256::neg(usd(1_000))
+        );
+        assert_eq!(
+            pool_skew_tokens(&positions, MarketId(2), &prices(300)).unwrap(),
+            SignedU256::neg(U256::from(3))
+        );
+        assert!(pool_skew(&positions, MarketId(3)).is_zero());
+    }
+
+    #[test]
+    fn net_exposure_nets_an_accounts_long_and_short() {
+        let usdc = AssetId(10);
+        let account = AccountId([1; 32]);
+        let mut positions = PositionStore::new();
+        // $1_000 long in USDC + $1_000 long in another token vs a $1_500 short.
+        positions.upsert(pos(1, Side::Long, usdc));
+        positions.upsert(pos(1, Side::Long, AssetId(20)));
+        let mut short = pos(1, Side::Short, usdc);
+        short.size_usd = usd(1_500);
+        positions.upsert(short);
+        // Other accounts and markets do not count.
+        positions.upsert(pos(2, Side::Short, usdc));
+        let mut elsewhere = pos(1, Side::Short, usdc);
+        elsewhere.key.market_id = MarketId(2);
+        positions.upsert(elsewhere);
+
+        assert_eq!(
+            net_exposure(&positions, account, MarketId(1)),
+            SignedU256::pos(usd(500))
+        );
+        assert_eq!(
+            net_exposure(&positions, account, MarketId(2)),
+            SignedU256::neg(usd(1_000))
+        );
+        assert!(net_exposure(&positions, AccountId([9; 32]), MarketId(1)).is_zero());
+    }
+}
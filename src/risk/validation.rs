@@ -1,10 +1,274 @@
 use primitive_types::U256;
 
-use crate::risk::RiskCfg;
-use crate::state::Position;
-use crate::types::{OraclePrices, Order};
+use crate::risk::{BPS_DENOM, DustPolicy, RiskCfg};
+use crate::state::{MarketState, Position, PositionKey, PositionStore};
+use crate::types::{ExecutionType, OraclePrices, Order, OrderType, Side, Timestamp};
 use crate::types::{TokenAmount, Usd};
 
+/// Why `validate_order` rejected an order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ValidationError {
+    /// Malformed order or trigger not satisfied (same strings as on submit / execute).
+    InvalidOrder(String),
+    /// `now < valid_from`.
+    NotActiveYet,
+    /// `now > valid_until`.
+    Expired,
+    /// Market does not accept orders.
+    MarketPaused,
+    /// Decrease / liquidation without a position.
+    PositionNotFound,
+    /// Position key does not match the order (account / market / token / side).
+    PositionMismatch,
+    /// Resulting position would be below `min_position_size_usd`.
+    BelowMinSize,
+    /// Resulting position would exceed the max leverage implied by `RiskCfg`.
+    LeverageTooHigh,
+    /// Other risk / math failure (decrease precheck, overflow).
+    Risk(String),
+}
+
+impl From<ValidationError> for String {
+    fn from(e: ValidationError) -> Self {
+        match e {
+            ValidationError::InvalidOrder(s) | ValidationError::Risk(s) => s,
+            ValidationError::NotActiveYet => "order_not_active_yet".into(),
+            ValidationError::Expired => "order_expired".into(),
+            ValidationError::MarketPaused => "market_paused".into(),
+            ValidationError::PositionNotFound => "position_not_found".into(),
+            ValidationError::PositionMismatch => "position_order_mismatch".into(),
+            ValidationError::BelowMinSize => "position_below_min_size".into(),
+            ValidationError::LeverageTooHigh => "leverage_too_high".into(),
+        }
+    }
+}
+
+/// Run every precondition for `order` in one call, dispatching on `order.order_type`:
+/// shape, timing, trigger, position matching, and
+/// - Increase: market pause, min size + max leverage of the resulting position;
+/// - Decrease / Liquidation: `precheck_decrease_and_withdraw`.
+///
+/// Read-only; does not include impact / fees (execution can still fail on those).
+pub fn validate_order(
+    order: &Order,
+    pos: Option<&Position>,
+    market: &MarketState,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+    now: Timestamp,
+) -> Result<(), ValidationError> {
+    validate_order_shape(order).map_err(ValidationError::InvalidOrder)?;
+    check_order_timing(order, now)?;
+    // A paused market still lets users exit and keepers liquidate.
+    if market.paused && order.order_type == OrderType::Increase {
+        return Err(ValidationError::MarketPaused);
+    }
+    if market.id != order.market_id {
+        return Err(ValidationError::PositionMismatch);
+    }
+    check_order_trigger(order, prices).map_err(ValidationError::InvalidOrder)?;
+    if let Some(pos) = pos {
+        check_position_matches(order, pos)?;
+    }
+
+    match order.order_type {
+        OrderType::Increase => check_increase_size_and_leverage(order, pos, prices, risk),
+        OrderType::Decrease | OrderType::Liquidation => {
+            let pos = pos.ok_or(ValidationError::PositionNotFound)?;
+            precheck_decrease_and_withdraw(pos, order, prices, risk)
+                .map(|_| ())
+                .map_err(ValidationError::Risk)
+        }
+    }
+}
+
+/// `valid_from <= now <= valid_until`.
+pub fn check_order_timing(order: &Order, now: Timestamp) -> Result<(), ValidationError> {
+    if now < order.valid_from {
+        return Err(ValidationError::NotActiveYet);
+    }
+    if now > order.valid_until {
+        return Err(ValidationError::Expired);
+    }
+    Ok(())
+}
+
+/// The position must be the one the order addresses.
+pub fn check_position_matches(order: &Order, pos: &Position) -> Result<(), ValidationError> {
+    let k = &pos.key;
+    if k.account != order.account
+        || k.market_id != order.market_id
+        || k.collateral_token != order.collateral_token
+        || k.side != order.side
+    {
+        return Err(ValidationError::PositionMismatch);
+    }
+    Ok(())
+}
+
+/// Increase: size and leverage of the position after the order (oracle-based sizing,
+/// same as execution: size_delta = collateral_delta * collateral_price_min * leverage).
+pub fn check_increase_size_and_leverage(
+    order: &Order,
+    pos: Option<&Position>,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Result<(), ValidationError> {
+    let overflow = || ValidationError::Risk("u256_mul_overflow".into());
+    if prices.collateral_price_min.is_zero() {
+        return Err(ValidationError::Risk("invalid_collateral_price_min".into()));
+    }
+
+    let collateral_delta_usd = order
+        .collateral_delta_tokens
+        .checked_mul(prices.collateral_price_min)
+        .ok_or_else(overflow)?;
+    let size_delta_usd = collateral_delta_usd
+        .checked_mul(U256::from(order.target_leverage_x))
+        .ok_or_else(overflow)?;
+    if size_delta_usd.is_zero() {
+        return Err(ValidationError::InvalidOrder(
+            "size_delta_usd_must_be_positive".into(),
+        ));
+    }
+
+    let (size_usd, collateral_tokens) = match pos {
+        Some(p) => (p.size_usd, p.collateral_amount),
+        None => (U256::zero(), U256::zero()),
+    };
+    let next_size_usd = size_usd.checked_add(size_delta_usd).ok_or_else(overflow)?;
+    if next_size_usd < risk.min_position_size_usd {
+        return Err(ValidationError::BelowMinSize);
+    }
+
+    let next_collateral = collateral_tokens
+        .checked_add(order.collateral_delta_tokens)
+        .ok_or_else(overflow)?;
+    if !will_position_collateral_be_sufficient_pre(
+        next_size_usd,
+        next_collateral,
+        U256::zero(),
+        prices,
+        risk,
+    ) {
+        return Err(ValidationError::LeverageTooHigh);
+    }
+    Ok(())
+}
+
+/// The order's prepaid `execution_fee_tokens`, valued at `collateral_price_min`,
+/// must cover `RiskCfg::execution_fee_required` for the configured keeper gas.
+pub fn check_execution_fee(
+    order: &Order,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Result<(), String> {
+    let required = risk.execution_fee_required(order.order_type)?;
+    if required.is_zero() {
+        return Ok(());
+    }
+    let provided = order
+        .execution_fee_tokens
+        .checked_mul(prices.collateral_price_min)
+        .ok_or("execution_fee_overflow")?;
+    if provided < required {
+        return Err("insufficient_execution_fee".into());
+    }
+    Ok(())
+}
+
+/// Static order checks done on submit (time window, trigger / execution type combos).
+pub fn validate_order_shape(order: &Order) -> Result<(), String> {
+    use ExecutionType as Ex;
+    if order.valid_until <= order.valid_from {
+        return Err("invalid_order_time_window".into());
+    }
+    if order.execution_type == Ex::Market && order.trigger_price.is_some() {
+        return Err("market_order_must_not_have_trigger_price".into());
+    }
+    if matches!(
+        order.execution_type,
+        Ex::Limit | Ex::StopLoss | Ex::TakeProfit
+    ) && order.trigger_price.is_none()
+    {
+        return Err("trigger_price_required".into());
+    }
+
+    if order.order_type == OrderType::Increase
+        && matches!(order.execution_type, Ex::StopLoss | Ex::TakeProfit)
+    {
+        return Err("stop_loss_take_profit_only_for_decrease".into());
+    }
+
+    if order.order_type == OrderType::Liquidation {
+        if order.execution_type != Ex::Market {
+            return Err("liquidation_must_be_market".into());
+        }
+        if order.trigger_price.is_some() {
+            return Err("liquidation_must_not_have_trigger_price".into());
+        }
+    }
+
+    match order.order_type {
+        OrderType::Increase => {
+            if order.target_leverage_x == 0 {
+                return Err("target_leverage_must_be_positive".into());
+            }
+        }
+        OrderType::Decrease => {
+            if order.size_delta_usd.is_zero() {
+                return Err("size_delta_usd_must_be_positive_for_decrease".into());
+            }
+        }
+        OrderType::Liquidation => {}
+    }
+    Ok(())
+}
+
+/// Trigger check for Limit / StopLoss / TakeProfit orders against current prices.
+pub fn check_order_trigger(order: &Order, prices: &OraclePrices) -> Result<(), String> {
+    use ExecutionType as Ex;
+
+    if order.execution_type == Ex::Market {
+        return Ok(());
+    }
+
+    let trigger = order
+        .trigger_price
+        .ok_or_else(|| "trigger_price_required".to_string())?;
+
+    // Liquidation is always executed by liquidation flow (no triggers here)
+    if order.order_type == OrderType::Liquidation {
+        return Ok(());
+    }
+
+    let satisfied = match (order.execution_type, order.order_type, order.side) {
+        // -------------------- LIMIT --------------------
+        (Ex::Limit, OrderType::Increase, Side::Long) => prices.index_price_max <= trigger,
+        (Ex::Limit, OrderType::Increase, Side::Short) => prices.index_price_min >= trigger,
+
+        (Ex::Limit, OrderType::Decrease, Side::Long) => prices.index_price_min >= trigger,
+        (Ex::Limit, OrderType::Decrease, Side::Short) => prices.index_price_max <= trigger,
+
+        // -------------------- STOP LOSS (Decrease only) --------------------
+        (Ex::StopLoss, OrderType::Decrease, Side::Long) => prices.index_price_min <= trigger,
+        (Ex::StopLoss, OrderType::Decrease, Side::Short) => prices.index_price_max >= trigger,
+
+        // -------------------- TAKE PROFIT (Decrease only) --------------------
+        (Ex::TakeProfit, OrderType::Decrease, Side::Long) => prices.index_price_min >= trigger,
+        (Ex::TakeProfit, OrderType::Decrease, Side::Short) => prices.index_price_max <= trigger,
+
+        // Others
+        _ => return Err("unsupported_order_execution_type".into()),
+    };
+
+    if satisfied {
+        Ok(())
+    } else {
+        Err("order_not_triggered".into())
+    }
+}
+
 /// Pre-check + normalization for decrease orders (no state mutation).
 ///
 /// Returns:
@@ -60,13 +324,17 @@ pub fn precheck_decrease_and_withdraw(
         withdraw_tokens = pos.collateral_amount;
     }
 
-    // 4) Dust check: remaining size below min => force full close.
+    // 4) Dust check: remaining size below min => force full close or reject,
+    //    depending on `risk.dust_policy`.
     let mut next_size_usd = pos
         .size_usd
         .checked_sub(size_delta_usd)
         .expect("size_delta_usd clamped to <= pos.size_usd");
 
     if !next_size_usd.is_zero() && next_size_usd < risk.min_position_size_usd {
+        if risk.dust_policy == DustPolicy::Reject {
+            return Err("remaining_below_min".into());
+        }
         size_delta_usd = pos.size_usd;
         withdraw_tokens = U256::zero();
         is_full_close = true;
@@ -74,9 +342,13 @@ pub fn precheck_decrease_and_withdraw(
     }
 
     // 5) Conservative pre-check for partial close.
-    // If unsafe with withdraw -> try withdraw=0.
+    // Clamp withdraw to what the size reduction frees (see `partial_close_withdraw_cap`).
+    // If still unsafe with withdraw -> try withdraw=0.
     // If still unsafe -> force full close.
     if !next_size_usd.is_zero() {
+        let cap = partial_close_withdraw_cap(pos, size_delta_usd, next_size_usd, prices, risk)?;
+        withdraw_tokens = withdraw_tokens.min(cap);
+
         let ok_with_withdraw = will_position_collateral_be_sufficient_pre(
             next_size_usd,
             pos.collateral_amount,
@@ -111,11 +383,110 @@ pub fn precheck_decrease_and_withdraw(
     Ok((size_delta_usd, withdraw_tokens, is_full_close))
 }
 
+/// Most collateral a partial close of `size_delta_usd` may withdraw:
+/// the collateral freed in proportion to the size reduction
+/// (`collateral * size_delta / size`, floor) plus whatever the rest holds above
+/// the requirement of the remaining `next_size_usd` (`min_collateral_usd` and
+/// the leverage floor, valued like `effective_collateral_usd`), never leaving
+/// less than `min_collateral_tokens`.
+///
+/// The remaining collateral always passes `will_position_collateral_be_sufficient_pre`
+/// when the position was healthy to begin with, so a larger request is trimmed
+/// instead of being dropped altogether.
+fn partial_close_withdraw_cap(
+    pos: &Position,
+    size_delta_usd: Usd,
+    next_size_usd: Usd,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Result<TokenAmount, String> {
+    let proportional = pos
+        .collateral_amount
+        .checked_mul(size_delta_usd)
+        .ok_or("withdraw_cap_overflow")?
+        / pos.size_usd;
+    let remaining = pos.collateral_amount - proportional;
+
+    let required_usd = next_size_usd
+        .checked_mul(risk.min_collateral_factor_fp)
+        .ok_or("withdraw_cap_overflow")?
+        .checked_div(risk.factor_scale)
+        .ok_or("invalid_factor_scale")?;
+    let required_usd = required_usd.max(risk.min_collateral_usd);
+    let remaining_usd =
+        effective_collateral_usd(remaining, prices, risk).ok_or("withdraw_cap_overflow")?;
+    // Never dip below the token floor either.
+    let above_token_floor = pos
+        .collateral_amount
+        .saturating_sub(risk.min_collateral_tokens.unwrap_or_default());
+    if remaining_usd <= required_usd {
+        return Ok(proportional.min(above_token_floor));
+    }
+
+    // excess tokens = floor(excess_usd / (price_min * (1 - haircut)))
+    let keep_bps = U256::from(BPS_DENOM - risk.collateral_haircut_bps.min(BPS_DENOM));
+    let excess_tokens = (remaining_usd - required_usd)
+        .checked_mul(U256::from(BPS_DENOM))
+        .ok_or("withdraw_cap_overflow")?
+        / prices
+            .collateral_price_min
+            .checked_mul(keep_bps)
+            .ok_or("withdraw_cap_overflow")?;
+    Ok((proportional + excess_tokens.min(remaining)).min(above_token_floor))
+}
+
+/// Whether `collateral_tokens` is below `risk.min_collateral_tokens` (if set).
+fn below_min_collateral_tokens(collateral_tokens: TokenAmount, risk: RiskCfg) -> bool {
+    risk.min_collateral_tokens
+        .is_some_and(|min| collateral_tokens < min)
+}
+
+/// Pre-check for increase orders: opening a NEW position must not exceed
+/// `risk.max_positions_per_account`. Increasing an existing position is always allowed.
+pub fn precheck_increase_position_count(
+    positions: &PositionStore,
+    key: &PositionKey,
+    risk: RiskCfg,
+) -> Result<(), String> {
+    if risk.max_positions_per_account == 0 || positions.get(key).is_some() {
+        return Ok(());
+    }
+    let open = positions.positions_for_account(key.account).count();
+    if open >= risk.max_positions_per_account as usize {
+        return Err("too_many_positions".into());
+    }
+    Ok(())
+}
+
+/// Collateral value used by the sufficiency checks:
+/// `tokens * collateral_price_min * (10_000 - haircut_bps) / 10_000` (floor).
+///
+/// `None` on overflow.
+pub fn effective_collateral_usd(
+    collateral_tokens: TokenAmount,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Option<Usd> {
+    let face = collateral_tokens.checked_mul(prices.collateral_price_min)?;
+    let haircut = risk.collateral_haircut_bps.min(BPS_DENOM);
+    if haircut == 0 {
+        return Some(face);
+    }
+    Some(face.checked_mul(U256::from(BPS_DENOM - haircut))? / U256::from(BPS_DENOM))
+}
+
+/// Whether `pos` has outlived `risk.max_position_age_secs` and must be force-closed.
+pub fn is_position_expired(pos: &Position, now: Timestamp, risk: RiskCfg) -> bool {
+    risk.max_position_age_secs > 0 && pos.age_secs(now) > risk.max_position_age_secs
+}
+
 /// Conservative "willPositionCollateralBeSufficient" PRE-check.
 ///
-/// remainingCollateralUsd = (collateral - withdraw) * collateral_price_min
+/// remainingCollateralUsd = effective_collateral_usd(collateral - withdraw)
+///   (collateral_price_min, minus `risk.collateral_haircut_bps`)
 /// must satisfy:
 /// 1) remainingCollateralUsd >= min_collateral_usd
+///    (and the remaining tokens >= `min_collateral_tokens`, if set)
 /// 2) remainingCollateralUsd >= next_size_usd * min_collateral_factor
 ///
 /// Returns false for user-level invalid requests.
@@ -140,9 +511,11 @@ pub fn will_position_collateral_be_sufficient_pre(
     let next_collateral_tokens = current_collateral_tokens
         .checked_sub(withdraw_tokens)
         .expect("withdraw_tokens <= collateral_tokens enforced above");
+    if below_min_collateral_tokens(next_collateral_tokens, risk) {
+        return false;
+    }
 
-    let remaining_collateral_usd = next_collateral_tokens
-        .checked_mul(prices.collateral_price_min)
+    let remaining_collateral_usd = effective_collateral_usd(next_collateral_tokens, prices, risk)
         .expect("remaining_collateral_usd overflow");
 
     if remaining_collateral_usd < risk.min_collateral_usd {
@@ -159,6 +532,54 @@ pub fn will_position_collateral_be_sufficient_pre(
     remaining_collateral_usd >= min_for_leverage
 }
 
+/// Largest `next_size_usd` that `collateral_tokens` can back under
+/// `will_position_collateral_be_sufficient_pre` (no withdraw).
+///
+/// ~ effective_collateral_usd * max_leverage, where max leverage is
+/// `factor_scale / min_collateral_factor_fp`. Exact inverse of the floor in the
+/// leverage check: the returned size passes and one more USD unit fails.
+/// Zero if the collateral is below `min_collateral_usd` or `min_collateral_tokens`;
+/// `U256::MAX` with a zero factor (no leverage limit).
+pub fn max_size_for_collateral(
+    collateral_tokens: TokenAmount,
+    prices: &OraclePrices,
+    risk: RiskCfg,
+) -> Result<Usd, String> {
+    let collateral_usd = effective_collateral_usd(collateral_tokens, prices, risk)
+        .ok_or("collateral_usd_overflow")?;
+    if collateral_usd < risk.min_collateral_usd
+        || below_min_collateral_tokens(collateral_tokens, risk)
+    {
+        return Ok(U256::zero());
+    }
+    if risk.min_collateral_factor_fp.is_zero() {
+        return Ok(U256::MAX);
+    }
+    // floor(size * factor / scale) <= collateral  <=>  size * factor < (collateral + 1) * scale
+    let bound = collateral_usd
+        .checked_add(U256::one())
+        .and_then(|c| c.checked_mul(risk.factor_scale))
+        .ok_or("max_size_overflow")?;
+    Ok((bound - 1) / risk.min_collateral_factor_fp)
+}
+
+/// Maintenance margin: the USD collateral floor implied by the leverage limit,
+/// `size_usd * min_collateral_factor_fp / factor_scale` (floor).
+///
+/// This is the `min_for_leverage` bound of the collateral checks and the
+/// leverage part of `liquidation::required_collateral_usd`; it does not
+/// include the absolute `min_collateral_usd` floor.
+pub fn maintenance_margin_usd(pos: &Position, risk: RiskCfg) -> Result<Usd, String> {
+    if risk.factor_scale.is_zero() {
+        return Err("invalid_factor_scale".into());
+    }
+    Ok(pos
+        .size_usd
+        .checked_mul(risk.min_collateral_factor_fp)
+        .ok_or("maintenance_margin_overflow")?
+        / risk.factor_scale)
+}
+
 /// Post-check after settlement (fees, realized PnL, collateral changes).
 ///
 /// Use this after you compute the new `pos` values (or right before persisting them).
@@ -171,21 +592,18 @@ pub fn postcheck_remaining_position(
         return Ok(()); // closed is always fine
     }
 
-    let remaining_collateral_usd = pos_after
-        .collateral_amount
-        .checked_mul(prices.collateral_price_min)
-        .ok_or_else(|| "collateral_usd_overflow".to_string())?;
+    let remaining_collateral_usd =
+        effective_collateral_usd(pos_after.collateral_amount, prices, risk)
+            .ok_or_else(|| "collateral_usd_overflow".to_string())?;
 
     if remaining_collateral_usd < risk.min_collateral_usd {
         return Err("remaining_collateral_below_min".into());
     }
+    if below_min_collateral_tokens(pos_after.collateral_amount, risk) {
+        return Err("remaining_collateral_below_min_tokens".into());
+    }
 
-    let min_for_leverage = pos_after
-        .size_usd
-        .checked_mul(risk.min_collateral_factor_fp)
-        .ok_or_else(|| "min_for_leverage_overflow".to_string())?
-        .checked_div(risk.factor_scale)
-        .ok_or_else(|| "invalid_factor_scale".to_string())?;
+    let min_for_leverage = maintenance_margin_usd(pos_after, risk)?;
 
     if remaining_collateral_usd < min_for_leverage {
         return Err("remaining_position_exceeds_max_leverage".into());
@@ -204,3 +622,434 @@ pub fn postcheck_remaining_position(
 pub fn is_position_liquidatable_future_placeholder() {
     // TODO
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PositionKey;
+    use crate::types::{AccountId, AssetId, ExecutionType, MarketId, OrderType, Side};
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    fn pos_100_usd() -> Position {
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        // $100 size, $50 collateral at $1 per atom.
+        Position::open(
+            key,
+            &MarketState::default(),
+            usd(100),
+            U256::from(1),
+            U256::from(50),
+            1,
+        )
+        .unwrap()
+    }
+
+    fn decrease(pos: &Position, size_delta_usd: Usd, withdraw: TokenAmount) -> Order {
+        Order {
+            account: pos.key.account,
+            market_id: pos.key.market_id,
+            collateral_token: pos.key.collateral_token,
+            side: pos.key.side,
+            order_type: OrderType::Decrease,
+            execution_type: ExecutionType::Market,
+            collateral_delta_tokens: U256::zero(),
+            size_delta_usd,
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: withdraw,
+            execution_fee_tokens: U256::zero(),
+            reduce_only: false,
+            target_leverage_x: 1,
+            created_at: 1,
+            valid_from: 1,
+            valid_until: 100,
+        }
+    }
+
+    fn prices() -> OraclePrices {
+        OraclePrices {
+            index_price_min: usd(100),
+            index_price_max: usd(100),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        }
+    }
+
+    #[test]
+    fn dust_remainder_follows_dust_policy() {
+        let pos = pos_100_usd();
+        // Leaves $5 < $10 dust threshold.
+        let order = decrease(&pos, usd(95), U256::from(10));
+        let mut risk = RiskCfg::default();
+
+        assert_eq!(risk.dust_policy, DustPolicy::ForceClose);
+        let (size_delta, withdraw, is_full_close) =
+            precheck_decrease_and_withdraw(&pos, &order, &prices(), risk).unwrap();
+        assert_eq!(size_delta, pos.size_usd);
+        assert!(withdraw.is_zero());
+        assert!(is_full_close);
+
+        risk.dust_policy = DustPolicy::Reject;
+        assert_eq!(
+            precheck_decrease_and_withdraw(&pos, &order, &prices(), risk).unwrap_err(),
+            "remaining_below_min"
+        );
+
+        // Above the threshold the policy does not matter.
+        let order = decrease(&pos, usd(50), U256::zero());
+        let (size_delta, _, is_full_close) =
+            precheck_decrease_and_withdraw(&pos, &order, &prices(), risk).unwrap();
+        assert_eq!(size_delta, usd(50));
+        assert!(!is_full_close);
+    }
+
+    #[test]
+    fn partial_close_withdraw_is_capped_not_dropped() {
+        // $100 size on 50 atoms ($50); closing half frees 25 atoms.
+        let pos = pos_100_usd();
+        let withdraw_for = |risk: RiskCfg, requested: u64| {
+            let order = decrease(&pos, usd(50), U256::from(requested));
+            let (size_delta, withdraw, is_full_close) =
+                precheck_decrease_and_withdraw(&pos, &order, &prices(), risk).unwrap();
+            assert_eq!(size_delta, usd(50));
+            assert!(!is_full_close);
+            withdraw
+        };
+
+        // At 2x the remaining $50 needs the other 25 atoms: only the freed share.
+        let at_2x = RiskCfg::with_max_leverage(2);
+        assert_eq!(withdraw_for(at_2x, 50), U256::from(25));
+        assert_eq!(withdraw_for(at_2x, 10), U256::from(10));
+
+        // At 4x the remaining $50 needs $12.5: 25 freed + 12 excess atoms.
+        let at_4x = RiskCfg::with_max_leverage(4);
+        assert_eq!(withdraw_for(at_4x, 50), U256::from(37));
+        assert!(will_position_collateral_be_sufficient_pre(
+            usd(50),
+            pos.collateral_amount,
+            U256::from(37),
+            &prices(),
+            at_4x
+        ));
+        assert!(!will_position_collateral_be_sufficient_pre(
+            usd(50),
+            pos.collateral_amount,
+            U256::from(38),
+            &prices(),
+            at_4x
+        ));
+    }
+
+    #[test]
+    fn execution_fee_must_cover_keeper_gas() {
+        let pos = pos_100_usd();
+        // 200k gas at $0.00001 per unit = $2 of gas.
+        let risk = RiskCfg {
+            execution_gas_price_usd: U256::exp10(25),
+            execution_gas_units: 200_000,
+            ..RiskCfg::default()
+        };
+
+        let mut order = decrease(&pos, usd(50), U256::zero());
+        assert_eq!(risk.execution_fee_required(order.order_type), Ok(usd(2)));
+
+        // $1 of collateral prepaid (1 atom at $1): underfunded.
+        order.execution_fee_tokens = U256::from(1);
+        assert_eq!(
+            check_execution_fee(&order, &prices(), risk).unwrap_err(),
+            "insufficient_execution_fee"
+        );
+
+        order.execution_fee_tokens = U256::from(2);
+        assert_eq!(check_execution_fee(&order, &prices(), risk), Ok(()));
+
+        // Keeper-initiated liquidations do not need a prepaid fee.
+        order.order_type = OrderType::Liquidation;
+        order.execution_fee_tokens = U256::zero();
+        assert_eq!(check_execution_fee(&order, &prices(), risk), Ok(()));
+    }
+
+    #[test]
+    fn max_size_is_the_exact_leverage_limit() {
+        for (risk, collateral) in [
+            (RiskCfg::default(), 50u64),
+            (RiskCfg::with_max_leverage(7), 1_234),
+            (
+                RiskCfg::with_max_leverage(20).with_collateral_haircut_bps(2_000),
+                100,
+            ),
+        ] {
+            let collateral = U256::from(collateral);
+            let max = max_size_for_collateral(collateral, &prices(), risk).unwrap();
+            let passes = |size: Usd| {
+                will_position_collateral_be_sufficient_pre(
+                    size,
+                    collateral,
+                    U256::zero(),
+                    &prices(),
+                    risk,
+                )
+            };
+            assert!(passes(max));
+            assert!(!passes(max + 1));
+
+            // ~ collateral_usd * max leverage (factor rounding adds at most ~1e-15 relative).
+            let approx = effective_collateral_usd(collateral, &prices(), risk).unwrap()
+                * U256::from(risk.max_leverage_x());
+            assert!(max >= approx);
+            assert!((max - approx) * U256::exp10(15) <= approx);
+        }
+
+        // $1 of collateral is below the $5 minimum: nothing can be opened.
+        assert_eq!(
+            max_size_for_collateral(U256::one(), &prices(), RiskCfg::default()),
+            Ok(U256::zero())
+        );
+    }
+
+    #[test]
+    fn maintenance_margin_is_the_leverage_floor_of_the_checks() {
+        // Margins above the $5 `min_collateral_usd`, so only the leverage bound binds.
+        for risk in [RiskCfg::with_max_leverage(2), RiskCfg::with_max_leverage(7)] {
+            let mut pos = pos_100_usd();
+            let margin = maintenance_margin_usd(&pos, risk).unwrap();
+            // Smallest whole-atom collateral (at $1 per atom) covering the margin.
+            let floor_atoms = (margin + usd(1) - 1) / usd(1);
+
+            pos.collateral_amount = floor_atoms;
+            assert_eq!(postcheck_remaining_position(&pos, &prices(), risk), Ok(()));
+            assert!(will_position_collateral_be_sufficient_pre(
+                pos.size_usd,
+                floor_atoms,
+                U256::zero(),
+                &prices(),
+                risk
+            ));
+
+            pos.collateral_amount = floor_atoms - 1;
+            assert_eq!(
+                postcheck_remaining_position(&pos, &prices(), risk).unwrap_err(),
+                "remaining_position_exceeds_max_leverage"
+            );
+            assert!(!will_position_collateral_be_sufficient_pre(
+                pos.size_usd,
+                floor_atoms - 1,
+                U256::zero(),
+                &prices(),
+                risk
+            ));
+        }
+
+        // $100 needs $50 at 2x and $2 at the default 50x (below the $5 minimum).
+        assert_eq!(
+            maintenance_margin_usd(&pos_100_usd(), RiskCfg::with_max_leverage(2)),
+            Ok(usd(50))
+        );
+        assert_eq!(
+            maintenance_margin_usd(&pos_100_usd(), RiskCfg::default()),
+            Ok(usd(2))
+        );
+        let broken = RiskCfg {
+            factor_scale: U256::zero(),
+            ..RiskCfg::default()
+        };
+        assert_eq!(
+            maintenance_margin_usd(&pos_100_usd(), broken).unwrap_err(),
+            "invalid_factor_scale"
+        );
+        assert_eq!(
+            partial_close_withdraw_cap(&pos_100_usd(), usd(50), usd(50), &prices(), broken,)
+                .unwrap_err(),
+            "invalid_factor_scale"
+        );
+    }
+
+    #[test]
+    fn token_floor_rejects_collateral_that_meets_usd_floor() {
+        // 50 atoms = $50: well above the $5 USD floor, below a 60-atom token floor.
+        let pos = pos_100_usd();
+        let usd_only = RiskCfg::with_max_leverage(2);
+        let with_tokens = RiskCfg {
+            min_collateral_tokens: Some(U256::from(60)),
+            ..usd_only
+        };
+
+        assert_eq!(
+            postcheck_remaining_position(&pos, &prices(), usd_only),
+            Ok(())
+        );
+        assert_eq!(
+            postcheck_remaining_position(&pos, &prices(), with_tokens).unwrap_err(),
+            "remaining_collateral_below_min_tokens"
+        );
+        assert!(!will_position_collateral_be_sufficient_pre(
+            pos.size_usd,
+            pos.collateral_amount,
+            U256::zero(),
+            &prices(),
+            with_tokens,
+        ));
+        assert_eq!(
+            max_size_for_collateral(pos.collateral_amount, &prices(), with_tokens),
+            Ok(U256::zero())
+        );
+
+        // The same $100 opened on 50 atoms at 2x is rejected only with the token floor.
+        let order = increase(50, 2);
+        assert_eq!(
+            check_increase_size_and_leverage(&order, None, &prices(), usd_only),
+            Ok(())
+        );
+        assert_eq!(
+            check_increase_size_and_leverage(&order, None, &prices(), with_tokens),
+            Err(ValidationError::LeverageTooHigh)
+        );
+
+        // A partial close keeps at least the token floor as collateral.
+        let floor_40 = RiskCfg {
+            min_collateral_tokens: Some(U256::from(40)),
+            ..RiskCfg::with_max_leverage(4)
+        };
+        let order = decrease(&pos, usd(50), U256::from(50));
+        let (_, withdraw, is_full_close) =
+            precheck_decrease_and_withdraw(&pos, &order, &prices(), floor_40).unwrap();
+        assert!(!is_full_close);
+        assert_eq!(withdraw, U256::from(10));
+    }
+
+    #[test]
+    fn collateral_haircut_flips_safety_verdict() {
+        // $100 size on $50 collateral is exactly at the 2x limit.
+        let pos = pos_100_usd();
+        let plain = RiskCfg::with_max_leverage(2);
+        let haircut = plain.with_collateral_haircut_bps(2_000);
+
+        assert_eq!(
+            effective_collateral_usd(pos.collateral_amount, &prices(), haircut),
+            Some(usd(40))
+        );
+
+        assert!(will_position_collateral_be_sufficient_pre(
+            pos.size_usd,
+            pos.collateral_amount,
+            U256::zero(),
+            &prices(),
+            plain,
+        ));
+        assert!(!will_position_collateral_be_sufficient_pre(
+            pos.size_usd,
+            pos.collateral_amount,
+            U256::zero(),
+            &prices(),
+            haircut,
+        ));
+
+        assert_eq!(postcheck_remaining_position(&pos, &prices(), plain), Ok(()));
+        assert_eq!(
+            postcheck_remaining_position(&pos, &prices(), haircut).unwrap_err(),
+            "remaining_position_exceeds_max_leverage"
+        );
+    }
+
+    fn market() -> MarketState {
+        MarketState {
+            id: MarketId(1),
+            ..Default::default()
+        }
+    }
+
+    fn increase(collateral_tokens: u64, leverage: u32) -> Order {
+        let mut order = decrease(&pos_100_usd(), U256::zero(), U256::zero());
+        order.order_type = OrderType::Increase;
+        order.collateral_delta_tokens = U256::from(collateral_tokens);
+        order.target_leverage_x = leverage;
+        order
+    }
+
+    #[test]
+    fn validate_order_accepts_valid_order_of_each_type() {
+        let pos = pos_100_usd();
+        let risk = RiskCfg::default();
+        let m = market();
+
+        // New position and growing the existing one.
+        let order = increase(100, 5);
+        assert_eq!(
+            validate_order(&order, None, &m, &prices(), risk, 10),
+            Ok(())
+        );
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 10),
+            Ok(())
+        );
+
+        let order = decrease(&pos, usd(50), U256::zero());
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 10),
+            Ok(())
+        );
+
+        let mut order = decrease(&pos, pos.size_usd, U256::zero());
+        order.order_type = OrderType::Liquidation;
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 10),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn validate_order_rejects_expired_paused_and_invalid_orders() {
+        let pos = pos_100_usd();
+        let risk = RiskCfg::default();
+        let mut m = market();
+        let order = decrease(&pos, usd(50), U256::zero());
+
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 101),
+            Err(ValidationError::Expired)
+        );
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 0),
+            Err(ValidationError::NotActiveYet)
+        );
+        assert_eq!(
+            validate_order(&order, None, &m, &prices(), risk, 10),
+            Err(ValidationError::PositionNotFound)
+        );
+
+        // 100x on a 50x-max config.
+        assert_eq!(
+            validate_order(&increase(100, 100), None, &m, &prices(), risk, 10),
+            Err(ValidationError::LeverageTooHigh)
+        );
+        // $5 position under the $10 minimum.
+        assert_eq!(
+            validate_order(&increase(1, 5), None, &m, &prices(), risk, 10),
+            Err(ValidationError::BelowMinSize)
+        );
+
+        // Pausing blocks increases only.
+        assert_eq!(
+            validate_order(&increase(100, 5), None, &m, &prices(), risk, 10),
+            Ok(())
+        );
+        m.paused = true;
+        assert_eq!(
+            validate_order(&increase(100, 5), None, &m, &prices(), risk, 10),
+            Err(ValidationError::MarketPaused)
+        );
+        assert_eq!(
+            validate_order(&order, Some(&pos), &m, &prices(), risk, 10),
+            Ok(())
+        );
+        assert_eq!(String::from(ValidationError::MarketPaused), "market_paused");
+    }
+}
@@ -1,6 +1,6 @@
 use primitive_types::{U256, U512};
 
-use crate::state::{MarketState, PoolBalances, Position};
+use crate::state::{MarketState, PoolBalances, Position, UtilizationMode};
 use crate::types::{AssetId, MarketId, Timestamp, TokenAmount, Usd};
 
 /// Internal scale for borrowing index.
@@ -57,8 +57,15 @@ pub trait BorrowingService {
     fn update_index(&self, market: &mut MarketState, now: Timestamp);
 
     /// Compute borrowing fee for a position and update its snapshot.
-    fn settle_position_borrowing(&self, market: &MarketState, pos: &mut Position)
-    -> BorrowingDelta;
+    ///
+    /// `now` lets implementations apply position-age rules (e.g. a grace period).
+    /// On error (corrupted snapshot, overflow) the position is left untouched.
+    fn settle_position_borrowing(
+        &self,
+        market: &MarketState,
+        pos: &mut Position,
+        now: Timestamp,
+    ) -> Result<BorrowingDelta, String>;
 }
 
 /// Basic implementation:
@@ -66,26 +73,38 @@ pub trait BorrowingService {
 /// - utilization ≈ (oi_long + oi_short) / liquidity
 /// - rate is a simple linear function of utilization:
 ///     rate_per_sec = base_rate + slope * utilization
+/// - positions younger than `grace_period_secs` accrue nothing.
 #[derive(Default, Clone)]
-pub struct BasicBorrowingService;
+pub struct BasicBorrowingService {
+    /// Borrowing-free period after `pos.opened_at`. Zero disables it.
+    pub grace_period_secs: u64,
+}
 
 impl BasicBorrowingService {
-    /// Compute utilization as a fixed-point in [0, 1] * BORROW_INDEX_SCALE.
-    fn compute_utilization_fp(market: &MarketState) -> U256 {
-        let borrowed = market.oi_long_usd + market.oi_short_usd;
-        let liquidity = market.liquidity_usd;
-
-        if liquidity == U256::zero() {
-            return U256::zero();
-        }
-
-        let ratio_fp = borrowed.saturating_mul(borrow_index_scale()) / liquidity;
+    pub fn with_grace_period(grace_period_secs: u64) -> Self {
+        Self { grace_period_secs }
+    }
 
-        // Cap at 1.0 in FP
-        ratio_fp.min(borrow_index_scale())
+    /// Whether `pos` is still inside the borrowing-free grace period at `now`.
+    pub fn in_grace_period(&self, pos: &Position, now: Timestamp) -> bool {
+        now.saturating_sub(pos.opened_at) < self.grace_period_secs
     }
 }
 
+/// Current borrowing rate (index units per second, scale 1e18) at the
+/// market's current utilization: base + slope * utilization.
+pub fn current_borrowing_rate_fp_per_sec(market: &MarketState) -> U256 {
+    let base_rate_fp_per_sec = bps_per_day_to_fp_per_sec(BASE_RATE_PER_DAY_BPS); // ~1_157_407_407
+    let slope_fp_per_sec = bps_per_day_to_fp_per_sec(SLOPE_PER_DAY_BPS); // ~10_416_666_667
+    let slope_term = mul_div_u256(
+        slope_fp_per_sec,
+        utilization_fp(market),
+        borrow_index_scale(),
+    )
+    .unwrap_or(U256::zero());
+    base_rate_fp_per_sec.saturating_add(slope_term)
+}
+
 impl BorrowingService for BasicBorrowingService {
     fn update_index(&self, market: &mut MarketState, now: Timestamp) {
         if market.borrowing.last_updated_at == 0 {
@@ -101,12 +120,7 @@ impl BorrowingService for BasicBorrowingService {
             return;
         }
 
-        // 1) Utilization in [0, 1] * SCALE
-        let util_fp = Self::compute_utilization_fp(market);
-
-        let borrowing = &mut market.borrowing;
-
-        // 2) Simple linear rate:
+        // Simple linear rate on utilization in [0, 1] * SCALE:
         //
         //    rate_per_sec_fp = base_rate_fp + slope_fp * util
         //
@@ -115,14 +129,9 @@ impl BorrowingService for BasicBorrowingService {
         //   - slope_fp: how fast rate grows with utilization.
         //
         // Units: index units per second (same scale: BORROW_INDEX_SCALE).
-        let base_rate_fp_per_sec = bps_per_day_to_fp_per_sec(BASE_RATE_PER_DAY_BPS); // ~1_157_407_407
-        let slope_fp_per_sec = bps_per_day_to_fp_per_sec(SLOPE_PER_DAY_BPS); // ~10_416_666_667
-
-        // rate_per_sec_fp = base + slope * util / SCALE
-        let slope_term =
-            mul_div_u256(slope_fp_per_sec, util_fp, borrow_index_scale()).unwrap_or(U256::zero());
-        let rate_per_sec_fp = base_rate_fp_per_sec.saturating_add(slope_term);
+        let rate_per_sec_fp = current_borrowing_rate_fp_per_sec(market);
 
+        let borrowing = &mut market.borrowing;
         let delta_index_fp = rate_per_sec_fp.saturating_mul(U256::from(dt));
 
         borrowing.cumulative_factor = borrowing.cumulative_factor.saturating_add(delta_index_fp);
@@ -133,26 +142,37 @@ impl BorrowingService for BasicBorrowingService {
         &self,
         market: &MarketState,
         pos: &mut Position,
-    ) -> BorrowingDelta {
+        now: Timestamp,
+    ) -> Result<BorrowingDelta, String> {
         let current_idx = market.borrowing.cumulative_factor;
         let prev_idx = pos.borrowing_index;
 
-        let delta_idx = current_idx - prev_idx;
-        if delta_idx <= U256::zero() || pos.size_usd == U256::zero() {
+        // The cumulative factor never decreases: a snapshot above it is corrupted.
+        let delta_idx = current_idx
+            .checked_sub(prev_idx)
+            .ok_or("borrowing_index_anomaly")?;
+
+        // Within the grace period the snapshot moves forward without charging,
+        // so accrual starts from the index at the end of the grace period.
+        if delta_idx.is_zero() || pos.size_usd.is_zero() || self.in_grace_period(pos, now) {
             pos.borrowing_index = current_idx;
-            return BorrowingDelta {
+            return Ok(BorrowingDelta {
                 borrowing_fee_usd: U256::zero(),
-            };
+            });
         }
 
         // borrowing_fee = sizeUsd * deltaIndex / SCALE
-        let fee = pos.size_usd.saturating_mul(delta_idx) / borrow_index_scale();
+        let fee = pos
+            .size_usd
+            .checked_mul(delta_idx)
+            .ok_or("borrowing_fee_mul_overflow")?
+            / borrow_index_scale();
 
         pos.borrowing_index = current_idx;
 
-        BorrowingDelta {
+        Ok(BorrowingDelta {
             borrowing_fee_usd: fee,
-        }
+        })
     }
 }
 
@@ -171,8 +191,16 @@ pub fn apply_borrowing_fees_to_pool(
     pools.add_fee_to_pool(market_id, collateral_token, borrowing_tokens);
 }
 
-fn utilization_fp(market: &MarketState) -> U256 {
-    let borrowed = market.oi_long_usd.saturating_add(market.oi_short_usd);
+/// Utilization = used / liquidity, fixed-point in [0, 1] * 1e18.
+///
+/// `used` depends on `market.utilization_mode`:
+///  - OpenInterest: oi_long + oi_short;
+///  - Reserved: reserved_usd.
+pub fn utilization_fp(market: &MarketState) -> U256 {
+    let borrowed = match market.utilization_mode {
+        UtilizationMode::OpenInterest => market.oi_long_usd.saturating_add(market.oi_short_usd),
+        UtilizationMode::Reserved => market.reserved_usd,
+    };
     let liquidity = market.liquidity_usd;
     if liquidity.is_zero() {
         return U256::zero();
@@ -217,3 +245,143 @@ pub fn preview_borrowing_fee_usd(
     // fee_usd = size_usd * delta_idx / SCALE
     Ok(pos.size_usd.saturating_mul(delta_idx) / borrow_index_scale())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn reserve_based_utilization_tracks_locked_capital() {
+        // $100k long reserving 100% of size, $100k short reserving 50%.
+        let mut market = MarketState {
+            oi_long_usd: usd(100_000),
+            oi_short_usd: usd(100_000),
+            reserved_usd: usd(100_000) + usd(50_000),
+            liquidity_usd: usd(1_000_000),
+            ..Default::default()
+        };
+
+        let oi_util = utilization_fp(&market);
+        let oi_rate = current_borrowing_rate_fp_per_sec(&market);
+        assert_eq!(oi_util, borrow_index_scale() / 5); // 20%
+
+        market.utilization_mode = UtilizationMode::Reserved;
+        let reserved_util = utilization_fp(&market);
+        assert_eq!(reserved_util, borrow_index_scale() * 15 / 100); // 15%
+        assert!(current_borrowing_rate_fp_per_sec(&market) < oi_rate);
+
+        // Both are capped at 100%.
+        market.reserved_usd = usd(2_000_000);
+        assert_eq!(utilization_fp(&market), borrow_index_scale());
+    }
+
+    #[test]
+    fn no_borrowing_accrues_within_grace_period() {
+        use crate::state::PositionKey;
+        use crate::types::{AccountId, MarketId, Side};
+
+        let svc = BasicBorrowingService::with_grace_period(3_600);
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let opened_at = 1_000;
+        let mut pos = Position::open(
+            key,
+            &MarketState::default(),
+            usd(10_000),
+            U256::from(1),
+            U256::zero(),
+            opened_at,
+        )
+        .unwrap();
+        let mut market = MarketState::default();
+
+        // Inside the grace period: the index moved but nothing is charged.
+        market.borrowing.cumulative_factor = borrow_index_scale() / 1_000;
+        let delta = svc
+            .settle_position_borrowing(&market, &mut pos, opened_at + 600)
+            .unwrap();
+        assert!(delta.borrowing_fee_usd.is_zero());
+        assert_eq!(pos.borrowing_index, market.borrowing.cumulative_factor);
+
+        // After the grace period only the accrual since the last snapshot is charged.
+        market.borrowing.cumulative_factor = borrow_index_scale() * 3 / 1_000;
+        let delta = svc
+            .settle_position_borrowing(&market, &mut pos, opened_at + 3_600)
+            .unwrap();
+        assert_eq!(delta.borrowing_fee_usd, usd(20)); // 10_000 * 0.2%
+
+        // Without a grace period the same position is charged immediately.
+        let mut fresh = Position::open(
+            key,
+            &MarketState::default(),
+            usd(10_000),
+            U256::from(1),
+            U256::zero(),
+            opened_at,
+        )
+        .unwrap();
+        let delta = BasicBorrowingService::default()
+            .settle_position_borrowing(&market, &mut fresh, opened_at)
+            .unwrap();
+        assert_eq!(delta.borrowing_fee_usd, usd(30));
+    }
+
+    #[test]
+    fn corrupted_or_overflowing_borrowing_settlement_errors() {
+        use crate::state::PositionKey;
+        use crate::types::{AccountId, MarketId, Side};
+
+        let svc = BasicBorrowingService::default();
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Short,
+        };
+        let mut market = MarketState::default();
+        market.borrowing.cumulative_factor = borrow_index_scale();
+        let mut pos = Position::open(
+            key,
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(1),
+            U256::zero(),
+            1,
+        )
+        .unwrap();
+
+        // Normal settlement.
+        let delta = svc
+            .settle_position_borrowing(&market, &mut pos, 10)
+            .unwrap();
+        assert_eq!(delta.borrowing_fee_usd, usd(1_000));
+        assert_eq!(pos.borrowing_index, borrow_index_scale());
+
+        // Snapshot ahead of the market factor.
+        pos.borrowing_index = borrow_index_scale() * 2;
+        assert_eq!(
+            svc.settle_position_borrowing(&market, &mut pos, 10)
+                .unwrap_err(),
+            "borrowing_index_anomaly"
+        );
+        assert_eq!(pos.borrowing_index, borrow_index_scale() * 2);
+
+        // size * delta overflows U256.
+        pos.borrowing_index = U256::zero();
+        market.borrowing.cumulative_factor = U256::MAX / 2;
+        assert_eq!(
+            svc.settle_position_borrowing(&market, &mut pos, 10)
+                .unwrap_err(),
+            "borrowing_fee_mul_overflow"
+        );
+        assert!(pos.borrowing_index.is_zero());
+    }
+}
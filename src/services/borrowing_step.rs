@@ -2,7 +2,7 @@ use primitive_types::U256;
 
 use crate::services::BorrowingService;
 use crate::state::{MarketState, Position};
-use crate::types::Usd;
+use crate::types::{Timestamp, Usd};
 
 /// Result of applying borrowing for a single position on a single step.
 #[derive(Debug, Clone, Copy)]
@@ -14,20 +14,22 @@ pub struct BorrowingStep {
 
 /// Apply borrowing logic to a single position:
 ///
-///  - calls `borrowing_svc.settle_position_borrowing(market, pos)`
+///  - calls `borrowing_svc.settle_position_borrowing(market, pos, now)`
 ///    which updates borrowing index snapshot inside the position;
 ///  - interprets the returned `borrowing_fee_usd` as a pure cost (payer-only)
-///    and returns it as `BorrowingStep { cost_usd }`.
+///    and returns it as `BorrowingStep { cost_usd }`;
+///  - propagates settlement errors (position left untouched).
 pub fn apply_borrowing_step<B: BorrowingService>(
     borrowing_svc: &B,
     market: &MarketState,
     pos: &mut Position,
-) -> BorrowingStep {
-    let delta = borrowing_svc.settle_position_borrowing(market, pos);
+    now: Timestamp,
+) -> Result<BorrowingStep, String> {
+    let delta = borrowing_svc.settle_position_borrowing(market, pos, now)?;
     let fee: Usd = delta.borrowing_fee_usd;
 
     // Borrowing is expected to be a cost.
-    BorrowingStep {
+    Ok(BorrowingStep {
         cost_usd: fee.max(U256::zero()),
-    }
+    })
 }
@@ -1,7 +1,11 @@
+use std::collections::{HashMap, HashSet};
+
 use primitive_types::U256;
 
 use crate::state::{Claimables, PoolBalances, Position};
-use crate::types::{AssetId, MarketId, OraclePrices, Order, OrderType, TokenAmount, Usd};
+use crate::types::{
+    AccountId, AssetId, MarketId, OraclePrices, Order, OrderType, SignedU256, TokenAmount, Usd,
+};
 
 /// Per-step trading fees for a single position change.
 #[derive(Debug, Clone)]
@@ -10,6 +14,9 @@ pub struct StepFees {
     pub position_fee_tokens: TokenAmount,
     pub liquidation_fee_usd: Usd,
     pub liquidation_fee_tokens: TokenAmount,
+    /// Discount granted to a helpful trade (already netted out of `position_fee_*`).
+    pub helpful_rebate_usd: Usd,
+    pub helpful_rebate_tokens: TokenAmount,
     pub market_id: MarketId,
     pub fee_asset: AssetId,
 }
@@ -23,6 +30,36 @@ fn div_ceil(n: U256, d: U256) -> Result<U256, String> {
     Ok(if r.is_zero() { q } else { q + U256::one() })
 }
 
+/// Direction of a USD -> collateral token conversion.
+///
+/// Both directions are conservative for the protocol:
+///  - `Charge` (fees taken from the user): ceil(usd / collateral_price_min),
+///    i.e. the most tokens;
+///  - `Refund` (rebates / refunds paid to the user): floor(usd / collateral_price_max),
+///    i.e. the fewest tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PriceDirection {
+    Charge,
+    Refund,
+}
+
+/// Convert a fee-like USD amount into collateral tokens for `direction`.
+pub fn fee_usd_to_collateral_tokens(
+    usd: Usd,
+    prices: &OraclePrices,
+    direction: PriceDirection,
+) -> Result<TokenAmount, String> {
+    match direction {
+        PriceDirection::Charge => div_ceil(usd, prices.collateral_price_min),
+        PriceDirection::Refund => {
+            if prices.collateral_price_max.is_zero() {
+                return Err("division_by_zero".into());
+            }
+            Ok(usd / prices.collateral_price_max)
+        }
+    }
+}
+
 /// High-level interface for fee calculation and distribution.
 ///
 /// The same interface is used for:
@@ -32,14 +69,21 @@ fn div_ceil(n: U256, d: U256) -> Result<U256, String> {
 pub trait FeesService {
     /// Compute position + liquidation fees for a single step.
     ///
-    /// `balance_was_improved` comes from pricing (price impact service) and
-    /// indicates whether this trade reduced OI imbalance.
+    /// `balance_was_improved` and `price_impact_usd` come from pricing (price
+    /// impact service): whether this trade reduced OI imbalance, and its impact
+    /// (zero for a neutral trade).
+    ///
+    /// `size_delta_usd` is the notional actually traded in this step. Both the
+    /// position fee and the liquidation fee are charged on it, never on
+    /// `pos.size_usd` or `order.size_delta_usd`, so a partial liquidation pays
+    /// only for the liquidated portion.
     fn compute_fees(
         &self,
         pos: &Position,
         order: &Order,
         prices: &OraclePrices,
         balance_was_improved: bool,
+        price_impact_usd: SignedU256,
         size_delta_usd: Usd,
     ) -> Result<StepFees, String>;
 
@@ -51,8 +95,9 @@ pub trait FeesService {
     );
 }
 
-#[derive(Debug, Clone, Default)]
-pub struct BasicFeesService {
+/// Fee schedule for a single market.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FeeParams {
     /// Trading fee in basis points (e.g. 10 = 0.1%, 30 = 0.3%)
     pub position_fee_bps_increase: u32,
     pub position_fee_bps_decrease: u32,
@@ -61,30 +106,123 @@ pub struct BasicFeesService {
     /// % discount on position fee (not in bps, just integer percent) if
     /// the trade improves OI balance.
     pub helpful_rebate_percent: u32,
+
+    /// Also grant the helpful rebate to zero-impact (neutral) trades.
+    /// Default false: neutral trades pay the full fee.
+    pub neutral_gets_rebate: bool,
+}
+
+impl FeeParams {
+    fn base_position_fee_bps(&self, order_type: OrderType) -> u32 {
+        match order_type {
+            OrderType::Increase => self.position_fee_bps_increase,
+            OrderType::Decrease => self.position_fee_bps_decrease,
+            OrderType::Liquidation => 0,
+        }
+    }
+}
+
+/// Per-market fee schedules with a fallback for unregistered markets.
+#[derive(Debug, Clone, Default)]
+pub struct FeeRegistry {
+    /// Used for markets without an explicit entry.
+    pub default_params: FeeParams,
+    markets: HashMap<MarketId, FeeParams>,
+}
+
+impl FeeRegistry {
+    pub fn new(default_params: FeeParams) -> Self {
+        Self {
+            default_params,
+            markets: HashMap::new(),
+        }
+    }
+
+    /// Register (or replace) the fee schedule of a market.
+    pub fn register(&mut self, market_id: MarketId, params: FeeParams) {
+        self.markets.insert(market_id, params);
+    }
+
+    /// Remove a market-specific schedule; the market falls back to defaults.
+    pub fn unregister(&mut self, market_id: MarketId) -> Option<FeeParams> {
+        self.markets.remove(&market_id)
+    }
+
+    /// Resolve fee params for a market (falls back to `default_params`).
+    pub fn params_for(&self, market_id: MarketId) -> FeeParams {
+        self.markets
+            .get(&market_id)
+            .copied()
+            .unwrap_or(self.default_params)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BasicFeesService {
+    pub registry: FeeRegistry,
+    /// Accounts that pay no position fee (promotions / whitelists).
+    /// Liquidation fees still apply to them.
+    pub fee_exempt: HashSet<AccountId>,
+    /// Treasury account receiving the protocol cut of trading fees via `Claimables`.
+    pub protocol_account: AccountId,
+    /// Share of position + liquidation fees routed to `protocol_account`, in bps.
+    /// The rest goes to the pool. Zero = everything to the pool.
+    pub protocol_fee_share_bps: u32,
 }
 
 impl BasicFeesService {
+    /// Single fee schedule shared by every market (no per-market overrides yet).
     pub fn new(
         increase_bps: u32,
         decrease_bps: u32,
         liquidation_bps: u32,
         helpful_rebate_percent: u32,
     ) -> Self {
-        Self {
+        Self::with_registry(FeeRegistry::new(FeeParams {
             position_fee_bps_increase: increase_bps,
             position_fee_bps_decrease: decrease_bps,
             liquidation_fee_bps: liquidation_bps,
             helpful_rebate_percent,
+            neutral_gets_rebate: false,
+        }))
+    }
+
+    pub fn with_registry(registry: FeeRegistry) -> Self {
+        Self {
+            registry,
+            fee_exempt: HashSet::new(),
+            protocol_account: AccountId::default(),
+            protocol_fee_share_bps: 0,
         }
     }
 
-    fn base_position_fee_bps(&self, order_type: OrderType) -> u32 {
-        match order_type {
-            OrderType::Increase => self.position_fee_bps_increase,
-            OrderType::Decrease => self.position_fee_bps_decrease,
-            OrderType::Liquidation => 0,
+    /// Route `share_bps` of trading fees to `account` (capped at 100%).
+    pub fn with_protocol_account(self, account: AccountId, share_bps: u32) -> Self {
+        Self {
+            protocol_account: account,
+            protocol_fee_share_bps: share_bps.min(10_000),
+            ..self
         }
     }
+
+    /// Exempt `account` from position fees.
+    pub fn add_fee_exempt(&mut self, account: AccountId) {
+        self.fee_exempt.insert(account);
+    }
+
+    /// Remove a position fee exemption. Returns whether it was present.
+    pub fn remove_fee_exempt(&mut self, account: AccountId) -> bool {
+        self.fee_exempt.remove(&account)
+    }
+
+    pub fn is_fee_exempt(&self, account: AccountId) -> bool {
+        self.fee_exempt.contains(&account)
+    }
+
+    /// Register a market-specific fee schedule.
+    pub fn register_market(&mut self, market_id: MarketId, params: FeeParams) {
+        self.registry.register(market_id, params);
+    }
 }
 
 impl FeesService for BasicFeesService {
@@ -94,15 +232,25 @@ impl FeesService for BasicFeesService {
         order: &Order,
         prices: &OraclePrices,
         balance_was_improved: bool,
+        price_impact_usd: SignedU256,
         size_delta_usd: Usd,
     ) -> Result<StepFees, String> {
         let notional_usd = size_delta_usd;
+        let params = self.registry.params_for(pos.key.market_id);
 
         // 1) Position fee bps with optional rebate for helpful trades.
-        let mut pos_bps = self.base_position_fee_bps(order.order_type);
-        if balance_was_improved && pos_bps > 0 && self.helpful_rebate_percent > 0 {
+        // Exempt accounts pay no position fee (and get no rebate on top).
+        let base_bps = if self.is_fee_exempt(pos.key.account) {
+            0
+        } else {
+            params.base_position_fee_bps(order.order_type)
+        };
+        let mut pos_bps = base_bps;
+        let rebated =
+            balance_was_improved || (params.neutral_gets_rebate && price_impact_usd.is_zero());
+        if rebated && pos_bps > 0 && params.helpful_rebate_percent > 0 {
             // effective_bps = pos_bps * (100 - rebate%) / 100
-            pos_bps = pos_bps.saturating_mul(100 - self.helpful_rebate_percent) / 100;
+            pos_bps = pos_bps.saturating_mul(100 - params.helpful_rebate_percent) / 100;
         }
 
         // position_fee_usd = notional_usd * pos_bps / 10_000
@@ -110,27 +258,37 @@ impl FeesService for BasicFeesService {
             .checked_mul(U256::from(pos_bps))
             .ok_or("position_fee_mul_overflow")?
             / U256::from(10_000u64);
+        let base_position_fee_usd = notional_usd
+            .checked_mul(U256::from(base_bps))
+            .ok_or("position_fee_mul_overflow")?
+            / U256::from(10_000u64);
+        let helpful_rebate_usd = base_position_fee_usd.saturating_sub(position_fee_usd);
         // 2) Liquidation fee only for liquidation orders.
         let liquidation_fee_usd: Usd = if order.order_type == OrderType::Liquidation {
             notional_usd
-                .checked_mul(U256::from(self.liquidation_fee_bps as u64))
+                .checked_mul(U256::from(params.liquidation_fee_bps as u64))
                 .ok_or("liquidation_fee_mul_overflow")?
                 / U256::from(10_000u64)
         } else {
             U256::zero()
         };
         // 3) Convert USD → collateral tokens.
-        let p = prices.collateral_price_min;
-        let position_fee_tokens = div_ceil(position_fee_usd, p)?;
-        let liquidation_fee_tokens = div_ceil(liquidation_fee_usd, p)?;
+        // Fees are charged (price min, round up); the rebate is valued as a refund
+        // (price max, round down).
+        let position_fee_tokens =
+            fee_usd_to_collateral_tokens(position_fee_usd, prices, PriceDirection::Charge)?;
+        let liquidation_fee_tokens =
+            fee_usd_to_collateral_tokens(liquidation_fee_usd, prices, PriceDirection::Charge)?;
+        let helpful_rebate_tokens =
+            fee_usd_to_collateral_tokens(helpful_rebate_usd, prices, PriceDirection::Refund)?;
 
-        println!("position_fee_usd {:?}", position_fee_usd);
-        println!("position_fee_tokens {:?}", position_fee_tokens);
         Ok(StepFees {
             position_fee_usd,
             position_fee_tokens,
             liquidation_fee_usd,
             liquidation_fee_tokens,
+            helpful_rebate_usd,
+            helpful_rebate_tokens,
             market_id: pos.key.market_id,
             fee_asset: pos.key.collateral_token,
         })
@@ -139,16 +297,358 @@ impl FeesService for BasicFeesService {
     fn apply_fees(
         &self,
         pools: &mut PoolBalances,
-        _claimables: &mut Claimables,
+        claimables: &mut Claimables,
         step_fees: &StepFees,
     ) {
-        // All position + liquidation fees go to the pool for now.
+        // Position + liquidation fees: protocol cut to the treasury, rest to the pool.
         let total_fee_tokens = step_fees.position_fee_tokens + step_fees.liquidation_fee_tokens;
 
         if total_fee_tokens.is_zero() {
             return;
         }
 
-        pools.add_fee_to_pool(step_fees.market_id, step_fees.fee_asset, total_fee_tokens);
+        // Floor: rounding dust stays with the pool.
+        let protocol_tokens = total_fee_tokens
+            * U256::from(self.protocol_fee_share_bps.min(10_000))
+            / U256::from(10_000u32);
+        if !protocol_tokens.is_zero() {
+            claimables.add_fee(self.protocol_account, step_fees.fee_asset, protocol_tokens);
+        }
+
+        pools.add_fee_to_pool(
+            step_fees.market_id,
+            step_fees.fee_asset,
+            total_fee_tokens - protocol_tokens,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::PositionKey;
+    use crate::types::{AccountId, ExecutionType, Side, SignedU256};
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    fn pos(market_id: MarketId) -> Position {
+        Position {
+            key: PositionKey {
+                account: AccountId([1u8; 32]),
+                market_id,
+                collateral_token: AssetId(10),
+                side: Side::Long,
+            },
+            size_usd: U256::zero(),
+            size_tokens: U256::zero(),
+            collateral_amount: U256::from(1_000),
+            collateral_balances: HashMap::new(),
+            pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
+            funding_index: SignedU256::zero(),
+            funding_updated_at: 0,
+            pending_funding_usd: SignedU256::zero(),
+            borrowing_index: U256::zero(),
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
+            opened_at: 1,
+            last_updated_at: 1,
+        }
+    }
+
+    fn increase_order(market_id: MarketId) -> Order {
+        Order {
+            account: AccountId([1u8; 32]),
+            market_id,
+            collateral_token: AssetId(10),
+            side: Side::Long,
+            order_type: OrderType::Increase,
+            execution_type: ExecutionType::Market,
+            collateral_delta_tokens: U256::from(1_000),
+            size_delta_usd: U256::zero(),
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: U256::zero(),
+            execution_fee_tokens: U256::zero(),
+            reduce_only: false,
+            target_leverage_x: 1,
+            created_at: 1,
+            valid_from: 0,
+            valid_until: 100,
+        }
+    }
+
+    fn params(increase_bps: u32) -> FeeParams {
+        FeeParams {
+            position_fee_bps_increase: increase_bps,
+            position_fee_bps_decrease: increase_bps,
+            liquidation_fee_bps: 50,
+            helpful_rebate_percent: 0,
+            neutral_gets_rebate: false,
+        }
+    }
+
+    #[test]
+    fn each_market_pays_its_registered_fee() {
+        let btc = MarketId(1);
+        let alt = MarketId(2);
+        let unregistered = MarketId(3);
+
+        let mut svc = BasicFeesService::new(10, 10, 50, 0);
+        svc.register_market(btc, params(5));
+        svc.register_market(alt, params(30));
+
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        let fee_for = |market_id: MarketId| {
+            svc.compute_fees(
+                &pos(market_id),
+                &increase_order(market_id),
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                usd(10_000),
+            )
+            .unwrap()
+        };
+
+        // $10k notional: 5 bps => $5, 30 bps => $30, default 10 bps => $10.
+        let btc_fees = fee_for(btc);
+        assert_eq!(btc_fees.position_fee_usd, usd(5));
+        assert_eq!(btc_fees.market_id, btc);
+        assert_eq!(fee_for(alt).position_fee_usd, usd(30));
+        assert_eq!(fee_for(unregistered).position_fee_usd, usd(10));
+    }
+
+    #[test]
+    fn fees_charge_at_min_price_and_rebates_refund_at_max_price() {
+        let mut svc = BasicFeesService::new(10, 10, 50, 0);
+        let mut p = params(10);
+        p.helpful_rebate_percent = 50;
+        svc.register_market(MarketId(1), p);
+
+        // Collateral quoted $0.99 .. $1.01 per atom.
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(99) / 100,
+            collateral_price_max: usd(101) / 100,
+        };
+        let order = increase_order(MarketId(1));
+
+        // Harmful: full 10 bps on $10k = $10, charged at $0.99 => ceil(10.101..) = 11.
+        let fees = svc
+            .compute_fees(
+                &pos(MarketId(1)),
+                &order,
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                usd(10_000),
+            )
+            .unwrap();
+        assert_eq!(fees.position_fee_usd, usd(10));
+        assert_eq!(fees.position_fee_tokens, U256::from(11));
+        assert!(fees.helpful_rebate_usd.is_zero());
+
+        // Helpful: 5 bps => $5 charged (ceil(5.05..) = 6), $5 rebate refunded at $1.01
+        // => floor(4.95..) = 4.
+        let fees = svc
+            .compute_fees(
+                &pos(MarketId(1)),
+                &order,
+                &prices,
+                true,
+                SignedU256::pos(usd(1)),
+                usd(10_000),
+            )
+            .unwrap();
+        assert_eq!(fees.position_fee_usd, usd(5));
+        assert_eq!(fees.position_fee_tokens, U256::from(6));
+        assert_eq!(fees.helpful_rebate_usd, usd(5));
+        assert_eq!(fees.helpful_rebate_tokens, U256::from(4));
+    }
+
+    #[test]
+    fn exempt_account_pays_no_position_fee_but_still_liquidation_fee() {
+        let mut svc = BasicFeesService::new(10, 10, 50, 0);
+        let exempt = AccountId([1u8; 32]);
+        svc.add_fee_exempt(exempt);
+
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        let order = increase_order(MarketId(1));
+
+        let exempt_pos = pos(MarketId(1));
+        let fees = svc
+            .compute_fees(
+                &exempt_pos,
+                &order,
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                usd(10_000),
+            )
+            .unwrap();
+        assert!(fees.position_fee_usd.is_zero());
+        assert!(fees.position_fee_tokens.is_zero());
+
+        // A normal account pays the configured 10 bps.
+        let mut normal_pos = pos(MarketId(1));
+        normal_pos.key.account = AccountId([2u8; 32]);
+        let fees = svc
+            .compute_fees(
+                &normal_pos,
+                &order,
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                usd(10_000),
+            )
+            .unwrap();
+        assert_eq!(fees.position_fee_usd, usd(10));
+
+        // Liquidations are charged regardless of the exemption.
+        let liquidation = Order {
+            order_type: OrderType::Liquidation,
+            ..order
+        };
+        let fees = svc
+            .compute_fees(
+                &exempt_pos,
+                &liquidation,
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                usd(10_000),
+            )
+            .unwrap();
+        assert!(fees.position_fee_usd.is_zero());
+        assert_eq!(fees.liquidation_fee_usd, usd(50));
+    }
+
+    #[test]
+    fn partial_liquidation_fee_is_on_liquidated_portion() {
+        let svc = BasicFeesService::new(10, 10, 50, 0);
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        let mut position = pos(MarketId(1));
+        position.size_usd = usd(10_000);
+        position.size_tokens = U256::from(10_000);
+        // The order still carries the full size; only the step notional counts.
+        let liquidation = Order {
+            order_type: OrderType::Liquidation,
+            size_delta_usd: position.size_usd,
+            ..increase_order(MarketId(1))
+        };
+        let fee_for = |size_delta_usd| {
+            svc.compute_fees(
+                &position,
+                &liquidation,
+                &prices,
+                false,
+                SignedU256::neg(usd(1)),
+                size_delta_usd,
+            )
+            .unwrap()
+            .liquidation_fee_usd
+        };
+
+        // 50 bps: $2_500 of $10_000 => $12.5, a quarter of the full-close fee.
+        let partial = fee_for(usd(2_500));
+        assert_eq!(partial, usd(125) / 10);
+        assert_eq!(fee_for(usd(10_000)), partial * 4);
+    }
+
+    #[test]
+    fn protocol_cut_is_claimable_by_treasury() {
+        let treasury = AccountId([9u8; 32]);
+        let svc = BasicFeesService::new(10, 10, 50, 0).with_protocol_account(treasury, 2_500);
+        let market = MarketId(1);
+        let asset = AssetId(10);
+        let step_fees = StepFees {
+            position_fee_usd: usd(10),
+            position_fee_tokens: U256::from(1_000),
+            liquidation_fee_usd: U256::zero(),
+            liquidation_fee_tokens: U256::zero(),
+            helpful_rebate_usd: U256::zero(),
+            helpful_rebate_tokens: U256::zero(),
+            market_id: market,
+            fee_asset: asset,
+        };
+        let mut pools = PoolBalances::new();
+        let mut claimables = Claimables::default();
+
+        svc.apply_fees(&mut pools, &mut claimables, &step_fees);
+        assert_eq!(claimables.get_fee(treasury, asset), U256::from(250));
+        assert_eq!(pools.get_fee_for_pool(market, asset), U256::from(750));
+
+        svc.apply_fees(&mut pools, &mut claimables, &step_fees);
+        assert_eq!(claimables.get_fee(treasury, asset), U256::from(500));
+
+        // The treasury claims through the regular Claimables API.
+        assert_eq!(claimables.claim_all(treasury, asset), Ok(U256::from(500)));
+        assert!(claimables.get_fee(treasury, asset).is_zero());
+
+        // Without a configured share everything stays in the pool.
+        let svc = BasicFeesService::new(10, 10, 50, 0);
+        let mut pools = PoolBalances::new();
+        svc.apply_fees(&mut pools, &mut claimables, &step_fees);
+        assert_eq!(pools.get_fee_for_pool(market, asset), U256::from(1_000));
+        assert!(claimables.get_fee(AccountId::default(), asset).is_zero());
+    }
+
+    #[test]
+    fn neutral_trade_rebate_is_opt_in() {
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        let order = increase_order(MarketId(1));
+        let fee_with = |neutral_gets_rebate: bool, impact: SignedU256| {
+            let mut svc = BasicFeesService::new(10, 10, 50, 0);
+            svc.register_market(
+                MarketId(1),
+                FeeParams {
+                    helpful_rebate_percent: 50,
+                    neutral_gets_rebate,
+                    ..params(10)
+                },
+            );
+            svc.compute_fees(
+                &pos(MarketId(1)),
+                &order,
+                &prices,
+                false,
+                impact,
+                usd(10_000),
+            )
+            .unwrap()
+            .position_fee_usd
+        };
+
+        // Default: a zero-impact trade pays the full 10 bps.
+        assert!(!FeeParams::default().neutral_gets_rebate);
+        assert_eq!(fee_with(false, SignedU256::zero()), usd(10));
+        // Opted in: it gets the 50% helpful rebate.
+        assert_eq!(fee_with(true, SignedU256::zero()), usd(5));
+        // Harmful trades never do.
+        assert_eq!(fee_with(true, SignedU256::neg(usd(1))), usd(10));
     }
 }
@@ -1,8 +1,8 @@
-use primitive_types::U256;
+use primitive_types::{U256, U512};
 
 use crate::math;
 use crate::state::{MarketState, Position};
-use crate::types::{Side, SignedU256, Timestamp};
+use crate::types::{Side, SignedU256, Timestamp, Usd};
 /// Funding index scale.
 /// Index is stored as: (funding USD per 1 USD of position) * SCALE.
 ///
@@ -27,11 +27,204 @@ fn rate_fp_per_sec() -> U256 {
     (funding_index_scale() / U256::from(SECONDS_PER_DAY)) * U256::from(DAILY_RATE_BPS)
         / U256::from(BPS_DENOM)
 }
+
+/// Per-unit index move of the receiving side, so that total flow is conserved:
+///
+///   payer_oi * payer_delta == receiver_oi * receiver_delta
+///   => receiver_delta = payer_delta * payer_oi / receiver_oi (floor)
+///
+/// Flooring keeps receivers from getting more than payers pay.
+/// With no receiving OI nobody is credited.
+fn receiver_delta_fp(payer_delta_fp: U256, payer_oi: Usd, receiver_oi: Usd) -> U256 {
+    if receiver_oi.is_zero() {
+        return U256::zero();
+    }
+    let wide = U512::from(payer_delta_fp) * U512::from(payer_oi) / U512::from(receiver_oi);
+    U256::try_from(wide).unwrap_or(U256::MAX)
+}
+
+/// Hard cap on |cumulative funding index| (1e34 in index scale 1e18).
+///
+/// Keeps `size_usd * delta_index` well inside U256 for any realistic size
+/// (up to ~1e40 USD(1e30), i.e. $1e10 notional). Indices saturate at the cap
+/// instead of overflowing; once a side is pinned, funding for it stops moving.
+/// Re-basing both indices (subtracting a common baseline) is only safe after
+/// every position of the market has been settled to the current index.
+pub const MAX_FUNDING_INDEX_MAG: U256 = U256([0x378d_8e64_0000_0000, 0x0001_ed09_bead_87c0, 0, 0]);
+
+/// idx + delta, clamped to ±`MAX_FUNDING_INDEX_MAG` (never overflows).
+fn accumulate_index(idx: SignedU256, delta: SignedU256) -> SignedU256 {
+    let next = math::checked_signed_add(idx, delta).unwrap_or(SignedU256 {
+        is_negative: delta.is_negative,
+        mag: U256::MAX,
+    });
+    if next.mag > MAX_FUNDING_INDEX_MAG {
+        SignedU256 {
+            is_negative: next.is_negative,
+            mag: MAX_FUNDING_INDEX_MAG,
+        }
+    } else {
+        next
+    }
+}
+
+/// Default `MarketState::max_funding_rate_fp_per_sec`: 1% per hour
+/// (1e18 / 100 / 3_600), far above the fixed 1 bp/day payer rate.
+pub const DEFAULT_MAX_FUNDING_RATE_FP_PER_SEC: U256 = U256([2_777_777_777_777, 0, 0, 0]);
+
+/// Most a funding index may move over `elapsed` seconds at `max_rate`
+/// (`MarketState::max_funding_rate_fp_per_sec`); `None` = unbounded.
+fn max_index_delta(max_rate: U256, elapsed: u64) -> Option<U256> {
+    (!max_rate.is_zero()).then(|| max_rate.saturating_mul(U256::from(elapsed)))
+}
+
+/// Funding rate model used by `BasicFundingService::update_indices`.
+///
+/// Only decides how fast the paying side's index grows and who pays; index
+/// accumulation, receiver weighting and saturation stay in the service.
+pub trait FundingRateModel {
+    /// Payer-side rate (index units per second, scale 1e18) and the paying
+    /// side. `None` = no funding for this interval.
+    fn rate_fp_per_sec(
+        &self,
+        long_oi: Usd,
+        short_oi: Usd,
+        liquidity_usd: Usd,
+    ) -> (U256, Option<Side>);
+}
+
+/// MVP model: the heavier side pays a fixed `DAILY_RATE_BPS` per day,
+/// regardless of the size of the imbalance or pool liquidity.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct FixedRateFundingModel;
+
+impl FundingRateModel for FixedRateFundingModel {
+    fn rate_fp_per_sec(
+        &self,
+        long_oi: Usd,
+        short_oi: Usd,
+        _liquidity_usd: Usd,
+    ) -> (U256, Option<Side>) {
+        if long_oi.is_zero() && short_oi.is_zero() {
+            (U256::zero(), None)
+        } else if long_oi > short_oi {
+            (rate_fp_per_sec(), Some(Side::Long))
+        } else {
+            (rate_fp_per_sec(), Some(Side::Short))
+        }
+    }
+}
+
+/// Side that currently pays funding under `model`, `None` when nobody pays.
+///
+/// With `FixedRateFundingModel`: long-heavy → longs pay; otherwise
+/// (short-heavy or balanced) → shorts pay.
+pub fn funding_payer(model: &dyn FundingRateModel, market: &MarketState) -> Option<Side> {
+    current_funding_rate_fp_per_sec(model, market).1
+}
+
+/// Current payer-side funding rate (index units per second, scale 1e18)
+/// and the paying side under `model` (see `FundingService::rate_model`).
+/// Zero rate when there is no open interest.
+pub fn current_funding_rate_fp_per_sec(
+    model: &dyn FundingRateModel,
+    market: &MarketState,
+) -> (U256, Option<Side>) {
+    if market.oi_long_usd.is_zero() && market.oi_short_usd.is_zero() {
+        return (U256::zero(), None);
+    }
+    model.rate_fp_per_sec(
+        market.oi_long_usd,
+        market.oi_short_usd,
+        market.liquidity_usd,
+    )
+}
+
+/// Estimated funding for holding `pos` another `horizon_secs` at the current
+/// rate of `model`, assuming OI stays as it is now. Same sign as `FundingDelta`:
+/// positive = the position pays, negative = it receives.
+///
+/// Only the future interval is projected; anything already accrued since the
+/// position's snapshot is not included (see `preview_funding_fee_usd`).
+pub fn project_funding_cost(
+    model: &dyn FundingRateModel,
+    pos: &Position,
+    market: &MarketState,
+    horizon_secs: u64,
+) -> Result<SignedU256, String> {
+    let (rate, Some(payer)) = current_funding_rate_fp_per_sec(model, market) else {
+        return Ok(SignedU256::zero());
+    };
+    let payer_delta_fp = rate.saturating_mul(U256::from(horizon_secs));
+
+    let (payer_oi, receiver_oi) = match payer {
+        Side::Long => (market.oi_long_usd, market.oi_short_usd),
+        Side::Short => (market.oi_short_usd, market.oi_long_usd),
+    };
+    let pays = pos.key.side == payer;
+    let delta_fp = if pays {
+        payer_delta_fp
+    } else {
+        receiver_delta_fp(payer_delta_fp, payer_oi, receiver_oi)
+    };
+
+    let fee_mag = pos
+        .size_usd
+        .checked_mul(delta_fp)
+        .ok_or("funding_fee_mul_overflow")?
+        / funding_index_scale();
+
+    Ok(if fee_mag.is_zero() {
+        SignedU256::zero()
+    } else if pays {
+        SignedU256::pos(fee_mag)
+    } else {
+        SignedU256::neg(fee_mag)
+    })
+}
+
+/// Funding owed by `size_usd` for an index move `from_index -> to_index`:
+/// `size_usd * (to - from) / scale`, magnitude floored.
+///
+/// Pure version of the formula used by `settle_position_funding`, for
+/// off-chain reconciliation. `scale` is the funding index scale (1e18,
+/// `fp::SCALE`). Positive => the holder pays, negative => receives.
+/// The settlement dust floor is not applied.
+pub fn funding_owed(
+    size_usd: Usd,
+    from_index: SignedU256,
+    to_index: SignedU256,
+    scale: U256,
+) -> Result<SignedU256, String> {
+    if scale.is_zero() {
+        return Err("funding_scale_zero".into());
+    }
+    let delta_idx =
+        math::checked_signed_sub(to_index, from_index).ok_or("funding_index_overflow")?;
+    let fee_mag = size_usd
+        .checked_mul(math::signed_abs(delta_idx))
+        .ok_or("funding_fee_mul_overflow")?
+        / scale;
+
+    // sign of fee == sign of delta_idx
+    Ok(if fee_mag.is_zero() {
+        SignedU256::zero()
+    } else if delta_idx.is_negative {
+        SignedU256::neg(fee_mag) // user receives
+    } else {
+        SignedU256::pos(fee_mag) // user pays
+    })
+}
+
 /// Result of funding settlement for a single position.
 #[derive(Debug, Clone, Copy)]
 pub struct FundingDelta {
     /// Positive value means "user pays", negative — "user receives".
     pub funding_fee_usd: SignedU256,
+    /// The position snapshot could not be reconciled with the market index
+    /// (index delta or fee overflowed, e.g. a corrupted / restored `funding_index`).
+    /// The fee is zero and the snapshot is resynced to the current index.
+    pub index_anomaly: bool,
 }
 
 /// Funding service: responsible for
@@ -41,22 +234,52 @@ pub trait FundingService {
     /// Update market funding indices up to `now`, based on current OI imbalance.
     fn update_indices(&self, market: &mut MarketState, now: Timestamp);
 
+    /// Rate model the indices accrue with; previews and summaries must use it
+    /// too (see `preview_funding_fee_usd`, `MarketState::summary`).
+    fn rate_model(&self) -> &dyn FundingRateModel;
+
     /// Compute funding delta for a given position (using market indices)
     /// and update the position snapshot to the latest index.
     ///
     /// Returns how much funding this position should pay (positive)
-    /// or receive (negative) in USD.
-    fn settle_position_funding(&self, market: &MarketState, pos: &mut Position) -> FundingDelta;
+    /// or receive (negative) in USD. Errors leave the position untouched.
+    ///
+    /// When `market.min_funding_settlement_usd` is set, a |fee| below it is
+    /// deferred: zero is returned and the fee is added to
+    /// `pos.pending_funding_usd`, to be settled once the carried total crosses
+    /// the floor. The snapshot always advances, so a deferred amount keeps the
+    /// size it accrued at even if the position is resized before it settles.
+    /// A position closed with a deferred amount forfeits it as dust.
+    fn settle_position_funding(
+        &self,
+        market: &MarketState,
+        pos: &mut Position,
+    ) -> Result<FundingDelta, String>;
 }
 
 /// Basic implementation:
 ///
-/// - Uses a very simple rule:
+/// - Rate and payer come from `model` (`FixedRateFundingModel` by default):
 ///     * If longs > shorts → longs pay a fixed rate to shorts.
 ///     * If shorts > longs → shorts pay a fixed rate to longs.
-/// - Rate depends on imbalance **sign**, not magnitude (MVP).
+/// - The default rate depends on imbalance **sign**, not magnitude (MVP).
 #[derive(Default, Clone)]
-pub struct BasicFundingService;
+pub struct BasicFundingService<M: FundingRateModel = FixedRateFundingModel> {
+    pub model: M,
+}
+
+impl BasicFundingService {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl<M: FundingRateModel> BasicFundingService<M> {
+    /// Service accruing indices at the rate given by `model`.
+    pub fn with_model(model: M) -> Self {
+        Self { model }
+    }
+}
 
 fn current_index_for_side(market: &MarketState, side: Side) -> SignedU256 {
     match side {
@@ -65,8 +288,14 @@ fn current_index_for_side(market: &MarketState, side: Side) -> SignedU256 {
     }
 }
 
-impl FundingService for BasicFundingService {
+impl<M: FundingRateModel> FundingService for BasicFundingService<M> {
+    fn rate_model(&self) -> &dyn FundingRateModel {
+        &self.model
+    }
+
     fn update_indices(&self, market: &mut MarketState, now: Timestamp) {
+        let liquidity_usd = market.liquidity_usd;
+        let max_rate = market.max_funding_rate_fp_per_sec;
         let funding = &mut market.funding;
 
         // 1) First-time init or no time passed.
@@ -89,37 +318,42 @@ impl FundingService for BasicFundingService {
         let total_oi = long_oi + short_oi;
 
         // If there is no open interest at all, funding does not move.
+        // The clock either skips the gap (default) or stays put so the gap is
+        // accrued once OI shows up (`accrue_funding_over_zero_oi_gaps`).
         if total_oi.is_zero() {
-            funding.last_updated_at = now;
+            if !market.accrue_funding_over_zero_oi_gaps {
+                funding.last_updated_at = now;
+            }
             return;
         }
 
-        // 3) Very simple rule for MVP:
-        //
-        //    - If market is long-heavy → longs pay shorts at a fixed rate.
-        //    - If short-heavy → shorts pay longs.
-        //
-        // rate_abs_fp is "index units per second", in FUNDING_INDEX_SCALE.
+        // 3) Rate and paying side from the rate model (fixed rate, heavier
+        //    side pays, by default). The rate is "index units per second",
+        //    in FUNDING_INDEX_SCALE.
+        let (rate_fp, payer) = self.model.rate_fp_per_sec(long_oi, short_oi, liquidity_usd);
+        let Some(payer) = payer else {
+            funding.last_updated_at = now;
+            return;
+        };
 
-        let delta_index_fp = rate_fp_per_sec().saturating_mul(U256::from(dt));
-        if long_oi > short_oi {
-            // Long-heavy → longs pay (their index increases), shorts receive (their index decreases)
-            funding.cumulative_index_long = math::signed_add(
+        let max_delta_fp = max_index_delta(max_rate, dt).unwrap_or(U256::MAX);
+        let delta_index_fp = rate_fp.saturating_mul(U256::from(dt)).min(max_delta_fp);
+        // Clamping receivers only credits less than payers pay.
+        if payer == Side::Long {
+            // Longs pay (their index increases), shorts receive (their index decreases)
+            let receive_fp = receiver_delta_fp(delta_index_fp, long_oi, short_oi).min(max_delta_fp);
+            funding.cumulative_index_long = accumulate_index(
                 funding.cumulative_index_long,
                 SignedU256::pos(delta_index_fp),
             );
-            funding.cumulative_index_short = math::signed_sub(
-                funding.cumulative_index_short,
-                SignedU256::pos(delta_index_fp),
-            );
+            funding.cumulative_index_short =
+                accumulate_index(funding.cumulative_index_short, SignedU256::neg(receive_fp));
         } else {
-            // Short-heavy → shorts pay, longs receive
-            // Short-heavy: shorts pay
-            funding.cumulative_index_long = math::signed_sub(
-                funding.cumulative_index_long,
-                SignedU256::pos(delta_index_fp),
-            );
-            funding.cumulative_index_short = math::signed_add(
+            // Shorts pay, longs receive
+            let receive_fp = receiver_delta_fp(delta_index_fp, short_oi, long_oi).min(max_delta_fp);
+            funding.cumulative_index_long =
+                accumulate_index(funding.cumulative_index_long, SignedU256::neg(receive_fp));
+            funding.cumulative_index_short = accumulate_index(
                 funding.cumulative_index_short,
                 SignedU256::pos(delta_index_fp),
             );
@@ -128,19 +362,43 @@ impl FundingService for BasicFundingService {
         funding.last_updated_at = now;
     }
 
-    fn settle_position_funding(&self, market: &MarketState, pos: &mut Position) -> FundingDelta {
+    fn settle_position_funding(
+        &self,
+        market: &MarketState,
+        pos: &mut Position,
+    ) -> Result<FundingDelta, String> {
         // 1) Choose market index for position side (long/short).
         let current_idx = current_index_for_side(market, pos.key.side);
         let prev_idx = pos.funding_index;
+        let prev_updated_at = pos.funding_updated_at;
+        let elapsed = market
+            .funding
+            .last_updated_at
+            .saturating_sub(prev_updated_at);
 
         // delta_idx = current - prev (signed)
-        let delta_idx = math::signed_sub(current_idx, prev_idx);
         pos.funding_index = current_idx;
+        pos.funding_updated_at = market.funding.last_updated_at;
+        let anomaly = FundingDelta {
+            funding_fee_usd: SignedU256::zero(),
+            index_anomaly: true,
+        };
+        let Some(delta_idx) = math::checked_signed_sub(current_idx, prev_idx) else {
+            return Ok(anomaly);
+        };
+        // No index moves faster than the market's max rate, so a larger delta
+        // comes from a corrupted snapshot rather than accrued funding.
+        if max_index_delta(market.max_funding_rate_fp_per_sec, elapsed)
+            .is_some_and(|max| delta_idx.mag > max)
+        {
+            return Ok(anomaly);
+        }
 
         if delta_idx.is_zero() || pos.size_usd.is_zero() {
-            return FundingDelta {
+            return Ok(FundingDelta {
                 funding_fee_usd: SignedU256::zero(),
-            };
+                index_anomaly: false,
+            });
         }
         // 2) funding_fee_usd = sizeUsd * deltaIndex / SCALE
         //
@@ -149,34 +407,44 @@ impl FundingService for BasicFundingService {
         //   - Negative funding_fee_usd → user receives.
         //
         // Since we made payers' index go UP, receivers' index go DOWN,
-        // the formula below automatically gives the right sign:
-        // fee_mag = size_usd * abs(delta_idx) / SCALE
-        let abs_idx = math::signed_abs(delta_idx);
-        let scale = funding_index_scale();
-
-        let fee_mag = match pos.size_usd.checked_mul(abs_idx) {
-            Some(prod) => prod / scale, // floor
-            None => U256::MAX,          // MVP fallback
+        // `funding_owed` automatically gives the right sign.
+        //
+        // An overflowing product cannot come from a real index move:
+        // flag it instead of charging / paying a saturated fee.
+        let Ok(fee) = funding_owed(pos.size_usd, prev_idx, current_idx, funding_index_scale())
+        else {
+            return Ok(anomaly);
         };
 
-        // sign of fee == sign of delta_idx
-        let fee = if delta_idx.is_negative {
-            SignedU256::neg(fee_mag) // user receives
-        } else {
-            SignedU256::pos(fee_mag) // user pays
+        // Dust floor: carry the fee (already priced at the current size) forward.
+        let Some(fee) = math::checked_signed_add(fee, pos.pending_funding_usd) else {
+            pos.funding_index = prev_idx;
+            pos.funding_updated_at = prev_updated_at;
+            return Err("funding_fee_overflow".into());
         };
+        if fee.mag < market.min_funding_settlement_usd {
+            pos.pending_funding_usd = fee;
+            return Ok(FundingDelta {
+                funding_fee_usd: SignedU256::zero(),
+                index_anomaly: false,
+            });
+        }
+        pos.pending_funding_usd = SignedU256::zero();
 
-        FundingDelta {
+        Ok(FundingDelta {
             funding_fee_usd: fee,
-        }
+            index_anomaly: false,
+        })
     }
 }
 
-/// Preview funding fee for the position if we advanced indices to `now`.
+/// Preview funding fee for the position if we advanced indices to `now`
+/// with `model` (same accrual rule as `BasicFundingService::update_indices`).
 /// Returns SignedU256:
 ///   + => user pays,
 ///   - => user receives.
 pub fn preview_funding_fee_usd(
+    model: &dyn FundingRateModel,
     market: &MarketState,
     pos: &Position,
     now: Timestamp,
@@ -192,27 +460,29 @@ pub fn preview_funding_fee_usd(
 
     let long_oi = market.oi_long_usd;
     let short_oi = market.oi_short_usd;
-    if long_oi.is_zero() && short_oi.is_zero() {
+    let (rate_fp, Some(payer)) = current_funding_rate_fp_per_sec(model, market) else {
         return Ok(SignedU256::zero());
-    }
-
-    let delta_index_fp = rate_fp_per_sec().saturating_mul(U256::from(dt));
+    };
+    let max_delta_fp = max_index_delta(market.max_funding_rate_fp_per_sec, dt).unwrap_or(U256::MAX);
+    let delta_index_fp = rate_fp.saturating_mul(U256::from(dt)).min(max_delta_fp);
 
     // Compute hypothetical indices after update (same rule as FundingService)
     let mut idx_long = market.funding.cumulative_index_long;
     let mut idx_short = market.funding.cumulative_index_short;
 
-    if long_oi > short_oi {
-        // long-heavy: longs pay (index up), shorts receive (index down)
-        idx_long = math::signed_add(idx_long, SignedU256::pos(delta_index_fp));
-        idx_short = math::signed_sub(idx_short, SignedU256::pos(delta_index_fp));
-    } else if short_oi > long_oi {
-        // short-heavy: shorts pay, longs receive
-        idx_long = math::signed_sub(idx_long, SignedU256::pos(delta_index_fp));
-        idx_short = math::signed_add(idx_short, SignedU256::pos(delta_index_fp));
-    } else {
-        // balanced: no move
-        return Ok(SignedU256::zero());
+    match payer {
+        Side::Long => {
+            // longs pay (index up), shorts receive (index down)
+            let receive_fp = receiver_delta_fp(delta_index_fp, long_oi, short_oi).min(max_delta_fp);
+            idx_long = accumulate_index(idx_long, SignedU256::pos(delta_index_fp));
+            idx_short = accumulate_index(idx_short, SignedU256::neg(receive_fp));
+        }
+        Side::Short => {
+            // shorts pay, longs receive
+            let receive_fp = receiver_delta_fp(delta_index_fp, short_oi, long_oi).min(max_delta_fp);
+            idx_long = accumulate_index(idx_long, SignedU256::neg(receive_fp));
+            idx_short = accumulate_index(idx_short, SignedU256::pos(delta_index_fp));
+        }
     }
 
     let current_idx = match pos.key.side {
@@ -220,7 +490,8 @@ pub fn preview_funding_fee_usd(
         Side::Short => idx_short,
     };
     let prev_idx = pos.funding_index;
-    let delta_idx = math::signed_sub(current_idx, prev_idx);
+    let delta_idx =
+        math::checked_signed_sub(current_idx, prev_idx).ok_or("funding_index_anomaly")?;
     if delta_idx.is_zero() || pos.size_usd.is_zero() {
         return Ok(SignedU256::zero());
     }
@@ -240,3 +511,483 @@ pub fn preview_funding_fee_usd(
         SignedU256::pos(fee_mag) // user pays
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::fp;
+    use crate::state::PositionKey;
+    use crate::types::{AccountId, AssetId, MarketId};
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn imbalanced_book_conserves_funding_flow() {
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(300_000);
+        market.oi_short_usd = usd(100_000);
+
+        let svc = BasicFundingService::new();
+        svc.update_indices(&mut market, 1 + 86_400);
+
+        let long_idx = market.funding.cumulative_index_long;
+        let short_idx = market.funding.cumulative_index_short;
+        assert!(!long_idx.is_negative && short_idx.is_negative);
+        // Receivers are 3x smaller, so their per-unit credit is 3x larger.
+        assert_eq!(short_idx.mag, long_idx.mag * 3);
+
+        let paid = market.oi_long_usd * long_idx.mag / funding_index_scale();
+        let received = market.oi_short_usd * short_idx.mag / funding_index_scale();
+        assert_eq!(paid, received);
+
+        // Preview uses the same weighting.
+        let mut market2 = market.clone();
+        market2.funding.cumulative_index_long = SignedU256::zero();
+        market2.funding.cumulative_index_short = SignedU256::zero();
+        market2.funding.last_updated_at = 1;
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Short,
+        };
+        let short = Position::open(
+            key,
+            &market2,
+            market.oi_short_usd,
+            U256::one(),
+            U256::zero(),
+            1,
+        )
+        .unwrap();
+        let preview =
+            preview_funding_fee_usd(&FixedRateFundingModel, &market2, &short, 1 + 86_400).unwrap();
+        assert_eq!(preview, SignedU256::neg(received));
+    }
+
+    #[test]
+    fn custom_rate_model_drives_index_accumulation() {
+        /// Default rate, but the lighter side pays.
+        struct InvertedPayer;
+
+        impl FundingRateModel for InvertedPayer {
+            fn rate_fp_per_sec(
+                &self,
+                long_oi: Usd,
+                short_oi: Usd,
+                liquidity_usd: Usd,
+            ) -> (U256, Option<Side>) {
+                let (rate, payer) =
+                    FixedRateFundingModel.rate_fp_per_sec(long_oi, short_oi, liquidity_usd);
+                let inverted = payer.map(|side| match side {
+                    Side::Long => Side::Short,
+                    Side::Short => Side::Long,
+                });
+                (rate, inverted)
+            }
+        }
+
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(300_000);
+        market.oi_short_usd = usd(100_000);
+
+        let mut default_market = market.clone();
+        BasicFundingService::new().update_indices(&mut default_market, 1 + 86_400);
+        BasicFundingService::with_model(InvertedPayer).update_indices(&mut market, 1 + 86_400);
+
+        // Long-heavy book, yet shorts pay and longs receive.
+        let long_idx = market.funding.cumulative_index_long;
+        let short_idx = market.funding.cumulative_index_short;
+        assert!(long_idx.is_negative && !short_idx.is_negative);
+        // Same payer-side move as the default model, receivers weighted by OI.
+        assert_eq!(short_idx, default_market.funding.cumulative_index_long);
+        assert_eq!(long_idx.mag * 3, short_idx.mag);
+        assert_eq!(market.funding.last_updated_at, 1 + 86_400);
+
+        // Read-side helpers follow the service's model, not the default one.
+        let svc = BasicFundingService::with_model(InvertedPayer);
+        assert_eq!(funding_payer(svc.rate_model(), &market), Some(Side::Short));
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Short,
+        };
+        let short =
+            Position::open(key, &market, usd(10_000), U256::one(), U256::zero(), 1).unwrap();
+        let projected = project_funding_cost(svc.rate_model(), &short, &market, 3_600).unwrap();
+        assert!(!projected.is_negative && !projected.is_zero());
+
+        let mut later = market.clone();
+        svc.update_indices(&mut later, 1 + 2 * 86_400);
+        let preview =
+            preview_funding_fee_usd(svc.rate_model(), &market, &short, 1 + 2 * 86_400).unwrap();
+        let delta = svc
+            .settle_position_funding(&later, &mut short.clone())
+            .unwrap();
+        assert_eq!(preview, delta.funding_fee_usd);
+    }
+
+    #[test]
+    fn funding_owed_matches_settlement() {
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(300_000);
+        market.oi_short_usd = usd(100_000);
+        let svc = BasicFundingService::new();
+
+        for side in [Side::Long, Side::Short] {
+            let key = PositionKey {
+                account: AccountId([1; 32]),
+                market_id: market.id,
+                collateral_token: AssetId(10),
+                side,
+            };
+            let mut pos =
+                Position::open(key, &market, usd(12_345), U256::one(), U256::zero(), 1).unwrap();
+            let from = pos.funding_index;
+
+            let mut m = market.clone();
+            svc.update_indices(&mut m, 1 + 86_400 + 7);
+            let to = current_index_for_side(&m, side);
+
+            let settled = svc.settle_position_funding(&m, &mut pos).unwrap();
+            let owed = funding_owed(pos.size_usd, from, to, fp::SCALE).unwrap();
+            assert!(!settled.funding_fee_usd.is_zero());
+            assert_eq!(owed, settled.funding_fee_usd);
+            assert_eq!(owed.is_negative, side == Side::Short);
+        }
+
+        assert!(
+            funding_owed(
+                usd(1),
+                SignedU256::pos(U256::one()),
+                SignedU256::pos(U256::one()),
+                fp::SCALE
+            )
+            .unwrap()
+            .is_zero()
+        );
+        assert_eq!(
+            funding_owed(
+                usd(1),
+                SignedU256::zero(),
+                SignedU256::pos(U256::MAX),
+                fp::SCALE
+            )
+            .unwrap_err(),
+            "funding_fee_mul_overflow"
+        );
+        assert_eq!(
+            funding_owed(usd(1), SignedU256::zero(), SignedU256::zero(), U256::zero()).unwrap_err(),
+            "funding_scale_zero"
+        );
+    }
+
+    #[test]
+    fn zero_oi_gap_accrues_only_when_configured() {
+        let svc = BasicFundingService::new();
+        let run = |accrue_gap: bool| {
+            let mut market = MarketState {
+                accrue_funding_over_zero_oi_gaps: accrue_gap,
+                ..MarketState::new(MarketId(1), Usd::zero(), 1)
+            };
+
+            // Quiet period: an update with no OI moves nothing.
+            svc.update_indices(&mut market, 1_001);
+            assert!(market.funding.cumulative_index_long.is_zero());
+            let clock = market.funding.last_updated_at;
+
+            // A long-only trade lands, then the indices are synced at 2_001.
+            market.oi_long_usd = usd(1_000);
+            svc.update_indices(&mut market, 2_001);
+            assert_eq!(market.funding.last_updated_at, 2_001);
+            (clock, market.funding.cumulative_index_long)
+        };
+
+        let (clock, skipped) = run(false);
+        assert_eq!(clock, 1_001);
+        assert_eq!(skipped, SignedU256::pos(rate_fp_per_sec() * 1_000));
+
+        let (clock, accrued) = run(true);
+        assert_eq!(clock, 1);
+        assert_eq!(accrued, SignedU256::pos(rate_fp_per_sec() * 2_000));
+    }
+
+    #[test]
+    fn sub_threshold_funding_is_deferred_until_it_crosses_the_floor() {
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(3_000);
+        market.oi_short_usd = usd(1_000);
+        market.min_funding_settlement_usd = usd(1);
+
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        // 1 bp/day on $1_000 is just under $0.10 per day.
+        let mut pos =
+            Position::open(key, &market, usd(1_000), U256::one(), U256::zero(), 1).unwrap();
+        let opening_idx = pos.funding_index;
+
+        let svc = BasicFundingService::new();
+        let mut now: Timestamp = 1;
+        let mut settled = None;
+        for day in 1..=20u64 {
+            now += 86_400;
+            svc.update_indices(&mut market, now);
+            let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+            if !delta.funding_fee_usd.is_zero() {
+                settled = Some((day, delta.funding_fee_usd));
+                break;
+            }
+            // Deferred: the fee is carried on the position, the snapshot advances.
+            assert_eq!(pos.funding_index, market.funding.cumulative_index_long);
+            assert!(!pos.pending_funding_usd.is_negative);
+            assert!(!pos.pending_funding_usd.is_zero());
+        }
+
+        // Ten days round to just under $1, so the 11th settles everything at once.
+        let (day, fee) = settled.expect("funding must eventually settle");
+        assert_eq!(day, 11);
+        let accumulated = math::signed_sub(market.funding.cumulative_index_long, opening_idx);
+        let whole = usd(1_000) * accumulated.mag / funding_index_scale();
+        // Each daily step floors separately.
+        assert!(!fee.is_negative);
+        assert!(fee.mag <= whole && whole - fee.mag <= U256::from(11));
+        assert!(fee.mag >= usd(1));
+        assert!(pos.pending_funding_usd.is_zero());
+    }
+
+    #[test]
+    fn deferred_funding_keeps_the_size_it_accrued_at() {
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(1_000);
+        market.oi_short_usd = usd(3_000);
+        market.min_funding_settlement_usd = usd(1);
+
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        // A small receiver accrues a few days of sub-floor rewards...
+        let mut pos = Position::open(key, &market, usd(100), U256::one(), U256::zero(), 1).unwrap();
+        let svc = BasicFundingService::new();
+        svc.update_indices(&mut market, 1 + 5 * 86_400);
+        let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+        assert!(delta.funding_fee_usd.is_zero());
+        let deferred = pos.pending_funding_usd;
+        assert!(deferred.is_negative);
+
+        // ...then grows 1000x before the next settlement.
+        pos.size_usd = usd(100_000);
+        let before = pos.funding_index;
+        svc.update_indices(&mut market, 1 + 5 * 86_400 + 3_600);
+        let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+
+        // Only the last hour is priced at the new size.
+        let last_hour = funding_owed(
+            usd(100_000),
+            before,
+            market.funding.cumulative_index_long,
+            funding_index_scale(),
+        )
+        .unwrap();
+        assert_eq!(delta.funding_fee_usd, math::signed_add(last_hour, deferred));
+        assert!(pos.pending_funding_usd.is_zero());
+    }
+
+    #[test]
+    fn projected_funding_matches_settlement_over_the_horizon() {
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.oi_long_usd = usd(300_000);
+        market.oi_short_usd = usd(100_000);
+
+        let open = |side| {
+            let key = PositionKey {
+                account: AccountId([1; 32]),
+                market_id: market.id,
+                collateral_token: AssetId(10),
+                side,
+            };
+            Position::open(key, &market, usd(10_000), U256::one(), U256::zero(), 1).unwrap()
+        };
+        let mut long = open(Side::Long);
+        let mut short = open(Side::Short);
+
+        // 8 hours at 1 bp/day on $10_000 is ~$0.33 for the paying longs.
+        let horizon = 8 * 3_600;
+        let model = &FixedRateFundingModel;
+        let long_cost = project_funding_cost(model, &long, &market, horizon).unwrap();
+        let short_cost = project_funding_cost(model, &short, &market, horizon).unwrap();
+        assert_eq!(
+            long_cost,
+            SignedU256::pos(
+                usd(10_000) * rate_fp_per_sec() * U256::from(horizon) / funding_index_scale()
+            )
+        );
+        assert!(
+            long_cost.mag < usd(1) / U256::from(3u64) && long_cost.mag > usd(1) / U256::from(4u64)
+        );
+        // Receivers are 3x smaller, so they earn 3x per unit.
+        assert!(short_cost.is_negative);
+        assert!(short_cost.mag.abs_diff(long_cost.mag * U256::from(3u64)) < U256::from(3u64));
+
+        // Holding for the horizon settles to exactly the projection.
+        let svc = BasicFundingService::new();
+        svc.update_indices(&mut market, 1 + horizon);
+        let settled_long = svc.settle_position_funding(&market, &mut long).unwrap();
+        let settled_short = svc.settle_position_funding(&market, &mut short).unwrap();
+        assert_eq!(settled_long.funding_fee_usd, long_cost);
+        assert_eq!(settled_short.funding_fee_usd, short_cost);
+
+        // No open interest, no funding.
+        assert!(
+            project_funding_cost(model, &long, &MarketState::default(), horizon)
+                .unwrap()
+                .is_zero()
+        );
+    }
+
+    #[test]
+    fn corrupted_funding_index_is_flagged_not_charged() {
+        let mut market = MarketState::default();
+        market.funding.cumulative_index_long = SignedU256::neg(U256::exp10(15));
+
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let mut pos =
+            Position::open(key, &market, usd(1_000), U256::one(), U256::zero(), 1).unwrap();
+        // Snapshot far above anything the market index has ever been.
+        pos.funding_index = SignedU256::pos(U256::MAX / 2);
+
+        let svc = BasicFundingService::new();
+        let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+        assert!(delta.index_anomaly);
+        assert!(delta.funding_fee_usd.is_zero());
+        assert_eq!(pos.funding_index, market.funding.cumulative_index_long);
+
+        // Resynced snapshot settles normally afterwards.
+        let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+        assert!(!delta.index_anomaly);
+        assert!(delta.funding_fee_usd.is_zero());
+
+        // Overflow in the index subtraction itself is caught too.
+        pos.funding_index = SignedU256::pos(U256::MAX);
+        assert!(
+            svc.settle_position_funding(&market, &mut pos)
+                .unwrap()
+                .index_anomaly
+        );
+    }
+
+    #[test]
+    fn index_moves_are_bounded_by_the_max_rate() {
+        let mut market = MarketState::new(MarketId(1), usd(1_000_000), 1);
+        market.max_funding_rate_fp_per_sec = rate_fp_per_sec() * 10;
+        // Receivers 100x thinner than payers would move 100x the payer rate.
+        market.oi_long_usd = usd(100_000);
+        market.oi_short_usd = usd(1_000);
+
+        let key = |side| PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side,
+        };
+        let open = |market: &MarketState, side| {
+            Position::open(key(side), market, usd(1_000), U256::one(), U256::zero(), 1).unwrap()
+        };
+        let mut long = open(&market, Side::Long);
+        let mut short = open(&market, Side::Short);
+
+        let svc = BasicFundingService::new();
+        let dt = 86_400u64;
+        svc.update_indices(&mut market, 1 + dt);
+        let max_delta = market.max_funding_rate_fp_per_sec * U256::from(dt);
+        assert_eq!(
+            market.funding.cumulative_index_long,
+            SignedU256::pos(rate_fp_per_sec() * U256::from(dt))
+        );
+        assert_eq!(
+            market.funding.cumulative_index_short,
+            SignedU256::neg(max_delta)
+        );
+
+        // Both sides settle within the bound.
+        let paid = svc.settle_position_funding(&market, &mut long).unwrap();
+        let received = svc.settle_position_funding(&market, &mut short).unwrap();
+        assert!(!paid.index_anomaly && !received.index_anomaly);
+        assert!(!paid.funding_fee_usd.is_negative && received.funding_fee_usd.is_negative);
+        assert_eq!(long.funding_updated_at, 1 + dt);
+
+        // A snapshot one atom past what the max rate allows since its clock.
+        short.funding_index = SignedU256::pos(U256::one());
+        short.funding_updated_at = 1;
+        let delta = svc.settle_position_funding(&market, &mut short).unwrap();
+        assert!(delta.index_anomaly);
+        assert!(delta.funding_fee_usd.is_zero());
+        assert_eq!(short.funding_index, market.funding.cumulative_index_short);
+    }
+
+    #[test]
+    fn very_long_uptime_saturates_indices_without_overflow() {
+        assert_eq!(MAX_FUNDING_INDEX_MAG, U256::exp10(34));
+
+        // Tiny receiving side => receiver index moves ~1e12x faster than payers'.
+        let mut market = MarketState::default();
+        market.funding.last_updated_at = 1;
+        market.max_funding_rate_fp_per_sec = U256::zero();
+        market.oi_long_usd = usd(1_000_000_000);
+        market.oi_short_usd = U256::exp10(18); // $1e-12
+
+        let svc = BasicFundingService::new();
+        let mut now: Timestamp = 1;
+        for _ in 0..1_000 {
+            now += u64::MAX / 2_000;
+            svc.update_indices(&mut market, now);
+        }
+
+        let long_idx = market.funding.cumulative_index_long;
+        let short_idx = market.funding.cumulative_index_short;
+        assert!(!long_idx.is_negative && short_idx.is_negative);
+        assert!(long_idx.mag <= MAX_FUNDING_INDEX_MAG);
+        assert_eq!(short_idx.mag, MAX_FUNDING_INDEX_MAG);
+
+        // A position settling across the whole capped range does not overflow.
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Short,
+        };
+        let mut pos = Position::open(
+            key,
+            &market,
+            usd(1_000_000_000),
+            U256::one(),
+            U256::zero(),
+            1,
+        )
+        .unwrap();
+        pos.funding_index = SignedU256::pos(MAX_FUNDING_INDEX_MAG);
+        let delta = svc.settle_position_funding(&market, &mut pos).unwrap();
+        assert!(!delta.index_anomaly);
+        assert!(delta.funding_fee_usd.is_negative);
+    }
+}
@@ -4,6 +4,7 @@ use primitive_types::U256;
 
 use crate::math;
 use crate::services::FundingService;
+use crate::services::funding::FundingDelta;
 use crate::state::{Claimables, MarketState, Position};
 use crate::types::{OraclePrices, TokenAmount};
 /// Result of applying funding for a single position on a single step.
@@ -12,10 +13,72 @@ pub struct FundingStep {
     /// How much funding this position must pay in USD (payer side).
     /// Always >= 0.
     pub cost_usd: U256, // signed USD(1e30)
+    /// How much funding this position earns in USD (receiver side).
+    /// Always >= 0.
+    pub reward_usd: U256,
+    /// Raw settlement result this step was derived from.
+    pub delta: FundingDelta,
 }
 
-/// Apply funding for a single position:
+/// Settle funding for a single position without crediting rewards:
+///  - rejects zero collateral prices with `"invalid_price"` before touching
+///    anything (both paths: the payer cost is later converted at
+///    `collateral_price_min`, the receiver reward at `collateral_price_max`);
 ///  - calls FundingService::settle_position_funding (updates pos.funding_index),
+///  - splits the fee into `cost_usd` (payer side) and `reward_usd` (receiver side).
+///
+/// Callers that batch several positions use this to scale rewards before
+/// crediting them with `credit_funding_reward`.
+pub fn settle_funding_step<F: FundingService>(
+    funding_svc: &F,
+    market: &MarketState,
+    pos: &mut Position,
+    prices: &OraclePrices,
+) -> Result<FundingStep, String> {
+    // Index updates are OI-only (no prices); this is the only price use in funding.
+    if prices.collateral_price_min.is_zero() || prices.collateral_price_max.is_zero() {
+        return Err("invalid_price".into());
+    }
+
+    let delta = funding_svc.settle_position_funding(market, pos)?;
+    let fee_usd = delta.funding_fee_usd;
+
+    let (cost_usd, reward_usd) = if fee_usd.is_negative {
+        (U256::zero(), fee_usd.mag)
+    } else {
+        (fee_usd.mag, U256::zero())
+    };
+    Ok(FundingStep {
+        cost_usd,
+        reward_usd,
+        delta,
+    })
+}
+
+/// Mint a funding reward into Claimables in the position's collateral token.
+///
+/// Converts USD -> atoms at collateral_price_max, rounding down to avoid
+/// overpaying. Returns the credited token amount.
+pub fn credit_funding_reward(
+    pos: &Position,
+    reward_usd: U256,
+    claimables: &mut Claimables,
+    prices: &OraclePrices,
+) -> Result<TokenAmount, String> {
+    let reward_tokens: TokenAmount = math::rounding::div_round(
+        reward_usd,
+        prices.collateral_price_max,
+        math::rounding::Rounding::Down,
+    )?;
+
+    if !reward_tokens.is_zero() {
+        claimables.add_funding(pos.key.account, pos.key.collateral_token, reward_tokens);
+    }
+    Ok(reward_tokens)
+}
+
+/// Apply funding for a single position:
+///  - `settle_funding_step` (price checks, snapshot update, cost / reward split);
 ///  - if the position is on the payer side => returns positive cost_usd,
 ///  - if on receiver side => mints Claimables in collateral token and returns cost_usd = 0.
 pub fn apply_funding_step<F: FundingService>(
@@ -25,43 +88,86 @@ pub fn apply_funding_step<F: FundingService>(
     claimables: &mut Claimables,
     prices: &OraclePrices,
 ) -> Result<FundingStep, String> {
-    let delta = funding_svc.settle_position_funding(market, pos);
-    let fee_usd = delta.funding_fee_usd;
-
-    if fee_usd.mag.is_zero() {
-        return Ok(FundingStep {
-            cost_usd: U256::zero(),
-        });
+    let step = settle_funding_step(funding_svc, market, pos, prices)?;
+    if !step.reward_usd.is_zero() {
+        credit_funding_reward(pos, step.reward_usd, claimables, prices)?;
     }
+    Ok(step)
+}
 
-    if !fee_usd.is_negative {
-        // Payer side: position pays funding in USD.
-        return Ok(FundingStep {
-            cost_usd: fee_usd.mag,
-        });
-    }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::funding::BasicFundingService;
+    use crate::state::PositionKey;
+    use crate::types::{AccountId, AssetId, MarketId, Side, SignedU256};
 
-    // Receiver side: position earns funding.
-    //
-    // Convert reward USD -> collateral tokens (atoms).
-    // Using collateral_price_max to minimize token payout (conservative).
-    let price = prices.collateral_price_max;
-    if price.is_zero() {
-        return Err("invalid_collateral_price_max_for_funding".into());
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
     }
 
-    let reward_usd: U256 = fee_usd.mag;
+    #[test]
+    fn payer_path_rejects_zero_collateral_price() {
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let mut pos = Position::open(
+            key,
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(1),
+            U256::from(1_000),
+            1,
+        )
+        .unwrap();
+        // Longs owe funding since the position snapshot.
+        let mut market = MarketState::default();
+        market.funding.cumulative_index_long = SignedU256::pos(U256::exp10(15));
+        market.funding.last_updated_at = 3_600;
+        let mut claimables = Claimables::default();
 
-    // reward_tokens_atoms = reward_usd / price_per_unit
-    // Round Down to avoid overpaying.
-    let reward_tokens: TokenAmount =
-        math::rounding::div_round(reward_usd, price, math::rounding::Rounding::Down)?;
+        let ok_prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        for bad in [
+            OraclePrices {
+                collateral_price_min: U256::zero(),
+                ..ok_prices
+            },
+            OraclePrices {
+                collateral_price_max: U256::zero(),
+                ..ok_prices
+            },
+        ] {
+            let before = pos.clone();
+            assert_eq!(
+                apply_funding_step(
+                    &BasicFundingService::new(),
+                    &market,
+                    &mut pos,
+                    &mut claimables,
+                    &bad
+                )
+                .unwrap_err(),
+                "invalid_price"
+            );
+            assert_eq!(pos, before);
+        }
 
-    if !reward_tokens.is_zero() {
-        claimables.add_funding(pos.key.account, pos.key.collateral_token, reward_tokens);
+        let step = apply_funding_step(
+            &BasicFundingService::new(),
+            &market,
+            &mut pos,
+            &mut claimables,
+            &ok_prices,
+        )
+        .unwrap();
+        assert!(!step.cost_usd.is_zero());
     }
-
-    Ok(FundingStep {
-        cost_usd: U256::zero(),
-    })
 }
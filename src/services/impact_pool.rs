@@ -1,12 +1,134 @@
-use crate::types::Timestamp;
+use primitive_types::{U256, U512};
+
+use crate::state::{MarketState, PoolBalances};
+use crate::types::{Timestamp, TokenAmount};
+
+/// Seconds in the distribution rate period.
+pub const DISTRIBUTION_PERIOD_SECS: u64 = 86_400;
 
 pub trait ImpactPoolService {
-    fn distribute(&self, _now: Timestamp) {
-        // TODO
+    /// Release part of the accumulated impact pool back to LPs.
+    ///
+    /// Moves tokens from `market.impact_pool.impact_tokens` into the market's
+    /// pool balance and returns the amount moved.
+    fn distribute(
+        &self,
+        _market: &mut MarketState,
+        _pools: &mut PoolBalances,
+        _now: Timestamp,
+    ) -> TokenAmount {
+        U256::zero()
     }
 }
 
+/// Linear distribution schedule.
+///
+/// Every call releases
+///   impact_tokens * distribution_rate_bps / 10_000 * elapsed / DISTRIBUTION_PERIOD_SECS
+/// (floor, capped at the pool), i.e. `distribution_rate_bps` of the current pool per day.
+/// Impact tokens are index tokens, so they are credited to
+/// `(market.id, market.index_token)` in `PoolBalances`.
+///
+/// A zero rate (the default) never distributes.
 #[derive(Default, Clone)]
-pub struct BasicImpactPoolService;
+pub struct BasicImpactPoolService {
+    pub distribution_rate_bps: u32,
+}
+
+impl BasicImpactPoolService {
+    pub fn new(distribution_rate_bps: u32) -> Self {
+        Self {
+            distribution_rate_bps,
+        }
+    }
+}
 
-impl ImpactPoolService for BasicImpactPoolService {}
+impl ImpactPoolService for BasicImpactPoolService {
+    fn distribute(
+        &self,
+        market: &mut MarketState,
+        pools: &mut PoolBalances,
+        now: Timestamp,
+    ) -> TokenAmount {
+        let pool = &mut market.impact_pool;
+
+        // First-time init or no time passed.
+        if pool.last_distributed_at == 0 {
+            pool.last_distributed_at = now;
+            return U256::zero();
+        }
+        if now <= pool.last_distributed_at {
+            return U256::zero();
+        }
+
+        let dt = now - pool.last_distributed_at;
+        pool.last_distributed_at = now;
+
+        if self.distribution_rate_bps == 0 || pool.impact_tokens.is_zero() {
+            return U256::zero();
+        }
+
+        let num = U512::from(pool.impact_tokens)
+            * U512::from(self.distribution_rate_bps)
+            * U512::from(dt);
+        let den = U512::from(10_000u64) * U512::from(DISTRIBUTION_PERIOD_SECS);
+        // Capped at the pool, so it always fits back into U256.
+        let amount = U256::try_from((num / den).min(U512::from(pool.impact_tokens)))
+            .unwrap_or(pool.impact_tokens);
+
+        pool.impact_tokens -= amount;
+        pools.add_to_pool(market.id, market.index_token, amount);
+        amount
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AssetId, MarketId};
+
+    fn market() -> MarketState {
+        MarketState {
+            id: MarketId(1),
+            index_token: AssetId(1),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn distributes_scheduled_fraction_into_pool() {
+        // 10% of the impact pool per day.
+        let svc = BasicImpactPoolService::new(1_000);
+        let mut market = market();
+        let mut pools = PoolBalances::new();
+        market.impact_pool.impact_tokens = U256::from(1_000_000u64);
+
+        // First call only starts the clock.
+        assert!(svc.distribute(&mut market, &mut pools, 1_000).is_zero());
+
+        // Half a day later: 5% of the pool.
+        let moved = svc.distribute(&mut market, &mut pools, 1_000 + 43_200);
+        assert_eq!(moved, U256::from(50_000u64));
+        assert_eq!(market.impact_pool.impact_tokens, U256::from(950_000u64));
+        assert_eq!(
+            pools.get_balance(MarketId(1), AssetId(1)),
+            U256::from(50_000u64)
+        );
+        assert_eq!(market.impact_pool.last_distributed_at, 1_000 + 43_200);
+
+        // Same timestamp: nothing more.
+        assert!(
+            svc.distribute(&mut market, &mut pools, 1_000 + 43_200)
+                .is_zero()
+        );
+
+        // A very long gap never distributes more than the pool holds.
+        let moved = svc.distribute(&mut market, &mut pools, 1_000 + 43_200 + 100 * 86_400);
+        assert_eq!(moved, U256::from(950_000u64));
+        assert!(market.impact_pool.impact_tokens.is_zero());
+        assert_eq!(
+            pools.get_balance(MarketId(1), AssetId(1)),
+            U256::from(1_000_000u64)
+        );
+    }
+}
@@ -12,16 +12,19 @@ pub mod margin;
 pub mod open_interest;
 pub mod price_impact;
 pub mod pricing;
+pub mod settlement;
 pub mod step_costs;
+pub mod telemetry;
 
 pub use borrowing::BorrowingService;
-pub use fees::{BasicFeesService, FeesService};
+pub use fees::{BasicFeesService, FeeParams, FeeRegistry, FeesService, PriceDirection};
 pub use funding::FundingService;
 pub use impact_pool::ImpactPoolService;
 pub use margin::MarginService;
 pub use open_interest::OpenInterestService;
 pub use price_impact::PriceImpactService;
 pub use pricing::{BasicPricingService, PricingService};
+pub use telemetry::{NoopTelemetry, Telemetry};
 
 pub trait ServicesBundle {
     type Pricing: PricingService;
@@ -32,6 +35,7 @@ pub trait ServicesBundle {
     type Fees: FeesService;
     type Margin: MarginService;
     type OpenInterest: OpenInterestService;
+    type Telemetry: Telemetry;
 
     fn pricing(&self) -> &Self::Pricing;
     fn price_impact(&self) -> &Self::PriceImpact;
@@ -41,10 +45,13 @@ pub trait ServicesBundle {
     fn fees(&self) -> &Self::Fees;
     fn margin(&self) -> &Self::Margin;
     fn open_interest(&self) -> &Self::OpenInterest;
+    fn telemetry(&self) -> &Self::Telemetry;
 }
 
+/// Default service set. `T` picks the telemetry sink (no-op unless replaced
+/// through `with_telemetry`).
 #[derive(Clone)]
-pub struct BasicServicesBundle {
+pub struct BasicServicesBundle<T: Telemetry = NoopTelemetry> {
     pub price_impact: price_impact::BasicPriceImpactService,
     pub pricing: pricing::BasicPricingService,
     pub impact_pool: impact_pool::BasicImpactPoolService,
@@ -53,6 +60,7 @@ pub struct BasicServicesBundle {
     pub fees: fees::BasicFeesService,
     pub margin: margin::BasicMarginService,
     pub open_interest: open_interest::BasicOpenInterestService,
+    pub telemetry: T,
 }
 
 impl Default for BasicServicesBundle {
@@ -72,11 +80,29 @@ impl Default for BasicServicesBundle {
             fees,
             margin: margin::BasicMarginService::default(),
             open_interest: open_interest::BasicOpenInterestService::default(),
+            telemetry: NoopTelemetry,
         }
     }
 }
 
-impl ServicesBundle for BasicServicesBundle {
+impl BasicServicesBundle {
+    /// Same services reporting to `telemetry`.
+    pub fn with_telemetry<T: Telemetry>(self, telemetry: T) -> BasicServicesBundle<T> {
+        BasicServicesBundle {
+            price_impact: self.price_impact,
+            pricing: self.pricing,
+            impact_pool: self.impact_pool,
+            funding: self.funding,
+            borrowing: self.borrowing,
+            fees: self.fees,
+            margin: self.margin,
+            open_interest: self.open_interest,
+            telemetry,
+        }
+    }
+}
+
+impl<T: Telemetry> ServicesBundle for BasicServicesBundle<T> {
     type Pricing = pricing::BasicPricingService;
     type PriceImpact = price_impact::BasicPriceImpactService;
     type ImpactPool = impact_pool::BasicImpactPoolService;
@@ -85,6 +111,7 @@ impl ServicesBundle for BasicServicesBundle {
     type Fees = fees::BasicFeesService;
     type Margin = margin::BasicMarginService;
     type OpenInterest = open_interest::BasicOpenInterestService;
+    type Telemetry = T;
 
     fn pricing(&self) -> &Self::Pricing {
         &self.pricing
@@ -111,4 +138,7 @@ impl ServicesBundle for BasicServicesBundle {
     fn open_interest(&self) -> &Self::OpenInterest {
         &self.open_interest
     }
+    fn telemetry(&self) -> &Self::Telemetry {
+        &self.telemetry
+    }
 }
@@ -1,16 +1,23 @@
 // src/services/price_impact.rs
 
-use crate::services::open_interest::OpenInterestParams;
-use crate::types::SignedU256;
+use crate::math::fp;
+use crate::oracle::mid_index_price;
+use crate::services::open_interest::{OpenInterestParams, OpenInterestSnapshot};
+use crate::state::MarketState;
+use crate::types::{OraclePrices, Side, SignedU256, Usd};
 use primitive_types::{U256, U512};
 
-/// Generic fixed-point scale = 10^18.
-fn fp_scale() -> U256 {
-    U256::exp10(18)
-}
+/// Supported range for `ImpactRebalanceConfig::impact_exponent`.
+///
+/// d^e is kept in USD(1e30) scale and then multiplied by an fp(1e18) factor.
+/// With OI in the 1e30..1e36 range, e > 3 overflows U256 in the factor
+/// multiplication (saturates to a meaningless impact), and e = 0 makes
+/// impact independent of the trade.
+pub const MIN_IMPACT_EXPONENT: u32 = 1;
+pub const MAX_IMPACT_EXPONENT: u32 = 3;
 
 /// Config for impact curve and factors.
-/// All factors are fixed-point with scale = fp_scale().
+/// All factors are fixed-point with scale = `fp::SCALE`.
 #[derive(Clone, Debug)]
 pub struct ImpactRebalanceConfig {
     /// Exponent "e" in d^e (e.g. 1, 2, 3).
@@ -29,12 +36,46 @@ pub struct ImpactRebalanceConfig {
 
     /// Cross-over negative factor (applied to next diff).
     pub crossover_negative_factor_fp: U256,
+
+    /// Pool depth at which the factors apply as configured, USD(1e30).
+    /// When non-zero, `for_liquidity` scales every factor by
+    /// `reference_liquidity_usd / liquidity_usd`, so deeper markets get gentler
+    /// impact. Zero = disabled (static factors).
+    pub reference_liquidity_usd: Usd,
 }
 
 impl ImpactRebalanceConfig {
+    /// Build a config, rejecting exponents outside `1..=3`.
+    pub fn new(
+        impact_exponent: u32,
+        same_side_positive_factor_fp: U256,
+        same_side_negative_factor_fp: U256,
+        crossover_positive_factor_fp: U256,
+        crossover_negative_factor_fp: U256,
+    ) -> Result<Self, String> {
+        let cfg = Self {
+            impact_exponent,
+            same_side_positive_factor_fp,
+            same_side_negative_factor_fp,
+            crossover_positive_factor_fp,
+            crossover_negative_factor_fp,
+            reference_liquidity_usd: Usd::zero(),
+        };
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Check that the exponent is within the range supported by `pow_usd_scaled`.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_IMPACT_EXPONENT..=MAX_IMPACT_EXPONENT).contains(&self.impact_exponent) {
+            return Err("impact_exponent_out_of_range".into());
+        }
+        Ok(())
+    }
+
     /// Simple quadratic profile for MVP.
     pub fn default_quadratic() -> Self {
-        let one = fp_scale();
+        let one = fp::SCALE;
         // GMX-compatible factors (roughly 100x smaller than previous defaults).
         Self {
             impact_exponent: 2,
@@ -45,10 +86,115 @@ impl ImpactRebalanceConfig {
             // crossover: similar scale
             crossover_positive_factor_fp: one / 100_000_000, // 1e-8
             crossover_negative_factor_fp: one * 42 / 1_000_000_000, // 4.2e-8
+            reference_liquidity_usd: Usd::zero(),
+        }
+    }
+
+    /// Effective config for a market with `liquidity_usd` of pool depth.
+    ///
+    /// Scales all four factors by `reference_liquidity_usd / liquidity_usd`
+    /// (rounded down). Returns the config unchanged when depth scaling is
+    /// disabled or the pool is empty.
+    pub fn for_liquidity(&self, liquidity_usd: Usd) -> Result<Self, String> {
+        if self.reference_liquidity_usd.is_zero() || liquidity_usd.is_zero() {
+            return Ok(self.clone());
+        }
+        let scale = |factor_fp: U256| -> Result<U256, String> {
+            mul_div_u256(factor_fp, self.reference_liquidity_usd, liquidity_usd)
+                .map_err(|_| "impact_liquidity_scale_overflow".to_string())
+        };
+        Ok(Self {
+            same_side_positive_factor_fp: scale(self.same_side_positive_factor_fp)?,
+            same_side_negative_factor_fp: scale(self.same_side_negative_factor_fp)?,
+            crossover_positive_factor_fp: scale(self.crossover_positive_factor_fp)?,
+            crossover_negative_factor_fp: scale(self.crossover_negative_factor_fp)?,
+            ..self.clone()
+        })
+    }
+
+    /// Builder seeded with `default_quadratic`.
+    pub fn builder() -> ImpactRebalanceConfigBuilder {
+        ImpactRebalanceConfigBuilder {
+            cfg: Self::default_quadratic(),
+            error: None,
+        }
+    }
+}
+
+/// Builder for `ImpactRebalanceConfig` taking factors as plain fractions
+/// (e.g. `4.2e-8`) instead of fp-scaled integers.
+///
+/// Starts from `default_quadratic`; every setter overrides only its fields.
+/// Conversion and exponent errors surface from `build`.
+#[derive(Clone, Debug)]
+pub struct ImpactRebalanceConfigBuilder {
+    cfg: ImpactRebalanceConfig,
+    error: Option<String>,
+}
+
+impl ImpactRebalanceConfigBuilder {
+    pub fn with_exponent(mut self, impact_exponent: u32) -> Self {
+        self.cfg.impact_exponent = impact_exponent;
+        self
+    }
+
+    /// Same-side factors for helpful (`positive`) and harmful (`negative`) trades.
+    pub fn with_same_side_factors(mut self, positive: f64, negative: f64) -> Self {
+        if let Some(fp) = self.factor(positive) {
+            self.cfg.same_side_positive_factor_fp = fp;
+        }
+        if let Some(fp) = self.factor(negative) {
+            self.cfg.same_side_negative_factor_fp = fp;
+        }
+        self
+    }
+
+    /// Factors for trades that flip the skew to the other side.
+    pub fn with_crossover_factors(mut self, positive: f64, negative: f64) -> Self {
+        if let Some(fp) = self.factor(positive) {
+            self.cfg.crossover_positive_factor_fp = fp;
+        }
+        if let Some(fp) = self.factor(negative) {
+            self.cfg.crossover_negative_factor_fp = fp;
+        }
+        self
+    }
+
+    /// Enable depth scaling around `reference_liquidity_usd` (see `for_liquidity`).
+    pub fn with_reference_liquidity(mut self, reference_liquidity_usd: Usd) -> Self {
+        self.cfg.reference_liquidity_usd = reference_liquidity_usd;
+        self
+    }
+
+    pub fn build(self) -> Result<ImpactRebalanceConfig, String> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        self.cfg.validate()?;
+        Ok(self.cfg)
+    }
+
+    /// fp value of `x`, remembering the first conversion error.
+    fn factor(&mut self, x: f64) -> Option<U256> {
+        match factor_to_fp(x) {
+            Ok(fp) => Some(fp),
+            Err(e) => {
+                self.error.get_or_insert(e);
+                None
+            }
         }
     }
 }
 
+/// round(x * fp::SCALE). Rejects negative, non-finite and out-of-range values.
+fn factor_to_fp(x: f64) -> Result<U256, String> {
+    let scaled = (x * fp::SCALE_I128 as f64).round();
+    if !scaled.is_finite() || scaled < 0.0 || scaled > u128::MAX as f64 {
+        return Err("invalid_impact_factor".into());
+    }
+    Ok(U256::from(scaled as u128))
+}
+
 /// |a - b| for U256
 fn abs_diff(a: U256, b: U256) -> U256 {
     if a >= b { a - b } else { b - a }
@@ -81,7 +227,7 @@ fn mul_div_u256(a: U256, b: U256, den: U256) -> Result<U256, String> {
     u512_to_u256_checked(q)
 }
 
-/// x^exp but kept in USD(1e30) scale:
+/// x^exp but kept in USD(1e30) scale (small exp only, see `MAX_IMPACT_EXPONENT`):
 /// exp=1 => x
 /// exp=2 => x*x / 1e30
 /// exp=3 => x*x/1e30 * x/1e30
@@ -104,7 +250,7 @@ fn pow_usd_scaled(x: U256, exp: u32) -> Result<U256, String> {
 
 /// Convert fixed-point (val * SCALE) -> USD magnitude by dividing SCALE (round down).
 fn from_fp_to_usd_down(v_fp: U256) -> U256 {
-    v_fp / fp_scale()
+    v_fp / fp::SCALE
 }
 
 /// Inputs:
@@ -113,12 +259,15 @@ fn from_fp_to_usd_down(v_fp: U256) -> U256 {
 ///   - cfg with impact factors & exponent
 ///
 /// Returns:
-///   - price_impact_usd: signed USD amount
+///   - price_impact_usd: signed USD amount (positive = rebate to the user,
+///     negative = penalty; see the crossover branch for its sign rule)
 ///   - balance_was_improved: did abs diff shrink?
 fn get_price_impact_usd(
     oi: &OpenInterestParams,
     cfg: &ImpactRebalanceConfig,
 ) -> Result<(SignedU256, bool), String> {
+    cfg.validate()?;
+
     let long0 = oi.current.long_usd;
     let short0 = oi.current.short_usd;
     let long1 = oi.next.long_usd;
@@ -173,6 +322,14 @@ fn get_price_impact_usd(
         //
         //   impact = (d0^e * positiveFactor) - (d1^e * negativeFactor)
         //
+        // The trade closes the old skew d0 (credited at the positive factor)
+        // and opens an opposite skew d1 (charged at the negative factor):
+        //   - d0^e * p >  d1^e * n → positive (rebate), i.e. d1 < d0 * (p/n)^(1/e);
+        //   - d0^e * p == d1^e * n → exactly zero;
+        //   - d0^e * p <  d1^e * n → negative (user pays).
+        // With n > p a crossover can be negative even when the imbalance
+        // shrinks, so the sign does not follow `balance_was_improved`.
+        // The magnitude is rounded down in both directions.
         let p_fp = cfg.crossover_positive_factor_fp;
         let n_fp = cfg.crossover_negative_factor_fp;
 
@@ -219,6 +376,546 @@ impl PriceImpactService for BasicPriceImpactService {
     }
 }
 
+/// Quote the price impact of a hypothetical trade against the market's current OI.
+///
+/// Read-only: builds the before/after `OpenInterestParams` from `market`
+/// (`size_delta_usd` added on increase, removed on decrease) and runs the
+/// same math as `BasicPriceImpactService`, with `cfg` scaled to the market's
+/// `liquidity_usd` (see `ImpactRebalanceConfig::for_liquidity`).
+pub fn quote_impact(
+    market: &MarketState,
+    side: Side,
+    size_delta_usd: Usd,
+    is_increase: bool,
+    cfg: &ImpactRebalanceConfig,
+) -> Result<(SignedU256, bool), String> {
+    let current = OpenInterestSnapshot {
+        long_usd: market.oi_long_usd,
+        short_usd: market.oi_short_usd,
+    };
+
+    let side_oi = match side {
+        Side::Long => current.long_usd,
+        Side::Short => current.short_usd,
+    };
+    let next_side_oi = if is_increase {
+        side_oi
+            .checked_add(size_delta_usd)
+            .ok_or("quote_impact_oi_overflow")?
+    } else {
+        side_oi
+            .checked_sub(size_delta_usd)
+            .ok_or("quote_impact_oi_underflow")?
+    };
+
+    let next = match side {
+        Side::Long => OpenInterestSnapshot {
+            long_usd: next_side_oi,
+            short_usd: current.short_usd,
+        },
+        Side::Short => OpenInterestSnapshot {
+            long_usd: current.long_usd,
+            short_usd: next_side_oi,
+        },
+    };
+
+    let cfg = cfg.for_liquidity(market.liquidity_usd)?;
+    get_price_impact_usd(&OpenInterestParams { current, next }, &cfg)
+}
+
+/// Impact-adjusted "fair" index price for display (informational only).
+///
+/// Moves the oracle mid by the marginal impact of an infinitesimal trade at
+/// the current skew d = |long - short|. For same-side impact d^e * factor the
+/// marginal rate is e * d^(e-1) * factor; a long buying into a long-heavy
+/// book pays it at the negative factor, a short selling into it earns it at
+/// the positive one, and the mark sits between the two (average factor):
+///
+///   mark = mid * (1 ± e * d^(e-1) * (positive + negative) / 2)
+///
+/// Above mid when long-heavy, below when short-heavy (floored at zero), mid
+/// when balanced. Factors are scaled to the market's `liquidity_usd`.
+pub fn mark_price(
+    market: &MarketState,
+    prices: &OraclePrices,
+    cfg: &ImpactRebalanceConfig,
+) -> Result<Usd, String> {
+    let cfg = cfg.for_liquidity(market.liquidity_usd)?;
+    cfg.validate()?;
+    let mid = mid_index_price(prices);
+    let skew = abs_diff(market.oi_long_usd, market.oi_short_usd);
+    if skew.is_zero() {
+        return Ok(mid);
+    }
+
+    // e * d^(e-1), USD(1e30) scale.
+    let e = cfg.impact_exponent;
+    let d_pow = if e == 1 {
+        usd_scale()
+    } else {
+        pow_usd_scaled(skew, e - 1)?
+    };
+    let factor_fp = (cfg.same_side_positive_factor_fp / 2)
+        .checked_add(cfg.same_side_negative_factor_fp / 2)
+        .ok_or("mark_price_overflow")?;
+    // rate = e * d^(e-1) * factor, USD(1e30) scale (1e30 = 100%).
+    let rate = mul_div_u256(
+        d_pow
+            .checked_mul(U256::from(e))
+            .ok_or("mark_price_overflow")?,
+        factor_fp,
+        fp::SCALE,
+    )?;
+    let adjustment = mul_div_u256(mid, rate, usd_scale())?;
+
+    Ok(if market.oi_long_usd > market.oi_short_usd {
+        mid.checked_add(adjustment).ok_or("mark_price_overflow")?
+    } else {
+        mid.saturating_sub(adjustment)
+    })
+}
+
+/// Scale a decrease's negative price impact by `scale_bps` (see
+/// `MarketState::impact_on_close_bps_scale`), rounding the magnitude toward
+/// zero. Positive impact (a close that rebalances the market) is returned
+/// unchanged.
+pub fn scale_close_impact(impact: SignedU256, scale_bps: u32) -> SignedU256 {
+    let scale = scale_bps.min(10_000);
+    if !impact.is_negative || scale == 10_000 {
+        return impact;
+    }
+    let mag = impact.mag * U256::from(scale) / U256::from(10_000u32);
+    SignedU256 {
+        is_negative: !mag.is_zero(),
+        mag,
+    }
+}
+
+/// Price impact for a batch of orders executed together (e.g. in one block).
+///
+/// Each entry is `(side, size_delta_usd, is_increase)`. Instead of applying the
+/// orders one after another, the batch is priced once against the *net* OI
+/// change. That impact belongs to the orders that moved the long - short skew
+/// in the net direction (long increases / short decreases when the batch nets
+/// long, the others when it nets short) and is split between them pro rata by
+/// `size_delta_usd`; the rounding remainder goes to the last of them. Orders
+/// on the other side were absorbed by the netting and get zero impact, so an
+/// offset order is neither charged for a swing it reduced nor credited for
+/// one it didn't cause. If the skew does not move, every order gets a share.
+pub fn batch_impact(
+    orders: &[(Side, Usd, bool)],
+    market: &MarketState,
+    cfg: &ImpactRebalanceConfig,
+) -> Result<Vec<SignedU256>, String> {
+    let current = OpenInterestSnapshot {
+        long_usd: market.oi_long_usd,
+        short_usd: market.oi_short_usd,
+    };
+
+    let mut long_add = U256::zero();
+    let mut long_sub = U256::zero();
+    let mut short_add = U256::zero();
+    let mut short_sub = U256::zero();
+    for &(side, size, is_increase) in orders {
+        let bucket = match (side, is_increase) {
+            (Side::Long, true) => &mut long_add,
+            (Side::Long, false) => &mut long_sub,
+            (Side::Short, true) => &mut short_add,
+            (Side::Short, false) => &mut short_sub,
+        };
+        *bucket = bucket.checked_add(size).ok_or("batch_impact_oi_overflow")?;
+    }
+
+    let net = |oi: U256, add: U256, sub: U256| -> Result<U256, String> {
+        oi.checked_add(add)
+            .ok_or("batch_impact_oi_overflow")?
+            .checked_sub(sub)
+            .ok_or_else(|| "batch_impact_oi_underflow".to_string())
+    };
+    let next = OpenInterestSnapshot {
+        long_usd: net(current.long_usd, long_add, long_sub)?,
+        short_usd: net(current.short_usd, short_add, short_sub)?,
+    };
+
+    let cfg = cfg.for_liquidity(market.liquidity_usd)?;
+    let (total, _) = get_price_impact_usd(&OpenInterestParams { current, next }, &cfg)?;
+
+    // Skew moved toward longs by long increases and short decreases.
+    // Each bucket fits in U256 on its own, so the sums are compared in U512.
+    let toward_long = U512::from(long_add) + U512::from(short_sub);
+    let toward_short = U512::from(short_add) + U512::from(long_sub);
+    let weights: Vec<U256> = orders
+        .iter()
+        .map(|&(side, size, is_increase)| {
+            let moves_long = (side == Side::Long) == is_increase;
+            let takes_share = if toward_long > toward_short {
+                moves_long
+            } else if toward_short > toward_long {
+                !moves_long
+            } else {
+                true
+            };
+            if takes_share { size } else { U256::zero() }
+        })
+        .collect();
+    let total_weight = weights.iter().try_fold(U256::zero(), |acc, w| {
+        acc.checked_add(*w).ok_or("batch_impact_oi_overflow")
+    })?;
+    let last = weights.iter().rposition(|w| !w.is_zero());
+
+    let mut out = Vec::with_capacity(orders.len());
+    let mut allocated = U256::zero();
+    for (i, weight) in weights.into_iter().enumerate() {
+        let mag = if Some(i) == last {
+            total.mag - allocated
+        } else if weight.is_zero() {
+            U256::zero()
+        } else {
+            mul_div_u256(total.mag, weight, total_weight)?
+        };
+        allocated += mag;
+        out.push(SignedU256 {
+            is_negative: total.is_negative && !mag.is_zero(),
+            mag,
+        });
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::open_interest::{BasicOpenInterestService, OpenInterestService};
+
+    fn usd(v: u64) -> Usd {
+        U256::from(v) * U256::exp10(30)
+    }
+
+    fn market(long_usd: Usd, short_usd: Usd) -> MarketState {
+        MarketState {
+            oi_long_usd: long_usd,
+            oi_short_usd: short_usd,
+            ..Default::default()
+        }
+    }
+
+    fn oi(long0: u64, short0: u64, long1: u64, short1: u64) -> OpenInterestParams {
+        OpenInterestParams {
+            current: OpenInterestSnapshot {
+                long_usd: usd(long0),
+                short_usd: usd(short0),
+            },
+            next: OpenInterestSnapshot {
+                long_usd: usd(long1),
+                short_usd: usd(short1),
+            },
+        }
+    }
+
+    /// Quadratic curve with crossover factors p = 1e-8, n = 4.2e-8.
+    fn crossover_cfg() -> ImpactRebalanceConfig {
+        ImpactRebalanceConfig::new(
+            2,
+            U256::exp10(9),
+            U256::exp10(9) * 2,
+            U256::exp10(10),
+            U256::from(42u64) * U256::exp10(9),
+        )
+        .unwrap()
+    }
+
+    fn with_exponent(e: u32) -> Result<ImpactRebalanceConfig, String> {
+        let d = ImpactRebalanceConfig::default_quadratic();
+        ImpactRebalanceConfig::new(
+            e,
+            d.same_side_positive_factor_fp,
+            d.same_side_negative_factor_fp,
+            d.crossover_positive_factor_fp,
+            d.crossover_negative_factor_fp,
+        )
+    }
+
+    #[test]
+    fn exponent_outside_supported_range_is_rejected() {
+        assert_eq!(
+            with_exponent(0).unwrap_err(),
+            "impact_exponent_out_of_range"
+        );
+        assert_eq!(
+            with_exponent(4).unwrap_err(),
+            "impact_exponent_out_of_range"
+        );
+        for e in MIN_IMPACT_EXPONENT..=MAX_IMPACT_EXPONENT {
+            assert!(with_exponent(e).is_ok());
+        }
+        assert!(
+            ImpactRebalanceConfig::default_quadratic()
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn builder_converts_human_factors_to_fp() {
+        let cfg = ImpactRebalanceConfig::builder()
+            .with_exponent(3)
+            .with_same_side_factors(1e-8, 4.2e-8)
+            .with_crossover_factors(0.5, 0.0)
+            .build()
+            .unwrap();
+        assert_eq!(cfg.impact_exponent, 3);
+        assert_eq!(cfg.same_side_positive_factor_fp, U256::exp10(10));
+        assert_eq!(
+            cfg.same_side_negative_factor_fp,
+            U256::from(42u64) * U256::exp10(9)
+        );
+        assert_eq!(cfg.crossover_positive_factor_fp, fp::SCALE / 2);
+        assert!(cfg.crossover_negative_factor_fp.is_zero());
+
+        // Untouched builder reproduces the quadratic defaults.
+        let d = ImpactRebalanceConfig::default_quadratic();
+        let b = ImpactRebalanceConfig::builder().build().unwrap();
+        assert_eq!(b.impact_exponent, d.impact_exponent);
+        assert_eq!(
+            b.same_side_positive_factor_fp,
+            d.same_side_positive_factor_fp
+        );
+        assert_eq!(
+            b.same_side_negative_factor_fp,
+            d.same_side_negative_factor_fp
+        );
+        assert_eq!(
+            b.crossover_positive_factor_fp,
+            d.crossover_positive_factor_fp
+        );
+        assert_eq!(
+            b.crossover_negative_factor_fp,
+            d.crossover_negative_factor_fp
+        );
+
+        // Bad inputs are reported at build time.
+        let err = |b: ImpactRebalanceConfigBuilder| b.build().unwrap_err();
+        assert_eq!(
+            err(ImpactRebalanceConfig::builder().with_exponent(4)),
+            "impact_exponent_out_of_range"
+        );
+        assert_eq!(
+            err(ImpactRebalanceConfig::builder().with_same_side_factors(-1e-8, 1e-8)),
+            "invalid_impact_factor"
+        );
+        assert_eq!(
+            err(ImpactRebalanceConfig::builder().with_crossover_factors(1e-8, f64::NAN)),
+            "invalid_impact_factor"
+        );
+    }
+
+    #[test]
+    fn quote_matches_manually_built_oi_params() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let m = market(usd(120_000), usd(80_000));
+        let oi_svc = BasicOpenInterestService;
+
+        for side in [Side::Long, Side::Short] {
+            let size = usd(30_000);
+
+            let oi = oi_svc.for_increase(m.oi_long_usd, m.oi_short_usd, size, side);
+            let expected = get_price_impact_usd(&oi, &cfg).unwrap();
+            assert_eq!(quote_impact(&m, side, size, true, &cfg).unwrap(), expected);
+
+            let oi = oi_svc.for_decrease(m.oi_long_usd, m.oi_short_usd, size, side);
+            let expected = get_price_impact_usd(&oi, &cfg).unwrap();
+            assert_eq!(quote_impact(&m, side, size, false, &cfg).unwrap(), expected);
+        }
+
+        // Long increase on a long-heavy market is harmful.
+        let (impact, improved) = quote_impact(&m, Side::Long, usd(30_000), true, &cfg).unwrap();
+        assert!(impact.is_negative && !improved);
+    }
+
+    #[test]
+    fn deep_pool_scales_impact_down() {
+        let cfg = ImpactRebalanceConfig::builder()
+            .with_reference_liquidity(usd(1_000_000))
+            .build()
+            .unwrap();
+        let at_depth = |liquidity_usd| MarketState {
+            liquidity_usd,
+            ..market(usd(120_000), usd(80_000))
+        };
+        let quote = |m: &MarketState, cfg| quote_impact(m, Side::Long, usd(30_000), true, cfg);
+
+        // (70k^2 - 40k^2) * 4.2e-8 = $138.6 at the reference depth.
+        let (reference, _) = quote(&at_depth(usd(1_000_000)), &cfg).unwrap();
+        let (shallow, _) = quote(&at_depth(usd(500_000)), &cfg).unwrap();
+        let (deep, _) = quote(&at_depth(usd(4_000_000)), &cfg).unwrap();
+        assert_eq!(reference, SignedU256::neg(usd(1_386) / 10));
+        assert_eq!(shallow, SignedU256::neg(usd(2_772) / 10));
+        assert_eq!(deep, SignedU256::neg(usd(3_465) / 100));
+        assert!(deep.mag < shallow.mag);
+
+        // Disabled scaling and empty pools keep the static factors.
+        let static_cfg = ImpactRebalanceConfig::default_quadratic();
+        assert_eq!(
+            quote(&at_depth(usd(4_000_000)), &static_cfg).unwrap().0,
+            reference
+        );
+        assert_eq!(quote(&at_depth(Usd::zero()), &cfg).unwrap().0, reference);
+    }
+
+    #[test]
+    fn mark_price_leans_toward_the_heavy_side() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let prices = OraclePrices {
+            index_price_min: usd(2_999),
+            index_price_max: usd(3_001),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+
+        // Skew $40k at e = 2: rate = 2 * 40_000 * (1e-8 + 4.2e-8) / 2 = 0.208%.
+        let long_heavy = mark_price(&market(usd(120_000), usd(80_000)), &prices, &cfg).unwrap();
+        assert_eq!(long_heavy, usd(300_624) / 100);
+        assert!(long_heavy > mid_index_price(&prices));
+
+        let short_heavy = mark_price(&market(usd(80_000), usd(120_000)), &prices, &cfg).unwrap();
+        assert_eq!(short_heavy, usd(299_376) / 100);
+
+        let balanced = mark_price(&market(usd(100_000), usd(100_000)), &prices, &cfg).unwrap();
+        assert_eq!(balanced, usd(3_000));
+    }
+
+    #[test]
+    fn quote_decrease_larger_than_oi_is_rejected() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let m = market(usd(1_000), usd(0));
+        assert_eq!(
+            quote_impact(&m, Side::Long, usd(2_000), false, &cfg).unwrap_err(),
+            "quote_impact_oi_underflow"
+        );
+    }
+
+    #[test]
+    fn offsetting_orders_are_netted() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let mut m = MarketState {
+            oi_long_usd: usd(100_000),
+            oi_short_usd: usd(100_000),
+            ..Default::default()
+        };
+        let size = usd(50_000);
+
+        let batch = batch_impact(
+            &[(Side::Long, size, true), (Side::Short, size, true)],
+            &m,
+            &cfg,
+        )
+        .unwrap();
+        assert_eq!(batch.len(), 2);
+        assert!(batch.iter().all(|i| i.is_zero()));
+
+        // Sequentially, the long pays for the whole swing it creates.
+        let (long_impact, _) = quote_impact(&m, Side::Long, size, true, &cfg).unwrap();
+        m.oi_long_usd += size;
+        let (short_impact, _) = quote_impact(&m, Side::Short, size, true, &cfg).unwrap();
+        assert!(long_impact.is_negative);
+        assert!(long_impact.mag > usd(1));
+        assert!(!short_impact.is_negative);
+    }
+
+    #[test]
+    fn batch_impact_is_split_pro_rata_and_sums_to_net() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let m = MarketState::default();
+        let orders = [
+            (Side::Long, usd(30_000), true),
+            (Side::Long, usd(10_000), true),
+        ];
+
+        let batch = batch_impact(&orders, &m, &cfg).unwrap();
+        let (net, _) = quote_impact(&m, Side::Long, usd(40_000), true, &cfg).unwrap();
+        assert!(batch.iter().all(|i| i.is_negative));
+        assert_eq!(batch[0].mag + batch[1].mag, net.mag);
+        // 3:1 split, up to the rounding remainder on the last order.
+        assert!(batch[1].mag - batch[0].mag / 3 <= U256::one());
+
+        assert_eq!(
+            batch_impact(&[(Side::Short, usd(1), false)], &m, &cfg).unwrap_err(),
+            "batch_impact_oi_underflow"
+        );
+    }
+
+    #[test]
+    fn partly_offset_order_gets_no_share_of_the_net_impact() {
+        let cfg = ImpactRebalanceConfig::default_quadratic();
+        let m = MarketState {
+            oi_long_usd: usd(100_000),
+            oi_short_usd: usd(100_000),
+            ..Default::default()
+        };
+        let orders = [
+            (Side::Long, usd(30_000), true),
+            (Side::Short, usd(10_000), true),
+        ];
+
+        // The batch nets 20k long; the short reduced that swing.
+        let batch = batch_impact(&orders, &m, &cfg).unwrap();
+        let (net, _) = quote_impact(&m, Side::Long, usd(20_000), true, &cfg).unwrap();
+        assert!(net.is_negative);
+        assert_eq!(batch[0], net);
+        assert!(batch[1].is_zero());
+
+        // Closing shorts moves the skew toward longs as well.
+        let orders = [
+            (Side::Short, usd(5_000), false),
+            (Side::Long, usd(25_000), true),
+            (Side::Long, usd(10_000), false),
+        ];
+        let batch = batch_impact(&orders, &m, &cfg).unwrap();
+        assert!(batch[0].is_negative && batch[1].is_negative);
+        assert!(batch[2].is_zero());
+        assert_eq!(batch[0].mag + batch[1].mag, net.mag);
+    }
+
+    #[test]
+    fn crossover_impact_matches_hand_computed_values() {
+        // Long-heavy 150k/50k flips to short-heavy 80k/120k: d0 = 100k, d1 = 40k.
+        // 100k^2 * 1e-8 - 40k^2 * 4.2e-8 = 100 - 67.2 = +$32.8.
+        let (impact, improved) =
+            get_price_impact_usd(&oi(150_000, 50_000, 80_000, 120_000), &crossover_cfg()).unwrap();
+        assert_eq!(impact, SignedU256::pos(usd(328) / 10));
+        assert!(improved);
+
+        // Flipping to 0/200k: d1 = 200k, 100 - 200k^2 * 4.2e-8 = 100 - 1_680 = -$1_580.
+        let (impact, improved) =
+            get_price_impact_usd(&oi(150_000, 50_000, 0, 200_000), &crossover_cfg()).unwrap();
+        assert_eq!(impact, SignedU256::neg(usd(1_580)));
+        assert!(!improved);
+
+        // Negative even though the imbalance shrinks (d1 = 60k < d0 = 100k):
+        // 100 - 60k^2 * 4.2e-8 = 100 - 151.2 = -$51.2.
+        let (impact, improved) =
+            get_price_impact_usd(&oi(150_000, 50_000, 70_000, 130_000), &crossover_cfg()).unwrap();
+        assert_eq!(impact, SignedU256::neg(usd(512) / 10));
+        assert!(improved);
+    }
+
+    #[test]
+    fn crossover_impact_is_zero_when_terms_balance() {
+        // d0^2 * p == d1^2 * n with p = n / 4 and d1 = d0 / 2.
+        let cfg = ImpactRebalanceConfig::new(
+            2,
+            U256::zero(),
+            U256::zero(),
+            U256::exp10(10),
+            U256::exp10(10) * 4,
+        )
+        .unwrap();
+        let (impact, _) =
+            get_price_impact_usd(&oi(150_000, 50_000, 75_000, 125_000), &cfg).unwrap();
+        assert!(impact.is_zero());
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
@@ -13,6 +13,17 @@ pub enum TradeDirection {
     Decrease,
 }
 
+/// Which oracle price is used to size base tokens.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PriceSelection {
+    /// Worst price for the trader: max for (Increase, Long) / (Decrease, Short),
+    /// min for (Increase, Short) / (Decrease, Long).
+    #[default]
+    Conservative,
+    /// Mid price (min + max) / 2, for tighter spreads.
+    Mid,
+}
+
 pub struct ExecutionPriceParams<'a> {
     /// Long / short OI before and after the action.
     pub oi: &'a OpenInterestParams,
@@ -26,6 +37,23 @@ pub struct ExecutionPriceParams<'a> {
     pub size_delta_usd: Usd,
     /// Oracle min / max prices.
     pub prices: OraclePrices,
+    /// Price used for base token sizing (conservative by default).
+    pub price_selection: PriceSelection,
+}
+
+/// Pricing failure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PricingError {
+    /// Oracle index prices are inverted (min > max) or zero for a nonzero order.
+    InvalidPrices,
+    /// Impact / rounding math failure (overflow, zero tokens after impact, ...).
+    Math(String),
+}
+
+impl From<String> for PricingError {
+    fn from(e: String) -> Self {
+        PricingError::Math(e)
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +64,10 @@ pub struct ExecutionPriceResult {
     pub size_delta_tokens: TokenAmount,
     pub execution_price: Usd,
     pub balance_was_improved: bool,
+    /// Rounding of the USD -> token conversions behind `base_size_delta_tokens`
+    /// and `price_impact_amount_tokens`, signed from the protocol's side
+    /// (see `math::rounding::rounding_leakage`), in USD(1e30).
+    pub rounding_leakage_usd: SignedU256,
 }
 
 /// Convert signed USD -> signed tokens(atoms) using a per-unit price.
@@ -61,25 +93,68 @@ fn signed_usd_to_tokens(
     }
 }
 
+/// Signed spread of `execution_price` vs `index_mid`, in bps (rounded toward zero):
+///   (execution_price - index_mid) * 10_000 / index_mid
+///
+/// `index_mid = (index_price_min + index_price_max) / 2`. Positive means the
+/// trade executed above mid (a long paid more than mid), negative below it.
+/// Returns 0 for a zero mid; saturates at the i128 bounds.
+pub fn execution_spread_bps(execution_price: Usd, index_mid: Usd) -> i128 {
+    if index_mid.is_zero() {
+        return 0;
+    }
+    let (diff, negative) = if execution_price >= index_mid {
+        (execution_price - index_mid, false)
+    } else {
+        (index_mid - execution_price, true)
+    };
+    let bps = diff.saturating_mul(U256::from(10_000u64)) / index_mid;
+    let mag = if bps > U256::from(i128::MAX as u128) {
+        i128::MAX
+    } else {
+        bps.as_u128() as i128
+    };
+    if negative { -mag } else { mag }
+}
+
 /// High-level trait for pricing logic.
 pub trait PricingService {
     fn get_execution_price(
         &self,
         price_impact: &dyn PriceImpactService,
         params: ExecutionPriceParams,
-    ) -> Result<ExecutionPriceResult, String>;
+    ) -> Result<ExecutionPriceResult, PricingError>;
 }
 
+/// Default cap on positive impact tokens: disabled, so pricing is unchanged
+/// unless a market opts in.
+pub const DEFAULT_MAX_IMPACT_BONUS_BPS: u32 = 0;
+
 /// Basic implementation that uses a PriceImpactService inside.
-#[derive(Default, Clone)]
-pub struct BasicPricingService;
+#[derive(Clone)]
+pub struct BasicPricingService {
+    /// Upper bound on positive impact tokens, in bps of `base_size_delta_tokens`.
+    ///
+    /// Safety rail against a mis-configured impact factor granting more tokens
+    /// than the pool can cover; independent of the impact-pool funding check.
+    /// Zero disables the cap.
+    pub max_impact_bonus_bps: u32,
+}
+
+impl Default for BasicPricingService {
+    fn default() -> Self {
+        Self {
+            max_impact_bonus_bps: DEFAULT_MAX_IMPACT_BONUS_BPS,
+        }
+    }
+}
 
 impl PricingService for BasicPricingService {
     fn get_execution_price(
         &self,
         price_impact: &dyn PriceImpactService,
         params: ExecutionPriceParams,
-    ) -> Result<ExecutionPriceResult, String> {
+    ) -> Result<ExecutionPriceResult, PricingError> {
         let ExecutionPriceParams {
             oi,
             impact_cfg,
@@ -87,16 +162,36 @@ impl PricingService for BasicPricingService {
             direction,
             size_delta_usd,
             prices,
+            price_selection,
         } = params;
 
+        if prices.index_price_min > prices.index_price_max {
+            return Err(PricingError::InvalidPrices);
+        }
+        if !size_delta_usd.is_zero()
+            && (prices.index_price_min.is_zero() || prices.index_price_max.is_zero())
+        {
+            return Err(PricingError::InvalidPrices);
+        }
+
+        let mid_price = prices
+            .index_price_min
+            .saturating_add(prices.index_price_max)
+            / 2;
+
         // 0) trivial branch: sizeDeltaUsd == 0
         if size_delta_usd == U256::zero() {
             // No impact, just pick index price.
-            let execution_price = match (direction, side) {
-                (TradeDirection::Increase, Side::Long)
-                | (TradeDirection::Decrease, Side::Short) => prices.index_price_max,
-                (TradeDirection::Increase, Side::Short)
-                | (TradeDirection::Decrease, Side::Long) => prices.index_price_min,
+            let execution_price = match (price_selection, direction, side) {
+                (PriceSelection::Mid, _, _) => mid_price,
+                (PriceSelection::Conservative, TradeDirection::Increase, Side::Long)
+                | (PriceSelection::Conservative, TradeDirection::Decrease, Side::Short) => {
+                    prices.index_price_max
+                }
+                (PriceSelection::Conservative, TradeDirection::Increase, Side::Short)
+                | (PriceSelection::Conservative, TradeDirection::Decrease, Side::Long) => {
+                    prices.index_price_min
+                }
             };
 
             return Ok(ExecutionPriceResult {
@@ -106,6 +201,7 @@ impl PricingService for BasicPricingService {
                 size_delta_tokens: U256::zero(),
                 execution_price,
                 balance_was_improved: false,
+                rounding_leakage_usd: SignedU256::zero(),
             });
         }
 
@@ -118,32 +214,79 @@ impl PricingService for BasicPricingService {
         //        use indexPrice.max and round down (minimize bonus tokens)
         //  - if priceImpactUsd < 0:
         //        use indexPrice.min and round UP (maximize penalty tokens)
-        let price_impact_amount_tokens = signed_usd_to_tokens(
+        let mut price_impact_usd = price_impact_usd;
+        let mut price_impact_amount_tokens = signed_usd_to_tokens(
             price_impact_usd,
             prices.index_price_max,
             prices.index_price_min,
         )?;
+        let mut impact_leakage = if price_impact_usd.is_negative {
+            math::rounding::rounding_leakage(
+                price_impact_usd.mag,
+                prices.index_price_min,
+                math::rounding::Rounding::Up,
+                true,
+            )
+        } else {
+            math::rounding::rounding_leakage(
+                price_impact_usd.mag,
+                prices.index_price_max,
+                math::rounding::Rounding::Down,
+                false,
+            )
+        };
         // 3) baseSizeDeltaInTokens (without price impact)
         //
         // (Increase, Long) | (Decrease, Short): use indexPrice.max, floor
         // (Increase, Short)| (Decrease, Long) : use indexPrice.min, ceil
+        //
+        // With PriceSelection::Mid the price is (min + max) / 2; rounding stays the same.
+        let (price_for_floor, price_for_ceil) = match price_selection {
+            PriceSelection::Conservative => (prices.index_price_max, prices.index_price_min),
+            PriceSelection::Mid => (mid_price, mid_price),
+        };
 
+        let (base_price, base_rounding, base_user_pays) = match (direction, side) {
+            (TradeDirection::Increase, Side::Long) | (TradeDirection::Decrease, Side::Short) => {
+                (price_for_floor, math::rounding::Rounding::Down, false)
+            }
+            (TradeDirection::Increase, Side::Short) | (TradeDirection::Decrease, Side::Long) => {
+                (price_for_ceil, math::rounding::Rounding::Up, true)
+            }
+        };
         let base_size_delta_tokens: TokenAmount =
-            match (direction, side) {
-                (TradeDirection::Increase, Side::Long)
-                | (TradeDirection::Decrease, Side::Short) => math::rounding::div_round(
-                    size_delta_usd,
-                    prices.index_price_max,
-                    math::rounding::Rounding::Down,
-                )?,
-                (TradeDirection::Increase, Side::Short)
-                | (TradeDirection::Decrease, Side::Long) => math::rounding::div_round(
-                    size_delta_usd,
-                    prices.index_price_min,
-                    math::rounding::Rounding::Up,
-                )?,
-            };
+            math::rounding::div_round(size_delta_usd, base_price, base_rounding)?;
+        let base_leakage = math::rounding::rounding_leakage(
+            size_delta_usd,
+            base_price,
+            base_rounding,
+            base_user_pays,
+        );
+        // 3b) Cap the bonus: positive impact tokens <= base * max_impact_bonus_bps / 10_000.
+        // The USD impact is re-derived from the capped tokens at indexPrice.max.
+        // A zero cap means "no cap".
+        if self.max_impact_bonus_bps > 0 && !price_impact_amount_tokens.is_negative {
+            let max_bonus_tokens = base_size_delta_tokens
+                .checked_mul(U256::from(self.max_impact_bonus_bps))
+                .ok_or_else(|| PricingError::Math("impact_bonus_cap_overflow".into()))?
+                / U256::from(10_000u64);
+            if price_impact_amount_tokens.mag > max_bonus_tokens {
+                price_impact_amount_tokens = SignedU256::pos(max_bonus_tokens);
+                price_impact_usd = SignedU256::pos(
+                    max_bonus_tokens
+                        .checked_mul(prices.index_price_max)
+                        .ok_or_else(|| PricingError::Math("impact_bonus_cap_overflow".into()))?,
+                );
+                // The capped USD is exact in tokens.
+                impact_leakage = SignedU256::zero();
+            }
+        }
+
         //  4) total sizeDeltaInTokens including impact
+        //
+        // Long increase buys tokens: positive impact adds tokens (lower price paid).
+        // Short increase sells tokens: positive impact removes tokens, so the same
+        // USD is sold for fewer tokens, i.e. a HIGHER execution price (better for a short).
         println!("base_size_delta_tokens {:?}", base_size_delta_tokens);
         println!(
             "price_impact_amount_tokens {:?}",
@@ -159,7 +302,7 @@ impl PricingService for BasicPricingService {
         };
         // If negative impact wipes more than base tokens -> underflow already caught above.
         if size_delta_tokens == U256::zero() {
-            return Err("ZeroSizeTokensAfterImpact".into());
+            return Err(PricingError::Math("ZeroSizeTokensAfterImpact".into()));
         }
 
         // TODO: acceptablePrice
@@ -178,10 +321,320 @@ impl PricingService for BasicPricingService {
             size_delta_tokens,
             execution_price,
             balance_was_improved,
+            rounding_leakage_usd: math::signed_add(base_leakage, impact_leakage),
         })
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::open_interest::OpenInterestSnapshot;
+    use crate::services::price_impact::BasicPriceImpactService;
+
+    fn prices(min: u64, max: u64) -> OraclePrices {
+        OraclePrices {
+            index_price_min: U256::from(min),
+            index_price_max: U256::from(max),
+            collateral_price_min: U256::one(),
+            collateral_price_max: U256::one(),
+        }
+    }
+
+    fn price_increase(
+        side: Side,
+        prices: OraclePrices,
+        price_selection: PriceSelection,
+    ) -> Result<ExecutionPriceResult, PricingError> {
+        // Balanced OI with no change => zero impact, only base sizing matters.
+        let snap = OpenInterestSnapshot {
+            long_usd: U256::zero(),
+            short_usd: U256::zero(),
+        };
+        let oi = OpenInterestParams {
+            current: snap.clone(),
+            next: snap,
+        };
+        BasicPricingService::default().get_execution_price(
+            &BasicPriceImpactService,
+            ExecutionPriceParams {
+                oi: &oi,
+                impact_cfg: &ImpactRebalanceConfig::default_quadratic(),
+                side,
+                direction: TradeDirection::Increase,
+                size_delta_usd: U256::from(1_000_000u64),
+                prices,
+                price_selection,
+            },
+        )
+    }
+
+    fn base_tokens(side: Side, price_selection: PriceSelection) -> TokenAmount {
+        price_increase(side, prices(1_000, 1_100), price_selection)
+            .expect("pricing must succeed")
+            .base_size_delta_tokens
+    }
+
+    #[test]
+    fn mid_price_sizing_is_between_conservative_extremes() {
+        // Long uses max (fewest tokens), short uses min (most tokens).
+        let long_conservative = base_tokens(Side::Long, PriceSelection::Conservative);
+        let short_conservative = base_tokens(Side::Short, PriceSelection::Conservative);
+        assert_eq!(long_conservative, U256::from(909u64)); // floor(1e6 / 1100)
+        assert_eq!(short_conservative, U256::from(1_000u64)); // ceil(1e6 / 1000)
+
+        for side in [Side::Long, Side::Short] {
+            let mid = base_tokens(side, PriceSelection::Mid);
+            assert!(mid > long_conservative && mid < short_conservative);
+        }
+    }
+
+    fn price_increase_with_oi(side: Side, long0: u64, short0: u64) -> ExecutionPriceResult {
+        // $3_000 per 18-decimals token, i.e. 3_000e12 USD(1e30) per atom.
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+        price_increase_with_oi_at(side, long0, short0, px, px)
+    }
+
+    fn price_increase_with_oi_at(
+        side: Side,
+        long0: u64,
+        short0: u64,
+        price_min: U256,
+        price_max: U256,
+    ) -> ExecutionPriceResult {
+        let usd = |x: u64| U256::from(x) * U256::exp10(30);
+        let size = usd(10_000);
+        let current = OpenInterestSnapshot {
+            long_usd: usd(long0),
+            short_usd: usd(short0),
+        };
+        let next = match side {
+            Side::Long => OpenInterestSnapshot {
+                long_usd: current.long_usd + size,
+                short_usd: current.short_usd,
+            },
+            Side::Short => OpenInterestSnapshot {
+                long_usd: current.long_usd,
+                short_usd: current.short_usd + size,
+            },
+        };
+        BasicPricingService::default()
+            .get_execution_price(
+                &BasicPriceImpactService,
+                ExecutionPriceParams {
+                    oi: &OpenInterestParams { current, next },
+                    impact_cfg: &ImpactRebalanceConfig::default_quadratic(),
+                    side,
+                    direction: TradeDirection::Increase,
+                    size_delta_usd: size,
+                    prices: OraclePrices {
+                        index_price_min: price_min,
+                        index_price_max: price_max,
+                        collateral_price_min: U256::one(),
+                        collateral_price_max: U256::one(),
+                    },
+                    price_selection: PriceSelection::Conservative,
+                },
+            )
+            .expect("pricing must succeed")
+    }
+
+    #[test]
+    fn helpful_short_sells_at_higher_price_like_helpful_long_buys_lower() {
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+
+        // Long-heavy market: a short increase is helpful.
+        let short = price_increase_with_oi(Side::Short, 1_000_000, 0);
+        assert!(short.balance_was_improved);
+        assert!(!short.price_impact_usd.is_negative && !short.price_impact_usd.is_zero());
+        assert!(short.size_delta_tokens < short.base_size_delta_tokens);
+        assert!(short.execution_price > px);
+
+        // Mirror case for longs: helpful long gets more tokens, lower price.
+        let long = price_increase_with_oi(Side::Long, 0, 1_000_000);
+        assert!(long.balance_was_improved);
+        assert!(long.size_delta_tokens > long.base_size_delta_tokens);
+        assert!(long.execution_price < px);
+
+        // Harmful short: more tokens sold for the same USD, lower price.
+        let harmful = price_increase_with_oi(Side::Short, 0, 1_000_000);
+        assert!(harmful.price_impact_usd.is_negative);
+        assert!(harmful.size_delta_tokens > harmful.base_size_delta_tokens);
+        assert!(harmful.execution_price < px);
+    }
+
+    #[test]
+    fn harmful_short_sells_more_tokens_with_rounded_up_penalty() {
+        // Odd per-atom prices so that every division has a remainder.
+        let price_min = U256::from(2_999_999_999_997u64);
+        let price_max = U256::from(3_000_000_000_011u64);
+        let size = U256::from(10_000u64) * U256::exp10(30);
+
+        // Short-heavy market: a short increase is harmful.
+        let res = price_increase_with_oi_at(Side::Short, 0, 1_000_000, price_min, price_max);
+        assert!(!res.balance_was_improved);
+        assert!(res.price_impact_usd.is_negative);
+
+        // Base: ceil(size / min). Penalty: ceil(|impact| / min), both maximize tokens sold.
+        let ceil = |a: U256, b: U256| (a + b - 1) / b;
+        assert_eq!(res.base_size_delta_tokens, ceil(size, price_min));
+        let penalty = ceil(res.price_impact_usd.mag, price_min);
+        assert_eq!(res.price_impact_amount_tokens, SignedU256::neg(penalty));
+
+        // base - (-penalty): the short sells MORE tokens for the same USD...
+        assert_eq!(res.size_delta_tokens, res.base_size_delta_tokens + penalty);
+        // ...i.e. a worse (lower) execution price than the no-impact price.
+        assert!(res.execution_price < price_min);
+        assert_eq!(res.execution_price, size / res.size_delta_tokens);
+    }
+
+    fn price_long_close(long0: u64, short0: u64) -> ExecutionPriceResult {
+        use crate::services::open_interest::{BasicOpenInterestService, OpenInterestService};
+
+        let usd = |x: u64| U256::from(x) * U256::exp10(30);
+        let size = usd(10_000);
+        // Same OI params the executor builds on decrease: the position's side shrinks.
+        let oi = BasicOpenInterestService.for_decrease(usd(long0), usd(short0), size, Side::Long);
+        assert_eq!(oi.next.long_usd, usd(long0) - size);
+        assert_eq!(oi.next.short_usd, usd(short0));
+
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+        BasicPricingService::default()
+            .get_execution_price(
+                &BasicPriceImpactService,
+                ExecutionPriceParams {
+                    oi: &oi,
+                    impact_cfg: &ImpactRebalanceConfig::default_quadratic(),
+                    side: Side::Long,
+                    direction: TradeDirection::Decrease,
+                    size_delta_usd: size,
+                    prices: OraclePrices {
+                        index_price_min: px,
+                        index_price_max: px,
+                        collateral_price_min: U256::one(),
+                        collateral_price_max: U256::one(),
+                    },
+                    price_selection: PriceSelection::Conservative,
+                },
+            )
+            .expect("pricing must succeed")
+    }
+
+    #[test]
+    fn closing_long_on_long_heavy_book_is_helpful() {
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+
+        // Long-heavy: closing a long reduces the imbalance => bonus,
+        // fewer tokens for the same USD => higher (better) exit price.
+        let res = price_long_close(1_000_000, 0);
+        assert!(res.balance_was_improved);
+        assert!(!res.price_impact_usd.is_negative && !res.price_impact_usd.is_zero());
+        assert!(res.size_delta_tokens < res.base_size_delta_tokens);
+        assert!(res.execution_price > px);
+
+        // Short-heavy: closing a long widens the imbalance => penalty, worse exit.
+        let res = price_long_close(10_000, 1_000_000);
+        assert!(!res.balance_was_improved);
+        assert!(res.price_impact_usd.is_negative);
+        assert!(res.execution_price < px);
+    }
+
+    #[test]
+    fn positive_impact_bonus_is_capped() {
+        // Absurd helpful factor: uncapped, the bonus would exceed the base size.
+        let cfg = ImpactRebalanceConfig {
+            same_side_positive_factor_fp: crate::math::fp::SCALE,
+            crossover_positive_factor_fp: crate::math::fp::SCALE,
+            ..ImpactRebalanceConfig::default_quadratic()
+        };
+        let usd = |x: u64| U256::from(x) * U256::exp10(30);
+        let size = usd(10_000);
+        let oi = OpenInterestParams {
+            current: OpenInterestSnapshot {
+                long_usd: U256::zero(),
+                short_usd: usd(20_000),
+            },
+            next: OpenInterestSnapshot {
+                long_usd: size,
+                short_usd: usd(20_000),
+            },
+        };
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+        let price = |svc: &BasicPricingService| {
+            svc.get_execution_price(
+                &BasicPriceImpactService,
+                ExecutionPriceParams {
+                    oi: &oi,
+                    impact_cfg: &cfg,
+                    side: Side::Long,
+                    direction: TradeDirection::Increase,
+                    size_delta_usd: size,
+                    prices: OraclePrices {
+                        index_price_min: px,
+                        index_price_max: px,
+                        collateral_price_min: U256::one(),
+                        collateral_price_max: U256::one(),
+                    },
+                    price_selection: PriceSelection::Conservative,
+                },
+            )
+            .expect("pricing must succeed")
+        };
+
+        // Default (zero) cap is disabled: the position more than doubles.
+        let uncapped = price(&BasicPricingService::default());
+        assert!(uncapped.size_delta_tokens >= uncapped.base_size_delta_tokens * 2);
+
+        // 10% cap: at most +10% tokens, impact USD consistent with the capped tokens.
+        let capped = price(&BasicPricingService {
+            max_impact_bonus_bps: 1_000,
+        });
+        let max_bonus = capped.base_size_delta_tokens / 10;
+        assert_eq!(
+            capped.price_impact_amount_tokens,
+            SignedU256::pos(max_bonus)
+        );
+        assert_eq!(
+            capped.size_delta_tokens,
+            capped.base_size_delta_tokens + max_bonus
+        );
+        assert_eq!(capped.price_impact_usd, SignedU256::pos(max_bonus * px));
+        assert!(capped.execution_price < px);
+    }
+
+    #[test]
+    fn harmful_long_pays_above_mid_and_helpful_long_below() {
+        let px = U256::exp10(30) * 3_000 / U256::exp10(18);
+
+        // Long-heavy: another long is harmful.
+        let harmful = price_increase_with_oi(Side::Long, 1_000_000, 0);
+        assert!(execution_spread_bps(harmful.execution_price, px) > 0);
+
+        // Short-heavy: a long is helpful.
+        let helpful = price_increase_with_oi(Side::Long, 0, 1_000_000);
+        assert!(execution_spread_bps(helpful.execution_price, px) < 0);
+
+        assert_eq!(execution_spread_bps(px * 10_050 / 10_000, px), 50);
+        assert_eq!(execution_spread_bps(px * 9_950 / 10_000, px), -50);
+        assert_eq!(execution_spread_bps(px, U256::zero()), 0);
+    }
+
+    #[test]
+    fn inverted_or_zero_index_prices_are_rejected() {
+        for side in [Side::Long, Side::Short] {
+            assert_eq!(
+                price_increase(side, prices(1_100, 1_000), PriceSelection::Conservative)
+                    .unwrap_err(),
+                PricingError::InvalidPrices
+            );
+            assert_eq!(
+                price_increase(side, prices(0, 1_000), PriceSelection::Conservative).unwrap_err(),
+                PricingError::InvalidPrices
+            );
+        }
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
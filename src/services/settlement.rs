@@ -0,0 +1,305 @@
+// src/services/settlement.rs
+
+use primitive_types::{U256, U512};
+
+use crate::math::rounding::{Rounding, div_round, rounding_leakage};
+use crate::risk::{BPS_DENOM, RiskCfg};
+use crate::services::borrowing::{BorrowingDelta, apply_borrowing_fees_to_pool};
+use crate::services::borrowing_step::{BorrowingStep, apply_borrowing_step};
+use crate::services::funding::FundingDelta;
+use crate::services::funding_step::{credit_funding_reward, settle_funding_step};
+use crate::services::{BorrowingService, FundingService, Telemetry};
+use crate::state::{Claimables, MarketState, PoolBalances, Position, PositionKey, PositionStore};
+use crate::types::{AssetId, OraclePrices, SignedU256, Timestamp, TokenAmount, Usd};
+
+/// Result of settling funding + borrowing for one position.
+#[derive(Debug, Clone)]
+pub struct PositionSettlement {
+    pub key: PositionKey,
+    /// Funding paid (payer side only), in USD.
+    pub funding_usd: Usd,
+    /// Borrowing paid, in USD.
+    pub borrowing_usd: Usd,
+    /// Total collateral tokens taken from the position.
+    pub cost_tokens: TokenAmount,
+    /// Cost left unpaid by the cap and carried on `Position::unpaid_cost_usd`
+    /// (includes debt carried from earlier settlements), in USD.
+    pub unpaid_usd: Usd,
+    /// The cost hit `RiskCfg::max_settlement_cost_bps` and was capped;
+    /// the position should be liquidated (mirrors `Position::needs_liquidation`).
+    pub needs_liquidation: bool,
+}
+
+/// Market-level inputs of `settle_market_all`.
+#[derive(Debug, Clone, Copy)]
+pub struct SettlementParams<'a> {
+    /// Market to settle; its indices must already be synced to `now`
+    /// (update_indices / update_index).
+    pub market: &'a MarketState,
+    pub prices: &'a OraclePrices,
+    pub risk: &'a RiskCfg,
+    /// Only used for position-age rules such as the borrowing grace period.
+    pub now: Timestamp,
+}
+
+/// Settle funding + borrowing for every position of `params.market`,
+/// all-or-nothing.
+///
+/// Positions are settled on copies; pool fees and funding rewards are collected
+/// first and everything is written back only if every position succeeded.
+/// On error nothing is modified.
+///
+/// Per position:
+///  - funding + borrowing snapshots updated;
+///  - funding + borrowing + `unpaid_cost_usd` taken from collateral (via
+///    collateral_price_min, rounded up against the trader), capped at `risk.max_settlement_cost_bps` of collateral.
+///    The uncharged remainder stays on `unpaid_cost_usd` and the position is
+///    flagged with `needs_liquidation`;
+///  - funding is paid first; the rest of the charge (borrowing, carried debt)
+///    is routed to the pool fee bucket.
+///
+/// Receiver rewards are minted into Claimables after all payers settled, scaled
+/// down by the share of payer funding the caps left unpaid, so the market never
+/// credits more funding than it collected.
+///
+/// Funding, borrowing and rounding events are reported to `telemetry` on
+/// commit only.
+pub fn settle_market_all<F, B, T>(
+    funding_svc: &F,
+    borrowing_svc: &B,
+    telemetry: &T,
+    params: SettlementParams,
+    positions: &mut PositionStore,
+    pool_balances: &mut PoolBalances,
+    claimables: &mut Claimables,
+) -> Result<Vec<PositionSettlement>, String>
+where
+    F: FundingService,
+    B: BorrowingService,
+    T: Telemetry,
+{
+    let SettlementParams {
+        market,
+        prices,
+        risk,
+        now,
+    } = params;
+    risk.validate()?;
+    if prices.collateral_price_min.is_zero() {
+        return Err("invalid_collateral_price_min".into());
+    }
+
+    let mut settled: Vec<Position> = positions
+        .iter()
+        .filter(|(k, _)| k.market_id == market.id)
+        .map(|(_, p)| p.clone())
+        .collect();
+    let mut results = Vec::with_capacity(settled.len());
+    let mut pool_fees: Vec<(AssetId, TokenAmount)> = Vec::new();
+    let mut rewards: Vec<(usize, Usd)> = Vec::new();
+    let mut events: Vec<(FundingDelta, BorrowingDelta)> = Vec::with_capacity(settled.len());
+    let mut leakages: Vec<(AssetId, SignedU256)> = Vec::with_capacity(settled.len());
+    let mut funding_owed = U256::zero();
+    let mut funding_paid = U256::zero();
+
+    for (i, pos) in settled.iter_mut().enumerate() {
+        let funding = settle_funding_step(funding_svc, market, pos, prices)?;
+        let borrowing = apply_borrowing_step(borrowing_svc, market, pos, now)?;
+        if !funding.reward_usd.is_zero() {
+            rewards.push((i, funding.reward_usd));
+        }
+
+        let owed_usd = funding
+            .cost_usd
+            .checked_add(borrowing.cost_usd)
+            .and_then(|v| v.checked_add(pos.unpaid_cost_usd))
+            .ok_or("settlement_cost_overflow")?;
+        // Costs round up, as in apply_step_costs_to_position.
+        let mut cost_tokens = div_round(owed_usd, prices.collateral_price_min, Rounding::Up)?;
+        let mut paid_usd = owed_usd;
+        let mut needs_liquidation = false;
+        if risk.max_settlement_cost_bps > 0 {
+            let cap = pos
+                .collateral_amount
+                .saturating_mul(U256::from(risk.max_settlement_cost_bps))
+                / U256::from(BPS_DENOM);
+            if cost_tokens > cap {
+                cost_tokens = cap;
+                // cap < owed / price, so this cannot overflow.
+                paid_usd = cap * prices.collateral_price_min;
+                needs_liquidation = true;
+            }
+        }
+        if cost_tokens > pos.collateral_amount {
+            return Err(format!(
+                "insufficient_collateral_for_settlement:{:?}",
+                pos.key
+            ));
+        }
+        pos.collateral_amount -= cost_tokens;
+        pos.unpaid_cost_usd = owed_usd - paid_usd;
+        if !needs_liquidation {
+            // A capped charge is whole atoms: no remainder.
+            leakages.push((
+                pos.key.collateral_token,
+                rounding_leakage(owed_usd, prices.collateral_price_min, Rounding::Up, true),
+            ));
+        }
+        pos.needs_liquidation = needs_liquidation;
+
+        // Funding is owed to other traders, so it is paid first.
+        let funding_paid_usd = funding.cost_usd.min(paid_usd);
+        funding_owed += funding.cost_usd;
+        funding_paid += funding_paid_usd;
+
+        // The rounding surplus of `cost_tokens` goes to the pool with the fees.
+        let funding_tokens = (funding_paid_usd / prices.collateral_price_min).min(cost_tokens);
+        let fee_tokens = cost_tokens - funding_tokens;
+        pool_fees.push((pos.key.collateral_token, fee_tokens));
+
+        events.push((
+            funding.delta,
+            BorrowingDelta {
+                borrowing_fee_usd: borrowing.cost_usd,
+            },
+        ));
+        results.push(PositionSettlement {
+            key: pos.key,
+            funding_usd: funding.cost_usd,
+            borrowing_usd: borrowing.cost_usd,
+            cost_tokens,
+            unpaid_usd: pos.unpaid_cost_usd,
+            needs_liquidation,
+        });
+    }
+
+    // Scale receiver rewards down to what the payers actually paid.
+    let mut credits = Vec::with_capacity(rewards.len());
+    for (i, reward_usd) in rewards {
+        let reward_usd = if funding_paid < funding_owed {
+            let scaled =
+                U512::from(reward_usd) * U512::from(funding_paid) / U512::from(funding_owed);
+            U256::try_from(scaled).map_err(|_| "funding_reward_overflow")?
+        } else {
+            reward_usd
+        };
+        // Rewards are floored at collateral_price_max (see credit_funding_reward).
+        leakages.push((
+            settled[i].key.collateral_token,
+            rounding_leakage(
+                reward_usd,
+                prices.collateral_price_max,
+                Rounding::Down,
+                false,
+            ),
+        ));
+        credits.push((i, reward_usd));
+    }
+
+    // Commit.
+    for (token, fee_tokens) in pool_fees {
+        apply_borrowing_fees_to_pool(pool_balances, market.id, token, fee_tokens);
+    }
+    for (i, reward_usd) in credits {
+        credit_funding_reward(&settled[i], reward_usd, claimables, prices)?;
+    }
+    for pos in settled {
+        positions.upsert(pos);
+    }
+    for (s, (funding, borrowing)) in results.iter().zip(&events) {
+        telemetry.on_funding(&s.key, funding);
+        telemetry.on_borrowing(&s.key, borrowing);
+    }
+    for (token, leakage) in leakages {
+        telemetry.on_rounding(token, leakage);
+    }
+    Ok(results)
+}
+
+/// Settle borrowing for every position of `market` (per-block batch).
+///
+/// Calls `update_index` once, then for each position of the market:
+///  - settles the borrowing snapshot (`apply_borrowing_step`);
+///  - takes the cost plus any carried `unpaid_cost_usd` from collateral
+///    (via collateral_price_min, rounded up against the trader);
+///  - if the collateral cannot cover it, takes all of it, records the
+///    shortfall on `unpaid_cost_usd` and flags `needs_liquidation`;
+///  - routes the taken tokens to the pool fee bucket.
+///
+/// Rejects a zero `collateral_price_min` before touching anything.
+/// Positions and pools are only written back if every position settled;
+/// on error they are unchanged (the market index stays synced to `now`).
+/// Borrowing and rounding events are reported to `telemetry` on commit only.
+pub fn settle_market_borrowing<B: BorrowingService, T: Telemetry>(
+    borrowing_svc: &B,
+    telemetry: &T,
+    market: &mut MarketState,
+    positions: &mut PositionStore,
+    pools: &mut PoolBalances,
+    prices: &OraclePrices,
+    now: Timestamp,
+) -> Result<Vec<(PositionKey, BorrowingStep)>, String> {
+    if prices.collateral_price_min.is_zero() {
+        return Err("invalid_collateral_price_min".into());
+    }
+    borrowing_svc.update_index(market, now);
+
+    let mut settled: Vec<Position> = positions
+        .iter()
+        .filter(|(k, _)| k.market_id == market.id)
+        .map(|(_, p)| p.clone())
+        .collect();
+    let mut pool_fees: Vec<(AssetId, TokenAmount)> = Vec::with_capacity(settled.len());
+    let mut leakages: Vec<(AssetId, SignedU256)> = Vec::with_capacity(settled.len());
+
+    let mut out = Vec::with_capacity(settled.len());
+    for pos in settled.iter_mut() {
+        let key = pos.key;
+        let step = apply_borrowing_step(borrowing_svc, market, pos, now)?;
+
+        let owed_usd = step
+            .cost_usd
+            .checked_add(pos.unpaid_cost_usd)
+            .ok_or("settlement_cost_overflow")?;
+        let owed_tokens = div_round(owed_usd, prices.collateral_price_min, Rounding::Up)?;
+        if owed_tokens > pos.collateral_amount {
+            // collateral * price < owed, so neither side can overflow.
+            let paid_usd = pos.collateral_amount * prices.collateral_price_min;
+            pos.unpaid_cost_usd = owed_usd - paid_usd;
+            pos.needs_liquidation = true;
+            pool_fees.push((key.collateral_token, pos.collateral_amount));
+            pos.collateral_amount = TokenAmount::zero();
+        } else {
+            pos.unpaid_cost_usd = Usd::zero();
+            pos.needs_liquidation = false;
+            pos.collateral_amount -= owed_tokens;
+            pool_fees.push((key.collateral_token, owed_tokens));
+            leakages.push((
+                key.collateral_token,
+                rounding_leakage(owed_usd, prices.collateral_price_min, Rounding::Up, true),
+            ));
+        }
+
+        out.push((key, step));
+    }
+
+    // Commit.
+    for (token, fee_tokens) in pool_fees {
+        apply_borrowing_fees_to_pool(pools, market.id, token, fee_tokens);
+    }
+    for pos in settled {
+        positions.upsert(pos);
+    }
+    for (key, step) in &out {
+        telemetry.on_borrowing(
+            key,
+            &BorrowingDelta {
+                borrowing_fee_usd: step.cost_usd,
+            },
+        );
+    }
+    for (token, leakage) in leakages {
+        telemetry.on_rounding(token, leakage);
+    }
+    Ok(out)
+}
@@ -1,12 +1,15 @@
 use primitive_types::U256;
 
+use crate::math::rounding::{Rounding, div_round, rounding_leakage};
 use crate::services::BorrowingService;
 use crate::services::FundingService;
+use crate::services::Telemetry;
+use crate::services::borrowing::BorrowingDelta;
 use crate::services::borrowing_step::{apply_borrowing_step};
 use crate::services::fees::{FeesService, StepFees};
 use crate::services::funding_step::{apply_funding_step};
 use crate::state::{Claimables, MarketState, Position};
-use crate::types::{OraclePrices, Order, TokenAmount, Usd};
+use crate::types::{OraclePrices, Order, SignedU256, Timestamp, TokenAmount, Usd};
 /// Full cost breakdown for a single "step" (one position update).
 #[derive(Debug, Clone)]
 pub struct StepCosts {
@@ -14,67 +17,125 @@ pub struct StepCosts {
     pub funding_usd: Usd,
     /// Borrowing cost in USD.
     pub borrowing_usd: Usd,
-    /// Borrowing cost converted to collateral tokens (for pool yield).
+    /// Settlement debt carried on the position (`Position::unpaid_cost_usd`),
+    /// collected by this step, in USD.
+    pub carried_usd: Usd,
+    /// Borrowing cost plus carried debt converted to collateral tokens (for pool yield).
     pub borrowing_tokens: TokenAmount,
     /// Trading cost (position + liquidation fees), in USD.
     pub trading_usd: Usd,
-    /// Total step cost in USD (funding + borrowing + trading).
+    /// Total step cost in USD (funding + borrowing + carried + trading).
     pub total_usd: Usd,
 
     /// Detailed trading-related fees (position + liquidation).
     pub trading_fees: StepFees,
 }
 
+/// Inputs of a single step, shared by increase, decrease and previews.
+pub struct StepCostParams<'a> {
+    pub market: &'a MarketState,
+    pub prices: &'a OraclePrices,
+    pub order: &'a Order,
+    /// From the pricing step: the trade reduced the long/short imbalance.
+    pub balance_was_improved: bool,
+    /// From the pricing step, before any close scaling.
+    pub price_impact_usd: SignedU256,
+    pub size_delta_usd: Usd,
+    pub now: Timestamp,
+}
+
 /// Compute all per-step costs: funding + borrowing + trading.
 ///
 /// Side effects:
 ///  - updates funding snapshot in the position;
 ///  - adds funding rewards into Claimables (for receiver side);
+///  - reads settlement debt carried on the position (`unpaid_cost_usd`)
+///    into the step; it is only cleared by `apply_step_costs_to_position`;
+///  - reports funding / borrowing / fee results to `telemetry`;
 ///  - does NOT yet touch collateral or pool balances.
-pub fn compute_step_costs<F, B, Fe>(
+pub fn compute_step_costs<F, B, Fe, T>(
     funding_svc: &F,
     borrowing_svc: &B,
     fees_svc: &Fe,
-    market: &MarketState,
+    telemetry: &T,
+    params: StepCostParams,
     pos: &mut Position,
     claimables: &mut Claimables,
-    prices: &OraclePrices,
-    order: &Order,
-    balance_was_improved: bool,
-    size_delta_usd: Usd,
 ) -> Result<StepCosts, String>
 where
     F: FundingService,
     B: BorrowingService,
     Fe: FeesService,
+    T: Telemetry,
 {
+    let StepCostParams {
+        market,
+        prices,
+        order,
+        balance_was_improved,
+        price_impact_usd,
+        size_delta_usd,
+        now,
+    } = params;
+
     // 1) Funding: updates pos.funding_index and claimables (for receiver side).
     let funding_step = apply_funding_step(funding_svc, market, pos, claimables, prices)?;
+    telemetry.on_funding(&pos.key, &funding_step.delta);
+    let funding_fee = funding_step.delta.funding_fee_usd;
+    if funding_fee.is_negative {
+        // Reward tokens are floored at collateral_price_max (see apply_funding_step).
+        telemetry.on_rounding(
+            pos.key.collateral_token,
+            rounding_leakage(
+                funding_fee.mag,
+                prices.collateral_price_max,
+                Rounding::Down,
+                false,
+            ),
+        );
+    }
 
     // 2) Borrowing: cost in USD for this step.
-    let borrowing_step = apply_borrowing_step(borrowing_svc, market, pos);
+    let borrowing_step = apply_borrowing_step(borrowing_svc, market, pos, now)?;
+    telemetry.on_borrowing(
+        &pos.key,
+        &BorrowingDelta {
+            borrowing_fee_usd: borrowing_step.cost_usd,
+        },
+    );
+
+    // Debt left by a capped settlement is collected with this step.
+    let carried_usd = pos.unpaid_cost_usd;
 
-    // Convert borrowing from USD to collateral tokens (for pool yield).
+    // Convert borrowing + carried debt from USD to collateral tokens (for pool yield).
     let borrowing_tokens: TokenAmount = if prices.collateral_price_min > U256::zero() {
-        borrowing_step.cost_usd / prices.collateral_price_min
+        (borrowing_step.cost_usd + carried_usd) / prices.collateral_price_min
     } else {
         U256::zero()
     };
 
     // 3) Trading fees (position + liquidation).
     let trading_fees =
-        fees_svc.compute_fees(pos, order, prices, balance_was_improved, size_delta_usd)?;
+        fees_svc.compute_fees(
+            pos,
+            order,
+            prices,
+            balance_was_improved,
+            price_impact_usd,
+            size_delta_usd,
+        )?;
+    telemetry.on_fee(&pos.key, &trading_fees);
 
     let funding_usd = funding_step.cost_usd;
     let borrowing_usd = borrowing_step.cost_usd;
     let trading_usd = trading_fees.position_fee_usd + trading_fees.liquidation_fee_usd;
 
-    let total_usd = funding_usd + borrowing_usd + trading_usd;
+    let total_usd = funding_usd + borrowing_usd + carried_usd + trading_usd;
 
-    println!("TOTAL USD {:?}", total_usd);
     Ok(StepCosts {
         funding_usd,
         borrowing_usd,
+        carried_usd,
         borrowing_tokens,
         trading_usd,
         total_usd,
@@ -84,23 +145,52 @@ where
 
 /// Apply all step costs to position collateral.
 ///
-/// total_usd = funding + borrowing + trading.
-/// We convert total_usd → collateral tokens via collateral_price_min and
+/// total_usd = funding + borrowing + carried debt + trading.
+/// We convert total_usd → collateral tokens via collateral_price_min, rounded
+/// UP (a floor would undercharge the trader by up to one atom per step), and
 /// subtract from pos.collateral_amount, reverting on insufficient collateral.
-pub fn apply_step_costs_to_position(
+/// The conversion remainder is reported to `telemetry.on_rounding`.
+///
+/// Only a successful charge clears `unpaid_cost_usd` and `needs_liquidation`.
+/// On insufficient collateral the funding / borrowing snapshots have already
+/// advanced, so this step's funding + borrowing join the carried debt instead
+/// of being forgiven; the liquidation flag is left as it was.
+pub fn apply_step_costs_to_position<T: Telemetry>(
     pos: &mut Position,
     prices: &OraclePrices,
     step_costs: &StepCosts,
+    telemetry: &T,
 ) -> Result<(), String> {
     if prices.collateral_price_min <= U256::zero() {
         return Err("invalid_collateral_price_min".into());
     }
 
-    let total_tokens_cost: TokenAmount = step_costs.total_usd / prices.collateral_price_min;
+    // Costs round up so the remainder stays with the protocol.
+    let total_tokens_cost: TokenAmount = div_round(
+        step_costs.total_usd,
+        prices.collateral_price_min,
+        Rounding::Up,
+    )?;
 
     if total_tokens_cost > pos.collateral_amount {
+        pos.unpaid_cost_usd = step_costs
+            .carried_usd
+            .checked_add(step_costs.funding_usd)
+            .and_then(|v| v.checked_add(step_costs.borrowing_usd))
+            .ok_or("settlement_cost_overflow")?;
         return Err("insufficient_collateral_for_step_costs".into());
     }
     pos.collateral_amount -= total_tokens_cost;
+    pos.unpaid_cost_usd = Usd::zero();
+    pos.needs_liquidation = false;
+    telemetry.on_rounding(
+        pos.key.collateral_token,
+        rounding_leakage(
+            step_costs.total_usd,
+            prices.collateral_price_min,
+            Rounding::Up,
+            true,
+        ),
+    );
     Ok(())
 }
@@ -0,0 +1,158 @@
+// src/services/telemetry.rs
+
+use crate::math::rounding::RoundingAudit;
+use crate::services::borrowing::BorrowingDelta;
+use crate::services::fees::StepFees;
+use crate::services::funding::FundingDelta;
+use crate::state::PositionKey;
+use crate::types::{AssetId, SignedU256};
+
+/// Opt-in hooks for tracing per-step cost computations (audit / observability).
+///
+/// Called from `compute_step_costs` after each component is computed. Every
+/// method defaults to a no-op, so implementors only override what they need.
+/// Hooks take `&self`; recording implementations use interior mutability.
+pub trait Telemetry {
+    fn on_fee(&self, _key: &PositionKey, _fees: &StepFees) {}
+    fn on_funding(&self, _key: &PositionKey, _delta: &FundingDelta) {}
+    fn on_borrowing(&self, _key: &PositionKey, _delta: &BorrowingDelta) {}
+    /// Remainder of a USD -> `asset` token conversion (see `rounding_leakage`).
+    fn on_rounding(&self, _asset: AssetId, _leakage_usd: SignedU256) {}
+}
+
+/// Default telemetry: discards every event.
+#[derive(Default, Clone)]
+pub struct NoopTelemetry;
+
+impl Telemetry for NoopTelemetry {}
+
+impl Telemetry for RoundingAudit {
+    fn on_rounding(&self, asset: AssetId, leakage_usd: SignedU256) {
+        self.record(asset, leakage_usd);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    use primitive_types::U256;
+
+    use super::*;
+    use crate::services::borrowing::BasicBorrowingService;
+    use crate::services::fees::BasicFeesService;
+    use crate::services::funding::BasicFundingService;
+    use crate::services::step_costs::{StepCostParams, compute_step_costs};
+    use crate::state::{Claimables, MarketState, Position};
+    use crate::types::{
+        AccountId, AssetId, ExecutionType, MarketId, OraclePrices, Order, OrderType, Side,
+        SignedU256,
+    };
+
+    #[derive(Default)]
+    struct RecordingTelemetry {
+        fees: RefCell<Vec<(PositionKey, StepFees)>>,
+        funding: RefCell<Vec<PositionKey>>,
+    }
+
+    impl Telemetry for RecordingTelemetry {
+        fn on_fee(&self, key: &PositionKey, fees: &StepFees) {
+            self.fees.borrow_mut().push((*key, fees.clone()));
+        }
+        fn on_funding(&self, key: &PositionKey, _delta: &FundingDelta) {
+            self.funding.borrow_mut().push(*key);
+        }
+    }
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn recording_telemetry_collects_fee_event() {
+        let key = PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let mut pos = Position {
+            key,
+            size_usd: U256::zero(),
+            size_tokens: U256::zero(),
+            collateral_amount: U256::from(1_000u64),
+            collateral_balances: HashMap::new(),
+            pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
+            funding_index: SignedU256::zero(),
+            funding_updated_at: 0,
+            pending_funding_usd: SignedU256::zero(),
+            borrowing_index: U256::zero(),
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
+            opened_at: 1,
+            last_updated_at: 1,
+        };
+        let order = Order {
+            account: key.account,
+            market_id: key.market_id,
+            side: key.side,
+            collateral_token: key.collateral_token,
+            size_delta_usd: usd(1_000),
+            collateral_delta_tokens: U256::zero(),
+            target_leverage_x: 1,
+            order_type: OrderType::Increase,
+            execution_type: ExecutionType::Market,
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: U256::zero(),
+            execution_fee_tokens: U256::zero(),
+            reduce_only: false,
+            created_at: 1,
+            valid_from: 1,
+            valid_until: 100,
+        };
+        let prices = OraclePrices {
+            index_price_min: usd(1),
+            index_price_max: usd(1),
+            collateral_price_min: usd(1),
+            collateral_price_max: usd(1),
+        };
+        let fees = BasicFeesService::new(10, 10, 50, 20);
+        let telemetry = RecordingTelemetry::default();
+
+        let costs = compute_step_costs(
+            &BasicFundingService::new(),
+            &BasicBorrowingService::default(),
+            &fees,
+            &telemetry,
+            StepCostParams {
+                market: &MarketState::default(),
+                prices: &prices,
+                order: &order,
+                balance_was_improved: false,
+                price_impact_usd: SignedU256::zero(),
+                size_delta_usd: usd(1_000),
+                now: 1,
+            },
+            &mut pos,
+            &mut Claimables::default(),
+        )
+        .unwrap();
+
+        let recorded = telemetry.fees.borrow();
+        assert_eq!(recorded.len(), 1);
+        assert_eq!(recorded[0].0, key);
+        assert_eq!(
+            recorded[0].1.position_fee_usd,
+            costs.trading_fees.position_fee_usd
+        );
+        // 10 bps of $1_000.
+        assert_eq!(recorded[0].1.position_fee_usd, usd(1));
+        assert_eq!(*telemetry.funding.borrow(), vec![key]);
+
+        // The no-op default accepts the same calls.
+        NoopTelemetry.on_fee(&key, &recorded[0].1);
+    }
+}
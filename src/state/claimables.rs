@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use primitive_types::U256;
 
-use crate::types::{AccountId, AssetId, TokenAmount};
+use crate::types::{AccountId, AssetId, TokenAmount, Usd};
 
 /// Claimables is a ledger of "rights to receive something later".
 /// We don't move real tokens immediately; we just accumulate how much
@@ -45,6 +45,11 @@ impl Claimables {
             .unwrap_or(U256::zero())
     }
 
+    /// Iterate all non-consumed funding claimables.
+    pub fn funding_entries(&self) -> impl Iterator<Item = (&(AccountId, AssetId), &TokenAmount)> {
+        self.funding.iter()
+    }
+
     /// Take (consume) the whole funding claimable for (account, asset).
     ///
     /// Returns the amount that was stored, and resets it to zero.
@@ -74,6 +79,11 @@ impl Claimables {
             .unwrap_or(U256::zero())
     }
 
+    /// Iterate all non-consumed fee claimables.
+    pub fn fee_entries(&self) -> impl Iterator<Item = (&(AccountId, AssetId), &TokenAmount)> {
+        self.fees.iter()
+    }
+
     /// Take all fee claimables for (account, asset).
     pub fn take_fee_all(&mut self, account: AccountId, asset: AssetId) -> TokenAmount {
         self.fees.remove(&(account, asset)).unwrap_or(U256::zero())
@@ -86,6 +96,27 @@ impl Claimables {
             .saturating_add(self.get_fee(account, asset))
     }
 
+    /// USD(1e30) value of `balance_of(account, asset)`.
+    ///
+    /// `price` is USD(1e30) per atom of `asset`. Saturates at `U256::MAX`
+    /// on overflow (display-only value).
+    pub fn balance_usd(&self, account: AccountId, asset: AssetId, price: Usd) -> Usd {
+        self.balance_of(account, asset).saturating_mul(price)
+    }
+
+    /// Total USD(1e30) value of all claimables of `account`.
+    ///
+    /// Assets without an entry in `prices_by_asset` are not counted.
+    /// Saturates at `U256::MAX` on overflow.
+    pub fn total_usd(&self, account: AccountId, prices_by_asset: &HashMap<AssetId, Usd>) -> Usd {
+        self.list_by_account(account)
+            .into_iter()
+            .filter_map(|(asset, amount)| {
+                prices_by_asset.get(&asset).map(|price| amount.saturating_mul(*price))
+            })
+            .fold(U256::zero(), |acc, v| acc.saturating_add(v))
+    }
+
     /// Claim *all* claimables (funding + fees) for (account, asset).
     /// Returns total amount claimed.
     fn take_all(&mut self, account: AccountId, asset: AssetId) -> TokenAmount {
@@ -125,3 +156,37 @@ impl Claimables {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn claimables_are_valued_per_asset_price() {
+        let account = AccountId([1u8; 32]);
+        let usdc = AssetId(10);
+        let weth = AssetId(11);
+
+        let mut c = Claimables::default();
+        c.add_funding(account, usdc, U256::from(30));
+        c.add_fee(account, usdc, U256::from(20));
+        c.add_funding(account, weth, U256::from(2));
+        c.add_fee(AccountId([2u8; 32]), weth, U256::from(100));
+
+        assert_eq!(c.balance_usd(account, usdc, usd(1)), usd(50));
+        assert_eq!(c.balance_usd(account, weth, usd(3_000)), usd(6_000));
+
+        let mut prices = HashMap::new();
+        prices.insert(usdc, usd(1));
+        prices.insert(weth, usd(3_000));
+        assert_eq!(c.total_usd(account, &prices), usd(6_050));
+
+        // Unpriced assets are skipped.
+        prices.remove(&weth);
+        assert_eq!(c.total_usd(account, &prices), usd(50));
+    }
+}
@@ -1,4 +1,16 @@
 // src/state/market_state.rs
+use std::collections::{HashSet, VecDeque};
+
+use primitive_types::U256;
+
+use crate::math::position::PendingImpactRounding;
+use crate::math::rounding::{Rounding, div_round};
+use crate::risk::BPS_DENOM;
+use crate::services::borrowing::{current_borrowing_rate_fp_per_sec, utilization_fp};
+use crate::services::funding::{
+    DEFAULT_MAX_FUNDING_RATE_FP_PER_SEC, FundingRateModel, current_funding_rate_fp_per_sec,
+};
+use crate::services::price_impact::ImpactRebalanceConfig;
 use crate::types::*;
 
 #[derive(Clone, Debug, Default)]
@@ -16,7 +28,8 @@ pub struct MarketBorrowing {
 pub struct ImpactPoolState {
     pub impact_tokens: TokenAmount,
     pub total_pending_impact_tokens: TokenAmount,
-    pub last_bleed_at: Timestamp,
+    /// Last time `ImpactPoolService::distribute` ran (0 = never).
+    pub last_distributed_at: Timestamp,
 }
 
 #[derive(Clone, Debug, Default)]
@@ -32,7 +45,35 @@ pub struct MarketConfig {
     pub min_position_size_usd: Usd,
 }
 
-#[derive(Clone, Debug, Default)]
+/// What borrowing utilization is measured against pool liquidity.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UtilizationMode {
+    /// (oi_long + oi_short) / liquidity.
+    #[default]
+    OpenInterest,
+    /// reserved_usd / liquidity: pool capital actually locked for worst-case payouts.
+    Reserved,
+}
+
+/// Trading status of a single market, on top of `MarketState::paused`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MarketStatus {
+    #[default]
+    Active,
+    /// Deleveraging mode: decreases and liquidations proceed, increases only
+    /// on the lighter side. Finer than `GlobalStatus::ReduceOnly`, which
+    /// blocks every increase.
+    ReduceSkewOnly,
+}
+
+/// Default `MarketState::reserve_factor_{long,short}_bps`: a side's whole OI
+/// is reserved as its worst-case payout.
+pub const DEFAULT_RESERVE_FACTOR_BPS: u32 = 10_000;
+
+/// Default `MarketState::impact_on_close_bps_scale`: full impact on close.
+pub const DEFAULT_IMPACT_ON_CLOSE_BPS_SCALE: u32 = 10_000;
+
+#[derive(Clone, Debug)]
 pub struct MarketState {
     /// Market identifier.
     pub id: MarketId,
@@ -55,8 +96,85 @@ pub struct MarketState {
     /// State of the position impact pool.
     pub impact_pool: ImpactPoolState,
     pub liquidity_usd: Usd,
+
+    /// Pool capital (USD) reserved to back worst-case payouts of open positions.
+    /// Recomputed from OI on every increase / decrease (`sync_reserved_usd`);
+    /// only read in `UtilizationMode::Reserved`.
+    pub reserved_usd: Usd,
+    /// Share of long / short OI reserved as worst-case payout, in bps.
+    pub reserve_factor_long_bps: u32,
+    pub reserve_factor_short_bps: u32,
+    /// Numerator used for borrowing utilization.
+    pub utilization_mode: UtilizationMode,
+
+    /// Mid index price of the last executed order (USD per atom). Zero = no reference yet.
+    pub last_index_price: Usd,
+    /// Max allowed move of the mid index price vs `last_index_price` in one update (bps).
+    /// Zero disables the price band.
+    pub max_price_deviation_bps: u32,
+    /// Max index spread `(max - min) / min` accepted for increases (bps).
+    /// Zero disables the check.
+    pub max_price_spread_bps: u32,
+
+    /// Paused markets reject increases; decreases and liquidations still execute.
+    pub paused: bool,
+    /// Which increases the market accepts while not paused.
+    pub status: MarketStatus,
+
+    /// Scale applied to negative price impact on decreases, in bps: 0 = no
+    /// impact penalty on close, 10_000 = full impact (default). Positive impact
+    /// on close and all impact on increases are never scaled.
+    pub impact_on_close_bps_scale: u32,
+    /// Rounding of pending impact realized on partial closes. Defaults to
+    /// rounding against the user; full closes always realize the remainder.
+    pub pending_impact_rounding: PendingImpactRounding,
+
+    /// Collateral tokens accepted for new exposure. Empty = any token.
+    pub allowed_collateral: HashSet<AssetId>,
+
+    /// Funding settlements below this |fee| (USD) are deferred: the position
+    /// keeps its old index snapshot and the amount carries forward until it
+    /// crosses the floor. Zero disables the floor.
+    pub min_funding_settlement_usd: Usd,
+
+    /// Max rate (index units per second, scale 1e18) at which either side's
+    /// funding index may move. `update_indices` clamps payer and receiver
+    /// deltas to it, and a settlement whose index delta exceeds it times the
+    /// elapsed time is flagged as an anomaly instead of charged. Zero disables
+    /// the bound.
+    pub max_funding_rate_fp_per_sec: U256,
+
+    /// Max OI added by increases within any `oi_window_secs` long span (USD).
+    /// The window is rolling, so back-to-back bursts across a window
+    /// boundary are capped too. Zero disables the rate limit.
+    pub max_oi_change_per_window_usd: Usd,
+    /// Length of the rolling OI rate-limit window in seconds (zero = per-order cap).
+    pub oi_window_secs: u64,
+    /// Increases still inside the rolling rate-limit window.
+    pub oi_window: OiWindowState,
+
+    /// Funding clock behavior while the market has no OI. By default an update
+    /// with zero OI leaves the indices alone but advances
+    /// `funding.last_updated_at`, so the gap never accrues. When set, the clock
+    /// is left at the start of the gap instead and the first update that sees
+    /// OI accrues the whole elapsed time at that OI (e.g. OI that arrived
+    /// mid-gap under `SettlementOrder::TradeThenSettle`).
+    pub accrue_funding_over_zero_oi_gaps: bool,
+
+    /// Max OI skew `|long - short| / (long + short)` an increase may leave, in bps.
+    /// Increases that shrink the skew are always allowed. Zero disables the cap.
+    /// Not enforced while the post-trade total OI is below
+    /// `min_oi_for_skew_cap_usd`, so a fresh (necessarily one-sided) market can
+    /// bootstrap; above it, a one-sided market is capped like any other.
+    pub max_oi_skew_bps: u32,
+    /// Total OI (USD) below which `max_oi_skew_bps` is not enforced.
+    pub min_oi_for_skew_cap_usd: Usd,
+
+    /// Price impact profile of this market. Its factors are scaled to the
+    /// current `liquidity_usd` when `reference_liquidity_usd` is set (see
+    /// `effective_impact_config`).
+    pub impact_config: ImpactRebalanceConfig,
     // TODO:
-    // pub impact_config: MarketImpactConfig,
     // pub limits: MarketLimits,
     // pub margin_config: MarginConfig,
 }
@@ -78,3 +196,292 @@ pub struct BorrowingState {
     /// Last time borrowing factor was updated.
     pub last_updated_at: Timestamp,
 }
+
+/// Increases counted by the OI rate limit, oldest first, as
+/// `(executed_at, size_delta_usd)` (see `MarketState::oi_window_secs`).
+#[derive(Clone, Debug, Default)]
+pub struct OiWindowState {
+    pub increases: VecDeque<(Timestamp, Usd)>,
+}
+
+/// Read-only snapshot of a market for info panels.
+///
+/// Rates are index units per second (scale 1e18) as accrued by the basic
+/// funding / borrowing services at the current OI and liquidity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MarketSummary {
+    pub oi_long_usd: Usd,
+    pub oi_short_usd: Usd,
+    pub liquidity_usd: Usd,
+    /// (oi_long + oi_short) / liquidity, fixed-point [0, 1] * 1e18.
+    pub utilization_fp: U256,
+    /// Rate at which the paying side's funding index grows.
+    pub funding_rate_fp_per_sec: U256,
+    /// Side currently paying funding (`None` without open interest).
+    pub funding_payer: Option<Side>,
+    pub borrowing_rate_fp_per_sec: U256,
+}
+
+impl Default for MarketState {
+    fn default() -> Self {
+        Self {
+            id: MarketId::default(),
+            index_token: AssetId::default(),
+            long_asset: AssetId::default(),
+            short_asset: AssetId::default(),
+            oi_long_usd: Usd::zero(),
+            oi_short_usd: Usd::zero(),
+            funding: FundingState::default(),
+            borrowing: BorrowingState::default(),
+            impact_pool: ImpactPoolState::default(),
+            liquidity_usd: Usd::zero(),
+            reserved_usd: Usd::zero(),
+            reserve_factor_long_bps: DEFAULT_RESERVE_FACTOR_BPS,
+            reserve_factor_short_bps: DEFAULT_RESERVE_FACTOR_BPS,
+            utilization_mode: UtilizationMode::default(),
+            last_index_price: Usd::zero(),
+            max_price_deviation_bps: 0,
+            max_price_spread_bps: 0,
+            paused: false,
+            status: MarketStatus::default(),
+            impact_on_close_bps_scale: DEFAULT_IMPACT_ON_CLOSE_BPS_SCALE,
+            pending_impact_rounding: PendingImpactRounding::default(),
+            allowed_collateral: HashSet::new(),
+            min_funding_settlement_usd: Usd::zero(),
+            max_funding_rate_fp_per_sec: DEFAULT_MAX_FUNDING_RATE_FP_PER_SEC,
+            max_oi_change_per_window_usd: Usd::zero(),
+            oi_window_secs: 0,
+            oi_window: OiWindowState::default(),
+            accrue_funding_over_zero_oi_gaps: false,
+            max_oi_skew_bps: 0,
+            min_oi_for_skew_cap_usd: Usd::zero(),
+            impact_config: ImpactRebalanceConfig::default_quadratic(),
+        }
+    }
+}
+
+impl MarketState {
+    /// Fresh market with zero OI and indices, funding and borrowing clocks
+    /// started at `now` so the first update accrues only from creation.
+    pub fn new(id: MarketId, liquidity_usd: Usd, now: Timestamp) -> Self {
+        Self {
+            id,
+            liquidity_usd,
+            funding: FundingState {
+                last_updated_at: now,
+                ..FundingState::default()
+            },
+            borrowing: BorrowingState {
+                last_updated_at: now,
+                ..BorrowingState::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// Recompute `reserved_usd` as each side's OI times its reserve factor,
+    /// rounded up so the reservation never understates the payout.
+    pub fn sync_reserved_usd(&mut self) -> Result<(), String> {
+        let reserve = |oi: Usd, factor_bps: u32| {
+            let n = oi
+                .checked_mul(U256::from(factor_bps))
+                .ok_or("reserved_usd_overflow")?;
+            div_round(n, U256::from(BPS_DENOM), Rounding::Up)
+        };
+        self.reserved_usd = reserve(self.oi_long_usd, self.reserve_factor_long_bps)?
+            .checked_add(reserve(self.oi_short_usd, self.reserve_factor_short_bps)?)
+            .ok_or("reserved_usd_overflow")?;
+        Ok(())
+    }
+
+    /// `impact_config` scaled to the market's current `liquidity_usd`.
+    pub fn effective_impact_config(&self) -> Result<ImpactRebalanceConfig, String> {
+        self.impact_config.for_liquidity(self.liquidity_usd)
+    }
+
+    /// OI added by increases in the `oi_window_secs` ending at `now`
+    /// (executed at `t` with `now < t + oi_window_secs`).
+    pub fn oi_added_in_window(&self, now: Timestamp) -> Usd {
+        self.oi_window
+            .increases
+            .iter()
+            .filter(|(t, _)| now < t.saturating_add(self.oi_window_secs))
+            .fold(Usd::zero(), |acc, (_, usd)| acc.saturating_add(*usd))
+    }
+
+    /// Rejects an increase of `size_delta_usd` at `now` that would push the
+    /// window's OI growth above `max_oi_change_per_window_usd`.
+    pub fn check_oi_rate_limit(&self, size_delta_usd: Usd, now: Timestamp) -> Result<(), String> {
+        if self.max_oi_change_per_window_usd.is_zero() {
+            return Ok(());
+        }
+        let next = self
+            .oi_added_in_window(now)
+            .checked_add(size_delta_usd)
+            .ok_or("oi_rate_limit_overflow")?;
+        if next > self.max_oi_change_per_window_usd {
+            return Err("oi_rate_limit_exceeded".into());
+        }
+        Ok(())
+    }
+
+    /// Counts an executed increase against the rolling window, dropping
+    /// increases that fell out of it.
+    pub fn record_oi_increase(&mut self, size_delta_usd: Usd, now: Timestamp) {
+        if self.max_oi_change_per_window_usd.is_zero() {
+            return;
+        }
+        let window = self.oi_window_secs;
+        let increases = &mut self.oi_window.increases;
+        while increases
+            .front()
+            .is_some_and(|(t, _)| now >= t.saturating_add(window))
+        {
+            increases.pop_front();
+        }
+        if window > 0 {
+            increases.push_back((now, size_delta_usd));
+        }
+    }
+
+    /// Under `MarketStatus::ReduceSkewOnly`, rejects an increase unless `side`
+    /// is the strictly lighter side and the trade does not leave a larger
+    /// (flipped) skew than before. Balanced markets accept no increases.
+    pub fn check_status_allows_increase(
+        &self,
+        side: Side,
+        size_delta_usd: Usd,
+    ) -> Result<(), String> {
+        if self.status == MarketStatus::Active {
+            return Ok(());
+        }
+        let (own, other) = match side {
+            Side::Long => (self.oi_long_usd, self.oi_short_usd),
+            Side::Short => (self.oi_short_usd, self.oi_long_usd),
+        };
+        // Lighter side: skew after = |own + size - other| must not exceed other - own.
+        let reduces_skew = own < other
+            && own
+                .checked_add(size_delta_usd)
+                .is_some_and(|next| next <= other || next - other <= other - own);
+        if !reduces_skew {
+            return Err("market_reduce_skew_only".into());
+        }
+        Ok(())
+    }
+
+    /// Rejects an increase of `size_delta_usd` on `side` that leaves the OI skew
+    /// above `max_oi_skew_bps`, unless it reduces `|long - short|` or the
+    /// resulting total OI is below `min_oi_for_skew_cap_usd`.
+    pub fn check_oi_skew(&self, side: Side, size_delta_usd: Usd) -> Result<(), String> {
+        if self.max_oi_skew_bps == 0 {
+            return Ok(());
+        }
+        let (mut long, mut short) = (self.oi_long_usd, self.oi_short_usd);
+        let grown = match side {
+            Side::Long => &mut long,
+            Side::Short => &mut short,
+        };
+        *grown = grown
+            .checked_add(size_delta_usd)
+            .ok_or("oi_skew_overflow")?;
+
+        let total = long.checked_add(short).ok_or("oi_skew_overflow")?;
+        if total < self.min_oi_for_skew_cap_usd {
+            return Ok(());
+        }
+
+        let skew = |a: Usd, b: Usd| if a >= b { a - b } else { b - a };
+        let skew_before = skew(self.oi_long_usd, self.oi_short_usd);
+        let skew_after = skew(long, short);
+        if skew_after < skew_before {
+            return Ok(());
+        }
+
+        // skew_after / total > max_bps / 10_000
+        let lhs = skew_after
+            .checked_mul(U256::from(10_000u32))
+            .ok_or("oi_skew_overflow")?;
+        let rhs = total
+            .checked_mul(U256::from(self.max_oi_skew_bps))
+            .ok_or("oi_skew_overflow")?;
+        if lhs > rhs {
+            return Err("oi_skew_exceeded".into());
+        }
+        Ok(())
+    }
+
+    /// Whether `asset` may be used as collateral for increases in this market.
+    pub fn accepts_collateral(&self, asset: AssetId) -> bool {
+        self.allowed_collateral.is_empty() || self.allowed_collateral.contains(&asset)
+    }
+
+    /// Read-only snapshot of OI, utilization and current rates. Funding is
+    /// priced with `funding_model` (the market's `FundingService::rate_model`).
+    pub fn summary(&self, funding_model: &dyn FundingRateModel) -> MarketSummary {
+        let (funding_rate_fp_per_sec, funding_payer) =
+            current_funding_rate_fp_per_sec(funding_model, self);
+        MarketSummary {
+            oi_long_usd: self.oi_long_usd,
+            oi_short_usd: self.oi_short_usd,
+            liquidity_usd: self.liquidity_usd,
+            utilization_fp: utilization_fp(self),
+            funding_rate_fp_per_sec,
+            funding_payer,
+            borrowing_rate_fp_per_sec: current_borrowing_rate_fp_per_sec(self),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::services::borrowing::{BasicBorrowingService, BorrowingService};
+    use crate::services::funding::{BasicFundingService, FundingService};
+    use crate::state::{Position, PositionKey};
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn new_market_settles_nothing_at_creation_and_accrues_from_now() {
+        let now: Timestamp = 1_000;
+        let mut market = MarketState::new(MarketId(7), usd(1_000_000), now);
+        assert_eq!(market.id, MarketId(7));
+        assert!(market.oi_long_usd.is_zero() && market.oi_short_usd.is_zero());
+        assert_eq!(market.funding.last_updated_at, now);
+        assert_eq!(market.borrowing.last_updated_at, now);
+
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: market.id,
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        };
+        let mut pos = Position::open(key, &market, usd(10_000), U256::one(), usd(1), now).unwrap();
+        market.oi_long_usd = pos.size_usd;
+
+        let funding = BasicFundingService::new();
+        let borrowing = BasicBorrowingService::default();
+        funding.update_indices(&mut market, now);
+        borrowing.update_index(&mut market, now);
+
+        let f = funding.settle_position_funding(&market, &mut pos).unwrap();
+        let b = borrowing
+            .settle_position_borrowing(&market, &mut pos, now)
+            .unwrap();
+        assert!(f.funding_fee_usd.is_zero());
+        assert!(b.borrowing_fee_usd.is_zero());
+
+        // The clock started at creation: one hour later exactly one hour accrues.
+        funding.update_indices(&mut market, now + 3_600);
+        borrowing.update_index(&mut market, now + 3_600);
+        let (rate, _) = current_funding_rate_fp_per_sec(funding.rate_model(), &market);
+        assert_eq!(
+            market.funding.cumulative_index_long,
+            SignedU256::pos(rate * U256::from(3_600u64))
+        );
+        assert!(!market.borrowing.cumulative_factor.is_zero());
+    }
+}
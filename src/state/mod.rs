@@ -5,12 +5,16 @@ mod market_state;
 mod order_store;
 mod pool_balances;
 mod position_store;
+mod snapshot;
+mod token_registry;
 
 pub use claimables::*;
 pub use market_state::*;
 pub use order_store::*;
 pub use pool_balances::*;
 pub use position_store::*;
+pub use snapshot::*;
+pub use token_registry::*;
 
 use crate::types::*;
 use std::collections::HashMap;
@@ -22,4 +26,5 @@ pub struct State {
     pub pool_balances: PoolBalances,
     pub claimables: Claimables,
     pub orders: OrderStore,
+    pub tokens: TokenRegistry,
 }
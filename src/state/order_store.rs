@@ -1,11 +1,13 @@
 use std::collections::HashMap;
 
-use crate::types::{Order, OrderId};
+use crate::types::{Order, OrderId, Timestamp};
 
 #[derive(Default, Clone)]
 pub struct OrderStore {
     orders: HashMap<OrderId, Order>,
     next_id: u64,
+    /// Soft cap on stored orders, enforced by `try_create`. Zero = unlimited.
+    pub max_orders: usize,
 }
 
 impl OrderStore {
@@ -13,6 +15,14 @@ impl OrderStore {
         Self {
             orders: HashMap::new(),
             next_id: 0,
+            max_orders: 0,
+        }
+    }
+
+    pub fn with_max_orders(max_orders: usize) -> Self {
+        Self {
+            max_orders,
+            ..Self::new()
         }
     }
 
@@ -23,6 +33,48 @@ impl OrderStore {
         id
     }
 
+    /// Check that `order` can be stored at trusted time `now`.
+    ///
+    /// Orders claiming `created_at > now` are rejected with `"order_created_in_future"`,
+    /// and a full store (see `max_orders`) with `"order_store_full"`.
+    pub fn check_create(&self, now: Timestamp, order: &Order) -> Result<(), String> {
+        if order.created_at > now {
+            return Err("order_created_in_future".into());
+        }
+        if self.is_full() {
+            return Err("order_store_full".into());
+        }
+        Ok(())
+    }
+
+    /// Create an order at trusted time `now` (see `check_create`).
+    ///
+    /// Does not prune: callers free space with `prune_expired` first, so that
+    /// the execution fees held by the removed orders can be refunded.
+    pub fn try_create(&mut self, now: Timestamp, order: Order) -> Result<OrderId, String> {
+        self.check_create(now, &order)?;
+        Ok(self.create(order))
+    }
+
+    /// Whether `max_orders` is set and reached.
+    pub fn is_full(&self) -> bool {
+        self.max_orders > 0 && self.orders.len() >= self.max_orders
+    }
+
+    /// Remove and return every order with `valid_until < now`.
+    pub fn prune_expired(&mut self, now: Timestamp) -> Vec<Order> {
+        let expired: Vec<OrderId> = self
+            .orders
+            .iter()
+            .filter(|(_, o)| o.valid_until < now)
+            .map(|(id, _)| *id)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|id| self.orders.remove(&id))
+            .collect()
+    }
+
     pub fn get(&self, id: OrderId) -> Option<&Order> {
         self.orders.get(&id)
     }
@@ -51,3 +103,79 @@ impl OrderStore {
         self.orders.iter()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{AccountId, AssetId, ExecutionType, MarketId, OrderType, Side};
+    use primitive_types::U256;
+
+    fn order(valid_until: Timestamp) -> Order {
+        Order {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+            order_type: OrderType::Increase,
+            execution_type: ExecutionType::Market,
+            collateral_delta_tokens: U256::from(1_000),
+            size_delta_usd: U256::zero(),
+            trigger_price: None,
+            acceptable_price: None,
+            withdraw_collateral_amount: U256::zero(),
+            execution_fee_tokens: U256::zero(),
+            reduce_only: false,
+            target_leverage_x: 1,
+            created_at: 100,
+            valid_from: 0,
+            valid_until,
+        }
+    }
+
+    #[test]
+    fn prune_removes_only_expired_orders() {
+        let mut store = OrderStore::new();
+        let expired = [store.create(order(50)), store.create(order(99))];
+        let live = [store.create(order(100)), store.create(order(500))];
+
+        assert_eq!(store.prune_expired(100).len(), 2);
+        for id in expired {
+            assert!(!store.contains(id));
+        }
+        for id in live {
+            assert!(store.contains(id));
+        }
+        assert!(store.prune_expired(100).is_empty());
+    }
+
+    #[test]
+    fn full_store_rejects_until_pruned() {
+        let mut store = OrderStore::with_max_orders(2);
+        store.try_create(100, order(50)).unwrap();
+        store.try_create(100, order(500)).unwrap();
+        assert_eq!(
+            store.try_create(100, order(500)).unwrap_err(),
+            "order_store_full"
+        );
+
+        // The first order has expired by the submission time.
+        let removed = store.prune_expired(100);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].valid_until, 50);
+        let id = store.try_create(100, order(500)).unwrap();
+        assert!(store.contains(id));
+        assert_eq!(store.len(), 2);
+    }
+
+    #[test]
+    fn future_created_at_is_rejected() {
+        let mut store = OrderStore::new();
+        let mut forged = order(10_000);
+        forged.created_at = 5_000;
+        assert_eq!(
+            store.try_create(100, forged).unwrap_err(),
+            "order_created_in_future"
+        );
+        assert!(store.is_empty());
+    }
+}
@@ -2,19 +2,42 @@ use std::{collections::HashMap};
 
 use primitive_types::U256;
 
-use crate::types::{AssetId, MarketId, TokenAmount};
+use crate::state::PositionStore;
+use crate::types::{AssetId, MarketId, Timestamp, TokenAmount, Usd, WithdrawalId};
+
+/// Liquidity removal escrowed until `executable_at`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PendingWithdrawal {
+    pub market_id: MarketId,
+    pub asset: AssetId,
+    pub amount: TokenAmount,
+    pub requested_at: Timestamp,
+    pub executable_at: Timestamp,
+}
 
 /// Simple pool balances storage.
 ///
 /// For each market you typically have two assets:
 ///  - long_token  (e.g. WETH, BTC, etc.)
 ///  - short_token (e.g. USDC, USDT, etc.)
+///
+/// Amounts are `TokenAmount` (unsigned `U256`), so a negative amount cannot be
+/// passed in. The only way to drive a balance below zero is an oversized removal,
+/// which is rejected without mutating anything.
 #[derive(Debug, Default, Clone)]
 pub struct PoolBalances {
     /// Total liquidity in tokens for each (market, asset).
     pub liquidity: HashMap<(MarketId, AssetId), TokenAmount>,
-    /// Accumulated trading / borrowing fees for each (market, asset).
+    /// Accumulated trading / borrowing fees for each (market, asset), plus
+    /// collateral seized from insolvent liquidations.
     pub fees: HashMap<(MarketId, AssetId), TokenAmount>,
+    /// Fee revenue ledger: only trading / borrowing fees (`add_fee_to_pool`),
+    /// never seized collateral. Add-only.
+    pub fees_collected: HashMap<(MarketId, AssetId), TokenAmount>,
+    /// Delay between `request_withdrawal` and the earliest `execute_withdrawal`.
+    pub withdrawal_delay_secs: u64,
+    withdrawals: HashMap<WithdrawalId, PendingWithdrawal>,
+    next_withdrawal_id: u64,
 }
 
 impl PoolBalances {
@@ -22,6 +45,17 @@ impl PoolBalances {
         Self {
             liquidity: HashMap::new(),
             fees: HashMap::new(),
+            fees_collected: HashMap::new(),
+            withdrawal_delay_secs: 0,
+            withdrawals: HashMap::new(),
+            next_withdrawal_id: 0,
+        }
+    }
+
+    pub fn with_withdrawal_delay(withdrawal_delay_secs: u64) -> Self {
+        Self {
+            withdrawal_delay_secs,
+            ..Self::new()
         }
     }
 
@@ -36,12 +70,33 @@ impl PoolBalances {
         *entry = entry.saturating_add(amount);
     }
 
-    /// Add trading fees to the pool for a specific (market, asset).
+    /// Add trading / borrowing fees to the pool for a specific (market, asset),
+    /// recording them as fee revenue.
     pub fn add_fee_to_pool(&mut self, market_id: MarketId, asset: AssetId, amount: TokenAmount) {
         if amount == U256::zero() {
             return;
         }
 
+        self.add_seized_collateral_to_pool(market_id, asset, amount);
+        let entry = self
+            .fees_collected
+            .entry((market_id, asset))
+            .or_insert(U256::zero());
+        *entry = entry.saturating_add(amount);
+    }
+
+    /// Credit collateral seized from an insolvent position to the fee bucket,
+    /// without counting it as fee revenue.
+    pub fn add_seized_collateral_to_pool(
+        &mut self,
+        market_id: MarketId,
+        asset: AssetId,
+        amount: TokenAmount,
+    ) {
+        if amount == U256::zero() {
+            return;
+        }
+
         let entry = self.fees.entry((market_id, asset)).or_insert(U256::zero());
         *entry = entry.saturating_add(amount);
     }
@@ -84,7 +139,10 @@ impl PoolBalances {
     }
 
     /// Remove liquidity for a single asset (either long or short) from a market pool.
-    pub fn remove_liquidity(
+    ///
+    /// Immediate, so only for trader payouts inside the crate; LPs withdraw
+    /// through `request_withdrawal` / `execute_withdrawal`.
+    pub(crate) fn remove_liquidity(
         &mut self,
         market_id: MarketId,
         asset: AssetId,
@@ -94,29 +152,109 @@ impl PoolBalances {
             return Ok(U256::zero());
         }
 
-        let key = (market_id, asset);
-        let bal = self.liquidity.entry(key).or_insert(U256::zero());
-
-        if *bal < amount {
-            return Err("insufficient_pool_liquidity".into());
-        }
+        let bal = self
+            .liquidity
+            .get_mut(&(market_id, asset))
+            .filter(|bal| **bal >= amount)
+            .ok_or("insufficient_pool_liquidity")?;
 
         *bal -= amount;
         Ok(amount)
     }
 
-    /// Convenience: remove liquidity for both long and short tokens at once.
-    pub fn remove_liquidity_pair(
+    /// Convenience: queue withdrawals of both long and short tokens at once.
+    ///
+    /// All-or-nothing: if either side cannot be queued, neither is.
+    pub fn request_withdrawal_pair(
         &mut self,
         market_id: MarketId,
         long_asset: AssetId,
         long_amount: TokenAmount,
         short_asset: AssetId,
         short_amount: TokenAmount,
-    ) -> Result<(TokenAmount, TokenAmount), String> {
-        let taken_long = self.remove_liquidity(market_id, long_asset, long_amount)?;
-        let taken_short = self.remove_liquidity(market_id, short_asset, short_amount)?;
-        Ok((taken_long, taken_short))
+        now: Timestamp,
+    ) -> Result<(WithdrawalId, WithdrawalId), String> {
+        let long_id = self.request_withdrawal(market_id, long_asset, long_amount, now)?;
+        match self.request_withdrawal(market_id, short_asset, short_amount, now) {
+            Ok(short_id) => Ok((long_id, short_id)),
+            Err(e) => {
+                self.withdrawals.remove(&long_id);
+                Err(e)
+            }
+        }
+    }
+
+    /// Queue a delayed liquidity removal.
+    ///
+    /// The tokens stay in the pool (and keep backing trader payouts) until the
+    /// request is executed; they are only reserved so the same liquidity cannot
+    /// be requested twice.
+    pub fn request_withdrawal(
+        &mut self,
+        market_id: MarketId,
+        asset: AssetId,
+        amount: TokenAmount,
+        now: Timestamp,
+    ) -> Result<WithdrawalId, String> {
+        if amount.is_zero() {
+            return Err("zero_withdrawal".into());
+        }
+        let available = self
+            .get_balance(market_id, asset)
+            .saturating_sub(self.pending_withdrawals(market_id, asset));
+        if available < amount {
+            return Err("insufficient_pool_liquidity".into());
+        }
+        let executable_at = now
+            .checked_add(self.withdrawal_delay_secs)
+            .ok_or("withdrawal_time_overflow")?;
+
+        let id = WithdrawalId(self.next_withdrawal_id);
+        self.next_withdrawal_id = self
+            .next_withdrawal_id
+            .checked_add(1)
+            .expect("withdrawal id overflow");
+        self.withdrawals.insert(
+            id,
+            PendingWithdrawal {
+                market_id,
+                asset,
+                amount,
+                requested_at: now,
+                executable_at,
+            },
+        );
+        Ok(id)
+    }
+
+    /// Execute a queued withdrawal once its delay has elapsed.
+    ///
+    /// On failure (too early, or the pool no longer holds the amount) the
+    /// request stays queued.
+    pub fn execute_withdrawal(
+        &mut self,
+        id: WithdrawalId,
+        now: Timestamp,
+    ) -> Result<TokenAmount, String> {
+        let w = *self.withdrawals.get(&id).ok_or("withdrawal_not_found")?;
+        if now < w.executable_at {
+            return Err("withdrawal_delay_not_elapsed".into());
+        }
+        let taken = self.remove_liquidity(w.market_id, w.asset, w.amount)?;
+        self.withdrawals.remove(&id);
+        Ok(taken)
+    }
+
+    pub fn get_withdrawal(&self, id: WithdrawalId) -> Option<&PendingWithdrawal> {
+        self.withdrawals.get(&id)
+    }
+
+    /// Total amount queued for withdrawal from (market, asset).
+    pub fn pending_withdrawals(&self, market_id: MarketId, asset: AssetId) -> TokenAmount {
+        self.withdrawals
+            .values()
+            .filter(|w| w.market_id == market_id && w.asset == asset)
+            .fold(U256::zero(), |acc, w| acc.saturating_add(w.amount))
     }
 
     /// Read current pool balance for (market, asset) without modifying it.
@@ -139,7 +277,335 @@ impl PoolBalances {
         (long_bal, short_bal)
     }
 
+    /// Consistency check for monitoring: every (market, asset) whose queued
+    /// withdrawals exceed its liquidity, with the shortfall in atoms.
+    ///
+    /// Balances are unsigned, so a balance itself cannot go negative; the
+    /// balance that can is the *available* one (`liquidity - pending`), e.g.
+    /// after liquidity backing a queued request was removed or paid out.
+    /// Sorted by (market, asset); empty when the pools are consistent.
+    pub fn audit(&self) -> Vec<(MarketId, AssetId, TokenAmount)> {
+        let mut pending: HashMap<(MarketId, AssetId), TokenAmount> = HashMap::new();
+        for w in self.withdrawals.values() {
+            let entry = pending
+                .entry((w.market_id, w.asset))
+                .or_insert(U256::zero());
+            *entry = entry.saturating_add(w.amount);
+        }
+
+        let mut out: Vec<(MarketId, AssetId, TokenAmount)> = pending
+            .into_iter()
+            .filter_map(|((market_id, asset), reserved)| {
+                let balance = self.get_balance(market_id, asset);
+                (reserved > balance).then(|| (market_id, asset, reserved - balance))
+            })
+            .collect();
+        out.sort_by_key(|(m, a, _)| (m.0, a.0));
+        out
+    }
+
     pub fn get_fee_for_pool(&self, market_id: MarketId, asset: AssetId) -> TokenAmount {
         *self.fees.get(&(market_id, asset)).unwrap_or(&U256::zero())
     }
+
+    /// Cumulative fee revenue of (market, asset) in atoms.
+    ///
+    /// Read from the `fees_collected` ledger: only fee paths write it, so
+    /// liquidity deposits / removals and seized liquidation collateral are
+    /// not counted.
+    pub fn fee_revenue(&self, market_id: MarketId, asset: AssetId) -> TokenAmount {
+        *self
+            .fees_collected
+            .get(&(market_id, asset))
+            .unwrap_or(&U256::zero())
+    }
+
+    /// USD(1e30) value of the fee revenue of `market` across all its assets.
+    ///
+    /// The ledger is add-only, so this is cumulative since the pool was created.
+    /// Assets without an entry in `prices_by_asset` are not counted.
+    /// Saturates at `U256::MAX` on overflow.
+    pub fn market_fees_usd(
+        &self,
+        market_id: MarketId,
+        prices_by_asset: &HashMap<AssetId, Usd>,
+    ) -> Usd {
+        self.fees_collected
+            .iter()
+            .filter(|((m, _), _)| *m == market_id)
+            .filter_map(|((_, asset), amount)| {
+                prices_by_asset
+                    .get(asset)
+                    .map(|price| amount.saturating_mul(*price))
+            })
+            .fold(U256::zero(), |acc, v| acc.saturating_add(v))
+    }
+}
+
+/// Total value locked in `asset`, in atoms: pool liquidity in every market
+/// plus the collateral every open position holds in that token (primary and
+/// multi-collateral balances). Accrued fee buckets are not counted.
+///
+/// Saturates at `U256::MAX`.
+pub fn total_value_locked(
+    pools: &PoolBalances,
+    positions: &PositionStore,
+    asset: AssetId,
+) -> TokenAmount {
+    let liquidity = pools
+        .liquidity
+        .iter()
+        .filter(|((_, a), _)| *a == asset)
+        .fold(U256::zero(), |acc, (_, amount)| acc.saturating_add(*amount));
+
+    positions
+        .iter()
+        .flat_map(|(_, pos)| pos.all_collateral())
+        .filter(|(a, _)| *a == asset)
+        .fold(liquidity, |acc, (_, amount)| acc.saturating_add(amount))
+}
+
+pub const SECONDS_PER_YEAR: u64 = 365 * 86_400;
+
+/// LP fee APR in bps: `fees_accrued_usd` earned over `elapsed_secs`, annualized
+/// against `pool_value_usd` (simple, not compounded; floor).
+///
+/// Zero pool value or zero elapsed time gives 0; saturates at `u32::MAX`.
+pub fn lp_apr_bps(pool_value_usd: Usd, fees_accrued_usd: Usd, elapsed_secs: u64) -> u32 {
+    if pool_value_usd.is_zero() || elapsed_secs == 0 {
+        return 0;
+    }
+    let Some(num) = fees_accrued_usd
+        .checked_mul(U256::from(10_000u32))
+        .and_then(|v| v.checked_mul(U256::from(SECONDS_PER_YEAR)))
+    else {
+        return u32::MAX;
+    };
+    let den = pool_value_usd.saturating_mul(U256::from(elapsed_secs));
+    let bps = num / den;
+    if bps > U256::from(u32::MAX) {
+        u32::MAX
+    } else {
+        bps.as_u32()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::{MarketState, Position, PositionKey};
+    use crate::types::{AccountId, Side};
+
+    #[test]
+    fn tvl_sums_liquidity_and_position_collateral_across_markets() {
+        let usdc = AssetId(10);
+        let weth = AssetId(11);
+        let mut pools = PoolBalances::new();
+        pools.add_liquidity_pair(MarketId(1), weth, U256::from(5), usdc, U256::from(1_000));
+        pools.add_liquidity(MarketId(2), usdc, U256::from(500));
+        // Fees are not part of TVL.
+        pools.add_fee_to_pool(MarketId(1), usdc, U256::from(7));
+
+        let mut positions = PositionStore::new();
+        let mut open = |account: u8, market: u32, token: AssetId, collateral: u64| {
+            let key = PositionKey {
+                account: AccountId([account; 32]),
+                market_id: MarketId(market),
+                collateral_token: token,
+                side: Side::Long,
+            };
+            let pos = Position::open(
+                key,
+                &MarketState::default(),
+                U256::exp10(30),
+                U256::one(),
+                U256::from(collateral),
+                1,
+            )
+            .unwrap();
+            positions.upsert(pos);
+        };
+        open(1, 1, usdc, 100);
+        open(2, 1, usdc, 250);
+        open(3, 2, usdc, 50);
+        open(4, 2, weth, 3);
+
+        // A multi-collateral position contributes its secondary balance too.
+        let key = PositionKey {
+            account: AccountId([1; 32]),
+            market_id: MarketId(1),
+            collateral_token: usdc,
+            side: Side::Long,
+        };
+        positions
+            .get_mut(&key)
+            .unwrap()
+            .collateral_balances
+            .insert(weth, U256::from(2));
+
+        assert_eq!(
+            total_value_locked(&pools, &positions, usdc),
+            U256::from(1_000 + 500 + 100 + 250 + 50)
+        );
+        assert_eq!(
+            total_value_locked(&pools, &positions, weth),
+            U256::from(5 + 3 + 2)
+        );
+        assert!(total_value_locked(&pools, &positions, AssetId(99)).is_zero());
+    }
+
+    #[test]
+    fn fee_revenue_excludes_liquidity() {
+        let market = MarketId(1);
+        let (long, short) = (AssetId(1), AssetId(2));
+        let mut pools = PoolBalances::new();
+        pools.add_liquidity_pair(market, long, U256::from(100), short, U256::from(1_000));
+        pools.add_fee_to_pool(market, short, U256::from(7));
+        pools.add_to_pool(market, short, U256::from(50));
+        pools.add_fee_to_pool(market, short, U256::from(3));
+
+        assert_eq!(pools.fee_revenue(market, short), U256::from(10));
+        assert!(pools.fee_revenue(market, long).is_zero());
+        assert_eq!(pools.get_balance(market, short), U256::from(1_050));
+
+        // Withdrawing liquidity leaves the revenue untouched.
+        pools
+            .remove_liquidity(market, short, U256::from(1_050))
+            .unwrap();
+        assert_eq!(pools.fee_revenue(market, short), U256::from(10));
+
+        // Seized collateral lands in the fee bucket but is not revenue.
+        pools.add_seized_collateral_to_pool(market, short, U256::from(5));
+        assert_eq!(pools.get_fee_for_pool(market, short), U256::from(15));
+        assert_eq!(pools.fee_revenue(market, short), U256::from(10));
+        assert!(pools.fee_revenue(MarketId(2), short).is_zero());
+    }
+
+    #[test]
+    fn oversized_removals_are_rejected_without_side_effects() {
+        let market = MarketId(1);
+        let (long, short) = (AssetId(1), AssetId(2));
+        let mut pools = PoolBalances::new();
+        pools.add_liquidity_pair(market, long, U256::from(100), short, U256::from(50));
+
+        assert_eq!(
+            pools.remove_liquidity(market, long, U256::from(101)),
+            Err("insufficient_pool_liquidity".into())
+        );
+        // Unknown (market, asset) is not materialized by a failed removal.
+        assert!(
+            pools
+                .remove_liquidity(MarketId(9), long, U256::one())
+                .is_err()
+        );
+        assert!(!pools.liquidity.contains_key(&(MarketId(9), long)));
+
+        // Pair: the long side fits but the short side does not => nothing is queued.
+        assert_eq!(
+            pools.request_withdrawal_pair(market, long, U256::from(10), short, U256::from(51), 1),
+            Err("insufficient_pool_liquidity".into())
+        );
+        assert!(pools.pending_withdrawals(market, long).is_zero());
+
+        let (long_id, short_id) = pools
+            .request_withdrawal_pair(market, long, U256::from(100), short, U256::from(50), 1)
+            .unwrap();
+        assert_eq!(
+            pools.get_pair_balances(market, long, short),
+            (U256::from(100), U256::from(50))
+        );
+        assert_eq!(pools.execute_withdrawal(long_id, 1), Ok(U256::from(100)));
+        assert_eq!(pools.execute_withdrawal(short_id, 1), Ok(U256::from(50)));
+        assert_eq!(
+            pools.get_pair_balances(market, long, short),
+            (U256::zero(), U256::zero())
+        );
+    }
+
+    #[test]
+    fn lp_apr_annualizes_accrued_fees() {
+        let usd = |x: u64| U256::from(x) * U256::exp10(30);
+        let market = MarketId(1);
+        let (long, short) = (AssetId(1), AssetId(2));
+        let mut pools = PoolBalances::new();
+
+        // 5 long atoms @ $200 + 1_000 short atoms @ $1 = $2_000 of fees (other markets ignored).
+        pools.add_fee_to_pool(market, long, U256::from(5));
+        pools.add_fee_to_pool(market, short, U256::from(1_000));
+        pools.add_fee_to_pool(MarketId(2), short, U256::from(7));
+        let prices = HashMap::from([(long, usd(200)), (short, usd(1))]);
+        let fees_usd = pools.market_fees_usd(market, &prices);
+        assert_eq!(fees_usd, usd(2_000));
+
+        // $2_000 on a $1M pool over 30 days = 0.2% * 365 / 30 = 2.433% APR.
+        let month = 30 * 86_400;
+        assert_eq!(lp_apr_bps(usd(1_000_000), fees_usd, month), 243);
+        // A full year of the same fees is exactly 20 bps.
+        assert_eq!(lp_apr_bps(usd(1_000_000), fees_usd, SECONDS_PER_YEAR), 20);
+
+        assert_eq!(lp_apr_bps(U256::zero(), fees_usd, month), 0);
+        assert_eq!(lp_apr_bps(usd(1_000_000), fees_usd, 0), 0);
+    }
+
+    #[test]
+    fn withdrawal_executes_only_after_delay() {
+        let market = MarketId(1);
+        let asset = AssetId(2);
+        let t = 1_000;
+        let mut pools = PoolBalances::with_withdrawal_delay(3_600);
+        pools.add_liquidity(market, asset, U256::from(100));
+
+        let id = pools
+            .request_withdrawal(market, asset, U256::from(60), t)
+            .unwrap();
+        // Reserved liquidity cannot be requested again.
+        assert_eq!(
+            pools.request_withdrawal(market, asset, U256::from(41), t),
+            Err("insufficient_pool_liquidity".into())
+        );
+
+        assert_eq!(
+            pools.execute_withdrawal(id, t + 3_599),
+            Err("withdrawal_delay_not_elapsed".into())
+        );
+        assert_eq!(pools.get_balance(market, asset), U256::from(100));
+        assert!(pools.get_withdrawal(id).is_some());
+
+        assert_eq!(pools.execute_withdrawal(id, t + 3_600), Ok(U256::from(60)));
+        assert_eq!(pools.get_balance(market, asset), U256::from(40));
+        assert!(pools.pending_withdrawals(market, asset).is_zero());
+        assert_eq!(
+            pools.execute_withdrawal(id, t + 3_600),
+            Err("withdrawal_not_found".into())
+        );
+    }
+
+    #[test]
+    fn audit_flags_withdrawals_exceeding_liquidity() {
+        let (long, short) = (AssetId(1), AssetId(2));
+        let mut pools = PoolBalances::new();
+        pools.add_liquidity_pair(MarketId(1), long, U256::from(100), short, U256::from(50));
+        pools.add_liquidity(MarketId(2), short, U256::from(10));
+        pools
+            .request_withdrawal(MarketId(1), long, U256::from(60), 1)
+            .unwrap();
+        pools
+            .request_withdrawal(MarketId(1), short, U256::from(50), 1)
+            .unwrap();
+        pools
+            .request_withdrawal(MarketId(2), short, U256::from(10), 1)
+            .unwrap();
+        assert!(pools.audit().is_empty());
+
+        // Corrupt two balances behind the queued requests.
+        pools.liquidity.insert((MarketId(2), short), U256::from(4));
+        pools.liquidity.insert((MarketId(1), long), U256::from(20));
+        assert_eq!(
+            pools.audit(),
+            vec![
+                (MarketId(1), long, U256::from(40)),
+                (MarketId(2), short, U256::from(6)),
+            ]
+        );
+    }
 }
@@ -5,7 +5,11 @@ use std::collections::hash_map::Entry;
 
 use primitive_types::U256;
 
-use crate::types::{AccountId, AssetId, MarketId, Side, SignedU256, Timestamp, TokenAmount, Usd};
+use crate::math;
+use crate::state::MarketState;
+use crate::types::{
+    AccountId, AssetId, MarketId, OraclePrices, Side, SignedU256, Timestamp, TokenAmount, Usd,
+};
 
 /// Ключ позиции: уникально определяет позицию пользователя.
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
@@ -16,7 +20,7 @@ pub struct PositionKey {
     pub side: Side,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 pub struct Position {
     pub key: PositionKey,
 
@@ -26,17 +30,217 @@ pub struct Position {
 
     pub collateral_amount: TokenAmount,
 
+    /// Extra collateral tokens held by this position.
+    ///
+    /// Does NOT include `key.collateral_token`, which always lives in `collateral_amount`.
+    /// Empty for regular single-collateral positions. These balances are held
+    /// (and counted in `total_value_locked`) but do not back the position:
+    /// margin, liquidation and cost charging only read `collateral_amount`,
+    /// since `OraclePrices` price one collateral token per market. They become
+    /// the primary through `PositionStore::rekey`.
+    pub collateral_balances: HashMap<AssetId, TokenAmount>,
+
     pub pending_impact_tokens: SignedU256,
 
+    /// Impact tokens already realized by decreases (moved out of `pending_impact_tokens`).
+    pub realized_impact_tokens: SignedU256,
+
     pub funding_index: SignedU256,
+    /// Market funding clock (`funding.last_updated_at`) at the `funding_index`
+    /// snapshot; bounds the index move the next settlement may charge.
+    pub funding_updated_at: Timestamp,
+
+    /// Funding below `MarketState::min_funding_settlement_usd`, priced at the size
+    /// held when it accrued and carried until the total crosses the floor.
+    /// Positive => the holder pays, negative => receives.
+    pub pending_funding_usd: SignedU256,
 
     pub borrowing_index: U256,
 
+    /// Funding + borrowing owed but not yet taken from collateral because a
+    /// settlement hit `RiskCfg::max_settlement_cost_bps`. Charged on the next
+    /// settlement or position update. USD(1e30)
+    pub unpaid_cost_usd: Usd,
+
+    /// Set by a capped settlement; the position is liquidatable while set.
+    pub needs_liquidation: bool,
+
     pub opened_at: Timestamp,
 
     pub last_updated_at: Timestamp,
 }
 
+impl Position {
+    /// Create an open position on `market` (the key's market), rejecting
+    /// inconsistent sizes.
+    ///
+    /// Requires `size_usd > 0` and `size_tokens > 0` (collateral may be zero).
+    /// The funding index is the market's current index for the key's side and
+    /// the borrowing index its current cumulative factor, so the first
+    /// settlement only charges costs accrued after `now`. Pending impact is empty.
+    pub fn open(
+        key: PositionKey,
+        market: &MarketState,
+        size_usd: Usd,
+        size_tokens: TokenAmount,
+        collateral_amount: TokenAmount,
+        now: Timestamp,
+    ) -> Result<Self, String> {
+        if size_usd.is_zero() {
+            return Err("position_size_usd_zero".into());
+        }
+        if size_tokens.is_zero() {
+            return Err("position_size_tokens_zero".into());
+        }
+
+        Ok(Self {
+            key,
+            size_usd,
+            size_tokens,
+            collateral_amount,
+            collateral_balances: HashMap::new(),
+            pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
+            funding_index: match key.side {
+                Side::Long => market.funding.cumulative_index_long,
+                Side::Short => market.funding.cumulative_index_short,
+            },
+            funding_updated_at: market.funding.last_updated_at,
+            pending_funding_usd: SignedU256::zero(),
+            borrowing_index: market.borrowing.cumulative_factor,
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
+            opened_at: now,
+            last_updated_at: now,
+        })
+    }
+
+    /// Create an empty position holding several collateral tokens.
+    ///
+    /// `collateral_balances` may include `key.collateral_token`; that entry is moved
+    /// into `collateral_amount`. Zero balances are dropped.
+    pub fn new_multi_collateral(
+        key: PositionKey,
+        collateral_balances: HashMap<AssetId, TokenAmount>,
+        funding_index: SignedU256,
+        borrowing_index: U256,
+        now: Timestamp,
+    ) -> Self {
+        let mut balances = collateral_balances;
+        let collateral_amount = balances
+            .remove(&key.collateral_token)
+            .unwrap_or(U256::zero());
+        balances.retain(|_, amount| !amount.is_zero());
+
+        Self {
+            key,
+            size_usd: U256::zero(),
+            size_tokens: U256::zero(),
+            collateral_amount,
+            collateral_balances: balances,
+            pending_impact_tokens: SignedU256::zero(),
+            realized_impact_tokens: SignedU256::zero(),
+            funding_index,
+            funding_updated_at: now,
+            pending_funding_usd: SignedU256::zero(),
+            borrowing_index,
+            unpaid_cost_usd: U256::zero(),
+            needs_liquidation: false,
+            opened_at: now,
+            last_updated_at: now,
+        }
+    }
+
+    /// Weighted-average entry price: `size_usd / size_tokens` (USD(1e30) per atom, floor).
+    ///
+    /// Because every increase adds `size_delta_usd` together with the tokens it
+    /// bought (see `apply_increase`), this is the token-weighted average of the
+    /// execution prices of all increases. Pending impact is not included.
+    pub fn entry_price(&self) -> Result<Usd, String> {
+        if self.size_tokens.is_zero() {
+            return Err("position_size_tokens_zero".into());
+        }
+        Ok(self.size_usd / self.size_tokens)
+    }
+
+    /// Grow the position by `size_delta_usd` bought as `size_delta_tokens`.
+    ///
+    /// Sizes are summed (never re-priced), which keeps `entry_price` the
+    /// weighted average over all increases.
+    pub fn apply_increase(
+        &mut self,
+        size_delta_usd: Usd,
+        size_delta_tokens: TokenAmount,
+    ) -> Result<(), String> {
+        let size_usd = self
+            .size_usd
+            .checked_add(size_delta_usd)
+            .ok_or("position_size_overflow")?;
+        let size_tokens = self
+            .size_tokens
+            .checked_add(size_delta_tokens)
+            .ok_or("position_size_overflow")?;
+        self.size_usd = size_usd;
+        self.size_tokens = size_tokens;
+        Ok(())
+    }
+
+    /// Move `tokens` of deferred impact from pending to realized.
+    pub fn realize_impact(&mut self, tokens: SignedU256) {
+        self.pending_impact_tokens = math::signed_sub(self.pending_impact_tokens, tokens);
+        self.realized_impact_tokens = math::signed_add(self.realized_impact_tokens, tokens);
+    }
+
+    /// Seconds since the position was opened (0 if `now` is before `opened_at`).
+    pub fn age_secs(&self, now: Timestamp) -> u64 {
+        now.saturating_sub(self.opened_at)
+    }
+
+    /// (realized, pending) impact tokens.
+    pub fn impact_summary(&self) -> (SignedU256, SignedU256) {
+        (self.realized_impact_tokens, self.pending_impact_tokens)
+    }
+
+    /// All collateral balances of this position, including the primary token.
+    pub fn all_collateral(&self) -> Vec<(AssetId, TokenAmount)> {
+        let mut out = Vec::with_capacity(1 + self.collateral_balances.len());
+        if !self.collateral_amount.is_zero() {
+            out.push((self.key.collateral_token, self.collateral_amount));
+        }
+        for (asset, amount) in self.collateral_balances.iter() {
+            if !amount.is_zero() {
+                out.push((*asset, *amount));
+            }
+        }
+        out
+    }
+}
+
+fn collateral_token_value_usd(
+    asset: AssetId,
+    amount: TokenAmount,
+    prices_by_asset: &HashMap<AssetId, OraclePrices>,
+) -> Result<Usd, String> {
+    let prices = prices_by_asset
+        .get(&asset)
+        .ok_or("missing_collateral_price")?;
+    if prices.collateral_price_min.is_zero() {
+        return Err("invalid_collateral_price_min".into());
+    }
+    amount
+        .checked_mul(prices.collateral_price_min)
+        .ok_or("collateral_value_overflow".into())
+}
+
+fn sum_oi_by_side<'a>(positions: impl Iterator<Item = &'a Position>) -> (Usd, Usd) {
+    positions.fold((U256::zero(), U256::zero()), |(long, short), p| {
+        match p.key.side {
+            Side::Long => (long.saturating_add(p.size_usd), short),
+            Side::Short => (long, short.saturating_add(p.size_usd)),
+        }
+    })
+}
+
 #[derive(Default, Clone)]
 pub struct PositionStore {
     positions: HashMap<PositionKey, Position>,
@@ -65,10 +269,129 @@ impl PositionStore {
         self.positions.remove(key)
     }
 
+    /// Remove a fully closed position (`size_usd == 0 && size_tokens == 0`).
+    ///
+    /// Refuses with `"position_still_open"` (and leaves the store untouched) if the
+    /// position still has size, so a bug cannot orphan OI or collateral.
+    /// Returns `Ok(None)` if there is no such position.
+    pub fn remove_if_closed(&mut self, key: &PositionKey) -> Result<Option<Position>, String> {
+        match self.positions.get(key) {
+            None => Ok(None),
+            Some(p) if !p.size_usd.is_zero() || !p.size_tokens.is_zero() => {
+                Err("position_still_open".into())
+            }
+            Some(_) => Ok(self.positions.remove(key)),
+        }
+    }
+
+    /// Move a position to `new_key` (collateral token migration).
+    ///
+    /// Only `collateral_token` may differ: account, market and side are part of
+    /// the position's identity and OI accounting. Token amounts are not
+    /// converted: the new token's extra balance becomes the primary
+    /// `collateral_amount` and the old primary moves into `collateral_balances`.
+    ///
+    /// Margin, liquidation and cost charging only read `collateral_amount`, so
+    /// the new primary must be worth at least the old one (both at
+    /// `collateral_price_min` from `prices_by_asset`); otherwise the rekey is
+    /// rejected with `"rekey_collateral_not_covered"`. Convert the collateral
+    /// first. Errors (store untouched) if `old_key` is missing or `new_key` is
+    /// already occupied.
+    pub fn rekey(
+        &mut self,
+        old_key: PositionKey,
+        new_key: PositionKey,
+        prices_by_asset: &HashMap<AssetId, OraclePrices>,
+    ) -> Result<(), String> {
+        if old_key == new_key {
+            return if self.positions.contains_key(&old_key) {
+                Ok(())
+            } else {
+                Err("position_not_found".into())
+            };
+        }
+        if old_key.account != new_key.account
+            || old_key.market_id != new_key.market_id
+            || old_key.side != new_key.side
+        {
+            return Err("rekey_identity_mismatch".into());
+        }
+        if self.positions.contains_key(&new_key) {
+            return Err("position_key_occupied".into());
+        }
+        let pos = self.positions.get(&old_key).ok_or("position_not_found")?;
+        let new_primary = pos
+            .collateral_balances
+            .get(&new_key.collateral_token)
+            .copied()
+            .unwrap_or(U256::zero());
+        let old_value = collateral_token_value_usd(
+            old_key.collateral_token,
+            pos.collateral_amount,
+            prices_by_asset,
+        )?;
+        let new_value =
+            collateral_token_value_usd(new_key.collateral_token, new_primary, prices_by_asset)?;
+        if new_value < old_value {
+            return Err("rekey_collateral_not_covered".into());
+        }
+
+        let mut pos = self
+            .positions
+            .remove(&old_key)
+            .ok_or("position_not_found")?;
+        pos.collateral_balances.remove(&new_key.collateral_token);
+        let old_primary = std::mem::replace(&mut pos.collateral_amount, new_primary);
+        if !old_primary.is_zero() {
+            pos.collateral_balances
+                .insert(old_key.collateral_token, old_primary);
+        }
+        pos.key = new_key;
+        self.positions.insert(new_key, pos);
+        Ok(())
+    }
+
     pub fn iter(&self) -> impl Iterator<Item = (&PositionKey, &Position)> {
         self.positions.iter()
     }
 
+    /// All positions of `account` (any market / side / collateral).
+    pub fn positions_for_account(&self, account: AccountId) -> impl Iterator<Item = &Position> {
+        self.positions
+            .values()
+            .filter(move |p| p.key.account == account)
+    }
+
+    /// Distinct markets where `account` holds at least one position, by id.
+    pub fn markets_for_account(&self, account: AccountId) -> Vec<MarketId> {
+        let mut markets: Vec<MarketId> = self
+            .positions_for_account(account)
+            .map(|p| p.key.market_id)
+            .collect();
+        markets.sort_by_key(|m| m.0);
+        markets.dedup();
+        markets
+    }
+
+    /// Total (long, short) `size_usd` over every position in every market.
+    pub fn global_open_interest(&self) -> (Usd, Usd) {
+        sum_oi_by_side(self.positions.values())
+    }
+
+    /// Total (long, short) `size_usd` of positions on `market_id`.
+    pub fn open_interest_for_market(&self, market_id: MarketId) -> (Usd, Usd) {
+        sum_oi_by_side(
+            self.positions
+                .values()
+                .filter(move |p| p.key.market_id == market_id),
+        )
+    }
+
+    /// Total (long, short) `size_usd` of `account` across all markets.
+    pub fn global_oi_for_account(&self, account: AccountId) -> (Usd, Usd) {
+        sum_oi_by_side(self.positions_for_account(account))
+    }
+
     pub fn get_or_insert_with<F>(&mut self, key: PositionKey, f: F) -> &mut Position
     where
         F: FnOnce(PositionKey) -> Position,
@@ -82,3 +405,408 @@ impl PositionStore {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    fn collateral_prices(min: U256, max: U256) -> OraclePrices {
+        OraclePrices {
+            index_price_min: usd(3_000),
+            index_price_max: usd(3_000),
+            collateral_price_min: min,
+            collateral_price_max: max,
+        }
+    }
+
+    fn key() -> PositionKey {
+        PositionKey {
+            account: AccountId([1u8; 32]),
+            market_id: MarketId(1),
+            collateral_token: AssetId(10),
+            side: Side::Long,
+        }
+    }
+
+    fn two_token_position() -> (Position, HashMap<AssetId, OraclePrices>) {
+        // 100 atoms of USDC-like ($1) + 2 atoms of ETH-like ($2000 min / $2010 max).
+        let mut balances = HashMap::new();
+        balances.insert(AssetId(10), U256::from(100));
+        balances.insert(AssetId(11), U256::from(2));
+        let pos =
+            Position::new_multi_collateral(key(), balances, SignedU256::zero(), U256::zero(), 1);
+
+        let mut prices = HashMap::new();
+        prices.insert(AssetId(10), collateral_prices(usd(1), usd(1)));
+        prices.insert(AssetId(11), collateral_prices(usd(2_000), usd(2_010)));
+        (pos, prices)
+    }
+
+    #[test]
+    fn open_rejects_inconsistent_sizes() {
+        assert_eq!(
+            Position::open(
+                key(),
+                &MarketState::default(),
+                usd(1_000),
+                U256::zero(),
+                U256::from(100),
+                1
+            )
+            .unwrap_err(),
+            "position_size_tokens_zero"
+        );
+        assert_eq!(
+            Position::open(
+                key(),
+                &MarketState::default(),
+                U256::zero(),
+                U256::from(1),
+                U256::from(100),
+                1
+            )
+            .unwrap_err(),
+            "position_size_usd_zero"
+        );
+
+        let pos = Position::open(
+            key(),
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(5),
+            U256::zero(),
+            7,
+        )
+        .unwrap();
+        assert_eq!(pos.size_usd, usd(1_000));
+        assert_eq!(pos.size_tokens, U256::from(5));
+        assert!(pos.collateral_amount.is_zero());
+        assert!(pos.pending_impact_tokens.is_zero());
+        assert_eq!((pos.opened_at, pos.last_updated_at), (7, 7));
+    }
+
+    #[test]
+    fn open_snapshots_the_market_indices() {
+        let mut market = MarketState::default();
+        market.funding.cumulative_index_long = SignedU256::pos(U256::from(3));
+        market.funding.cumulative_index_short = SignedU256::neg(U256::from(1));
+        market.borrowing.cumulative_factor = U256::from(5);
+
+        let long = Position::open(key(), &market, usd(1_000), U256::from(5), U256::zero(), 7);
+        let long = long.unwrap();
+        assert_eq!(long.funding_index, market.funding.cumulative_index_long);
+        assert_eq!(long.borrowing_index, market.borrowing.cumulative_factor);
+
+        let short_key = PositionKey {
+            side: Side::Short,
+            ..key()
+        };
+        let short = Position::open(
+            short_key,
+            &market,
+            usd(1_000),
+            U256::from(5),
+            U256::zero(),
+            7,
+        );
+        assert_eq!(
+            short.unwrap().funding_index,
+            market.funding.cumulative_index_short
+        );
+    }
+
+    #[test]
+    fn extra_collateral_is_held_but_not_margined() {
+        let (mut pos, prices) = two_token_position();
+
+        assert_eq!(pos.collateral_amount, U256::from(100));
+        assert_eq!(
+            pos.collateral_balances.get(&AssetId(11)),
+            Some(&U256::from(2))
+        );
+        let mut all = pos.all_collateral();
+        all.sort_by_key(|(asset, _)| asset.0);
+        assert_eq!(
+            all,
+            vec![(AssetId(10), U256::from(100)), (AssetId(11), U256::from(2))]
+        );
+
+        // Only the primary $100 backs the position; the $4000 ETH leg does not.
+        pos.size_usd = usd(3_000);
+        pos.size_tokens = U256::from(1);
+        let preview = crate::risk::liquidation::is_liquidatable_by_margin(
+            &pos,
+            &prices[&AssetId(10)],
+            crate::risk::liquidation::AccruedCosts::default(),
+            crate::risk::RiskCfg::default(),
+            crate::risk::liquidation::LiquidationFeeCfg {
+                close_position_fee_bps: 10,
+                liquidation_fee_bps: 50,
+            },
+            SignedU256::zero(),
+        )
+        .unwrap();
+        assert_eq!(preview.collateral_value_usd, usd(100));
+    }
+
+    #[test]
+    fn global_open_interest_sums_all_markets_by_side() {
+        let account_a = AccountId([1u8; 32]);
+        let account_b = AccountId([2u8; 32]);
+        let mut store = PositionStore::new();
+        for (account, market, side, size) in [
+            (account_a, 1, Side::Long, 1_000),
+            (account_a, 2, Side::Long, 2_000),
+            (account_a, 3, Side::Short, 500),
+            (account_b, 1, Side::Short, 4_000),
+            (account_b, 3, Side::Long, 300),
+        ] {
+            let key = PositionKey {
+                account,
+                market_id: MarketId(market),
+                collateral_token: AssetId(10),
+                side,
+            };
+            store.upsert(
+                Position::open(
+                    key,
+                    &MarketState::default(),
+                    usd(size),
+                    U256::from(1),
+                    U256::zero(),
+                    1,
+                )
+                .unwrap(),
+            );
+        }
+
+        assert_eq!(store.global_open_interest(), (usd(3_300), usd(4_500)));
+        assert_eq!(
+            store.global_oi_for_account(account_a),
+            (usd(3_000), usd(500))
+        );
+        assert_eq!(
+            store.global_oi_for_account(account_b),
+            (usd(300), usd(4_000))
+        );
+        assert_eq!(
+            store.global_oi_for_account(AccountId([9u8; 32])),
+            (U256::zero(), U256::zero())
+        );
+    }
+
+    #[test]
+    fn markets_for_account_lists_each_market_once() {
+        let account = AccountId([1u8; 32]);
+        let other = AccountId([2u8; 32]);
+        let mut store = PositionStore::new();
+        for (account, market, side) in [
+            (account, 3, Side::Long),
+            (account, 1, Side::Long),
+            (account, 1, Side::Short),
+            (other, 2, Side::Long),
+        ] {
+            let key = PositionKey {
+                account,
+                market_id: MarketId(market),
+                collateral_token: AssetId(10),
+                side,
+            };
+            store.upsert(
+                Position::open(
+                    key,
+                    &MarketState::default(),
+                    usd(100),
+                    U256::from(1),
+                    U256::zero(),
+                    1,
+                )
+                .unwrap(),
+            );
+        }
+
+        assert_eq!(
+            store.markets_for_account(account),
+            vec![MarketId(1), MarketId(3)]
+        );
+        assert_eq!(store.markets_for_account(other), vec![MarketId(2)]);
+        assert!(store.markets_for_account(AccountId([9u8; 32])).is_empty());
+    }
+
+    #[test]
+    fn remove_if_closed_refuses_open_positions() {
+        let mut store = PositionStore::new();
+        let pos = Position::open(
+            key(),
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(5),
+            U256::from(100),
+            1,
+        )
+        .unwrap();
+        store.upsert(pos);
+
+        assert_eq!(
+            store.remove_if_closed(&key()).unwrap_err(),
+            "position_still_open"
+        );
+        assert!(store.get(&key()).is_some());
+
+        let closed = store.get_mut(&key()).unwrap();
+        closed.size_usd = U256::zero();
+        closed.size_tokens = U256::zero();
+        let removed = store.remove_if_closed(&key()).unwrap().unwrap();
+        assert_eq!(removed.key, key());
+        assert!(store.get(&key()).is_none());
+        assert_eq!(store.remove_if_closed(&key()), Ok(None));
+    }
+
+    #[test]
+    fn rekey_migrates_collateral_token() {
+        let mut store = PositionStore::new();
+        // $3000 long at entry: 100 atoms of A ($1) as primary, 1 atom of B ($2000) held.
+        let mut pos = Position::open(
+            key(),
+            &MarketState::default(),
+            usd(3_000),
+            U256::from(1),
+            U256::from(100),
+            1,
+        )
+        .unwrap();
+        pos.collateral_balances.insert(AssetId(11), U256::from(1));
+        store.upsert(pos.clone());
+        let mut prices = HashMap::new();
+        prices.insert(AssetId(10), collateral_prices(usd(1), usd(1)));
+        prices.insert(AssetId(11), collateral_prices(usd(2_000), usd(2_010)));
+
+        let new_key = PositionKey {
+            collateral_token: AssetId(11),
+            ..key()
+        };
+        store.rekey(key(), new_key, &prices).unwrap();
+
+        assert!(store.get(&key()).is_none());
+        let moved = store.get(&new_key).unwrap();
+        assert_eq!(moved.key, new_key);
+        assert_eq!(moved.size_usd, pos.size_usd);
+        assert_eq!(moved.size_tokens, pos.size_tokens);
+        // Tokens are not converted: B becomes the primary, A an extra balance.
+        assert_eq!(moved.collateral_amount, U256::from(1));
+        assert_eq!(
+            moved.collateral_balances.get(&AssetId(10)),
+            Some(&U256::from(100))
+        );
+
+        // The migrated position is still solvent on its primary collateral.
+        let preview = crate::risk::liquidation::is_liquidatable_by_margin(
+            moved,
+            &prices[&AssetId(11)],
+            crate::risk::liquidation::AccruedCosts::default(),
+            crate::risk::RiskCfg::default(),
+            crate::risk::liquidation::LiquidationFeeCfg {
+                close_position_fee_bps: 10,
+                liquidation_fee_bps: 50,
+            },
+            SignedU256::zero(),
+        )
+        .unwrap();
+        assert!(!preview.is_liquidatable);
+        assert_eq!(preview.collateral_value_usd, usd(2_000));
+
+        // Moving back would swap $2000 of primary collateral for $100: rejected.
+        assert_eq!(
+            store.rekey(new_key, key(), &prices).unwrap_err(),
+            "rekey_collateral_not_covered"
+        );
+        assert!(store.get(&new_key).is_some());
+
+        // Occupied target / missing source / identity change are rejected.
+        store.upsert(pos.clone());
+        assert_eq!(
+            store.rekey(key(), new_key, &prices).unwrap_err(),
+            "position_key_occupied"
+        );
+        assert!(store.get(&key()).is_some());
+        store.remove(&new_key);
+        store.remove(&key());
+        assert_eq!(
+            store.rekey(key(), new_key, &prices).unwrap_err(),
+            "position_not_found"
+        );
+        let other_side = PositionKey {
+            side: Side::Short,
+            ..new_key
+        };
+        assert_eq!(
+            store.rekey(key(), other_side, &prices).unwrap_err(),
+            "rekey_identity_mismatch"
+        );
+    }
+
+    #[test]
+    fn entry_price_is_weighted_average_of_increases() {
+        // Price per atom: $2_000, then $3_000.
+        let mut pos = Position::open(
+            key(),
+            &MarketState::default(),
+            usd(2_000),
+            U256::from(1),
+            U256::from(100),
+            1,
+        )
+        .unwrap();
+        assert_eq!(pos.entry_price().unwrap(), usd(2_000));
+
+        // Add 3 tokens at $3_000 → (2_000 + 9_000) / 4 = $2_750.
+        pos.apply_increase(usd(9_000), U256::from(3)).unwrap();
+        assert_eq!(pos.size_tokens, U256::from(4));
+        assert_eq!(pos.entry_price().unwrap(), usd(2_750));
+
+        pos.size_tokens = U256::zero();
+        assert_eq!(pos.entry_price().unwrap_err(), "position_size_tokens_zero");
+        pos.size_usd = U256::MAX;
+        assert_eq!(
+            pos.apply_increase(usd(1), U256::one()).unwrap_err(),
+            "position_size_overflow"
+        );
+        assert!(pos.size_tokens.is_zero());
+    }
+
+    #[test]
+    fn realized_impact_accumulates_as_pending_drains() {
+        let mut pos = Position::open(
+            key(),
+            &MarketState::default(),
+            usd(1_000),
+            U256::from(5),
+            U256::from(100),
+            1,
+        )
+        .unwrap();
+        pos.pending_impact_tokens = SignedU256::neg(U256::from(90));
+
+        // Two partial closes realize a third each.
+        pos.realize_impact(SignedU256::neg(U256::from(30)));
+        pos.realize_impact(SignedU256::neg(U256::from(30)));
+        assert_eq!(
+            pos.impact_summary(),
+            (
+                SignedU256::neg(U256::from(60)),
+                SignedU256::neg(U256::from(30))
+            )
+        );
+
+        // Full close realizes the rest.
+        let rest = pos.pending_impact_tokens;
+        pos.realize_impact(rest);
+        let (realized, pending) = pos.impact_summary();
+        assert_eq!(realized, SignedU256::neg(U256::from(90)));
+        assert!(pending.is_zero());
+    }
+}
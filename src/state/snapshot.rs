@@ -0,0 +1,161 @@
+// src/state/snapshot.rs
+
+use std::collections::HashMap;
+
+use primitive_types::U256;
+
+use crate::math;
+use crate::state::{OpenInterest, Position, PositionKey, State};
+use crate::types::{AccountId, AssetId, MarketId, SignedU256, TokenAmount};
+
+/// Point-in-time copy of the parts of `State` that actions mutate.
+///
+/// Used for debugging state transitions and for test assertions via `diff_state`.
+#[derive(Clone, Debug, Default)]
+pub struct StateSnapshot {
+    pub positions: HashMap<PositionKey, Position>,
+    pub open_interest: HashMap<MarketId, OpenInterest>,
+    pub pool_liquidity: HashMap<(MarketId, AssetId), TokenAmount>,
+    pub pool_fees: HashMap<(MarketId, AssetId), TokenAmount>,
+    pub claimable_funding: HashMap<(AccountId, AssetId), TokenAmount>,
+    pub claimable_fees: HashMap<(AccountId, AssetId), TokenAmount>,
+}
+
+/// Signed OI change for a single market (after - before).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct OiDelta {
+    pub long_usd: SignedU256,
+    pub short_usd: SignedU256,
+}
+
+/// Structured before/after difference between two snapshots.
+///
+/// Only non-zero changes are reported.
+#[derive(Clone, Debug, Default)]
+pub struct StateDiff {
+    pub positions_created: Vec<PositionKey>,
+    pub positions_removed: Vec<PositionKey>,
+    pub positions_changed: Vec<PositionKey>,
+    pub oi_deltas: HashMap<MarketId, OiDelta>,
+    pub pool_liquidity_deltas: HashMap<(MarketId, AssetId), SignedU256>,
+    pub pool_fee_deltas: HashMap<(MarketId, AssetId), SignedU256>,
+    pub claimable_funding_deltas: HashMap<(AccountId, AssetId), SignedU256>,
+    pub claimable_fee_deltas: HashMap<(AccountId, AssetId), SignedU256>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.positions_created.is_empty()
+            && self.positions_removed.is_empty()
+            && self.positions_changed.is_empty()
+            && self.oi_deltas.is_empty()
+            && self.pool_liquidity_deltas.is_empty()
+            && self.pool_fee_deltas.is_empty()
+            && self.claimable_funding_deltas.is_empty()
+            && self.claimable_fee_deltas.is_empty()
+    }
+}
+
+impl State {
+    /// Take a snapshot of positions, OI, pool balances and claimables.
+    pub fn snapshot(&self) -> StateSnapshot {
+        StateSnapshot {
+            positions: self
+                .positions
+                .iter()
+                .map(|(k, p)| (*k, p.clone()))
+                .collect(),
+            open_interest: self
+                .markets
+                .iter()
+                .map(|(id, m)| {
+                    (
+                        *id,
+                        OpenInterest {
+                            long_usd: m.oi_long_usd,
+                            short_usd: m.oi_short_usd,
+                        },
+                    )
+                })
+                .collect(),
+            pool_liquidity: self.pool_balances.liquidity.clone(),
+            pool_fees: self.pool_balances.fees.clone(),
+            claimable_funding: self
+                .claimables
+                .funding_entries()
+                .map(|(k, v)| (*k, *v))
+                .collect(),
+            claimable_fees: self
+                .claimables
+                .fee_entries()
+                .map(|(k, v)| (*k, *v))
+                .collect(),
+        }
+    }
+}
+
+/// after - before, as a signed value.
+fn signed_delta(before: U256, after: U256) -> SignedU256 {
+    math::signed_sub(SignedU256::pos(after), SignedU256::pos(before))
+}
+
+/// Per-key signed deltas for two balance maps (missing keys count as zero).
+fn balance_deltas<K: Copy + Eq + std::hash::Hash>(
+    before: &HashMap<K, TokenAmount>,
+    after: &HashMap<K, TokenAmount>,
+) -> HashMap<K, SignedU256> {
+    let mut out = HashMap::new();
+    for key in before.keys().chain(after.keys()) {
+        let b = before.get(key).cloned().unwrap_or(U256::zero());
+        let a = after.get(key).cloned().unwrap_or(U256::zero());
+        let d = signed_delta(b, a);
+        if !d.is_zero() {
+            out.insert(*key, d);
+        }
+    }
+    out
+}
+
+/// Report changed positions, OI deltas, pool balance changes and claimable changes
+/// between two snapshots.
+pub fn diff_state(before: &StateSnapshot, after: &StateSnapshot) -> StateDiff {
+    let mut diff = StateDiff::default();
+
+    for (key, pos_after) in after.positions.iter() {
+        match before.positions.get(key) {
+            None => diff.positions_created.push(*key),
+            Some(pos_before) if pos_before != pos_after => diff.positions_changed.push(*key),
+            Some(_) => {}
+        }
+    }
+    for key in before.positions.keys() {
+        if !after.positions.contains_key(key) {
+            diff.positions_removed.push(*key);
+        }
+    }
+
+    let empty = OpenInterest::default();
+    for market_id in before
+        .open_interest
+        .keys()
+        .chain(after.open_interest.keys())
+    {
+        let b = before.open_interest.get(market_id).unwrap_or(&empty);
+        let a = after.open_interest.get(market_id).unwrap_or(&empty);
+        let delta = OiDelta {
+            long_usd: signed_delta(b.long_usd, a.long_usd),
+            short_usd: signed_delta(b.short_usd, a.short_usd),
+        };
+        if !delta.long_usd.is_zero() || !delta.short_usd.is_zero() {
+            diff.oi_deltas.insert(*market_id, delta);
+        }
+    }
+
+    diff.pool_liquidity_deltas = balance_deltas(&before.pool_liquidity, &after.pool_liquidity);
+    diff.pool_fee_deltas = balance_deltas(&before.pool_fees, &after.pool_fees);
+    diff.claimable_funding_deltas =
+        balance_deltas(&before.claimable_funding, &after.claimable_funding);
+    diff.claimable_fee_deltas = balance_deltas(&before.claimable_fees, &after.claimable_fees);
+
+    diff
+}
@@ -0,0 +1,185 @@
+// src/state/token_registry.rs
+
+use std::collections::HashMap;
+
+use primitive_types::U256;
+
+use crate::types::{AssetId, TokenAmount, Usd};
+
+/// Largest supported `TokenMeta::decimals`: 10^77 is the biggest power of ten
+/// that fits in a `U256`.
+pub const MAX_TOKEN_DECIMALS: u8 = 77;
+
+/// Static metadata of a token.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TokenMeta {
+    /// Number of decimals: 1 whole token = 10^decimals atoms (USDC = 6, WETH = 18).
+    pub decimals: u8,
+    /// Haircut applied to this token's value when it is used as collateral, in bps.
+    /// Zero for stablecoins; volatile collateral counts for less than face value.
+    pub collateral_haircut_bps: u32,
+}
+
+/// Token metadata keyed by `AssetId`.
+///
+/// Scaling convention:
+///  - token amounts are always raw atoms (`TokenAmount`);
+///  - `OraclePrices` are USD(1e30) **per atom**, so `amount * price` is USD(1e30)
+///    regardless of decimals;
+///  - prices quoted per **whole token** must go through `normalize_to_usd`
+///    (or `price_per_atom`) with the token's decimals before mixing with
+///    amounts of other tokens.
+#[derive(Debug, Default, Clone)]
+pub struct TokenRegistry {
+    tokens: HashMap<AssetId, TokenMeta>,
+}
+
+impl TokenRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register (or replace) `asset`. Rejects decimals above `MAX_TOKEN_DECIMALS`.
+    pub fn register(&mut self, asset: AssetId, meta: TokenMeta) -> Result<(), String> {
+        if meta.decimals > MAX_TOKEN_DECIMALS {
+            return Err("invalid_token_decimals".into());
+        }
+        self.tokens.insert(asset, meta);
+        Ok(())
+    }
+
+    pub fn get(&self, asset: AssetId) -> Option<&TokenMeta> {
+        self.tokens.get(&asset)
+    }
+
+    pub fn decimals(&self, asset: AssetId) -> Result<u8, String> {
+        self.get(asset)
+            .map(|m| m.decimals)
+            .ok_or_else(|| "unknown_token".into())
+    }
+
+    /// Collateral haircut for `asset` in bps. Unregistered tokens have none.
+    pub fn collateral_haircut_bps(&self, asset: AssetId) -> u32 {
+        self.get(asset).map_or(0, |m| m.collateral_haircut_bps)
+    }
+
+    /// USD(1e30) value of `amount` atoms of `asset`, priced per whole token.
+    pub fn amount_to_usd(
+        &self,
+        asset: AssetId,
+        amount: TokenAmount,
+        price_per_token: Usd,
+    ) -> Result<Usd, String> {
+        normalize_to_usd(amount, price_per_token, self.decimals(asset)?)
+    }
+}
+
+/// USD(1e30) value of `amount` atoms, given a USD(1e30) price per whole token.
+///
+/// usd = amount * price_per_token / 10^decimals (floor).
+pub fn normalize_to_usd(
+    amount: TokenAmount,
+    price_per_token: Usd,
+    decimals: u8,
+) -> Result<Usd, String> {
+    if decimals > MAX_TOKEN_DECIMALS {
+        return Err("invalid_token_decimals".into());
+    }
+    let value = amount
+        .checked_mul(price_per_token)
+        .ok_or("normalize_to_usd_overflow")?;
+    Ok(value / U256::exp10(decimals as usize))
+}
+
+/// Convert a USD(1e30) price per whole token into a USD(1e30) price per atom (floor).
+///
+/// Decimals above `MAX_TOKEN_DECIMALS` floor to zero: 10^decimals exceeds any `U256`.
+pub fn price_per_atom(price_per_token: Usd, decimals: u8) -> Usd {
+    if decimals > MAX_TOKEN_DECIMALS {
+        return U256::zero();
+    }
+    price_per_token / U256::exp10(decimals as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn usd(x: u64) -> U256 {
+        U256::from(x) * U256::exp10(30)
+    }
+
+    #[test]
+    fn mixed_decimal_collateral_normalizes_to_same_usd() {
+        let usdc = AssetId(10);
+        let weth = AssetId(11);
+        let mut registry = TokenRegistry::new();
+        registry
+            .register(
+                usdc,
+                TokenMeta {
+                    decimals: 6,
+                    collateral_haircut_bps: 0,
+                },
+            )
+            .unwrap();
+        registry
+            .register(
+                weth,
+                TokenMeta {
+                    decimals: 18,
+                    collateral_haircut_bps: 2_000,
+                },
+            )
+            .unwrap();
+
+        // 3_000 USDC @ $1 and 1 WETH @ $3_000 are both worth $3_000.
+        let usdc_amount = U256::from(3_000u64) * U256::exp10(6);
+        let weth_amount = U256::exp10(18);
+
+        let usdc_usd = registry.amount_to_usd(usdc, usdc_amount, usd(1)).unwrap();
+        let weth_usd = registry
+            .amount_to_usd(weth, weth_amount, usd(3_000))
+            .unwrap();
+        assert_eq!(usdc_usd, usd(3_000));
+        assert_eq!(weth_usd, usd(3_000));
+
+        // Same value through the per-atom price used by `OraclePrices`.
+        assert_eq!(weth_amount * price_per_atom(usd(3_000), 18), weth_usd);
+
+        assert_eq!(
+            registry.amount_to_usd(AssetId(99), weth_amount, usd(1)),
+            Err("unknown_token".into())
+        );
+
+        assert_eq!(registry.collateral_haircut_bps(usdc), 0);
+        assert_eq!(registry.collateral_haircut_bps(weth), 2_000);
+        assert_eq!(registry.collateral_haircut_bps(AssetId(99)), 0);
+    }
+
+    #[test]
+    fn decimals_beyond_u256_range_are_rejected() {
+        let mut registry = TokenRegistry::new();
+        let meta = |decimals| TokenMeta {
+            decimals,
+            collateral_haircut_bps: 0,
+        };
+
+        assert!(
+            registry
+                .register(AssetId(1), meta(MAX_TOKEN_DECIMALS))
+                .is_ok()
+        );
+        assert_eq!(
+            registry.register(AssetId(2), meta(MAX_TOKEN_DECIMALS + 1)),
+            Err("invalid_token_decimals".into())
+        );
+        assert_eq!(registry.get(AssetId(2)), None);
+
+        assert_eq!(
+            normalize_to_usd(U256::one(), usd(1), u8::MAX),
+            Err("invalid_token_decimals".into())
+        );
+        assert_eq!(price_per_atom(usd(1), u8::MAX), U256::zero());
+    }
+}
@@ -58,6 +58,9 @@ pub struct AssetId(pub u32);
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct OrderId(pub u64);
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WithdrawalId(pub u64);
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
 pub struct AccountId(pub [u8; 32]);
 
@@ -115,6 +118,15 @@ pub struct Order {
     /// This is independent from size_delta_usd and can increase leverage if not guarded.
     pub withdraw_collateral_amount: TokenAmount,
 
+    /// Collateral tokens prepaid to reimburse the keeper's execution gas.
+    /// Must cover `RiskCfg::execution_fee_required`. Taken on submit and paid
+    /// to the executing keeper (see `Executor::submit_order`).
+    pub execution_fee_tokens: TokenAmount,
+
+    /// Reduce-only guard: when set, the order may only shrink or close a position.
+    /// Any execution that would increase `size_usd` is rejected.
+    pub reduce_only: bool,
+
     /// Target leverage X for this step, e.g. 5 means 5x.
     pub target_leverage_x: u32,
 